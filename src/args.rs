@@ -1,9 +1,22 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
-use clap::{Args, Parser};
+use bevy::{
+    asset::AssetServerSettings,
+    log::{Level, LogSettings},
+    prelude::{default, Vec2},
+    window::PresentMode,
+};
+use clap::{Args, Parser, ValueEnum};
 
-use crate::{network, save};
+use crate::{
+    frame_limiter::{self, FrameLimiterPlugin},
+    network,
+    player::{self, client::CameraBounds, GravityDirection, MissingChunks, MovementModel},
+    save,
+    settings::Settings,
+    world,
+};
 
 pub fn get_args() -> GameArgs {
     GameArgs::parse()
@@ -18,6 +31,33 @@ pub enum GameArgs {
     Client(ClientArgs),
 }
 
+/// How much to log, each level includes the ones above it
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// Log settings to insert before LogPlugin is built
+    pub fn log_settings(self) -> LogSettings {
+        LogSettings {
+            level: match self {
+                LogLevel::Error => Level::ERROR,
+                LogLevel::Warn => Level::WARN,
+                LogLevel::Info => Level::INFO,
+                LogLevel::Debug => Level::DEBUG,
+                LogLevel::Trace => Level::TRACE,
+            },
+            // keep the default filter that quiets wgpu
+            ..default()
+        }
+    }
+}
+
 #[derive(Args, Debug, Clone)]
 // #[command(arg_required_else_help(true))]
 pub struct ServerArgs {
@@ -25,9 +65,88 @@ pub struct ServerArgs {
     #[arg(short = 'f', long = "file", default_value_os_t = save::default_save_path_server())]
     pub save_file: PathBuf,
 
+    /// Named save to load and save to instead, kept in the save folder
+    #[arg(long, conflicts_with = "save_file", value_parser = parse_slot_name)]
+    pub slot: Option<String>,
+
     /// Port to open server on
     #[arg(short = 'p', long, default_value_t = network::DEFAULT_SERVER_PORT)]
     pub port: u16,
+
+    /// Seconds between autosaves, 0 disables autosaving
+    #[arg(
+        short = 's',
+        long,
+        default_value_t = save::DEFAULT_SAVE_INTERVAL_SECS,
+        value_parser = clap::value_parser!(u64).range(0..=save::MAX_SAVE_INTERVAL_SECS)
+    )]
+    pub save_interval: u64,
+
+    /// Number of chunks to generate on startup, including the surface
+    #[arg(
+        long,
+        default_value_t = world::DEFAULT_INITIAL_CHUNKS,
+        value_parser = clap::value_parser!(u64).range(1..=world::MAX_INITIAL_CHUNKS)
+    )]
+    pub initial_chunks: u64,
+
+    /// Generate the world without caves
+    #[arg(long)]
+    pub no_caves: bool,
+
+    /// Seed to generate the world from, a random one is picked and logged if not given
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Pick the surface biomes from this seed, instead of the usual sand over limestone
+    #[arg(long)]
+    pub surface_seed: Option<u64>,
+
+    /// Generate flat, solid ground instead of the normal world, for testing
+    #[arg(long)]
+    pub flat: bool,
+
+    /// How far (in blocks) from a player they can mine or place blocks, further requests are ignored
+    #[arg(long, default_value_t = player::PLAYER_MINE_RADIUS)]
+    pub reach: f32,
+
+    /// Players speed up and slow down instead of starting and stopping instantly
+    #[arg(long)]
+    pub smooth_movement: bool,
+
+    /// Which way players fall
+    #[arg(long, value_enum, default_value_t = GravityDirection::Down)]
+    pub gravity: GravityDirection,
+
+    /// What players run into where a chunk isn't loaded
+    #[arg(long, value_enum, default_value_t = MissingChunks::Empty)]
+    pub missing_chunks: MissingChunks,
+
+    /// Tell every player which cell the others are pointing at, costs a few bytes per player per tick
+    #[arg(long)]
+    pub share_targets: bool,
+
+    /// Largest packet (in bytes) the server will send, bigger messages are split into up to
+    /// MAX_FRAGMENTS packets and lower priority bodies are dropped to fit those
+    #[arg(long, default_value_t = network::MAX_PACKET_SIZE)]
+    pub max_packet_size: usize,
+
+    /// Most bodies the server will read from or send in one packet, extras are dropped
+    #[arg(long, default_value_t = network::MAX_BODIES_PER_PACKET)]
+    pub max_bodies: usize,
+
+    /// IP address allowed to use admin commands, can be given multiple times
+    #[arg(short = 'a', long = "admin")]
+    pub admins: Vec<IpAddr>,
+
+    /// How much to log
+    #[arg(short = 'l', long, value_enum, default_value_t = LogLevel::Info)]
+    pub log_level: LogLevel,
+
+    /// JSON file to keep writing connected players' names, stats and inventories to
+    #[cfg(feature = "json-export")]
+    #[arg(long, value_name = "FILE")]
+    pub export_json: Option<PathBuf>,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -44,4 +163,278 @@ pub struct ClientArgs {
     /// Port of client
     #[arg(short = 'c', long, default_value_t = 0)]
     pub client_port: u16,
+
+    /// Reload textures when their files change, for development
+    #[arg(long)]
+    pub watch_assets: bool,
+
+    /// How much to log
+    #[arg(short = 'l', long, value_enum, default_value_t = LogLevel::Info)]
+    pub log_level: LogLevel,
+
+    /// Wait for the display to refresh before showing a frame
+    #[arg(long)]
+    pub vsync: bool,
+
+    /// Most frames to render per second, uncapped if not given
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=MAX_FPS_CAP))]
+    pub fps_cap: Option<u32>,
+
+    /// Width and height in pixels of the area the player can move in before the camera follows
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["WIDTH", "HEIGHT"],
+        default_values_t = player::CAMERA_BOUNDS_SIZE
+    )]
+    pub camera_bounds: Vec<f32>,
+
+    /// Name other players see, your address if not given
+    #[arg(short = 'n', long)]
+    pub name: Option<String>,
+
+    /// Watch the world without a player, move the camera with the arrow keys
+    #[arg(long)]
+    pub observe: bool,
+}
+
+/// Slot names become file names, so they can't leave the save folder
+fn parse_slot_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(String::from(
+            "must not be empty, start with '.' or contain path separators",
+        ));
+    }
+    Ok(name.to_string())
+}
+
+impl ServerArgs {
+    /// The file to load and save to, from --slot or --file
+    pub fn save_path(&self) -> PathBuf {
+        match &self.slot {
+            Some(slot) => save::slot_path(slot),
+            None => self.save_file.clone(),
+        }
+    }
+
+    /// How players move left and right
+    pub fn movement_model(&self) -> MovementModel {
+        if self.smooth_movement {
+            MovementModel::Accelerated {
+                acceleration: player::PLAYER_ACCELERATION,
+                friction: player::PLAYER_FRICTION,
+            }
+        } else {
+            MovementModel::Classic
+        }
+    }
+}
+
+/// Highest allowed --fps-cap
+pub const MAX_FPS_CAP: i64 = 1000;
+
+impl ClientArgs {
+    /// Fill in what wasn't given on the command line from the saved settings
+    pub fn with_settings(mut self, settings: &Settings) -> Self {
+        self.vsync |= settings.vsync;
+        self.fps_cap = self.fps_cap.or_else(|| {
+            // the file isn't checked by clap
+            settings
+                .fps_cap
+                .filter(|fps| (1..=MAX_FPS_CAP).contains(&i64::from(*fps)))
+        });
+        self.name = self.name.or_else(|| settings.name.clone());
+        self
+    }
+
+    /// Present mode for the window
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Immediate
+        }
+    }
+
+    /// Frame limiter settings, None if uncapped
+    pub fn frame_limiter(&self) -> Option<FrameLimiterPlugin> {
+        self.fps_cap.map(|fps| FrameLimiterPlugin {
+            frame_time: frame_limiter::frame_time(fps),
+        })
+    }
+
+    /// Camera follow settings, to insert before the player plugin
+    pub fn camera_bounds(&self) -> CameraBounds {
+        CameraBounds {
+            size: Vec2::new(self.camera_bounds[0], self.camera_bounds[1]),
+        }
+    }
+
+    /// Server to connect to unless another is picked on the connect screen
+    pub fn server_address(&self) -> SocketAddr {
+        SocketAddr::new(self.server_ip, self.server_port)
+    }
+
+    /// The name to send to the server, cleaned up; unset if no usable name was given
+    pub fn player_name(&self) -> network::PlayerName {
+        network::PlayerName::sanitized(self.name.as_deref().unwrap_or_default())
+    }
+
+    /// Asset server settings to insert before DefaultPlugins
+    pub fn asset_server_settings(&self) -> AssetServerSettings {
+        AssetServerSettings {
+            watch_for_changes: self.watch_assets,
+            ..default()
+        }
+    }
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// parse server arguments, panicking if they were client arguments
+    fn parse_server(args: &[&str]) -> Result<ServerArgs, clap::Error> {
+        let args = ["game", "server"].iter().chain(args);
+        match GameArgs::try_parse_from(args)? {
+            GameArgs::Server(args) => Ok(args),
+            GameArgs::Client(_) => panic!("parsed server args as client args"),
+        }
+    }
+
+    #[test]
+    fn watch_assets_flag() {
+        let args = parse_client(&[]);
+        assert!(!args.watch_assets);
+        assert!(!args.asset_server_settings().watch_for_changes);
+
+        let args = parse_client(&["--watch-assets"]);
+        assert!(args.watch_assets);
+        assert!(args.asset_server_settings().watch_for_changes);
+    }
+
+    /// parse valid client arguments
+    fn parse_client(args: &[&str]) -> ClientArgs {
+        let args = ["game", "client"].iter().chain(args);
+        match GameArgs::try_parse_from(args).unwrap() {
+            GameArgs::Client(args) => args,
+            GameArgs::Server(_) => panic!("parsed client args as server args"),
+        }
+    }
+
+    #[test]
+    fn log_level_maps_to_settings() {
+        // default matches bevy's default
+        let args = parse_server(&[]).unwrap();
+        assert_eq!(args.log_level, LogLevel::Info);
+        assert_eq!(args.log_level.log_settings().level, Level::INFO);
+
+        let args = parse_server(&["--log-level", "debug"]).unwrap();
+        assert_eq!(args.log_level.log_settings().level, Level::DEBUG);
+        let args = parse_server(&["-l", "error"]).unwrap();
+        assert_eq!(args.log_level.log_settings().level, Level::ERROR);
+        assert_eq!(
+            args.log_level.log_settings().filter,
+            LogSettings::default().filter
+        );
+
+        assert!(parse_server(&["--log-level", "loud"]).is_err());
+    }
+
+    #[test]
+    fn display_settings() {
+        let args = parse_client(&[]);
+        assert_eq!(args.present_mode(), PresentMode::Immediate);
+        assert!(args.frame_limiter().is_none());
+
+        let args = parse_client(&["--vsync", "--fps-cap", "50"]);
+        assert_eq!(args.present_mode(), PresentMode::Fifo);
+        let limiter = args.frame_limiter().unwrap();
+        assert_eq!(limiter.frame_time, std::time::Duration::from_millis(20));
+
+        let args = ["game", "client", "--fps-cap", "0"];
+        assert!(GameArgs::try_parse_from(args).is_err());
+
+        let args = parse_client(&[]);
+        assert_eq!(args.camera_bounds(), CameraBounds::default());
+        let args = parse_client(&["--camera-bounds", "300", "200"]);
+        assert_eq!(args.camera_bounds().size, Vec2::new(300., 200.));
+    }
+
+    #[test]
+    fn settings_fill_in_missing_args() {
+        let settings = Settings {
+            vsync: true,
+            fps_cap: Some(30),
+            name: Some(String::from("saved")),
+            ..Settings::default()
+        };
+
+        let args = parse_client(&[]).with_settings(&settings);
+        assert!(args.vsync);
+        assert_eq!(args.fps_cap, Some(30));
+        assert_eq!(args.name.as_deref(), Some("saved"));
+
+        // the command line wins
+        let args = parse_client(&["--fps-cap", "60", "-n", "given"]).with_settings(&settings);
+        assert_eq!(args.fps_cap, Some(60));
+        assert_eq!(args.name.as_deref(), Some("given"));
+
+        let bad_cap = Settings {
+            fps_cap: Some(0),
+            ..Settings::default()
+        };
+        assert!(parse_client(&[]).with_settings(&bad_cap).fps_cap.is_none());
+    }
+
+    #[test]
+    fn save_slots_pick_the_save_file() {
+        let args = parse_server(&[]).unwrap();
+        assert_eq!(args.save_path(), save::default_save_path_server());
+
+        let args = parse_server(&["-f", "elsewhere/world.sav"]).unwrap();
+        assert_eq!(args.save_path(), PathBuf::from("elsewhere/world.sav"));
+
+        let args = parse_server(&["--slot", "caves"]).unwrap();
+        assert_eq!(
+            args.save_path(),
+            std::path::Path::new(".")
+                .join(save::DEFAULT_SAVE_DIR)
+                .join("caves.sav")
+        );
+
+        assert!(parse_server(&["--slot", "../escape"]).is_err());
+        assert!(parse_server(&["--slot", ""]).is_err());
+        assert!(parse_server(&["--slot", "a", "-f", "b.sav"]).is_err());
+    }
+
+    #[test]
+    fn seed_parsing() {
+        assert_eq!(parse_server(&[]).unwrap().seed, None);
+        assert_eq!(parse_server(&["--seed", "1234"]).unwrap().seed, Some(1234));
+        assert!(parse_server(&["--seed", "-1"]).is_err());
+    }
+
+    #[test]
+    fn save_interval_parsing() {
+        // default
+        let args = parse_server(&[]).unwrap();
+        assert_eq!(args.save_interval, save::DEFAULT_SAVE_INTERVAL_SECS);
+
+        // in range, including disabled
+        assert_eq!(
+            parse_server(&["--save-interval", "30"])
+                .unwrap()
+                .save_interval,
+            30
+        );
+        assert_eq!(parse_server(&["-s", "0"]).unwrap().save_interval, 0);
+
+        // out of range or not a number
+        let too_long = (save::MAX_SAVE_INTERVAL_SECS + 1).to_string();
+        assert!(parse_server(&["--save-interval", &too_long]).is_err());
+        assert!(parse_server(&["--save-interval", "-1"]).is_err());
+        assert!(parse_server(&["--save-interval", "often"]).is_err());
+    }
 }
@@ -0,0 +1,119 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+    hud::HudVisible,
+    player::{client::LocalPlayer, PlayerPosition},
+    states::client::GameState,
+    world::{nearest_ore, Terrain},
+    CharacterCamera, WIN_H, WIN_W,
+};
+
+/// Size of the compass needle in pixels
+const NEEDLE_SIZE: [f32; 2] = [48., 6.];
+/// Distance of the compass from the top right corner of the screen
+const COMPASS_MARGIN: f32 = 64.;
+
+/// Shows an arrow pointing toward the closest ore to the local player
+pub struct CompassPlugin;
+
+impl Plugin for CompassPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Compass { enabled: true })
+            .add_enter_system(GameState::InGame, spawn_compass)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::InGame)
+                    .with_system(c_toggles_compass)
+                    .with_system(point_compass)
+                    .into(),
+            )
+            .add_exit_system(GameState::InGame, destroy_compass)
+            .add_exit_system(GameState::Paused, destroy_compass);
+    }
+}
+
+/// Settings for the ore compass; should be a resource
+/// TODO: only enable once the player has crafted a compass
+pub struct Compass {
+    pub enabled: bool,
+}
+
+/// Marker for the compass needle sprite
+#[derive(Component)]
+struct CompassNeedle;
+
+/// Spawns the needle as a child of the camera so that it stays in the corner of the screen
+fn spawn_compass(mut commands: Commands, camera: Query<Entity, With<CharacterCamera>>) {
+    for camera in camera.iter() {
+        commands.entity(camera).with_children(|parent| {
+            parent
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::GOLD,
+                        custom_size: Some(Vec2::from(NEEDLE_SIZE)),
+                        ..default()
+                    },
+                    // in front of the depth overlay
+                    transform: Transform::from_xyz(
+                        WIN_W / 2. - COMPASS_MARGIN,
+                        WIN_H / 2. - COMPASS_MARGIN,
+                        -0.5,
+                    ),
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                })
+                .insert(CompassNeedle);
+        });
+    }
+}
+
+/// Make the C key toggle the compass
+fn c_toggles_compass(input: Res<Input<KeyCode>>, mut compass: ResMut<Compass>) {
+    if input.just_pressed(KeyCode::C) {
+        compass.enabled = !compass.enabled;
+        info!("ore compass enabled: {}", compass.enabled);
+    }
+}
+
+/// Rotate the needle toward the ore closest to the local player
+/// Hidden if disabled, if the HUD is hidden, or if there is no ore in the loaded terrain
+fn point_compass(
+    compass: Res<Compass>,
+    hud: Res<HudVisible>,
+    terrain: Res<Terrain>,
+    player: Query<&PlayerPosition, With<LocalPlayer>>,
+    mut needles: Query<(&mut Transform, &mut Visibility), With<CompassNeedle>>,
+) {
+    let ore = match (compass.enabled && hud.0, player.get_single()) {
+        (true, Ok(position)) => nearest_ore(&terrain, position).map(|ore| (position, ore)),
+        _ => None,
+    };
+
+    for (mut transform, mut visibility) in needles.iter_mut() {
+        match ore {
+            Some((position, (ore_x, ore_y))) => {
+                // positions grow downwards as negative numbers
+                let dx = ore_x as f32 - position.x;
+                let dy = -(ore_y as f32) - position.y;
+                transform.rotation = Quat::from_rotation_z(dy.atan2(dx));
+
+                if !visibility.is_visible {
+                    visibility.is_visible = true;
+                }
+            }
+            None => {
+                if visibility.is_visible {
+                    visibility.is_visible = false;
+                }
+            }
+        }
+    }
+}
+
+fn destroy_compass(mut commands: Commands, query: Query<Entity, With<CompassNeedle>>) {
+    for entity in query.iter() {
+        // recursive also detaches it from the camera
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,204 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use iyes_loopless::prelude::*;
+
+use crate::{
+    player::{client::LocalPlayer, PlayerPosition},
+    settings::Settings,
+    states::client::GameState,
+    world::CHUNK_HEIGHT,
+    CharacterCamera, WIN_H, WIN_W,
+};
+
+/// Depth (in blocks) above which there is no darkening, roughly where the surface hills end
+const SURFACE_DEPTH: f32 = 16.;
+/// How many blocks below SURFACE_DEPTH it takes to reach full darkness
+const DARKEN_DISTANCE: f32 = CHUNK_HEIGHT as f32;
+/// Alpha of the overlay's edges when fully dark, keep it below 1 so the world stays visible
+const MAX_ALPHA: f32 = 0.6;
+/// Fraction of the way from the middle of the screen to a corner that stays clear
+const VIGNETTE_CLEAR_RADIUS: f32 = 0.3;
+/// Width and height of the vignette texture in pixels, it is stretched over the screen
+const VIGNETTE_TEXTURE_SIZE: u32 = 128;
+
+/// Darkens the edges of the screen as the local player digs further underground
+/// Turned on and off with Settings::depth_overlay
+pub struct DepthOverlayPlugin;
+
+impl Plugin for DepthOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(create_vignette)
+            .add_enter_system(GameState::InGame, spawn_overlay)
+            .add_system(update_overlay.run_in_state(GameState::InGame))
+            .add_exit_system(GameState::InGame, destroy_overlay)
+            .add_exit_system(GameState::Paused, destroy_overlay);
+    }
+}
+
+/// The texture the overlay sprite draws; should be a resource
+struct VignetteTexture(Handle<Image>);
+
+/// Marker for the overlay sprite
+#[derive(Component)]
+struct OverlaySprite;
+
+/// How dark the overlay's edges should be at a given depth (in blocks, positive is down)
+pub fn overlay_alpha(depth: f32) -> f32 {
+    ((depth - SURFACE_DEPTH) / DARKEN_DISTANCE).clamp(0., 1.) * MAX_ALPHA
+}
+
+/// How dark the vignette is, from 0 to 1, at `distance` from the middle of the screen,
+/// where 1 is a corner; clear in the middle and darkest in the corners
+pub fn vignette_alpha(distance: f32) -> f32 {
+    let t = ((distance - VIGNETTE_CLEAR_RADIUS) / (1. - VIGNETTE_CLEAR_RADIUS)).clamp(0., 1.);
+    // smoothstep, so there's no visible ring where it starts
+    t * t * (3. - 2. * t)
+}
+
+/// Black texture whose alpha follows vignette_alpha
+fn vignette_image() -> Image {
+    let size = VIGNETTE_TEXTURE_SIZE;
+    let data = (0..size)
+        .flat_map(|y| (0..size).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            // from -1 to 1 across the texture, measured from pixel centers
+            let dx = (x as f32 + 0.5) / size as f32 * 2. - 1.;
+            let dy = (y as f32 + 0.5) / size as f32 * 2. - 1.;
+            let distance = (dx * dx + dy * dy).sqrt() / std::f32::consts::SQRT_2;
+            [0, 0, 0, (vignette_alpha(distance) * 255.).round() as u8]
+        })
+        .collect();
+    Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn create_vignette(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    commands.insert_resource(VignetteTexture(images.add(vignette_image())));
+}
+
+/// Spawns the overlay as a child of the camera so that it always covers the screen
+/// It is a sprite rather than UI so that it darkens the world but not the HUD
+fn spawn_overlay(
+    mut commands: Commands,
+    camera: Query<Entity, With<CharacterCamera>>,
+    vignette: Res<VignetteTexture>,
+) {
+    for camera in camera.iter() {
+        commands.entity(camera).with_children(|parent| {
+            parent
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        // the texture is black, this only scales its alpha
+                        color: Color::rgba(1., 1., 1., 0.),
+                        custom_size: Some(Vec2::new(WIN_W, WIN_H)),
+                        ..default()
+                    },
+                    texture: vignette.0.clone(),
+                    // in front of everything else the camera can see
+                    transform: Transform::from_xyz(0., 0., -1.),
+                    ..default()
+                })
+                .insert(OverlaySprite);
+        });
+    }
+}
+
+/// Set the overlay alpha from the local player's depth
+fn update_overlay(
+    settings: Res<Settings>,
+    player: Query<&PlayerPosition, With<LocalPlayer>>,
+    mut sprites: Query<&mut Sprite, With<OverlaySprite>>,
+) {
+    let alpha = match (settings.depth_overlay, player.get_single()) {
+        (true, Ok(position)) => overlay_alpha(-position.y),
+        _ => 0.,
+    };
+
+    for mut sprite in sprites.iter_mut() {
+        // only touch the sprite if needed, avoids change detection every frame
+        if sprite.color.a() != alpha {
+            sprite.color.set_a(alpha);
+        }
+    }
+}
+
+fn destroy_overlay(mut commands: Commands, query: Query<Entity, With<OverlaySprite>>) {
+    for entity in query.iter() {
+        // recursive also detaches it from the camera
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_alpha_by_depth() {
+        // clear at and above the surface
+        assert_eq!(overlay_alpha(-5.), 0.);
+        assert_eq!(overlay_alpha(0.), 0.);
+        assert_eq!(overlay_alpha(SURFACE_DEPTH), 0.);
+
+        // gets darker going down
+        let shallow = overlay_alpha(SURFACE_DEPTH + 10.);
+        let deeper = overlay_alpha(SURFACE_DEPTH + 30.);
+        assert!(shallow > 0.);
+        assert!(deeper > shallow);
+
+        // never goes past the maximum
+        assert_eq!(overlay_alpha(SURFACE_DEPTH + DARKEN_DISTANCE), MAX_ALPHA);
+        assert_eq!(overlay_alpha(10_000.), MAX_ALPHA);
+    }
+
+    #[test]
+    fn vignette_darkens_toward_the_edges() {
+        assert_eq!(vignette_alpha(0.), 0.);
+        assert_eq!(vignette_alpha(VIGNETTE_CLEAR_RADIUS), 0.);
+        assert!(vignette_alpha(0.7) > vignette_alpha(0.5));
+        assert_eq!(vignette_alpha(1.), 1.);
+
+        // and the texture follows it, clear in the middle and dark in the corners
+        let image = vignette_image();
+        let alpha_at =
+            |x: u32, y: u32| image.data[((y * VIGNETTE_TEXTURE_SIZE + x) * 4 + 3) as usize];
+        let middle = VIGNETTE_TEXTURE_SIZE / 2;
+        assert_eq!(alpha_at(middle, middle), 0);
+        assert!(alpha_at(0, middle) > 0);
+        assert!(alpha_at(0, 0) > alpha_at(0, middle));
+    }
+
+    #[test]
+    fn overlay_follows_the_setting() {
+        let mut world = World::new();
+        world.insert_resource(Settings::default());
+        world
+            .spawn()
+            .insert(LocalPlayer)
+            .insert(PlayerPosition { x: 0., y: -1000. });
+        let sprite = world
+            .spawn()
+            .insert(Sprite::default())
+            .insert(OverlaySprite)
+            .id();
+        let mut stage = SystemStage::single_threaded().with_system(update_overlay);
+
+        stage.run(&mut world);
+        assert_eq!(world.get::<Sprite>(sprite).unwrap().color.a(), MAX_ALPHA);
+
+        world.resource_mut::<Settings>().depth_overlay = false;
+        stage.run(&mut world);
+        assert_eq!(world.get::<Sprite>(sprite).unwrap().color.a(), 0.);
+    }
+}
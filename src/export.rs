@@ -0,0 +1,149 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::Serialize;
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use crate::{
+    network::{server::ConnectedClientInfo, ClientAddress, PlayerName},
+    player::{Inventory, PlayerStats},
+    states,
+};
+
+/// Seconds between writes of the export file
+pub const EXPORT_INTERVAL_SECS: u64 = 10;
+
+/// Periodically writes the connected players to a JSON file, for dashboards and leaderboards
+pub struct ExportPlugin {
+    /// File to write to, replaced on every export
+    pub path: PathBuf,
+}
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ExportPath(self.path.clone()))
+            .add_fixed_timestep(
+                std::time::Duration::from_secs(EXPORT_INTERVAL_SECS),
+                "EXPORT_INTERVAL",
+            )
+            .add_fixed_timestep_system(
+                "EXPORT_INTERVAL",
+                0,
+                export_players
+                    .run_in_state(states::server::GameState::Running)
+                    .label("export_players"),
+            );
+    }
+}
+
+/// Where the export goes; should be a resource
+struct ExportPath(PathBuf);
+
+/// One player in the export file
+#[derive(Serialize)]
+struct PlayerExport<'a> {
+    /// The player's name, or their address if they didn't pick one
+    name: String,
+    address: String,
+    stats: &'a PlayerStats,
+    /// Count of each block type held, leaving out the ones they have none of
+    inventory: BTreeMap<String, usize>,
+}
+
+/// Turn players into a pretty printed JSON document: `{"players": [...]}`
+pub fn players_json<'a>(
+    players: impl Iterator<
+        Item = (
+            &'a ClientAddress,
+            &'a PlayerName,
+            &'a PlayerStats,
+            &'a Inventory,
+        ),
+    >,
+) -> serde_json::Result<String> {
+    let players: Vec<PlayerExport> = players
+        .map(|(addr, name, stats, inventory)| PlayerExport {
+            name: name.or_address(addr),
+            address: addr.to_string(),
+            stats,
+            inventory: inventory
+                .amounts
+                .iter()
+                .filter(|(_, count)| **count > 0)
+                .map(|(block, count)| (format!("{:?}", block), *count))
+                .collect(),
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&serde_json::json!({ "players": players }))
+}
+
+fn export_players(
+    path: Res<ExportPath>,
+    players: Query<
+        (&ClientAddress, &PlayerName, &PlayerStats, &Inventory),
+        With<ConnectedClientInfo>,
+    >,
+) {
+    let json = match players_json(players.iter()) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("unable to export players, {}", e);
+            return;
+        }
+    };
+
+    // write next to it then rename, so readers never see half a file
+    let temp = path.0.with_extension("json.tmp");
+    if let Err(e) = fs::write(&temp, json).and_then(|_| fs::rename(&temp, &path.0)) {
+        error!("could not export players to {}, {}", path.0.display(), e);
+    }
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::BlockType;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn export_is_valid_json() {
+        let addr = ClientAddress {
+            addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+        };
+        let stats = PlayerStats {
+            blocks_mined: 42,
+            blocks_placed: 3,
+            deaths: 1,
+            play_time_secs: 3600,
+        };
+        let mut inventory = Inventory::default();
+        inventory.amounts.insert(BlockType::Iron, 7);
+
+        let named = PlayerName::sanitized("Bob");
+        let unnamed = PlayerName::default();
+        let json = players_json(
+            [
+                (&addr, &named, &stats, &inventory),
+                (&addr, &unnamed, &stats, &Inventory::default()),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+
+        let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let players = parsed["players"].as_array().unwrap();
+        assert_eq!(players.len(), 2);
+
+        let bob = &players[0];
+        assert_eq!(bob["name"], "Bob");
+        assert_eq!(bob["address"], "127.0.0.1:1234");
+        assert_eq!(bob["stats"]["blocks_mined"], 42);
+        assert_eq!(bob["stats"]["play_time_secs"], 3600);
+        assert_eq!(bob["inventory"], serde_json::json!({ "Iron": 7 }));
+
+        // no name falls back to the address, empty inventory is an empty object
+        assert_eq!(players[1]["name"], "127.0.0.1:1234");
+        assert_eq!(players[1]["inventory"], serde_json::json!({}));
+    }
+}
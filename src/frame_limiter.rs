@@ -0,0 +1,41 @@
+use bevy::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Sleeps at the end of every frame so that frames take at least frame_time
+/// Game logic runs on fixed timesteps, so this only changes how often we render
+pub struct FrameLimiterPlugin {
+    pub frame_time: Duration,
+}
+
+impl Plugin for FrameLimiterPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FrameLimiter {
+            frame_time: self.frame_time,
+            frame_start: None,
+        })
+        .add_system_to_stage(CoreStage::Last, limit_frame_rate);
+    }
+}
+
+/// Should be a resource
+struct FrameLimiter {
+    /// Shortest time a frame is allowed to take
+    frame_time: Duration,
+    /// When the current frame started, None before the first frame
+    frame_start: Option<Instant>,
+}
+
+/// Minimum time between frames for a frame rate cap
+pub fn frame_time(fps_cap: u32) -> Duration {
+    Duration::from_secs_f64(1. / fps_cap as f64)
+}
+
+fn limit_frame_rate(mut limiter: ResMut<FrameLimiter>) {
+    if let Some(start) = limiter.frame_start {
+        let elapsed = start.elapsed();
+        if elapsed < limiter.frame_time {
+            std::thread::sleep(limiter.frame_time - elapsed);
+        }
+    }
+    limiter.frame_start = Some(Instant::now());
+}
@@ -0,0 +1,191 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{network::client::ServerPaused, player::client::BuildMode, states::client::GameState};
+
+/// Hides and shows every HUD element at once with F5, for screenshots
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(HudVisible(true))
+            .add_system(
+                f5_toggles_hud
+                    .run_in_state(GameState::InGame)
+                    .label("f5_toggles_hud"),
+            )
+            .add_system(
+                apply_hud_visibility
+                    .run_in_state(GameState::InGame)
+                    .after("f5_toggles_hud"),
+            )
+            .add_enter_system(GameState::InGame, spawn_pause_label)
+            .add_system(update_pause_label.run_in_state(GameState::InGame))
+            .add_enter_system(GameState::InGame, spawn_mode_label)
+            .add_system(update_mode_label.run_in_state(GameState::InGame))
+            .add_exit_system(GameState::InGame, show_hud_again)
+            .add_exit_system(GameState::InGame, destroy_pause_label)
+            .add_exit_system(GameState::InGame, destroy_mode_label)
+            .add_exit_system(GameState::Paused, show_hud_again)
+            .add_exit_system(GameState::Paused, destroy_pause_label)
+            .add_exit_system(GameState::Paused, destroy_mode_label);
+    }
+}
+
+/// Whether HUD elements are shown; should be a resource
+/// Anything drawing its own HUD visibility, like the compass, should check this too
+pub struct HudVisible(pub bool);
+
+/// Marker for the top-level entity of a HUD element, hidden along with its children
+/// Hidden UI nodes don't react to the mouse, so nothing needs to be done about clicks
+#[derive(Component)]
+pub struct Hud;
+
+/// Marker for the "server paused" text, which is empty while the server runs
+#[derive(Component)]
+struct PauseLabel;
+
+fn spawn_pause_label(mut commands: Commands, assets: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: assets.load("fonts/milky_coffee.ttf"),
+                    font_size: 48.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(16.),
+                    top: Val::Px(96.),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(PauseLabel)
+        .insert(Hud);
+}
+
+/// Show the label while the server's simulation is paused
+fn update_pause_label(paused: Res<ServerPaused>, mut labels: Query<&mut Text, With<PauseLabel>>) {
+    if !paused.is_changed() {
+        return;
+    }
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = if paused.0 {
+            String::from("Server paused")
+        } else {
+            String::new()
+        };
+    }
+}
+
+fn destroy_pause_label(mut commands: Commands, query: Query<Entity, With<PauseLabel>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Marker for the text saying whether the mine binding mines or places, see BuildMode
+#[derive(Component)]
+struct ModeLabel;
+
+fn spawn_mode_label(mut commands: Commands, assets: Res<AssetServer>, mode: Res<BuildMode>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                mode_text(&mode),
+                TextStyle {
+                    font: assets.load("fonts/milky_coffee.ttf"),
+                    font_size: 32.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(16.),
+                    top: Val::Px(152.),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(ModeLabel)
+        .insert(Hud);
+}
+
+fn mode_text(mode: &BuildMode) -> String {
+    String::from(if mode.placing { "Building" } else { "Mining" })
+}
+
+/// Keep the label in step with the build mode
+fn update_mode_label(mode: Res<BuildMode>, mut labels: Query<&mut Text, With<ModeLabel>>) {
+    if !mode.is_changed() {
+        return;
+    }
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = mode_text(&mode);
+    }
+}
+
+fn destroy_mode_label(mut commands: Commands, query: Query<Entity, With<ModeLabel>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Make the F5 key toggle the HUD
+fn f5_toggles_hud(input: Res<Input<KeyCode>>, mut hud: ResMut<HudVisible>) {
+    if input.just_pressed(KeyCode::F5) {
+        hud.0 = !hud.0;
+        info!("HUD visible: {}", hud.0);
+    }
+}
+
+/// Show or hide HUD elements to match HudVisible, including ones spawned after it changed
+fn apply_hud_visibility(hud: Res<HudVisible>, mut elements: Query<&mut Visibility, With<Hud>>) {
+    for mut visibility in elements.iter_mut() {
+        if visibility.is_visible != hud.0 {
+            visibility.is_visible = hud.0;
+        }
+    }
+}
+
+/// Don't come back to a game with the HUD still hidden
+fn show_hud_again(mut hud: ResMut<HudVisible>) {
+    hud.0 = true;
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggling_hides_hud_elements() {
+        let mut world = World::new();
+        world.insert_resource(HudVisible(true));
+        let hud = world.spawn().insert(Hud).insert(Visibility::default()).id();
+        let other = world.spawn().insert(Visibility::default()).id();
+        let mut stage = SystemStage::single_threaded().with_system(apply_hud_visibility);
+        let visible = |world: &World, e| world.get::<Visibility>(e).unwrap().is_visible;
+
+        stage.run(&mut world);
+        assert!(visible(&world, hud));
+
+        world.resource_mut::<HudVisible>().0 = false;
+        stage.run(&mut world);
+        assert!(!visible(&world, hud));
+        // not part of the HUD
+        assert!(visible(&world, other));
+
+        world.resource_mut::<HudVisible>().0 = true;
+        stage.run(&mut world);
+        assert!(visible(&world, hud));
+    }
+}
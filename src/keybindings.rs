@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::{fs, io, path::Path};
+
+/// File the client reads key binding overrides from, in the working directory
+pub const KEY_BINDINGS_FILE: &str = "keybindings.toml";
+
+/// A key or mouse button that triggers an action
+/// Written as `{ key = "Space" }` or `{ mouse = "Left" }` in the bindings file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl Binding {
+    /// Whether it is held down
+    pub fn pressed(&self, keys: &Input<KeyCode>, mouse: &Input<MouseButton>) -> bool {
+        match self {
+            Binding::Key(key) => keys.pressed(*key),
+            Binding::Mouse(button) => mouse.pressed(*button),
+        }
+    }
+
+    /// Whether it was pressed since the last frame
+    pub fn just_pressed(&self, keys: &Input<KeyCode>, mouse: &Input<MouseButton>) -> bool {
+        match self {
+            Binding::Key(key) => keys.just_pressed(*key),
+            Binding::Mouse(button) => mouse.just_pressed(*button),
+        }
+    }
+}
+
+/// What each action is bound to; should be a resource
+/// Actions missing from the bindings file keep their default binding
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub move_left: Binding,
+    pub move_right: Binding,
+    pub jump: Binding,
+    pub mine: Binding,
+    pub place: Binding,
+    /// Switches the mine binding between mining and placing, see BuildMode
+    pub toggle_build_mode: Binding,
+    /// Opens and closes the pause menu
+    pub pause: Binding,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            move_left: Binding::Key(KeyCode::A),
+            move_right: Binding::Key(KeyCode::D),
+            jump: Binding::Key(KeyCode::Space),
+            mine: Binding::Mouse(MouseButton::Left),
+            place: Binding::Mouse(MouseButton::Right),
+            toggle_build_mode: Binding::Key(KeyCode::F),
+            pause: Binding::Key(KeyCode::Escape),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyBindingsError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl KeyBindings {
+    /// Read bindings from a TOML file
+    pub fn load(path: &Path) -> Result<Self, KeyBindingsError> {
+        let text = fs::read_to_string(path).map_err(KeyBindingsError::Io)?;
+        toml::from_str(&text).map_err(KeyBindingsError::Parse)
+    }
+
+    /// Bindings from `path`, the defaults if it doesn't exist or can't be read
+    pub fn load_or_default(path: &Path) -> Self {
+        match Self::load(path) {
+            Ok(bindings) => {
+                info!("loaded key bindings from {}", path.display());
+                bindings
+            }
+            Err(KeyBindingsError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(KeyBindingsError::Io(e)) => {
+                warn!(
+                    "could not read key bindings from {}: {}, using the defaults",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }
+            Err(KeyBindingsError::Parse(e)) => {
+                warn!(
+                    "bad key bindings in {}: {}, using the defaults",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_files_override_defaults() {
+        let bindings: KeyBindings = toml::from_str(
+            r#"
+            jump = { key = "Up" }
+            mine = { key = "X" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(bindings.jump, Binding::Key(KeyCode::Up));
+        assert_eq!(bindings.mine, Binding::Key(KeyCode::X));
+        // everything else is left alone
+        assert_eq!(
+            KeyBindings {
+                jump: Binding::Key(KeyCode::Space),
+                mine: Binding::Mouse(MouseButton::Left),
+                ..bindings
+            },
+            KeyBindings::default()
+        );
+
+        // typos are errors rather than silently ignored
+        assert!(toml::from_str::<KeyBindings>("jmup = { key = \"Up\" }").is_err());
+        assert!(toml::from_str::<KeyBindings>("jump = { key = \"NotAKey\" }").is_err());
+    }
+
+    #[test]
+    fn missing_bindings_files_use_defaults() {
+        let path = std::env::temp_dir().join(format!("game-no-bindings-{}", std::process::id()));
+
+        assert!(matches!(
+            KeyBindings::load(&path),
+            Err(KeyBindingsError::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+        assert_eq!(KeyBindings::load_or_default(&path), KeyBindings::default());
+    }
+}
@@ -0,0 +1,43 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::states::client::GameState;
+
+/// Shows a "connecting" screen while waiting for the server to send the world
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Loading, spawn_loading_screen)
+            .add_exit_system(GameState::Loading, destroy_loading_screen);
+    }
+}
+
+/// Marker for the top-level entity of the loading screen
+#[derive(Component)]
+struct LoadingScreen;
+
+fn spawn_loading_screen(mut commands: Commands, assets: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "Connecting\u{2026}",
+                TextStyle {
+                    font: assets.load("fonts/milky_coffee.ttf"),
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                margin: UiRect::all(Val::Auto),
+                ..default()
+            }),
+        )
+        .insert(LoadingScreen);
+}
+
+fn destroy_loading_screen(mut commands: Commands, query: Query<Entity, With<LoadingScreen>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,13 +1,24 @@
-use bevy::{diagnostic, prelude::*, window::PresentMode};
+use bevy::{diagnostic, prelude::*, render::camera::ScalingMode, window::WindowSettings};
 
 mod args;
+mod compass;
 mod credit_image;
+mod depth_overlay;
+#[cfg(feature = "json-export")]
+mod export;
+mod frame_limiter;
+mod hud;
+mod keybindings;
+mod loading;
 mod menu;
 mod network;
 mod player;
 mod procedural_functions;
 mod save;
+mod settings;
+mod spectate;
 mod states;
+mod surface_view;
 mod world;
 
 const TITLE: &str = "The Krusty Krabs";
@@ -25,6 +36,8 @@ fn main() {
     match args {
         args::GameArgs::Server(args) => {
             // server specific plugins
+            app.insert_resource(args.log_level.log_settings());
+
             // DefaultPlugins minus the unnecessary ones
             app.add_plugins(MinimalPlugins)
                 .add_plugin(bevy::log::LogPlugin)
@@ -36,18 +49,48 @@ fn main() {
 
             app.add_plugin(states::server::StatePlugin);
 
+            // server save/load plugin
+            app.add_plugin(save::server::SaveLoadPlugin {
+                save_interval: args.save_interval,
+            });
+
+            // dump player info for other tools to read
+            #[cfg(feature = "json-export")]
+            if let Some(path) = args.export_json.clone() {
+                app.add_plugin(export::ExportPlugin { path });
+            }
+
             // server network plugin
             app.add_plugin(network::server::ServerPlugin { args });
 
             app.add_plugin(world::server::WorldPlugin);
-
-            // server save/load plugin
-            app.add_plugin(save::server::SaveLoadPlugin);
         }
 
         args::GameArgs::Client(args) => {
             // client specific plugins
 
+            // we close the window ourselves, after telling the server we're leaving
+            app.insert_resource(WindowSettings {
+                close_when_requested: false,
+                ..default()
+            });
+
+            let settings =
+                settings::Settings::load_or_default(std::path::Path::new(settings::SETTINGS_FILE));
+            let args = args.with_settings(&settings);
+
+            // must be inserted before AssetPlugin, LogPlugin and WindowPlugin are built
+            app.insert_resource(args.asset_server_settings())
+                .insert_resource(args.log_level.log_settings())
+                .insert_resource(WindowDescriptor {
+                    title: String::from(TITLE),
+                    width: settings.resolution[0] as f32,
+                    height: settings.resolution[1] as f32,
+                    present_mode: args.present_mode(),
+                    ..default()
+                })
+                .insert_resource(settings);
+
             // default plugins
             app.add_plugins(DefaultPlugins);
 
@@ -55,21 +98,33 @@ fn main() {
             app.add_plugin(states::client::StatePlugin)
                 .add_plugin(credit_image::CreditImagePlugin)
                 .add_plugin(menu::MenuPlugin)
-                .insert_resource(WindowDescriptor {
-                    title: String::from(TITLE),
-                    width: WIN_W,
-                    height: WIN_H,
-                    present_mode: PresentMode::Immediate,
-                    ..default()
-                })
+                .add_plugin(loading::LoadingPlugin)
                 .insert_resource(ClearColor(Color::rgb(0.0, 0.6, 0.8)))
                 .add_startup_system(|mut c: Commands| {
-                    c.spawn_bundle(Camera2dBundle::default())
-                        .insert(CharacterCamera);
+                    let mut camera = Camera2dBundle::default();
+                    // show the same WIN_W by WIN_H of the world whatever the window size
+                    camera.projection.scaling_mode = ScalingMode::Auto {
+                        min_width: WIN_W,
+                        min_height: WIN_H,
+                    };
+                    c.spawn_bundle(camera).insert(CharacterCamera);
                 })
                 .add_startup_system(setup_background)
                 .add_plugin(world::client::WorldPlugin)
-                .add_plugin(player::client::PlayerPlugin);
+                .insert_resource(args.camera_bounds())
+                .insert_resource(keybindings::KeyBindings::load_or_default(
+                    std::path::Path::new(keybindings::KEY_BINDINGS_FILE),
+                ))
+                .add_plugin(player::client::PlayerPlugin)
+                .add_plugin(depth_overlay::DepthOverlayPlugin)
+                .add_plugin(compass::CompassPlugin)
+                .add_plugin(surface_view::SurfaceViewPlugin)
+                .add_plugin(spectate::SpectatePlugin)
+                .add_plugin(hud::HudPlugin);
+
+            if let Some(limiter) = args.frame_limiter() {
+                app.add_plugin(limiter);
+            }
 
             // client network plugin
             app.add_plugin(network::client::ClientPlugin { args });
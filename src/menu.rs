@@ -1,7 +1,14 @@
 use bevy::prelude::*;
 use iyes_loopless::prelude::*;
 
-use crate::states::client::GameState;
+use std::{net::SocketAddr, path::Path};
+
+use crate::{
+    keybindings::KeyBindings,
+    network::client::{parse_server_address, AddressError, ServerAddress},
+    settings::{Settings, SETTINGS_FILE},
+    states::client::GameState,
+};
 
 //crate::states;
 
@@ -11,24 +18,100 @@ const NORMAL_BUTTON: Color = Color::rgb(0.717, 0.255, 0.055);
 const HOVERED_BUTTON: Color = Color::rgb(0.57, 0.20, 0.04);
 const HOVERED_PRESSED_BUTTON: Color = Color::rgb(0.478, 0.776, 0.906);
 const PRESSED_BUTTON: Color = Color::rgb(0.478, 0.776, 0.906);
+/// Dims the game behind the pause menu
+const PAUSE_OVERLAY_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+/// Longest text the connect screen's IP field takes, enough for any IPv6 address
+const MAX_IP_LENGTH: usize = 39;
+/// Longest text the connect screen's port field takes
+const MAX_PORT_LENGTH: usize = 5;
 
 #[derive(Component)]
 enum MenuButtonAction {
     Start,
+    Options,
     Quit,
+    Resume,
+    QuitToMenu,
+    NextResolution,
+    VolumeDown,
+    VolumeUp,
+    ToggleDepthOverlay,
+    /// Start typing into a field on the connect screen
+    Focus(AddressField),
+    /// Connect to the address typed into the connect screen
+    Connect,
+    /// Leave the options or connect screen for the main menu
+    Back,
 }
 
 pub struct MenuPlugin;
 
+/// Shown once under the title the next time the main menu opens, e.g. why we left the game
+#[derive(Default)]
+pub struct MenuNotice(pub Option<String>);
+
 #[derive(Component)]
 struct OnMainMenuScreen;
 
+#[derive(Component)]
+struct OnPauseMenuScreen;
+
+#[derive(Component)]
+struct OnOptionsScreen;
+
+/// Text on the options screen showing a setting's current value
+#[derive(Component)]
+enum SettingText {
+    Resolution,
+    Volume,
+    DepthOverlay,
+}
+
+#[derive(Component)]
+struct OnConnectScreen;
+
+/// A text field on the connect screen, also marks the text showing what was typed into it
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressField {
+    Ip,
+    Port,
+}
+
+/// Marks the text on the connect screen saying what's wrong with the address
+#[derive(Component)]
+struct AddressErrorText;
+
+/// What has been typed into the connect screen so far; should be a resource
+#[derive(Debug)]
+struct AddressInput {
+    ip: String,
+    port: String,
+    /// Where typing goes
+    focused: AddressField,
+    /// Why the last try to connect was rejected
+    error: Option<AddressError>,
+}
+
+impl From<SocketAddr> for AddressInput {
+    fn from(address: SocketAddr) -> Self {
+        Self {
+            ip: address.ip().to_string(),
+            port: address.port().to_string(),
+            focused: AddressField::Ip,
+            error: None,
+        }
+    }
+}
+
 #[derive(Component)]
 struct SelectedButton;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_enter_system(GameState::Menu, main_menu_setup)
+        app.init_resource::<MenuNotice>()
+            // filled in from ServerAddress whenever the connect screen opens
+            .insert_resource(AddressInput::from(SocketAddr::from(([127, 0, 0, 1], 0))))
+            .add_enter_system(GameState::Menu, main_menu_setup)
             .add_exit_system(GameState::Menu, despawn_screen::<OnMainMenuScreen>)
             .add_system_set(
                 ConditionSet::new()
@@ -36,7 +119,39 @@ impl Plugin for MenuPlugin {
                     .with_system(button_system)
                     .with_system(menu_action)
                     .into(),
-            );
+            )
+            .add_enter_system(GameState::Options, options_setup)
+            .add_exit_system(GameState::Options, despawn_screen::<OnOptionsScreen>)
+            .add_exit_system(GameState::Options, save_settings)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Options)
+                    .with_system(button_system)
+                    .with_system(menu_action)
+                    .with_system(show_settings)
+                    .into(),
+            )
+            .add_enter_system(GameState::Connect, connect_setup)
+            .add_exit_system(GameState::Connect, despawn_screen::<OnConnectScreen>)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Connect)
+                    .with_system(button_system)
+                    .with_system(menu_action)
+                    .with_system(type_address)
+                    .with_system(show_address_input)
+                    .into(),
+            )
+            .add_system(escape_pauses.run_in_state(GameState::InGame))
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Paused)
+                    .with_system(button_system)
+                    .with_system(menu_action)
+                    .with_system(escape_resumes)
+                    .into(),
+            )
+            .add_exit_system(GameState::Paused, despawn_screen::<OnPauseMenuScreen>);
     }
 }
 
@@ -58,21 +173,15 @@ fn button_system(
     }
 }
 
-fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn main_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut notice: ResMut<MenuNotice>,
+) {
     let font = asset_server.load("fonts/milky_coffee.ttf");
 
-    let button_style = Style {
-        size: Size::new(Val::Px(250.0), Val::Px(65.0)),
-        margin: UiRect::all(Val::Px(20.0)),
-        justify_content: JustifyContent::Center,
-        align_items: AlignItems::Center,
-        ..default()
-    };
-    let button_text_style = TextStyle {
-        font: font.clone(),
-        font_size: 40.0,
-        color: TEXT_COLOR,
-    };
+    let button_style = button_style();
+    let button_text_style = button_text_style(font.clone());
 
     commands
         .spawn_bundle(NodeBundle {
@@ -104,6 +213,18 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 }),
             );
 
+            // e.g. the server closed while we were playing
+            if let Some(notice) = notice.0.take() {
+                parent.spawn_bundle(TextBundle::from_section(
+                    notice,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 30.0,
+                        color: TEXT_COLOR,
+                    },
+                ));
+            }
+
             parent
                 .spawn_bundle(ButtonBundle {
                     style: button_style.clone(),
@@ -115,6 +236,19 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                     parent
                         .spawn_bundle(TextBundle::from_section("Start", button_text_style.clone()));
                 });
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: button_style.clone(),
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButtonAction::Options)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle::from_section(
+                        "Options",
+                        button_text_style.clone(),
+                    ));
+                });
             parent
                 .spawn_bundle(ButtonBundle {
                     style: button_style,
@@ -130,11 +264,221 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     info!("finished main menu setup");
 }
 
+/// Options screen: a button cycling the resolution, and - and + buttons around the volume
+fn options_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/milky_coffee.ttf");
+    let button_text_style = button_text_style(font.clone());
+    // just wide enough for the - and + labels
+    let small_button_style = Style {
+        size: Size::new(Val::Px(65.0), Val::Px(65.0)),
+        ..button_style()
+    };
+    let button = |parent: &mut ChildBuilder, style: Style, action, label: &str| {
+        parent
+            .spawn_bundle(ButtonBundle {
+                style,
+                color: NORMAL_BUTTON.into(),
+                ..default()
+            })
+            .insert(action)
+            .with_children(|parent| {
+                parent.spawn_bundle(TextBundle::from_section(label, button_text_style.clone()));
+            });
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                margin: UiRect::all(Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: bevy::prelude::UiColor(BUTTON_BACKGROUND_COLOR),
+            ..default()
+        })
+        .insert(OnOptionsScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    "Options",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 80.0,
+                        color: TEXT_COLOR,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(50.0)),
+                    ..default()
+                }),
+            );
+
+            // show_settings fills in the values
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(400.0), Val::Px(65.0)),
+                        ..button_style()
+                    },
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButtonAction::NextResolution)
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(TextBundle::from_section("", button_text_style.clone()))
+                        .insert(SettingText::Resolution);
+                });
+
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    button(
+                        parent,
+                        small_button_style.clone(),
+                        MenuButtonAction::VolumeDown,
+                        "-",
+                    );
+                    parent
+                        .spawn_bundle(TextBundle::from_section("", button_text_style.clone()))
+                        .insert(SettingText::Volume);
+                    button(
+                        parent,
+                        small_button_style.clone(),
+                        MenuButtonAction::VolumeUp,
+                        "+",
+                    );
+                });
+
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(400.0), Val::Px(65.0)),
+                        ..button_style()
+                    },
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButtonAction::ToggleDepthOverlay)
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(TextBundle::from_section("", button_text_style.clone()))
+                        .insert(SettingText::DepthOverlay);
+                });
+
+            button(parent, button_style(), MenuButtonAction::Back, "Back");
+        });
+}
+
+fn button_style() -> Style {
+    Style {
+        size: Size::new(Val::Px(250.0), Val::Px(65.0)),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    }
+}
+
+fn button_text_style(font: Handle<Font>) -> TextStyle {
+    TextStyle {
+        font,
+        font_size: 40.0,
+        color: TEXT_COLOR,
+    }
+}
+
+/// Open the pause menu over the game, on Escape unless the pause key was rebound
+/// Paused is set directly so the game isn't torn down, see GameState::Paused
+fn escape_pauses(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    bindings: Res<KeyBindings>,
+    asset_server: Res<AssetServer>,
+) {
+    if !bindings.pause.just_pressed(&input, &mouse) {
+        return;
+    }
+    info!("pausing");
+    commands.insert_resource(CurrentState(GameState::Paused));
+
+    let button_text_style = button_text_style(asset_server.load("fonts/milky_coffee.ttf"));
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: bevy::prelude::UiColor(PAUSE_OVERLAY_COLOR),
+            ..default()
+        })
+        .insert(OnPauseMenuScreen)
+        .with_children(|parent| {
+            for (action, label) in [
+                (MenuButtonAction::Resume, "Resume"),
+                (MenuButtonAction::QuitToMenu, "Quit to Menu"),
+            ] {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: button_style(),
+                        color: NORMAL_BUTTON.into(),
+                        ..default()
+                    })
+                    .insert(action)
+                    .with_children(|parent| {
+                        parent.spawn_bundle(TextBundle::from_section(
+                            label,
+                            button_text_style.clone(),
+                        ));
+                    });
+            }
+        });
+}
+
+/// Close the pause menu and carry on playing
+fn resume(commands: &mut Commands, pause_screen: &Query<Entity, With<OnPauseMenuScreen>>) {
+    info!("resuming");
+    for entity in pause_screen {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.insert_resource(CurrentState(GameState::InGame));
+}
+
+fn escape_resumes(
+    mut commands: Commands,
+    input: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    bindings: Res<KeyBindings>,
+    pause_screen: Query<Entity, With<OnPauseMenuScreen>>,
+) {
+    if bindings.pause.just_pressed(&input, &mouse) {
+        resume(&mut commands, &pause_screen);
+    }
+}
+
 fn menu_action(
     interaction_query: Query<
         (&Interaction, &MenuButtonAction),
         (Changed<Interaction>, With<Button>),
     >,
+    pause_screen: Query<Entity, With<OnPauseMenuScreen>>,
+    mut settings: ResMut<Settings>,
+    mut windows: ResMut<Windows>,
+    mut address: ResMut<AddressInput>,
     mut commands: Commands,
 ) {
     for (interaction, menu_button_action) in &interaction_query {
@@ -146,13 +490,241 @@ fn menu_action(
                 }
                 MenuButtonAction::Start => {
                     info!("start button pressed");
-                    commands.insert_resource(NextState(GameState::InGame));
+                    commands.insert_resource(NextState(GameState::Connect));
+                }
+                MenuButtonAction::Options => {
+                    commands.insert_resource(NextState(GameState::Options));
+                }
+                MenuButtonAction::NextResolution => {
+                    settings.next_resolution();
+                    let [width, height] = settings.resolution;
+                    info!("changing resolution to {}x{}", width, height);
+                    if let Some(window) = windows.get_primary_mut() {
+                        window.set_resolution(width as f32, height as f32);
+                    }
+                }
+                MenuButtonAction::VolumeDown => settings.step_volume(-1),
+                MenuButtonAction::VolumeUp => settings.step_volume(1),
+                MenuButtonAction::ToggleDepthOverlay => {
+                    settings.depth_overlay = !settings.depth_overlay
+                }
+                MenuButtonAction::Focus(field) => address.focused = *field,
+                MenuButtonAction::Connect => connect(&mut address, &mut commands),
+                MenuButtonAction::Back => {
+                    commands.insert_resource(NextState(GameState::Menu));
+                }
+                MenuButtonAction::Resume => resume(&mut commands, &pause_screen),
+                MenuButtonAction::QuitToMenu => {
+                    info!("quit to menu button pressed");
+                    commands.insert_resource(NextState(GameState::Menu));
                 }
             }
         }
     }
 }
 
+/// Connect screen: IP and port fields filled in with the last address, and a Connect button
+fn connect_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    server: Res<ServerAddress>,
+    mut address: ResMut<AddressInput>,
+) {
+    *address = AddressInput::from(server.0);
+
+    let font = asset_server.load("fonts/milky_coffee.ttf");
+    let button_text_style = button_text_style(font.clone());
+    let field_style = Style {
+        size: Size::new(Val::Px(500.0), Val::Px(65.0)),
+        ..button_style()
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                margin: UiRect::all(Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: bevy::prelude::UiColor(BUTTON_BACKGROUND_COLOR),
+            ..default()
+        })
+        .insert(OnConnectScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    "Connect",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 80.0,
+                        color: TEXT_COLOR,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(50.0)),
+                    ..default()
+                }),
+            );
+
+            // show_address_input fills in what's typed
+            for field in [AddressField::Ip, AddressField::Port] {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: field_style.clone(),
+                        color: NORMAL_BUTTON.into(),
+                        ..default()
+                    })
+                    .insert(MenuButtonAction::Focus(field))
+                    .with_children(|parent| {
+                        parent
+                            .spawn_bundle(TextBundle::from_section("", button_text_style.clone()))
+                            .insert(field);
+                    });
+            }
+
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 30.0,
+                        color: TEXT_COLOR,
+                    },
+                ))
+                .insert(AddressErrorText);
+
+            for (action, label) in [
+                (MenuButtonAction::Connect, "Connect"),
+                (MenuButtonAction::Back, "Back"),
+            ] {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: button_style(),
+                        color: NORMAL_BUTTON.into(),
+                        ..default()
+                    })
+                    .insert(action)
+                    .with_children(|parent| {
+                        parent.spawn_bundle(TextBundle::from_section(
+                            label,
+                            button_text_style.clone(),
+                        ));
+                    });
+            }
+        });
+}
+
+/// Go on to loading if the typed address is valid, otherwise say what's wrong with it
+fn connect(address: &mut AddressInput, commands: &mut Commands) {
+    match parse_server_address(&address.ip, &address.port) {
+        Ok(server) => {
+            info!("connecting to {}", server);
+            address.error = None;
+            commands.insert_resource(ServerAddress(server));
+            commands.insert_resource(NextState(GameState::Loading));
+        }
+        Err(e) => address.error = Some(e),
+    }
+}
+
+/// Type into the focused field, Tab switches fields and Enter connects
+fn type_address(
+    mut typed: EventReader<ReceivedCharacter>,
+    input: Res<Input<KeyCode>>,
+    mut address: ResMut<AddressInput>,
+    mut commands: Commands,
+) {
+    for c in typed.iter().map(|event| event.char) {
+        let (text, allowed, max_length) = match address.focused {
+            // IPv6 addresses have hex digits and colons
+            AddressField::Ip => (
+                &mut address.ip,
+                c.is_ascii_hexdigit() || c == '.' || c == ':',
+                MAX_IP_LENGTH,
+            ),
+            AddressField::Port => (&mut address.port, c.is_ascii_digit(), MAX_PORT_LENGTH),
+        };
+        if allowed && text.len() < max_length {
+            text.push(c);
+        }
+    }
+
+    if input.just_pressed(KeyCode::Back) {
+        match address.focused {
+            AddressField::Ip => address.ip.pop(),
+            AddressField::Port => address.port.pop(),
+        };
+    }
+    if input.just_pressed(KeyCode::Tab) {
+        address.focused = match address.focused {
+            AddressField::Ip => AddressField::Port,
+            AddressField::Port => AddressField::Ip,
+        };
+    }
+    if input.just_pressed(KeyCode::Return) {
+        connect(&mut address, &mut commands);
+    }
+}
+
+/// Keep the connect screen's fields showing what's been typed, with a bar after the focused one
+fn show_address_input(
+    address: Res<AddressInput>,
+    mut fields: Query<(&mut Text, &AddressField)>,
+    mut errors: Query<&mut Text, (With<AddressErrorText>, Without<AddressField>)>,
+) {
+    for (mut text, field) in &mut fields {
+        let (label, value) = match field {
+            AddressField::Ip => ("IP", &address.ip),
+            AddressField::Port => ("Port", &address.port),
+        };
+        let cursor = if *field == address.focused { "|" } else { "" };
+        let shown = format!("{}: {}{}", label, value, cursor);
+        // only touch it when it changes
+        if text.sections[0].value != shown {
+            text.sections[0].value = shown;
+        }
+    }
+
+    let error = match address.error {
+        None => "",
+        Some(AddressError::BadIp) => "Not an IP address",
+        Some(AddressError::BadPort) => "Ports go from 1 to 65535",
+    };
+    for mut text in &mut errors {
+        if text.sections[0].value != error {
+            text.sections[0].value = error.to_string();
+        }
+    }
+}
+
+/// Keep the options screen's labels up to date with the settings
+fn show_settings(settings: Res<Settings>, mut texts: Query<(&mut Text, &SettingText)>) {
+    for (mut text, setting) in &mut texts {
+        let value = match setting {
+            SettingText::Resolution => {
+                let [width, height] = settings.resolution;
+                format!("{}x{}", width, height)
+            }
+            SettingText::Volume => format!("Volume {:.0}%", settings.volume * 100.),
+            SettingText::DepthOverlay => format!(
+                "Depth darkening {}",
+                if settings.depth_overlay { "on" } else { "off" }
+            ),
+        };
+        // only touch it when it changes
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+/// Remember what was picked on the options screen for next time
+fn save_settings(settings: Res<Settings>) {
+    settings.save_or_warn(Path::new(SETTINGS_FILE));
+}
+
 fn despawn_screen<T: Component>(to_despawn: Query<Entity, With<T>>, mut commands: Commands) {
     for entity in &to_despawn {
         // info!("despawning {}", entity.id());
@@ -160,3 +732,130 @@ fn despawn_screen<T: Component>(to_despawn: Query<Entity, With<T>>, mut commands
     }
     // info!("despawning");
 }
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_buttons_change_settings() {
+        let mut world = World::new();
+        world.init_resource::<Settings>();
+        world.insert_resource(AddressInput::from(SocketAddr::from(([127, 0, 0, 1], 1))));
+        // no window to resize
+        world.init_resource::<Windows>();
+        let mut stage = SystemStage::single_threaded().with_system(menu_action);
+        let mut click = |world: &mut World, action| {
+            world
+                .spawn()
+                .insert(Button)
+                .insert(Interaction::Clicked)
+                .insert(action);
+            stage.run(world);
+        };
+
+        click(&mut world, MenuButtonAction::VolumeDown);
+        click(&mut world, MenuButtonAction::NextResolution);
+        click(&mut world, MenuButtonAction::ToggleDepthOverlay);
+
+        let settings = world.resource::<Settings>();
+        assert!((settings.volume - 0.9).abs() < 1e-6);
+        assert_ne!(settings.resolution, Settings::default().resolution);
+        assert!(!settings.depth_overlay);
+
+        click(&mut world, MenuButtonAction::Back);
+        assert_eq!(world.resource::<NextState<GameState>>().0, GameState::Menu);
+    }
+
+    #[test]
+    fn typing_an_address_connects_to_it() {
+        let mut world = World::new();
+        world.init_resource::<Events<ReceivedCharacter>>();
+        world.init_resource::<Input<KeyCode>>();
+        world.insert_resource(AddressInput::from(SocketAddr::from(([127, 0, 0, 1], 8080))));
+        let mut stage = SystemStage::single_threaded().with_system(type_address);
+        let mut type_text = |world: &mut World, text: &str, key: Option<KeyCode>| {
+            for char in text.chars() {
+                world.send_event(ReceivedCharacter {
+                    id: bevy::window::WindowId::primary(),
+                    char,
+                });
+            }
+            let mut input = world.resource_mut::<Input<KeyCode>>();
+            input.clear();
+            if let Some(key) = key {
+                input.press(key);
+            }
+            stage.run(world);
+            world.resource_mut::<Input<KeyCode>>().release_all();
+        };
+
+        // letters are ignored, backspace removes the last digit
+        type_text(&mut world, "x.5", Some(KeyCode::Back));
+        assert_eq!(world.resource::<AddressInput>().ip, "127.0.0.1.");
+        // not an address, so it stays on the connect screen
+        type_text(&mut world, "", Some(KeyCode::Return));
+        assert_eq!(
+            world.resource::<AddressInput>().error,
+            Some(AddressError::BadIp)
+        );
+        assert!(world.get_resource::<NextState<GameState>>().is_none());
+
+        type_text(&mut world, "", Some(KeyCode::Back));
+        // on to the port, 8080 becomes 8081
+        type_text(&mut world, "", Some(KeyCode::Tab));
+        type_text(&mut world, "", Some(KeyCode::Back));
+        type_text(&mut world, "1", Some(KeyCode::Return));
+        assert_eq!(
+            world.resource::<ServerAddress>().0,
+            SocketAddr::from(([127, 0, 0, 1], 8081))
+        );
+        assert_eq!(
+            world.resource::<NextState<GameState>>().0,
+            GameState::Loading
+        );
+    }
+
+    #[test]
+    fn escape_opens_and_closes_pause_menu() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(bevy::asset::AssetPlugin)
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Input<MouseButton>>()
+            .init_resource::<KeyBindings>()
+            .add_loopless_state(GameState::InGame)
+            .add_system(escape_pauses.run_in_state(GameState::InGame))
+            .add_system(escape_resumes.run_in_state(GameState::Paused));
+        let press_escape = |app: &mut App| {
+            let mut input = app.world.resource_mut::<Input<KeyCode>>();
+            input.clear();
+            input.press(KeyCode::Escape);
+            app.update();
+            app.world
+                .resource_mut::<Input<KeyCode>>()
+                .release(KeyCode::Escape);
+        };
+        let screens = |app: &mut App| {
+            app.world
+                .query_filtered::<Entity, With<OnPauseMenuScreen>>()
+                .iter(&app.world)
+                .count()
+        };
+
+        press_escape(&mut app);
+        assert_eq!(
+            app.world.resource::<CurrentState<GameState>>().0,
+            GameState::Paused
+        );
+        assert_eq!(screens(&mut app), 1);
+
+        press_escape(&mut app);
+        assert_eq!(
+            app.world.resource::<CurrentState<GameState>>().0,
+            GameState::InGame
+        );
+        assert_eq!(screens(&mut app), 0);
+    }
+}
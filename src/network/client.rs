@@ -1,18 +1,43 @@
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::net::{SocketAddr, UdpSocket};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
 
 use super::*;
 use crate::args::ClientArgs;
-use crate::player::client::{spawn_other_player_at, CameraBoundsBox, LocalPlayer, Player};
+use crate::keybindings::KeyBindings;
+use crate::menu::MenuNotice;
+use crate::player::client::{
+    block_under_cursor, cursor_in_view, spawn_other_player_at, BuildMode, CameraBoundsBox,
+    CameraZoom, Extrapolation, InterpolatedPosition, LocalPlayer, MiningEstimate, RemotePlayers,
+    RemoteTarget, SelectedSlot,
+};
 use crate::player::{
-    self, Inventory, PlayerInput, PlayerPosition, CAMERA_BOUNDS_SIZE, PLAYER_AND_BLOCK_SIZE,
+    self, Grounded, Inventory, PlayerInput, PlayerPosition, PlayerStats, CAMERA_BOUNDS_SIZE,
+    PLAYER_AND_BLOCK_SIZE, PLAYER_MINE_RADIUS,
 };
+use crate::settings::{Settings, SETTINGS_FILE};
 use crate::states;
-use crate::states::client::GameState;
-use crate::world::{derender_chunk, render_chunk, RenderedBlock, Terrain, WorldDelta};
-use crate::{WIN_H, WIN_W};
-use bevy::prelude::*;
+use crate::states::client::{connected_to_server, in_game, GameState};
+use crate::world::client::{baseline_pending, BaselineReceived};
+use crate::world::{
+    derender_block, derender_chunk, render_block, render_chunk, Block, BlockEntities, BlockType,
+    Terrain, WorldDelta, CHUNK_HEIGHT, CHUNK_WIDTH,
+};
+use crate::CharacterCamera;
+use bevy::{app::AppExit, prelude::*, window::WindowCloseRequested};
 use iyes_loopless::prelude::*;
+use strum::IntoEnumIterator;
+
+/// How many blocks around the cursor the admin clear key reaches
+const ADMIN_FILL_RADIUS: usize = 4;
+
+/// How much each new round trip time counts towards the smoothed one, like TCP's smoothed RTT
+const RTT_SMOOTHING: f32 = 0.125;
+/// Seconds between the pings sent to keep the round trip time up to date, which also logs it
+const RTT_PING_INTERVAL_SECS: u64 = 5;
+const RTT_PING_TICK_LABEL: &str = "RTT_PING_TICK";
+/// Pings without a pong after this long are given up on, so lost ones don't pile up
+const MAX_PING_AGE: Duration = Duration::from_secs(10);
 
 /// Should be used as a global resource on the client
 #[derive(Debug)]
@@ -33,6 +58,49 @@ struct Client {
     real_tick_count: u64,
     /// Network buffer
     buffer: [u8; BUFFER_SIZE],
+    /// Server messages that haven't fully arrived yet
+    fragments: Reassembler,
+    /// Watching without a player, told to the server in every header
+    observer: bool,
+    /// When each ping waiting for a pong was sent, by the sequence it was sent with
+    pending_pings: HashMap<u64, Instant>,
+    /// Smoothed round trip time to the server in milliseconds, None until the first pong
+    rtt_ms: Option<f32>,
+}
+
+/// Whether the server says an admin paused its simulation; should be a resource
+pub struct ServerPaused(pub bool);
+
+/// How far (in blocks) the server lets the local player mine and place; should be a resource
+/// PLAYER_MINE_RADIUS until the server says otherwise
+pub struct ServerReach(pub f32);
+
+impl Default for ServerReach {
+    fn default() -> Self {
+        Self(PLAYER_MINE_RADIUS)
+    }
+}
+
+/// Server to connect to on the next Start, from the command line until the connect screen
+/// picks another; should be a resource
+pub struct ServerAddress(pub SocketAddr);
+
+/// Why an address typed into the connect screen was rejected
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddressError {
+    BadIp,
+    BadPort,
+}
+
+/// The server address from what was typed into the connect screen's IP and port fields
+pub fn parse_server_address(ip: &str, port: &str) -> Result<SocketAddr, AddressError> {
+    let ip: IpAddr = ip.trim().parse().map_err(|_| AddressError::BadIp)?;
+    let port: u16 = port.trim().parse().map_err(|_| AddressError::BadPort)?;
+    // nothing listens on port 0
+    if port == 0 {
+        return Err(AddressError::BadPort);
+    }
+    Ok(SocketAddr::new(ip, port))
 }
 
 /// Global resource to contain messages, simplifies data path
@@ -44,7 +112,12 @@ struct Messages {
 impl Client {
     fn new(server_address: SocketAddr, local_port: u16) -> Result<Self, std::io::Error> {
         // port 0 means we let the OS decide
-        let addr = SocketAddr::from(([0, 0, 0, 0], local_port));
+        // an IPv4 socket can't send to an IPv6 server, so match the server's family
+        let addr = if server_address.is_ipv6() {
+            SocketAddr::from(([0u16; 8], local_port))
+        } else {
+            SocketAddr::from(([0, 0, 0, 0], local_port))
+        };
         let sock = UdpSocket::bind(addr)?;
 
         // we want nonblocking sockets!
@@ -61,6 +134,10 @@ impl Client {
             debug_paused: false,
             real_tick_count: 0,
             buffer: [0u8; BUFFER_SIZE],
+            fragments: Reassembler::default(),
+            observer: false,
+            pending_pings: HashMap::new(),
+            rtt_ms: None,
         })
     }
 
@@ -85,8 +162,14 @@ impl Client {
             return Err(ReceiveError::UnknownSender);
         }
 
-        // decode message
-        let (message, _size) = bincode::decode_from_slice(&self.buffer, BINCODE_CONFIG)
+        // decode the fragment, then the message once all of its fragments are in
+        let (fragment, _size) = bincode::decode_from_slice(&self.buffer, BINCODE_CONFIG)
+            .map_err(ReceiveError::DecodeError)?;
+        let encoded = self
+            .fragments
+            .insert(fragment)
+            .ok_or(ReceiveError::Incomplete)?;
+        let (message, _size) = bincode::decode_from_slice(&encoded, BINCODE_CONFIG)
             .map_err(ReceiveError::DecodeError)?;
 
         Ok(message)
@@ -96,6 +179,30 @@ impl Client {
     fn enqueue_body(&mut self, body: ClientBodyElem) {
         self.bodies.push(body);
     }
+
+    /// Queue a ping, unless one is already going out this network tick
+    fn queue_ping(&mut self) {
+        if !self
+            .bodies
+            .iter()
+            .any(|b| matches!(b, ClientBodyElem::Ping))
+        {
+            self.enqueue_body(ClientBodyElem::Ping);
+        }
+    }
+
+    /// Time the pong for the ping sent with this sequence, and fold it into rtt_ms
+    /// Returns the round trip time in milliseconds, None for a pong we already timed,
+    /// since the server keeps resending pongs until it hears back from us
+    fn record_pong(&mut self, sequence: u64, now: Instant) -> Option<f32> {
+        let sent = self.pending_pings.remove(&sequence)?;
+        let sample = now.saturating_duration_since(sent).as_secs_f32() * 1000.;
+        self.rtt_ms = Some(match self.rtt_ms {
+            Some(rtt) => rtt + (sample - rtt) * RTT_SMOOTHING,
+            None => sample,
+        });
+        Some(sample)
+    }
 }
 
 pub struct ClientPlugin {
@@ -108,11 +215,20 @@ impl Plugin for ClientPlugin {
         app.insert_resource(self.args.clone());
         app.insert_resource(Messages::default());
 
-        // enter system
-        app.add_enter_system(states::client::GameState::InGame, create_client);
+        app.insert_resource(ServerPaused(false));
+        app.init_resource::<ServerReach>();
+        app.insert_resource(ServerAddress(self.args.server_address()));
+
+        // enter system, connect as soon as loading starts
+        app.add_enter_system(states::client::GameState::Loading, create_client);
 
-        // exit system
+        // exit systems, unless loading is going on into the game
+        app.add_exit_system(
+            states::client::GameState::Loading,
+            destroy_client.run_if(baseline_pending),
+        );
         app.add_exit_system(states::client::GameState::InGame, destroy_client);
+        app.add_exit_system(states::client::GameState::Paused, destroy_client);
 
         // add timestep
         app.add_fixed_timestep(
@@ -120,6 +236,20 @@ impl Plugin for ClientPlugin {
             NETWORK_TICK_LABEL,
         );
 
+        // keep the round trip time fresh
+        app.add_fixed_timestep(
+            Duration::from_secs(RTT_PING_INTERVAL_SECS),
+            RTT_PING_TICK_LABEL,
+        );
+        app.add_fixed_timestep_system(
+            RTT_PING_TICK_LABEL,
+            0,
+            ping_and_log_rtt.run_in_state(states::client::GameState::InGame),
+        );
+
+        // runs in every state, the window can be closed at any time
+        app.add_system(handle_window_close);
+
         // input systems (debug)
         app.add_system(
             o_pause_client
@@ -130,6 +260,21 @@ impl Plugin for ClientPlugin {
             p_queues_ping
                 .run_in_state(states::client::GameState::InGame)
                 .label("p_queues_ping"),
+        )
+        .add_system(
+            t_queues_ore_teleport
+                .run_in_state(states::client::GameState::InGame)
+                .label("t_queues_ore_teleport"),
+        )
+        .add_system(
+            b_queues_world_resend
+                .run_in_state(states::client::GameState::InGame)
+                .label("b_queues_world_resend"),
+        )
+        .add_system(
+            f7_queues_sim_pause
+                .run_in_state(states::client::GameState::InGame)
+                .label("f7_queues_sim_pause"),
         );
 
         // network timestep systems
@@ -137,59 +282,88 @@ impl Plugin for ClientPlugin {
             NETWORK_TICK_LABEL,
             0,
             increase_tick
-                .run_in_state(states::client::GameState::InGame)
+                .run_if(connected_to_server)
                 .label("increase_tick"),
         )
         .add_fixed_timestep_system(
             NETWORK_TICK_LABEL,
             0,
             fetch_messages
-                .run_in_state(states::client::GameState::InGame)
+                .run_if(connected_to_server)
                 .label("fetch_messages")
                 .after("increase_tick"),
         )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            await_baseline
+                .run_in_state(states::client::GameState::Loading)
+                .label("await_baseline")
+                .after("fetch_messages"),
+        )
         .add_fixed_timestep_system(
             NETWORK_TICK_LABEL,
             0,
             queue_inputs
-                .run_in_state(states::client::GameState::InGame)
+                .run_if(in_game)
                 .label("queue_inputs")
                 .after("fetch_messages"),
         )
         .add_fixed_timestep_system(
             NETWORK_TICK_LABEL,
             0,
-            handle_messages
-                .run_in_state(states::client::GameState::InGame)
-                .label("handle_messages"),
+            queue_observer_view
+                .run_if(in_game)
+                .label("queue_observer_view")
+                .after("fetch_messages"),
+        )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            queue_name
+                .run_if(in_game)
+                .label("queue_name")
+                .after("fetch_messages"),
+        )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            handle_messages.run_if(in_game).label("handle_messages"),
+        )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            extrapolate_local_player
+                .run_if(in_game)
+                .label("extrapolate_local_player")
+                .after("handle_messages"),
         )
         .add_fixed_timestep_system(
             NETWORK_TICK_LABEL,
             0,
             send_bodies
-                .run_in_state(states::client::GameState::InGame)
+                .run_if(connected_to_server)
                 .label("send_bodies")
-                .after("handle_messages"),
+                .after("handle_messages")
+                .after("await_baseline"),
         )
         .add_fixed_timestep_system(
             NETWORK_TICK_LABEL,
             0,
             client_timeout
-                .run_in_state(states::client::GameState::InGame)
+                .run_if(connected_to_server)
                 .label("client_timeout")
                 .after("send_bodies"),
         );
     }
 }
 
-fn create_client(mut commands: Commands, args: Res<ClientArgs>) {
-    let client = match Client::new(
-        SocketAddr::from((args.server_ip, args.server_port)),
-        args.client_port,
-    ) {
+fn create_client(mut commands: Commands, args: Res<ClientArgs>, server: Res<ServerAddress>) {
+    let mut client = match Client::new(server.0, args.client_port) {
         Ok(s) => s,
         Err(e) => panic!("Unable to create client: {}", e),
     };
+    client.observer = args.observe;
     info!("client created");
     commands.insert_resource(client);
 }
@@ -197,6 +371,36 @@ fn create_client(mut commands: Commands, args: Res<ClientArgs>) {
 fn destroy_client(mut commands: Commands) {
     info!("destroying client");
     commands.remove_resource::<Client>();
+    commands.insert_resource(ServerPaused(false));
+    commands.insert_resource(ServerReach::default());
+}
+
+/// Tell the server we're leaving and save the settings, then exit, whenever the window's close
+/// button is pressed
+/// main.rs turns off bevy's own close handling so that this runs first
+fn handle_window_close(
+    mut close_requests: EventReader<WindowCloseRequested>,
+    client: Option<ResMut<Client>>,
+    settings: Option<Res<Settings>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if close_requests.iter().count() == 0 {
+        return;
+    }
+
+    // only connected when in game
+    if let Some(mut client) = client {
+        info!("window closed, disconnecting from server");
+        client.enqueue_body(ClientBodyElem::Disconnect);
+        // send now, there won't be another network tick
+        send_bodies(client);
+    }
+
+    if let Some(settings) = settings {
+        settings.save_or_warn(std::path::Path::new(SETTINGS_FILE));
+    }
+
+    exit.send(AppExit);
 }
 
 fn increase_tick(mut client: ResMut<Client>) {
@@ -232,73 +436,120 @@ fn p_queues_ping(mut client: ResMut<Client>, input: Res<Input<KeyCode>>) {
         return;
     }
 
-    let num_ping_bodies = client
-        .bodies
-        .iter()
-        .filter(|b| match b {
-            ClientBodyElem::Ping => true,
-            ClientBodyElem::Input(_) => false,
-        })
-        .count();
+    info!("client queueing a ping");
+    client.queue_ping();
+}
 
-    // only allow one ping per network cycle
-    if num_ping_bodies == 0 {
-        info!("client queueing a ping");
-        client.enqueue_body(ClientBodyElem::Ping);
+/// Ping the server every so often, so rtt_ms stays current, and log the round trip time so far
+fn ping_and_log_rtt(mut client: ResMut<Client>) {
+    if client.debug_paused {
+        return;
+    }
+
+    if let Some(rtt) = client.rtt_ms {
+        info!("round trip time to server: {:.1} ms", rtt);
+    }
+    client.queue_ping();
+}
+
+/// ADMIN: make T teleport to the nearest ore, going to the next type of ore on every press
+fn t_queues_ore_teleport(
+    mut client: ResMut<Client>,
+    input: Res<Input<KeyCode>>,
+    mut next_ore: Local<usize>,
+) {
+    if !input.just_pressed(KeyCode::T) || client.debug_paused {
+        return;
+    }
+
+    let ores: Vec<BlockType> = BlockType::iter().filter(|b| b.is_ore()).collect();
+    let ore = ores[*next_ore % ores.len()];
+    *next_ore += 1;
+
+    info!("asking to teleport to the nearest {:?}", ore);
+    client.enqueue_body(ClientBodyElem::AdminTeleportToOre(ore));
+}
+
+/// ADMIN: make B ask the server to resend the whole world, for when we are out of sync
+fn b_queues_world_resend(mut client: ResMut<Client>, input: Res<Input<KeyCode>>) {
+    if input.just_pressed(KeyCode::B) && !client.debug_paused {
+        info!("asking for the whole world again");
+        client.enqueue_body(ClientBodyElem::AdminResendWorld);
+    }
+}
+
+/// ADMIN: make F7 pause or resume the server's simulation
+fn f7_queues_sim_pause(mut client: ResMut<Client>, input: Res<Input<KeyCode>>) {
+    if input.just_pressed(KeyCode::F7) && !client.debug_paused {
+        info!("asking to toggle the server pause");
+        client.enqueue_body(ClientBodyElem::AdminTogglePause);
     }
 }
 
 /// Scrape client inputs and queue up sending them to server
+#[allow(clippy::too_many_arguments)]
 fn queue_inputs(
     mut client: ResMut<Client>,
     bevy_input: Res<Input<KeyCode>>,
     mouse: Res<Input<MouseButton>>,
-    mut windows: ResMut<Windows>,
-    mut query: Query<(&mut PlayerPosition, &mut CameraBoundsBox), With<LocalPlayer>>,
+    bindings: Res<KeyBindings>,
+    windows: Res<Windows>,
+    selected: Res<SelectedSlot>,
+    build_mode: Res<BuildMode>,
+    zoom: Res<CameraZoom>,
+    mut query: Query<
+        (
+            &mut PlayerPosition,
+            &mut CameraBoundsBox,
+            &mut Extrapolation,
+        ),
+        With<LocalPlayer>,
+    >,
+    state: Res<CurrentState<GameState>>,
 ) {
     // TODO: remove
     if client.debug_paused {
         return;
     }
 
+    // stand still behind the pause menu, the server still needs to hear from us
+    if state.0 == GameState::Paused {
+        if let Ok((_, _, mut extrapolation)) = query.get_single_mut() {
+            let input = PlayerInput::default();
+            client.enqueue_body(ClientBodyElem::Input(input.clone()));
+            extrapolation.input = input;
+        }
+        return;
+    }
+
     //Code to calculate the block x and y to mine based on the mouse x and y from bevy
 
     let mut block_x_from_mouse = 0;
     let mut block_y_from_mouse = 0;
 
-    let window = windows.get_primary_mut();
-
-    if window.is_none() {
-        error!("no window, cannot scrape inputs!");
-    }
-
-    let win = window.unwrap();
-    let (player_position, camera_box) = query.single();
-    let ms = win.cursor_position();
-
-    if !ms.is_none() {
-        let mouse_pos = ms.unwrap();
-
-        //calculate distance of click from camera center
-        let dist_x = mouse_pos.x - (WIN_W / 2.);
-        let dist_y = mouse_pos.y - (WIN_H / 2.);
-
-        //calculate bevy coords of click
-        let game_x = camera_box.center_coord.x + dist_x;
-        let game_y = camera_box.center_coord.y + dist_y;
-
-        //calculate block coords from bevy coords
-        block_x_from_mouse = (game_x / PLAYER_AND_BLOCK_SIZE).round() as usize;
-        block_y_from_mouse = (-game_y / PLAYER_AND_BLOCK_SIZE).round() as usize;
+    let (player_position, camera_box, _) = match query.get_single() {
+        Ok(player) => player,
+        // observing, queue_observer_view sends the camera instead
+        Err(_) => return,
+    };
+    // no window or no cursor in it, so no block to point at
+    if let Some(mouse_pos) = windows.get_primary().and_then(cursor_in_view) {
+        (block_x_from_mouse, block_y_from_mouse) = block_under_cursor(mouse_pos, camera_box, &zoom);
     }
 
+    let (mine, place) = build_mode.actions(
+        bindings.mine.pressed(&bevy_input, &mouse),
+        bindings.place.pressed(&bevy_input, &mouse),
+    );
     let mut input = PlayerInput {
-        left: bevy_input.pressed(KeyCode::A),
-        right: bevy_input.pressed(KeyCode::D),
-        jump: bevy_input.pressed(KeyCode::Space),
-        mine: mouse.pressed(MouseButton::Left),
+        left: bindings.move_left.pressed(&bevy_input, &mouse),
+        right: bindings.move_right.pressed(&bevy_input, &mouse),
+        jump: bindings.jump.pressed(&bevy_input, &mouse),
+        mine,
         block_x: block_x_from_mouse,
         block_y: block_y_from_mouse,
+        place,
+        place_block_type: selected.block_type(),
     };
 
     // TODO: remove
@@ -309,7 +560,77 @@ fn queue_inputs(
         input.block_y = (-player_position.y) as usize + 1;
     }
 
-    client.enqueue_body(ClientBodyElem::Input(input));
+    client.enqueue_body(ClientBodyElem::Input(input.clone()));
+
+    // keep moving with it if the server's updates stop coming for a bit
+    query.single_mut().2.input = input;
+
+    // ADMIN: make K clear the area around the cursor
+    // kept inside the world, the server rejects fills that go past its side walls
+    if bevy_input.pressed(KeyCode::K) {
+        client.enqueue_body(ClientBodyElem::AdminFill {
+            x1: block_x_from_mouse
+                .saturating_sub(ADMIN_FILL_RADIUS)
+                .min(CHUNK_WIDTH - 1),
+            y1: block_y_from_mouse.saturating_sub(ADMIN_FILL_RADIUS),
+            x2: block_x_from_mouse
+                .saturating_add(ADMIN_FILL_RADIUS)
+                .min(CHUNK_WIDTH - 1),
+            y2: block_y_from_mouse.saturating_add(ADMIN_FILL_RADIUS),
+            block: None,
+        });
+    }
+
+    // ADMIN: make J pour water into the cell under the cursor
+    if bevy_input.just_pressed(KeyCode::J) {
+        client.enqueue_body(ClientBodyElem::AdminFill {
+            x1: block_x_from_mouse,
+            y1: block_y_from_mouse,
+            x2: block_x_from_mouse,
+            y2: block_y_from_mouse,
+            block: Some(BlockType::Water),
+        });
+    }
+}
+
+/// Observers tell the server where their camera is, so it sends the terrain around it
+fn queue_observer_view(
+    mut client: ResMut<Client>,
+    camera: Query<&Transform, With<CharacterCamera>>,
+) {
+    if !client.observer || client.debug_paused {
+        return;
+    }
+
+    if let Ok(transform) = camera.get_single() {
+        client.enqueue_body(ClientBodyElem::ObserverView(PlayerPosition {
+            x: transform.translation.x / PLAYER_AND_BLOCK_SIZE,
+            y: transform.translation.y / PLAYER_AND_BLOCK_SIZE,
+        }));
+    }
+}
+
+/// Send our name until the server has it, the body isn't retransmitted if the packet is lost
+fn queue_name(
+    mut client: ResMut<Client>,
+    args: Res<ClientArgs>,
+    query: Query<&PlayerName, With<LocalPlayer>>,
+) {
+    if client.debug_paused {
+        return;
+    }
+
+    let wanted = args.player_name();
+    if !wanted.is_set() {
+        return;
+    }
+
+    // the local player isn't spawned until after we connect
+    if let Ok(name) = query.get_single() {
+        if *name != wanted {
+            client.enqueue_body(ClientBodyElem::SetName(wanted.0));
+        }
+    }
 }
 
 /// Get and handle all messages from server
@@ -321,6 +642,14 @@ fn fetch_messages(mut client: ResMut<Client>, mut messages: ResMut<Messages>) {
         return;
     }
 
+    let dropped = client.fragments.tick();
+    if dropped > 0 {
+        warn!(
+            "dropped {} server messages that never fully arrived",
+            dropped
+        );
+    }
+
     loop {
         match client.get_one_message() {
             Ok(message) => {
@@ -362,6 +691,9 @@ fn fetch_messages(mut client: ResMut<Client>, mut messages: ResMut<Messages>) {
             Err(ReceiveError::UnknownSender) => {
                 warn!("client got message, but not from server!");
             }
+            Err(ReceiveError::Incomplete) => {
+                // the rest of the message should be right behind it
+            }
             Err(ReceiveError::NoMessage) => {
                 // no more messages at the moment
                 break;
@@ -373,19 +705,178 @@ fn fetch_messages(mut client: ResMut<Client>, mut messages: ResMut<Messages>) {
     }
 }
 
+/// Apply terrain changes from the server, keeping the block sprites in step with them
+fn apply_world_deltas(
+    deltas: Vec<WorldDelta>,
+    commands: &mut Commands,
+    assets: &Res<AssetServer>,
+    terrain: &mut Terrain,
+    block_entities: &mut BlockEntities,
+) {
+    for delta in deltas {
+        match delta {
+            WorldDelta::NewChunks(new_terrain) => {
+                //
+                debug!(
+                    "got new completely new chunks!: {:?}",
+                    new_terrain
+                        .sorted_chunks()
+                        .iter()
+                        .map(|c| c.chunk_number)
+                        .collect::<Vec<_>>()
+                );
+
+                // de-render and destroy old chunks
+                for chunk in terrain.chunks.values() {
+                    derender_chunk(commands, chunk, block_entities)
+                }
+
+                // overwrite the terrain
+                *terrain = new_terrain;
+
+                // render new chunks
+                for chunk in terrain.chunks.values() {
+                    render_chunk(commands, assets, chunk, block_entities);
+                }
+            }
+            WorldDelta::BlockDelete(delete) => {
+                // info!("got block deletion: {:?}", delete);
+
+                if let Some(chunk) = terrain.get_chunk_mut(delete.chunk_number) {
+                    match chunk.get(delete.x, delete.y) {
+                        Some(_) => {
+                            // un-render block entity if it exists
+                            derender_block(
+                                commands,
+                                delete.chunk_number,
+                                delete.x,
+                                delete.y,
+                                block_entities,
+                            );
+                            // delete the block
+                            chunk.set(delete.x, delete.y, None);
+                        }
+                        None => {
+                            // block already deleted
+                            // warn!("client got BlockDelete but block already doesn't exist!");
+                        }
+                    }
+                }
+            }
+            WorldDelta::BlockPlace {
+                chunk_number,
+                x,
+                y,
+                block_type,
+            } => {
+                if let Some(chunk) = terrain.get_chunk_mut(chunk_number) {
+                    let block = Block::new(block_type);
+                    chunk.set(x, y, Some(block));
+                    // replaces the sprite of any block that was there
+                    render_block(commands, assets, chunk_number, x, y, &block, block_entities);
+                }
+            }
+        }
+    }
+}
+
+/// While loading, keep pinging the server until its first complete terrain arrives, then
+/// render it and go into the game
+/// Anything else the server sent is left for handle_messages once in game
+fn await_baseline(
+    mut client: ResMut<Client>,
+    mut messages: ResMut<Messages>,
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut terrain: ResMut<Terrain>,
+    mut block_entities: ResMut<BlockEntities>,
+    mut received: ResMut<BaselineReceived>,
+) {
+    // a frame can run more than one tick before the state changes
+    if received.0 {
+        return;
+    }
+
+    let has_baseline = |body: &ServerBodyElem| {
+        matches!(body, ServerBodyElem::WorldDeltas(deltas)
+            if deltas.iter().any(|d| matches!(d, WorldDelta::NewChunks(_))))
+    };
+    if !messages.messages.iter().any(has_baseline) {
+        // also lets the server know we're here
+        client.queue_ping();
+        return;
+    }
+
+    // the world deltas before and in the baseline are all that it covers
+    let bodies = std::mem::take(&mut messages.messages);
+    let mut baseline_seen = false;
+    for body in bodies {
+        match body {
+            ServerBodyElem::WorldDeltas(deltas) if !baseline_seen => {
+                baseline_seen = deltas.iter().any(|d| matches!(d, WorldDelta::NewChunks(_)));
+                apply_world_deltas(
+                    deltas,
+                    &mut commands,
+                    &assets,
+                    &mut terrain,
+                    &mut block_entities,
+                );
+            }
+            other => messages.messages.push_back(other),
+        }
+    }
+
+    info!(
+        "got the world from the server, {} chunks",
+        terrain.chunks.len()
+    );
+    received.0 = true;
+    commands.insert_resource(NextState(GameState::InGame));
+}
+
+/// Every remote player entity handle_messages might need to update
+type OtherPlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut PlayerPosition,
+        &'static mut Grounded,
+        &'static mut PlayerName,
+        &'static mut RemoteTarget,
+        &'static mut InterpolatedPosition,
+        &'static ClientAddress,
+    ),
+    RemotePlayers,
+>;
+
 /// Client logic for handling bodies received from the server
 /// TODO: improve performance by avoiding copies
+#[allow(clippy::too_many_arguments)]
 fn handle_messages(
     mut messages: ResMut<Messages>,
     mut commands: Commands,
     mut terrain: ResMut<Terrain>,
-    mut other_players: Query<
-        (Entity, &mut PlayerPosition, &ClientAddress),
-        (With<Player>, Without<LocalPlayer>),
+    mut other_players: OtherPlayerQuery,
+    mut local_player: Query<
+        (
+            &mut PlayerPosition,
+            &mut Sprite,
+            &mut Inventory,
+            &mut PlayerStats,
+            &mut Grounded,
+            &mut PlayerName,
+            &mut Extrapolation,
+        ),
+        With<LocalPlayer>,
     >,
-    mut local_player: Query<(&mut PlayerPosition, &mut Sprite, &mut Inventory), With<LocalPlayer>>,
-    old_blocks: Query<Entity, With<RenderedBlock>>,
     assets: Res<AssetServer>,
+    mut block_entities: ResMut<BlockEntities>,
+    mut notice: ResMut<MenuNotice>,
+    mut paused: ResMut<ServerPaused>,
+    mut reach: ResMut<ServerReach>,
+    mut mining: ResMut<MiningEstimate>,
+    mut client: ResMut<Client>,
 ) {
     // new players after this frame, so we can delete old players
     let mut all_players = HashSet::new();
@@ -394,100 +885,92 @@ fn handle_messages(
 
     while let Some(message) = messages.messages.pop_front() {
         match message {
-            ServerBodyElem::Pong(pong) => info!("got pong for seqnum: {}", pong),
+            ServerBodyElem::Pong(pong) => {
+                if let Some(sample) = client.record_pong(pong, Instant::now()) {
+                    debug!("got pong for seqnum: {}, {:.1} ms", pong, sample);
+                }
+            }
             ServerBodyElem::WorldDeltas(deltas) => {
-                for delta in deltas {
-                    match delta {
-                        WorldDelta::NewChunks(new_terrain) => {
-                            //
-                            info!(
-                                "got new completely new chunks!: {:?}",
-                                new_terrain
-                                    .chunks
-                                    .iter()
-                                    .map(|c| c.chunk_number)
-                                    .collect::<Vec<_>>()
-                            );
-
-                            // de-render and destroy old chunks
-                            for mut chunk in &mut terrain.chunks {
-                                derender_chunk(&mut commands, &mut chunk)
-                            }
-
-                            // overwrite the terrain
-                            *terrain = new_terrain;
-
-                            // render new chunks
-                            for mut chunk in &mut terrain.chunks {
-                                render_chunk(&mut commands, &assets, &mut chunk);
-                            }
-                        }
-                        WorldDelta::BlockDelete(delete) => {
-                            // info!("got block deletion: {:?}", delete);
-
-                            for chunk in &mut terrain.chunks {
-                                if chunk.chunk_number == delete.chunk_number {
-                                    let maybe_block = &mut chunk.blocks[delete.y][delete.x];
-                                    match maybe_block {
-                                        Some(block) => {
-                                            // un-render block entity if it exists
-                                            if let Some(e) = block.entity {
-                                                // info!("despawning mined block");
-                                                commands.entity(e).despawn();
-                                            }
-                                            // delete the block
-                                            *maybe_block = None;
-                                        }
-                                        None => {
-                                            // block already deleted
-                                            // warn!("client got BlockDelete but block already doesn't exist!");
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                for delta in &deltas {
+                    if let WorldDelta::BlockDelete(delete) = delta {
+                        let y = delete.chunk_number as usize * CHUNK_HEIGHT + delete.y;
+                        mining.block_deleted(delete.x, y);
                     }
-
-                    // info!("done processing received terrain");
                 }
+                apply_world_deltas(
+                    deltas,
+                    &mut commands,
+                    &assets,
+                    &mut terrain,
+                    &mut block_entities,
+                );
             }
             ServerBodyElem::PlayerInfo(info_vec) => {
                 got_some_player_info = true;
 
-                if info_vec.len() >= 1 {
-                    let info = &info_vec[0];
-                    // info!(
-                    //     "new local player position is: ({}, {})",
-                    //     info.position.x, info.position.y
-                    // );
-                    let (mut local_pos, mut local_sprite, _) = local_player.single_mut();
+                // observers have no local player, and aren't in the list, so everyone is remote
+                let others = match (info_vec.first(), local_player.get_single_mut()) {
+                    (Some(info), Ok(local)) => {
+                        // info!(
+                        //     "new local player position is: ({}, {})",
+                        //     info.position.x, info.position.y
+                        // );
+                        let (
+                            mut local_pos,
+                            mut local_sprite,
+                            _,
+                            _,
+                            mut local_grounded,
+                            mut local_name,
+                            mut extrapolation,
+                        ) = local;
+
+                        // update local player game position, will be rendered in another system
+                        // this also snaps back anything we extrapolated while updates were missing
+                        extrapolation.reconcile(&mut local_pos, &info.position, info.grounded);
+                        local_grounded.0 = info.grounded;
+                        // the name the server has for us, queue_name resends ours until they match
+                        if *local_name != info.name {
+                            *local_name = info.name.clone();
+                        }
 
-                    // update local player game position, will be rendered in another system
-                    *local_pos = info.position.clone();
+                        // recolor local player sprite
+                        local_sprite.color = info.addr.color();
 
-                    // recolor local player sprite
-                    local_sprite.color = info.addr.color();
-                }
+                        &info_vec[1..]
+                    }
+                    _ => &info_vec[..],
+                };
 
                 // setup non-local players
-                if info_vec.len() > 1 {
-                    // for each player
-                    for info in &info_vec[1..] {
-                        // if they already exist, set new position
-                        let mut found = false;
-                        for (e, mut pos, addr) in other_players.iter_mut() {
-                            if info.addr == *addr {
-                                *pos = info.position.clone();
-                                found = true;
+                for info in others {
+                    // if they already exist, set new position
+                    let mut found = false;
+                    for (e, mut pos, mut grounded, mut name, mut target, mut interpolation, addr) in
+                        other_players.iter_mut()
+                    {
+                        if info.addr == *addr {
+                            *pos = info.position.clone();
+                            interpolation.retarget(&info.position);
+                            grounded.0 = info.grounded;
+                            target.0 = info.target;
+                            if *name != info.name {
+                                info!(
+                                    "{} is now known as {}",
+                                    name.or_address(addr),
+                                    info.display_name()
+                                );
+                                *name = info.name.clone();
                             }
+                            found = true;
                         }
-                        if !found {
-                            // player wasn't found, spawn them in later
-                            new_players.insert(info.addr.clone(), info.clone());
-                        }
-                        // don't despawn this player
-                        all_players.insert(info.addr.clone());
                     }
+                    if !found {
+                        // player wasn't found, spawn them in later
+                        new_players.insert(info.addr.clone(), info.clone());
+                    }
+                    // don't despawn this player
+                    all_players.insert(info.addr.clone());
                 }
 
                 // info!(
@@ -502,9 +985,34 @@ fn handle_messages(
                 //     inv
                 // )
                 // overwrite our inventory with new one
-                let (_, _, mut our_inv) = local_player.single_mut();
+                let (_, _, mut our_inv, _, _, _, _) = local_player.single_mut();
                 *our_inv = new_inv;
             }
+            ServerBodyElem::Stats(new_stats) => {
+                // only overwrite if changed, so a stats screen can use change detection
+                let (_, _, _, mut our_stats, _, _, _) = local_player.single_mut();
+                if *our_stats != new_stats {
+                    *our_stats = new_stats;
+                }
+            }
+            ServerBodyElem::ServerShutdown => {
+                info!("server is shutting down");
+                notice.0 = Some(String::from("Server closed"));
+                commands.insert_resource(NextState(GameState::Menu));
+            }
+            ServerBodyElem::Paused(now_paused) => {
+                // only on change, so the label can use change detection
+                if paused.0 != now_paused {
+                    info!("server simulation paused: {}", now_paused);
+                    paused.0 = now_paused;
+                }
+            }
+            ServerBodyElem::MiningProgress(fraction) => mining.snap(fraction),
+            ServerBodyElem::Reach(new_reach) => {
+                if reach.0 != new_reach {
+                    reach.0 = new_reach;
+                }
+            }
         }
     }
 
@@ -516,26 +1024,37 @@ fn handle_messages(
                 &mut commands,
                 assets.as_ref(),
                 &player.addr,
+                &player.name,
                 &player.position,
             );
-            warn!("new player {}", player.addr);
+            info!("new player {}", player.display_name());
         }
     }
 
     // if we actually got some player info this frame
     if got_some_player_info {
         // for all previously spawned players
-        for (e, _pos, addr) in other_players.iter() {
+        for (e, _pos, _grounded, name, _target, _interpolation, addr) in other_players.iter() {
             // if we didn't hear about them this frame
             if !all_players.contains(addr) {
-                // delete
-                commands.entity(e).despawn();
-                warn!("delete player {}", addr);
+                // delete, along with their target marker
+                commands.entity(e).despawn_recursive();
+                info!("delete player {}", name.or_address(addr));
             }
         }
     }
 }
 
+/// Keep the local player moving while the server's PlayerInfo updates are missing
+fn extrapolate_local_player(
+    terrain: Res<Terrain>,
+    mut query: Query<(&mut PlayerPosition, &mut Extrapolation), With<LocalPlayer>>,
+) {
+    for (mut position, mut extrapolation) in query.iter_mut() {
+        extrapolation.step(&mut position, &terrain, 1. / NETWORK_TICK_HZ as f32);
+    }
+}
+
 fn send_bodies(mut client: ResMut<Client>) {
     if client.debug_paused {
         client.bodies.clear();
@@ -546,9 +1065,26 @@ fn send_bodies(mut client: ResMut<Client>) {
         header: ClientHeader {
             current_sequence: client.current_sequence,
             last_received_sequence: client.last_received_sequence,
+            observer: client.observer,
         },
         bodies: client.bodies.clone(),
     };
+
+    // time pings from here, the server answers with the sequence they were actually sent with
+    if message
+        .bodies
+        .iter()
+        .any(|b| matches!(b, ClientBodyElem::Ping))
+    {
+        let now = Instant::now();
+        client
+            .pending_pings
+            .retain(|_, sent| now.saturating_duration_since(*sent) < MAX_PING_AGE);
+        client
+            .pending_pings
+            .insert(message.header.current_sequence, now);
+    }
+
     let success_str = format!("client sent message to server: {:?}", message);
     match client.send_message(message) {
         Ok(_) => {
@@ -581,3 +1117,215 @@ fn on_timeout(mut client: ResMut<Client>, mut commands: Commands) {
     // go back to menu
     commands.insert_resource(NextState(GameState::Menu));
 }
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_close_sends_disconnect() {
+        // stand-in for the server
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let client = Client::new(server.local_addr().unwrap(), 0).unwrap();
+
+        let mut app = App::new();
+        app.add_event::<WindowCloseRequested>()
+            .add_event::<AppExit>()
+            .insert_resource(client)
+            .add_system(handle_window_close);
+
+        app.world.send_event(WindowCloseRequested {
+            id: bevy::window::WindowId::primary(),
+        });
+        app.update();
+
+        // the disconnect must already be on the wire
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let (size, _) = server.recv_from(&mut buffer).unwrap();
+        let (message, _): (ClientToServer, usize) =
+            bincode::decode_from_slice(&buffer[..size], BINCODE_CONFIG).unwrap();
+        assert!(message
+            .bodies
+            .iter()
+            .any(|b| matches!(b, ClientBodyElem::Disconnect)));
+
+        assert_eq!(app.world.resource::<Events<AppExit>>().len(), 1);
+    }
+
+    #[test]
+    fn typed_server_addresses_are_checked() {
+        assert_eq!(
+            parse_server_address("127.0.0.1", "8080"),
+            Ok(SocketAddr::from(([127, 0, 0, 1], 8080)))
+        );
+        // stray spaces from typing are fine
+        assert_eq!(
+            parse_server_address(" 10.0.0.2 ", "9000 "),
+            Ok(SocketAddr::from(([10, 0, 0, 2], 9000)))
+        );
+        assert!(parse_server_address("::1", "8080").unwrap().is_ipv6());
+
+        for ip in ["", "256.0.0.1", "1.2.3", "localhost", "127.0.0.1:8080"] {
+            assert_eq!(
+                parse_server_address(ip, "8080"),
+                Err(AddressError::BadIp),
+                "{}",
+                ip
+            );
+        }
+        for port in ["", "0", "65536", "-1", "80a"] {
+            assert_eq!(
+                parse_server_address("127.0.0.1", port),
+                Err(AddressError::BadPort),
+                "{}",
+                port
+            );
+        }
+    }
+
+    #[test]
+    fn inputs_follow_key_bindings() {
+        use crate::keybindings::Binding;
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(server.local_addr().unwrap(), 0).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(client);
+        world.init_resource::<Input<KeyCode>>();
+        world.init_resource::<Input<MouseButton>>();
+        world.insert_resource(KeyBindings {
+            jump: Binding::Key(KeyCode::Up),
+            ..default()
+        });
+        // no window, so no cursor
+        world.init_resource::<Windows>();
+        world.init_resource::<SelectedSlot>();
+        world.init_resource::<CameraZoom>();
+        world.insert_resource(CurrentState(GameState::InGame));
+        world
+            .spawn()
+            .insert(LocalPlayer)
+            .insert(PlayerPosition { x: 0., y: 0. })
+            .insert(CameraBoundsBox {
+                center_coord: Vec3::ZERO,
+            })
+            .insert(Extrapolation::default());
+        let mut stage = SystemStage::single_threaded().with_system(queue_inputs);
+        let mut jumps_with = |world: &mut World, key: KeyCode| {
+            let mut input = world.resource_mut::<Input<KeyCode>>();
+            input.release_all();
+            input.press(key);
+            world.resource_mut::<Client>().bodies.clear();
+            stage.run(world);
+            world
+                .resource::<Client>()
+                .bodies
+                .iter()
+                .any(|b| matches!(b, ClientBodyElem::Input(input) if input.jump))
+        };
+
+        assert!(jumps_with(&mut world, KeyCode::Up));
+        assert!(!jumps_with(&mut world, KeyCode::Space));
+    }
+
+    #[test]
+    fn loading_waits_for_baseline() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(server.local_addr().unwrap(), 0).unwrap();
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(bevy::asset::AssetPlugin)
+            .insert_resource(client)
+            .insert_resource(Messages::default())
+            .insert_resource(Terrain::empty())
+            .insert_resource(BlockEntities::default())
+            .insert_resource(BaselineReceived(false));
+        let mut stage = SystemStage::single_threaded().with_system(await_baseline);
+
+        // nothing from the server yet, so keep asking
+        stage.run(&mut app.world);
+        assert!(app.world.get_resource::<NextState<GameState>>().is_none());
+        assert!(matches!(
+            app.world.resource::<Client>().bodies.as_slice(),
+            [ClientBodyElem::Ping]
+        ));
+
+        let mut messages = app.world.resource_mut::<Messages>();
+        messages.messages.push_back(ServerBodyElem::Paused(false));
+        messages
+            .messages
+            .push_back(ServerBodyElem::WorldDeltas(vec![WorldDelta::NewChunks(
+                Terrain::new(2),
+            )]));
+        stage.run(&mut app.world);
+
+        assert!(app.world.resource::<BaselineReceived>().0);
+        assert_eq!(app.world.resource::<Terrain>().chunks.len(), 2);
+        assert!(!app.world.resource::<BlockEntities>().entities.is_empty());
+        assert!(matches!(
+            app.world.resource::<NextState<GameState>>().0,
+            GameState::InGame
+        ));
+        // the rest is left for handle_messages
+        assert!(matches!(
+            app.world
+                .resource::<Messages>()
+                .messages
+                .iter()
+                .collect::<Vec<_>>()[..],
+            [ServerBodyElem::Paused(false)]
+        ));
+    }
+
+    #[test]
+    fn socket_matches_server_address_family() {
+        let server = SocketAddr::from(([127, 0, 0, 1], 8080));
+        let client = Client::new(server, 0).unwrap();
+        assert!(client.socket.local_addr().unwrap().is_ipv4());
+
+        let server = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 8080));
+        let client = Client::new(server, 0).unwrap();
+        assert!(client.socket.local_addr().unwrap().is_ipv6());
+    }
+
+    #[test]
+    fn pongs_give_smoothed_round_trip_time() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut client = Client::new(server.local_addr().unwrap(), 0).unwrap();
+        client.current_sequence = 7;
+
+        // one ping per packet, timed from when it is sent
+        client.queue_ping();
+        client.queue_ping();
+        assert_eq!(client.bodies.len(), 1);
+        let mut world = World::new();
+        world.insert_resource(client);
+        SystemStage::single_threaded()
+            .with_system(send_bodies)
+            .run(&mut world);
+        let mut client = world.resource_mut::<Client>();
+        let sent = client.pending_pings[&7];
+
+        let sample = client.record_pong(7, sent + Duration::from_millis(40));
+        assert_eq!(sample, Some(40.));
+        assert_eq!(client.rtt_ms, Some(40.));
+
+        // the server resends pongs until it hears from us, those don't count again
+        assert_eq!(
+            client.record_pong(7, sent + Duration::from_millis(90)),
+            None
+        );
+        assert_eq!(client.rtt_ms, Some(40.));
+
+        // later samples only nudge the average
+        client.pending_pings.insert(8, sent);
+        client.record_pong(8, sent + Duration::from_millis(80));
+        assert_eq!(client.rtt_ms, Some(45.));
+    }
+}
@@ -1,14 +1,14 @@
 use bevy::prelude::*;
-use bincode::{Decode, Encode};
+use bincode::{enc::write::Writer, Decode, Encode};
 use std::{
-    collections::hash_map::DefaultHasher,
+    collections::{hash_map::DefaultHasher, HashMap},
     hash::{Hash, Hasher},
     net::{SocketAddr, UdpSocket},
 };
 
 use crate::{
-    player::{Inventory, PlayerInput, PlayerPosition},
-    world::{BlockType, Terrain, WorldDelta},
+    player::{Inventory, PlayerInput, PlayerPosition, PlayerStats},
+    world::{BlockType, WorldDelta},
 };
 
 /// This is the bincode config that we should use everywhere
@@ -27,6 +27,30 @@ pub const DEFAULT_CLIENT_SERVER_IP: [u8; 4] = [127, 0, 0, 1];
 /// max packet size in UDP is 2^16 bytes
 pub const BUFFER_SIZE: usize = (2 as usize).pow(16);
 
+/// Largest datagram we send by default, small enough to get through a typical 1500 byte MTU
+/// without IP fragmentation, bigger messages are split into fragments of this size
+/// Networks that can take more may raise it (see the server's --max-packet-size)
+pub const MAX_PACKET_SIZE: usize = 1200;
+
+/// Most bodies one packet may carry in either direction, anything past it is dropped
+/// Keeps a client from making the server work through a huge bodies vec (see the server's --max-bodies)
+pub const MAX_BODIES_PER_PACKET: usize = 32;
+
+// anything we are allowed to send has to fit in the buffer the other side receives into
+const _: () = assert!(MAX_PACKET_SIZE <= BUFFER_SIZE);
+
+/// Most fragments a ServerToClient message may be split into, bigger messages are trimmed
+pub const MAX_FRAGMENTS: usize = 32;
+
+/// Most bytes an encoded Fragment takes on top of its payload (header and payload length)
+pub const FRAGMENT_OVERHEAD: usize = 16;
+
+/// How many network ticks a partly received message waits for the rest of its fragments
+pub const FRAGMENT_TIMEOUT_TICKS: u64 = 3;
+
+/// Most partly received messages kept at once, the oldest is dropped to make room for another
+pub const MAX_PARTIAL_MESSAGES: usize = 8;
+
 /// Default size of allocated bodies vec, larger numbers may help reduce reallocation
 pub const DEFAULT_BODIES_VEC_CAPACITY: usize = 10;
 
@@ -75,6 +99,16 @@ pub enum ServerBodyElem {
     PlayerInfo(Vec<SingleNetPlayerInfo>),
     /// The local player's inventory
     Inventory(Inventory),
+    /// The local player's statistics
+    Stats(PlayerStats),
+    /// The server is about to exit, so stop waiting for it
+    ServerShutdown,
+    /// Whether an admin has paused the game simulation
+    Paused(bool),
+    /// How far the local player is through breaking the block they are mining, from 0 to 1
+    MiningProgress(f32),
+    /// How far (in blocks) from themselves players may mine or place, the server's --reach
+    Reach(f32),
 }
 
 /// Contains information about a single player
@@ -82,6 +116,19 @@ pub enum ServerBodyElem {
 pub struct SingleNetPlayerInfo {
     pub addr: ClientAddress,
     pub position: PlayerPosition, // TODO: put inputs here if we want client-side prediction
+    /// Whether the player is standing on a block
+    pub grounded: bool,
+    /// Name the player picked, may be unset
+    pub name: PlayerName,
+    /// Global block (x, y) the player is pointing at, only sent if the server shares targets
+    pub target: Option<(usize, usize)>,
+}
+
+impl SingleNetPlayerInfo {
+    /// What to call this player, their address if they haven't picked a name
+    pub fn display_name(&self) -> String {
+        self.name.or_address(&self.addr)
+    }
 }
 
 impl NetworkMessage for ServerToClient {}
@@ -101,6 +148,8 @@ pub struct ClientHeader {
     pub current_sequence: u64,
     /// Last received sequence/tick number
     pub last_received_sequence: u64,
+    /// Watching without a player, only looked at when the client first connects
+    pub observer: bool,
 }
 
 /// One element (message) for the body of a ClientToServer message
@@ -111,6 +160,28 @@ pub enum ClientBodyElem {
     Ping,
     /// sends entire input
     Input(PlayerInput),
+    /// tells the server we are leaving, so it doesn't have to wait for a timeout
+    Disconnect,
+    /// admin only: set every block in the rectangle to `block` (or clear it if `None`)
+    /// corners are global block coordinates and are inclusive
+    AdminFill {
+        x1: usize,
+        y1: usize,
+        x2: usize,
+        y2: usize,
+        block: Option<BlockType>,
+    },
+    /// admin only: move to the nearest block of this (ore) type
+    AdminTeleportToOre(BlockType),
+    /// admin only: forget what terrain this client has and send it all again
+    AdminResendWorld,
+    /// admin only: pause the game simulation, or resume it if it is paused
+    /// the network keeps running while paused, so nobody times out
+    AdminTogglePause,
+    /// pick the name other players see, sent until the server echoes it back in PlayerInfo
+    SetName(String),
+    /// observer only: where the camera is, so the server sends the terrain around it
+    ObserverView(PlayerPosition),
 }
 
 impl NetworkMessage for ClientToServer {}
@@ -119,6 +190,8 @@ impl NetworkMessage for ClientToServer {}
 pub enum SendError {
     IoError(std::io::Error),
     EncodeError(bincode::error::EncodeError),
+    /// message needs more than MAX_FRAGMENTS fragments
+    TooManyFragments,
     //NoSuchPeer,
 }
 
@@ -128,6 +201,10 @@ pub enum ReceiveError {
     DecodeError(bincode::error::DecodeError),
     UnknownSender,
     NoMessage,
+    /// sender went over their packet budget, message was not decoded
+    RateLimited,
+    /// got a fragment, but the rest of its message hasn't arrived yet
+    Incomplete,
 }
 
 /// Helper method for sending a message
@@ -147,6 +224,192 @@ pub fn send_message<M: NetworkMessage>(
     Ok(())
 }
 
+/// Header of one piece of a message too big for a single datagram
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHeader {
+    /// Same for every fragment of one message, different for each message from one sender
+    pub message_id: u32,
+    /// Where this fragment goes, counting from 0
+    pub fragment_index: u16,
+    /// How many fragments the message was split into
+    pub fragment_count: u16,
+}
+
+/// One datagram's worth of an encoded message
+/// Every ServerToClient message is sent as fragments, usually just one
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Fragment {
+    pub header: FragmentHeader,
+    pub payload: Vec<u8>,
+}
+
+impl NetworkMessage for Fragment {}
+
+/// Split an encoded message into fragments with at most payload_size bytes each
+pub fn fragment(encoded: &[u8], message_id: u32, payload_size: usize) -> Vec<Fragment> {
+    // an empty message still needs one fragment to arrive
+    let pieces: Vec<&[u8]> = if encoded.is_empty() {
+        vec![encoded]
+    } else {
+        encoded.chunks(payload_size.max(1)).collect()
+    };
+    let fragment_count = pieces.len() as u16;
+
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, payload)| Fragment {
+            header: FragmentHeader {
+                message_id,
+                fragment_index: i as u16,
+                fragment_count,
+            },
+            payload: payload.to_vec(),
+        })
+        .collect()
+}
+
+/// Largest encoded message that still fits in MAX_FRAGMENTS datagrams of max_packet_size bytes
+pub fn max_message_size(max_packet_size: usize) -> usize {
+    max_packet_size.saturating_sub(FRAGMENT_OVERHEAD) * MAX_FRAGMENTS
+}
+
+/// Helper method for sending a message as fragments that each fit in max_packet_size bytes
+pub fn send_fragmented<M: NetworkMessage>(
+    socket: &UdpSocket,
+    target: SocketAddr,
+    message: M,
+    message_id: u32,
+    max_packet_size: usize,
+    buffer: &mut [u8],
+) -> Result<(), SendError> {
+    // may be bigger than the buffer, so it can't be encoded into it
+    let encoded =
+        bincode::encode_to_vec(message, BINCODE_CONFIG).map_err(SendError::EncodeError)?;
+    let fragments = fragment(
+        &encoded,
+        message_id,
+        max_packet_size.saturating_sub(FRAGMENT_OVERHEAD),
+    );
+    if fragments.len() > MAX_FRAGMENTS {
+        return Err(SendError::TooManyFragments);
+    }
+
+    for fragment in fragments {
+        send_message(socket, target, fragment, buffer)?;
+    }
+    Ok(())
+}
+
+/// A message that some, but not all, fragments have arrived for
+#[derive(Debug)]
+struct PartialMessage {
+    /// Payloads by fragment index
+    payloads: Vec<Option<Vec<u8>>>,
+    /// How many payloads are still None
+    missing: usize,
+    /// Network ticks since the first fragment arrived
+    age_ticks: u64,
+}
+
+/// Puts fragments from one sender back together into encoded messages
+#[derive(Default, Debug)]
+pub struct Reassembler {
+    partial: HashMap<u32, PartialMessage>,
+}
+
+impl Reassembler {
+    /// Store a fragment, returns the whole encoded message once all of its fragments are in
+    pub fn insert(&mut self, fragment: Fragment) -> Option<Vec<u8>> {
+        let FragmentHeader {
+            message_id,
+            fragment_index,
+            fragment_count,
+        } = fragment.header;
+        let count = fragment_count as usize;
+        // the sender never splits a message into more, so don't make room for more
+        if count == 0 || count > MAX_FRAGMENTS || fragment_index >= fragment_count {
+            warn!("dropping fragment {} of {}", fragment_index, fragment_count);
+            return None;
+        }
+
+        // the common case, nothing to wait for
+        if count == 1 {
+            return Some(fragment.payload);
+        }
+
+        if !self.partial.contains_key(&message_id) && self.partial.len() >= MAX_PARTIAL_MESSAGES {
+            // ids count up, so the lowest of the oldest came first
+            let oldest = self
+                .partial
+                .iter()
+                .max_by_key(|(id, partial)| (partial.age_ticks, std::cmp::Reverse(**id)))
+                .map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                warn!("too many partial messages, dropping message {}", oldest);
+                self.partial.remove(&oldest);
+            }
+        }
+
+        let partial = self
+            .partial
+            .entry(message_id)
+            .or_insert_with(|| PartialMessage {
+                payloads: vec![None; count],
+                missing: count,
+                age_ticks: 0,
+            });
+        if partial.payloads.len() != count {
+            warn!("fragments of message {} disagree on its size", message_id);
+            return None;
+        }
+
+        let slot = &mut partial.payloads[fragment_index as usize];
+        if slot.is_none() {
+            *slot = Some(fragment.payload);
+            partial.missing -= 1;
+        }
+        if partial.missing > 0 {
+            return None;
+        }
+
+        // unwraps OK since nothing is missing
+        let partial = self.partial.remove(&message_id).unwrap();
+        Some(partial.payloads.into_iter().flatten().flatten().collect())
+    }
+
+    /// Call once per network tick, drops messages that have waited more than FRAGMENT_TIMEOUT_TICKS
+    /// Returns how many were dropped
+    pub fn tick(&mut self) -> usize {
+        let before = self.partial.len();
+        self.partial.retain(|_, partial| {
+            partial.age_ticks += 1;
+            partial.age_ticks <= FRAGMENT_TIMEOUT_TICKS
+        });
+        before - self.partial.len()
+    }
+}
+
+/// Bincode writer that only counts bytes
+#[derive(Default)]
+struct ByteCounter {
+    bytes: usize,
+}
+
+impl Writer for ByteCounter {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), bincode::error::EncodeError> {
+        self.bytes += bytes.len();
+        Ok(())
+    }
+}
+
+/// How many bytes a message will take up once encoded, without actually encoding it anywhere
+pub fn encoded_size<M: Encode>(message: &M) -> Result<usize, bincode::error::EncodeError> {
+    let mut counter = ByteCounter::default();
+    bincode::encode_into_writer(message, &mut counter, BINCODE_CONFIG)?;
+    Ok(counter.bytes)
+}
+
 /// A component on _all_ players, connected or not
 #[derive(Component, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub struct ClientAddress {
@@ -159,19 +422,323 @@ impl std::fmt::Display for ClientAddress {
     }
 }
 
+/// Longest player name, in characters
+pub const MAX_NAME_CHARS: usize = 16;
+
+/// A player's chosen name, empty if they haven't picked one
+#[derive(Component, Debug, Encode, Decode, Clone, Default, PartialEq, Eq)]
+pub struct PlayerName(pub String);
+
+impl PlayerName {
+    /// Keep only letters, digits, spaces, '-' and '_', trimmed and cut to MAX_NAME_CHARS
+    pub fn sanitized(raw: &str) -> Self {
+        let name: String = raw
+            .chars()
+            .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+            .collect();
+        Self(
+            name.trim()
+                .chars()
+                .take(MAX_NAME_CHARS)
+                .collect::<String>()
+                .trim_end()
+                .to_string(),
+        )
+    }
+
+    pub fn is_set(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    /// The name, or the address if no name was picked
+    pub fn or_address(&self, addr: &ClientAddress) -> String {
+        if self.is_set() {
+            self.0.clone()
+        } else {
+            addr.to_string()
+        }
+    }
+}
+
+/// Player tints as RGB, far enough apart to tell players apart and bright enough to show on the sprite
+pub const PLAYER_PALETTE: [[u8; 3]; 11] = [
+    [230, 25, 75],   // red
+    [60, 180, 75],   // green
+    [255, 225, 25],  // yellow
+    [67, 99, 216],   // blue
+    [245, 130, 49],  // orange
+    [145, 30, 180],  // purple
+    [66, 212, 244],  // cyan
+    [240, 50, 230],  // magenta
+    [191, 239, 69],  // lime
+    [250, 190, 212], // pink
+    [70, 153, 144],  // teal
+];
+
 impl ClientAddress {
-    pub fn color(&self) -> Color {
-        // generate color tint
+    /// Index into PLAYER_PALETTE for this player, always the same for the same address
+    pub fn palette_index(&self) -> usize {
         let mut hasher = DefaultHasher::new();
         self.addr.to_string().hash(&mut hasher);
-        let h = hasher.finish();
-        let bytes = h.to_le_bytes();
+        (hasher.finish() % PLAYER_PALETTE.len() as u64) as usize
+    }
+
+    /// Tint for this player's sprite
+    pub fn color(&self) -> Color {
+        let [r, g, b] = PLAYER_PALETTE[self.palette_index()];
+        Color::rgb_u8(r, g, b)
+    }
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Terrain;
+
+    /// Encode with BINCODE_CONFIG, decode it again and check that nothing changed,
+    /// including the encoded size
+    fn round_trip<M: Encode + Decode>(message: &M) -> M {
+        let encoded = bincode::encode_to_vec(message, BINCODE_CONFIG).unwrap();
+        assert!(encoded.len() <= max_message_size(MAX_PACKET_SIZE));
+        assert_eq!(encoded.len(), encoded_size(message).unwrap());
+
+        let (decoded, read): (M, usize) =
+            bincode::decode_from_slice(&encoded, BINCODE_CONFIG).unwrap();
+        assert_eq!(read, encoded.len());
+        assert_eq!(
+            bincode::encode_to_vec(&decoded, BINCODE_CONFIG).unwrap(),
+            encoded
+        );
+        decoded
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let addr = ClientAddress {
+            addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+        };
+
+        let client_message = ClientToServer {
+            header: ClientHeader {
+                current_sequence: 300,
+                last_received_sequence: u64::MAX,
+                observer: false,
+            },
+            bodies: vec![
+                ClientBodyElem::Ping,
+                ClientBodyElem::Input(PlayerInput {
+                    right: true,
+                    mine: true,
+                    block_x: 17,
+                    block_y: 9000,
+                    place: true,
+                    place_block_type: BlockType::Coal,
+                    ..default()
+                }),
+                ClientBodyElem::SetName(String::from("Bob")),
+                ClientBodyElem::AdminFill {
+                    x1: 0,
+                    y1: 1,
+                    x2: 2,
+                    y2: 3,
+                    block: Some(BlockType::Coal),
+                },
+            ],
+        };
+        let decoded = round_trip(&client_message);
+        assert_eq!(decoded.header.last_received_sequence, u64::MAX);
+        assert!(matches!(
+            &decoded.bodies[1],
+            ClientBodyElem::Input(PlayerInput {
+                block_y: 9000,
+                place: true,
+                place_block_type: BlockType::Coal,
+                ..
+            })
+        ));
+
+        let server_message = ServerToClient {
+            header: ServerHeader { sequence: 300 },
+            bodies: vec![
+                ServerBodyElem::Pong(299),
+                ServerBodyElem::PlayerInfo(vec![SingleNetPlayerInfo {
+                    addr: addr.clone(),
+                    position: PlayerPosition { x: 3.5, y: -70.25 },
+                    grounded: true,
+                    name: PlayerName::sanitized("Bob"),
+                    target: Some((4, 80)),
+                }]),
+                ServerBodyElem::Inventory(Inventory::default()),
+                ServerBodyElem::Stats(PlayerStats {
+                    blocks_mined: 12,
+                    ..default()
+                }),
+                ServerBodyElem::ServerShutdown,
+                ServerBodyElem::WorldDeltas(vec![WorldDelta::BlockPlace {
+                    chunk_number: 3,
+                    x: 7,
+                    y: 120,
+                    block_type: BlockType::Granite,
+                }]),
+            ],
+        };
+        let decoded = round_trip(&server_message);
+        match &decoded.bodies[1] {
+            ServerBodyElem::PlayerInfo(info) => {
+                assert_eq!(info[0].addr, addr);
+                assert_eq!(info[0].position.y, -70.25);
+                assert!(info[0].grounded);
+                assert_eq!(info[0].name.0, "Bob");
+                assert_eq!(info[0].target, Some((4, 80)));
+            }
+            other => panic!("expected player info, got {:?}", other),
+        }
+        assert!(matches!(decoded.bodies[4], ServerBodyElem::ServerShutdown));
+        assert!(matches!(
+            decoded.bodies[5].clone(),
+            ServerBodyElem::WorldDeltas(deltas) if matches!(
+                deltas.as_slice(),
+                [WorldDelta::BlockPlace {
+                    chunk_number: 3,
+                    x: 7,
+                    y: 120,
+                    block_type: BlockType::Granite,
+                }]
+            )
+        ));
+    }
+
+    #[test]
+    fn big_terrain_survives_fragmenting() {
+        let terrain = Terrain::new(60);
+        let encoded = bincode::encode_to_vec(&terrain, BINCODE_CONFIG).unwrap();
+        assert!(encoded.len() > MAX_PACKET_SIZE);
+
+        let mut fragments = fragment(&encoded, 7, MAX_PACKET_SIZE - FRAGMENT_OVERHEAD);
+        assert!(fragments.len() > 1);
+        for fragment in &fragments {
+            let size = encoded_size(fragment).unwrap();
+            assert!(size <= MAX_PACKET_SIZE, "fragment is {} bytes", size);
+        }
+
+        // datagrams can arrive in any order, and more than once
+        fragments.reverse();
+        let duplicate = fragments[0].clone();
+        let mut reassembler = Reassembler::default();
+        let mut whole = None;
+        for fragment in std::iter::once(duplicate).chain(fragments) {
+            assert!(whole.is_none());
+            whole = reassembler.insert(fragment);
+        }
+
+        let (decoded, _): (Terrain, usize) =
+            bincode::decode_from_slice(&whole.unwrap(), BINCODE_CONFIG).unwrap();
+        assert_eq!(decoded, terrain);
+    }
+
+    #[test]
+    fn incomplete_messages_are_dropped() {
+        let encoded = vec![1u8; 100];
+        let fragments = fragment(&encoded, 1, 40);
+        assert_eq!(fragments.len(), 3);
+        let mut reassembler = Reassembler::default();
+
+        // the last fragment is late
+        for fragment in &fragments[..2] {
+            assert!(reassembler.insert(fragment.clone()).is_none());
+        }
+
+        for _ in 0..FRAGMENT_TIMEOUT_TICKS {
+            assert_eq!(reassembler.tick(), 0);
+        }
+        assert_eq!(reassembler.tick(), 1);
+
+        // too late, the first fragment is gone so it can't complete the message
+        assert!(reassembler.insert(fragments[1].clone()).is_none());
+        assert!(reassembler.insert(fragments[2].clone()).is_none());
+    }
+
+    #[test]
+    fn oversized_fragment_counts_are_rejected() {
+        let mut reassembler = Reassembler::default();
+        let first_of = |message_id, fragment_count| Fragment {
+            header: FragmentHeader {
+                message_id,
+                fragment_index: 0,
+                fragment_count,
+            },
+            payload: vec![1, 2, 3],
+        };
+
+        // more than a sender would ever split a message into
+        assert!(reassembler
+            .insert(first_of(1, MAX_FRAGMENTS as u16 + 1))
+            .is_none());
+        assert!(reassembler.insert(first_of(2, u16::MAX)).is_none());
+        assert!(reassembler.insert(first_of(3, 0)).is_none());
+        assert!(reassembler.partial.is_empty());
+
+        // only so many messages wait at once, the oldest make way
+        for message_id in 0..MAX_PARTIAL_MESSAGES as u32 * 2 {
+            reassembler.insert(first_of(message_id, 2));
+        }
+        assert_eq!(reassembler.partial.len(), MAX_PARTIAL_MESSAGES);
+        assert!(!reassembler.partial.contains_key(&0));
+        assert!(reassembler
+            .partial
+            .contains_key(&(MAX_PARTIAL_MESSAGES as u32 * 2 - 1)));
+    }
+
+    #[test]
+    fn names_are_sanitized() {
+        assert_eq!(PlayerName::sanitized("  Bob  ").0, "Bob");
+        assert_eq!(PlayerName::sanitized("x\n<script>y").0, "xscripty");
+        assert_eq!(PlayerName::sanitized("dig_dug-2 jr").0, "dig_dug-2 jr");
+        // capped, without a dangling space
+        assert_eq!(
+            PlayerName::sanitized("abcdefghijklmno pqrst").0,
+            "abcdefghijklmno"
+        );
+        assert_eq!(
+            PlayerName::sanitized(&"a".repeat(100)).0.chars().count(),
+            MAX_NAME_CHARS
+        );
+
+        // nothing usable falls back to the address
+        let addr = ClientAddress {
+            addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+        };
+        let unset = PlayerName::sanitized(" !?\t");
+        assert!(!unset.is_set());
+        assert_eq!(unset.or_address(&addr), "127.0.0.1:1234");
+        assert_eq!(PlayerName::sanitized("Bob").or_address(&addr), "Bob");
+    }
+
+    #[test]
+    fn colors_come_from_the_palette() {
+        // every pair of colors should be easy to tell apart
+        for (i, a) in PLAYER_PALETTE.iter().enumerate() {
+            for b in &PLAYER_PALETTE[i + 1..] {
+                let dist_sq: f32 = a
+                    .iter()
+                    .zip(b)
+                    .map(|(a, b)| ((*a as f32 - *b as f32) / 255.).powi(2))
+                    .sum();
+                assert!(dist_sq > 0.25 * 0.25, "{:?} and {:?} are too close", a, b);
+            }
+        }
 
-        // use one byte for each color value
-        let r = bytes[0] as f32 / 255.;
-        let b = bytes[1] as f32 / 255.;
-        let g = bytes[2] as f32 / 255.;
+        for port in 1000..1100 {
+            let addr = ClientAddress {
+                addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            };
+            assert!(addr.palette_index() < PLAYER_PALETTE.len());
+            let [r, g, b] = PLAYER_PALETTE[addr.palette_index()];
+            assert_eq!(addr.color(), Color::rgb_u8(r, g, b));
 
-        return Color::rgb(r, b, g);
+            // same address, same color
+            assert_eq!(addr.clone().color(), addr.color());
+        }
     }
 }
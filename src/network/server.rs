@@ -2,25 +2,54 @@ use super::*;
 use crate::{
     args::ServerArgs,
     player::{
-        server::{handle_movement, JumpDuration, JumpState},
-        Inventory, PlayerInput, PlayerPosition,
+        server::{handle_movement, JumpDuration, JumpState, MiningProgress, Velocity},
+        Inventory, PlayerInput, PlayerPosition, PlayerStats,
     },
-    states,
+    save, states,
     world::{
-        self, server::check_generate_new_chunks, BlockDelete, Terrain, WorldDelta, CHUNK_HEIGHT,
-        CHUNK_WIDTH,
+        self,
+        server::{check_generate_new_chunks, despawn_distant_chunks, update_water, WorldReady},
+        Block, BlockDelete, Terrain, WorldDelta, WorldSeed, CHUNK_HEIGHT, CHUNK_WIDTH,
     },
 };
-use bevy::prelude::*;
+use bevy::{app::AppExit, prelude::*};
 use iyes_loopless::prelude::*;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     f32::consts::E,
     net::{SocketAddr, UdpSocket},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
 };
 
 pub const MESSAGE_QUEUE_SIZE: usize = 20;
 
+/// How many packets a single address may send per RATE_LIMIT_WINDOW before we drop them
+/// clients normally send one packet per network tick
+pub const RATE_LIMIT_PACKETS: u32 = NETWORK_TICK_HZ as u32 * 2;
+
+/// Length of the window that packets are counted over
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+/// How many sequences of sent deltas we keep waiting for an ack
+/// a client that falls further behind than this gets a new baseline instead
+/// Five seconds worth, so a slow or briefly lossy connection isn't sent baselines over and over
+pub const DELTA_TTL: u64 = NETWORK_TICK_HZ * 5;
+
+/// How long to wait after sending the shutdown message before exiting, so it gets out
+pub const SHUTDOWN_GRACE: Duration = Duration::from_millis(100);
+
+/// Whether an admin has paused the game simulation; should be a resource
+/// Game tick systems that change the world (movement, generation, mining) don't run while paused,
+/// but the network tick keeps going so clients stay connected
+pub struct SimPaused(pub bool);
+
+/// Set by the SIGINT handler, checked every frame by exit_on_interrupt
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// timestep for counting player play time
+const PLAY_TIME_TICK_LABEL: &str = "PLAY_TIME_TICK";
+
 /// Should be used as a global resource on the server
 pub struct Server {
     /// UDP socket that should be used for everything
@@ -29,6 +58,51 @@ pub struct Server {
     sequence: u64,
     /// Incoming buffer
     buffer: [u8; BUFFER_SIZE],
+    /// Id for the next message sent, so clients can tell fragments of different messages apart
+    next_message_id: u32,
+    /// Drops packets from addresses that send too many
+    rate_limiter: RateLimiter,
+}
+
+/// Counts packets from each address over fixed windows of time
+#[derive(Default)]
+struct RateLimiter {
+    /// When the current window started
+    window_start: Option<Instant>,
+    /// Packets received from each address in the current window
+    counts: HashMap<SocketAddr, u32>,
+    /// Addresses that have been rate limited in the current window, so we only complain once a
+    /// window rather than once a packet
+    warned: HashSet<SocketAddr>,
+}
+
+impl RateLimiter {
+    /// Count a packet from addr, returns false if it should be dropped
+    fn allow(&mut self, addr: SocketAddr, now: Instant) -> bool {
+        // start a new window if the old one is over
+        let expired = match self.window_start {
+            Some(start) => now.duration_since(start) >= RATE_LIMIT_WINDOW,
+            None => true,
+        };
+        if expired {
+            self.window_start = Some(now);
+            self.counts.clear();
+            // also keeps spoofed addresses from piling up in here
+            self.warned.clear();
+        }
+
+        let count = self.counts.entry(addr).or_insert(0);
+        *count += 1;
+
+        if *count <= RATE_LIMIT_PACKETS {
+            return true;
+        }
+
+        if self.warned.insert(addr) {
+            warn!("{} is sending too many packets, dropping the excess", addr);
+        }
+        false
+    }
 }
 
 /// Helper resource to decouple message reception and processing
@@ -50,8 +124,32 @@ pub struct ConnectedClientInfo {
     pub last_confirmed_terrain: Terrain,
     /// Map of sequence numbers to deltas sent
     pub deltas: HashMap<u64, Vec<WorldDelta>>,
+    /// Admin bodies waiting to be checked and applied
+    pub admin_requests: Vec<ClientBodyElem>,
 }
 
+/// Marks a connected client that is only watching, it has no player, inventory or stats
+/// Observers get terrain and player info like everyone else, but their inputs are ignored
+#[derive(Component, Default, Debug)]
+pub struct Observer {
+    /// Where the observer's camera is, terrain is sent around this instead of a player
+    pub view: PlayerPosition,
+}
+
+/// Every client entity handle_messages might need to update
+type ClientQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static ClientAddress,
+        Option<&'static mut ConnectedClientInfo>,
+        Option<&'static mut PlayerInput>,
+        Option<&'static mut PlayerName>,
+        Option<&'static mut Observer>,
+    ),
+>;
+
 impl Default for ConnectedClientInfo {
     fn default() -> Self {
         ConnectedClientInfo {
@@ -60,6 +158,22 @@ impl Default for ConnectedClientInfo {
             until_drop: FRAME_DIFFERENCE_BEFORE_DISCONNECT,
             last_confirmed_terrain: Terrain::empty(),
             deltas: HashMap::new(),
+            admin_requests: Vec::new(),
+        }
+    }
+}
+
+impl ConnectedClientInfo {
+    /// Drop stored deltas sent more than DELTA_TTL sequences before the current one
+    /// If any were dropped, the client is too far behind to ack its way back to our
+    /// baseline, so forget it and let enqueue_terrain send a fresh one
+    fn prune_deltas(&mut self, sequence: u64) {
+        let before = self.deltas.len();
+        self.deltas
+            .retain(|&seq_num, _| seq_num + DELTA_TTL > sequence);
+
+        if self.deltas.len() < before {
+            self.last_confirmed_terrain = Terrain::empty();
         }
     }
 }
@@ -79,17 +193,29 @@ impl Server {
             socket: sock,
             sequence: 1u64,
             buffer: [0u8; BUFFER_SIZE],
+            next_message_id: 0,
+            rate_limiter: RateLimiter::default(),
         })
     }
 
-    /// Send message to a specific client
+    /// Send message to a specific client, in as many datagrams of max_packet_size as it takes
     fn send_message(
         &mut self,
         client_addr: SocketAddr,
         message: ServerToClient,
+        max_packet_size: usize,
     ) -> Result<(), SendError> {
         // TODO: check if address is acually a connected client via a query?
-        send_message(&self.socket, client_addr, message, &mut self.buffer)?;
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+        send_fragmented(
+            &self.socket,
+            client_addr,
+            message,
+            message_id,
+            max_packet_size,
+            &mut self.buffer,
+        )?;
         Ok(())
     }
 
@@ -104,6 +230,11 @@ impl Server {
             _ => ReceiveError::IoError(e),
         })?;
 
+        // don't spend time decoding messages from flooding addresses
+        if !self.rate_limiter.allow(sender_addr, Instant::now()) {
+            return Err(ReceiveError::RateLimited);
+        }
+
         // decode
         let (message, _size) = bincode::decode_from_slice(&self.buffer, BINCODE_CONFIG)
             .map_err(ReceiveError::DecodeError)?;
@@ -123,6 +254,9 @@ impl Plugin for ServerPlugin {
         // add arguments
         app.insert_resource(self.args.clone());
 
+        app.insert_resource(SimPaused(false));
+        app.init_resource::<MiningProgress>();
+
         // add game tick
         app.add_fixed_timestep(
             std::time::Duration::from_secs_f64(1. / GAME_TICK_HZ as f64),
@@ -135,12 +269,49 @@ impl Plugin for ServerPlugin {
             NETWORK_TICK_LABEL,
         );
 
+        // add play time tick, once per second
+        app.add_fixed_timestep(Duration::from_secs(1), PLAY_TIME_TICK_LABEL);
+
         // enter systems
         app.add_enter_system(states::server::GameState::Running, create_server);
 
         // exit systems
         app.add_exit_system(states::server::GameState::Running, destroy_server);
 
+        // shutting down, Ctrl+C sends AppExit, and AppExit tells everyone we're leaving and saves
+        // these run in the last stage, since the app stops right after the frame AppExit is sent
+        catch_interrupt();
+        app.add_system_to_stage(CoreStage::First, exit_on_interrupt)
+            .add_system_to_stage(
+                CoreStage::Last,
+                enqueue_shutdown
+                    .run_in_state(states::server::GameState::Running)
+                    .run_if(exit_requested)
+                    .label("enqueue_shutdown"),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                send_all_messages
+                    .run_in_state(states::server::GameState::Running)
+                    .run_if(exit_requested)
+                    .label("send_shutdown")
+                    .after("enqueue_shutdown"),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                save::save_on_exit
+                    .run_in_state(states::server::GameState::Running)
+                    .run_if(exit_requested)
+                    .after("send_shutdown"),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                (|| std::thread::sleep(SHUTDOWN_GRACE))
+                    .run_in_state(states::server::GameState::Running)
+                    .run_if(exit_requested)
+                    .after("send_shutdown"),
+            );
+
         // game tick systems
         app.add_fixed_timestep_system(
             GAME_TICK_LABEL,
@@ -162,6 +333,7 @@ impl Plugin for ServerPlugin {
             0,
             check_generate_new_chunks
                 .run_in_state(states::server::GameState::Running)
+                .run_if(sim_running)
                 .label("check_generate_new_chunks")
                 .after("handle_messages"),
         )
@@ -170,8 +342,35 @@ impl Plugin for ServerPlugin {
             0,
             handle_movement
                 .run_in_state(states::server::GameState::Running)
+                .run_if(sim_running)
                 .label("handle_movement")
                 .after("check_generate_new_chunks"),
+        )
+        .add_fixed_timestep_system(
+            GAME_TICK_LABEL,
+            0,
+            update_water
+                .run_in_state(states::server::GameState::Running)
+                .run_if(sim_running)
+                .label("update_water")
+                .after("check_generate_new_chunks"),
+        )
+        .add_fixed_timestep_system(
+            GAME_TICK_LABEL,
+            0,
+            despawn_distant_chunks
+                .run_in_state(states::server::GameState::Running)
+                .label("despawn_distant_chunks")
+                .after("handle_movement"),
+        );
+
+        // play time tick systems
+        app.add_fixed_timestep_system(
+            PLAY_TIME_TICK_LABEL,
+            0,
+            count_play_time
+                .run_in_state(states::server::GameState::Running)
+                .label("count_play_time"),
         );
 
         // debug print player info
@@ -183,8 +382,8 @@ impl Plugin for ServerPlugin {
         //         .label("debug_print_players"),
         // );
 
-        // TODO: add run condition to only run if self.clients.len() > 0
         // network tick systems
+        // the enqueue and send systems only run if anyone is connected to receive them
         app.add_fixed_timestep_system(
             NETWORK_TICK_LABEL,
             0,
@@ -197,14 +396,33 @@ impl Plugin for ServerPlugin {
             0,
             process_player_mining
                 .run_in_state(states::server::GameState::Running)
+                .run_if(sim_running)
                 .label("process_player_mining")
                 .after("increase_network_tick"),
         )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            process_player_placement
+                .run_in_state(states::server::GameState::Running)
+                .run_if(sim_running)
+                .label("process_player_placement")
+                .after("process_player_mining"),
+        )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            process_admin_requests
+                .run_in_state(states::server::GameState::Running)
+                .label("process_admin_requests")
+                .after("increase_network_tick"),
+        )
         .add_fixed_timestep_system(
             NETWORK_TICK_LABEL,
             0,
             enqueue_player_info
                 .run_in_state(states::server::GameState::Running)
+                .run_if(has_connected_clients)
                 .label("enqueue_player_info")
                 .after("increase_network_tick"),
         )
@@ -213,7 +431,48 @@ impl Plugin for ServerPlugin {
             0,
             enqueue_inventory
                 .run_in_state(states::server::GameState::Running)
+                .run_if(has_connected_clients)
                 .label("enqueue_inventory")
+                .after("increase_network_tick")
+                .after("process_player_placement"),
+        )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            enqueue_stats
+                .run_in_state(states::server::GameState::Running)
+                .run_if(has_connected_clients)
+                .label("enqueue_stats")
+                .after("increase_network_tick")
+                .after("process_player_mining"),
+        )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            enqueue_mining_progress
+                .run_in_state(states::server::GameState::Running)
+                .run_if(has_connected_clients)
+                .label("enqueue_mining_progress")
+                .after("increase_network_tick")
+                .after("process_player_mining"),
+        )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            enqueue_pause_state
+                .run_in_state(states::server::GameState::Running)
+                .run_if(has_connected_clients)
+                .label("enqueue_pause_state")
+                .after("increase_network_tick")
+                .after("process_admin_requests"),
+        )
+        .add_fixed_timestep_system(
+            NETWORK_TICK_LABEL,
+            0,
+            enqueue_reach
+                .run_in_state(states::server::GameState::Running)
+                .run_if(has_connected_clients)
+                .label("enqueue_reach")
                 .after("increase_network_tick"),
         )
         .add_fixed_timestep_system(
@@ -221,16 +480,26 @@ impl Plugin for ServerPlugin {
             0,
             enqueue_terrain
                 .run_in_state(states::server::GameState::Running)
+                .run_if(has_connected_clients)
                 .label("enqueue_terrain")
-                .after("increase_network_tick"),
+                .after("increase_network_tick")
+                .after("process_player_mining")
+                .after("process_player_placement")
+                .after("process_admin_requests"),
         )
         .add_fixed_timestep_system(
             NETWORK_TICK_LABEL,
             0,
             send_all_messages
                 .run_in_state(states::server::GameState::Running)
+                .run_if(has_connected_clients)
                 .after("enqueue_terrain")
                 .after("enqueue_player_info")
+                .after("enqueue_inventory")
+                .after("enqueue_stats")
+                .after("enqueue_mining_progress")
+                .after("enqueue_pause_state")
+                .after("enqueue_reach")
                 .label("send_messages"),
         )
         .add_fixed_timestep_system(
@@ -244,6 +513,16 @@ impl Plugin for ServerPlugin {
     }
 }
 
+/// Run condition that is true unless an admin paused the simulation
+fn sim_running(paused: Res<SimPaused>) -> bool {
+    !paused.0
+}
+
+/// Run condition that is true if at least one client is connected
+fn has_connected_clients(clients: Query<(), With<ConnectedClientInfo>>) -> bool {
+    !clients.is_empty()
+}
+
 fn create_server(mut commands: Commands, args: Res<ServerArgs>) {
     // TODO: use command line arguments for port and handle failure better
     let server = match Server::new(args.port) {
@@ -262,23 +541,91 @@ fn destroy_server(mut commands: Commands) {
     commands.remove_resource::<Server>();
 }
 
+/// Make SIGINT set INTERRUPTED instead of killing the process
+/// A second Ctrl+C kills it as usual, in case shutting down gets stuck
+fn catch_interrupt() {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_interrupt(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::Relaxed);
+            // signal() is async-signal-safe, so this is fine inside a handler
+            unsafe {
+                libc::signal(libc::SIGINT, libc::SIG_DFL);
+            }
+        }
+
+        let handler = on_interrupt as extern "C" fn(libc::c_int);
+        // safety: the handler only touches an atomic and resets itself
+        if unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) } == libc::SIG_ERR {
+            warn!("unable to catch Ctrl+C, clients won't be told when the server stops");
+        }
+    }
+}
+
+/// Turn a caught Ctrl+C into a normal app exit
+fn exit_on_interrupt(mut exit: EventWriter<AppExit>) {
+    if INTERRUPTED.swap(false, Ordering::Relaxed) {
+        info!("interrupted, shutting down");
+        exit.send(AppExit);
+    }
+}
+
+/// Run condition that is true on the frame the app was told to exit
+fn exit_requested(mut exits: EventReader<AppExit>) -> bool {
+    exits.iter().count() > 0
+}
+
+/// Tell every connected client that the server is going away
+fn enqueue_shutdown(mut clients: Query<&mut ConnectedClientInfo>) {
+    info!(
+        "telling {} clients the server is closing",
+        clients.iter().len()
+    );
+    for mut client in clients.iter_mut() {
+        client.bodies.push(ServerBodyElem::ServerShutdown);
+    }
+}
+
 /// Server increase tick count
 fn increase_network_tick(mut server: ResMut<Server>) {
     server.sequence += 1;
 }
 
+/// Break blocks players have been mining for long enough, giving the drops to whoever finishes
+/// How long depends on the block, see BlockType::hardness
+/// Players mining the same block share its progress, which is lost once nobody is mining it
 fn process_player_mining(
     mut query: Query<(
         &ClientAddress,
         &PlayerInput,
+        &PlayerPosition,
         &mut ConnectedClientInfo,
         &mut Inventory,
+        &mut PlayerStats,
     )>,
     mut terrain: ResMut<Terrain>,
+    mut progress: ResMut<MiningProgress>,
+    seed: Res<WorldSeed>,
+    args: Res<ServerArgs>,
     mut commands: Commands,
 ) {
-    for (addr, inputs, mut client, mut inventory) in query.iter_mut() {
-        if inputs.mine {
+    let mut being_mined = HashSet::new();
+    for (addr, inputs, position, mut client, mut inventory, mut stats) in query.iter_mut() {
+        let target = (inputs.block_x, inputs.block_y);
+        let block_type = match terrain.get_block(target.0, target.1) {
+            // quietly ignore anything out of reach, a normal client never asks for it
+            Some(block) if inputs.mine && position.can_reach(target.0, target.1, args.reach) => {
+                block.block_type
+            }
+            _ => continue,
+        };
+        being_mined.insert(target);
+
+        // this runs once per network tick
+        *progress.ticks.entry(target).or_default() += 1;
+
+        if progress.elapsed(target) >= block_type.mine_duration() {
+            progress.ticks.remove(&target);
             // destroy the block
             let res = world::server::destroy_block(
                 inputs.block_x,
@@ -290,15 +637,25 @@ fn process_player_mining(
             match res {
                 Ok(block) => {
                     // modify inventory
-                    match inventory.amounts.get_mut(&block.block_type) {
-                        Some(amount) => {
-                            *amount += 1;
-                        }
-                        None => {
-                            error!("block_type {:?} not in inventory??", block.block_type);
+                    let drops = world::server::roll_drops(
+                        *seed,
+                        block.block_type,
+                        inputs.block_x,
+                        inputs.block_y,
+                    );
+                    for drop in drops {
+                        match inventory.amounts.get_mut(&drop) {
+                            Some(amount) => {
+                                *amount += 1;
+                            }
+                            None => {
+                                error!("block_type {:?} not in inventory??", drop);
+                            }
                         }
                     }
 
+                    stats.blocks_mined += 1;
+
                     // info!(
                     //     "player {} destroyed block at ({}, {}): {:?}, new inv: {:?}",
                     //     addr, inputs.block_x, inputs.block_y, block.block_type, inventory
@@ -313,16 +670,147 @@ fn process_player_mining(
             }
         }
     }
+
+    progress
+        .ticks
+        .retain(|target, _| being_mined.contains(target));
+}
+
+/// Place blocks for players asking to, taking them out of their inventory
+/// Blocks can only go into empty space that no player is standing in
+fn process_player_placement(
+    mut query: Query<
+        (
+            &ClientAddress,
+            &PlayerInput,
+            &PlayerPosition,
+            &mut Inventory,
+            &mut PlayerStats,
+        ),
+        With<ConnectedClientInfo>,
+    >,
+    players: Query<&PlayerPosition>,
+    mut terrain: ResMut<Terrain>,
+    args: Res<ServerArgs>,
+) {
+    for (addr, input, position, mut inventory, mut stats) in query.iter_mut() {
+        if !input.place || !input.place_block_type.is_real_block() {
+            continue;
+        }
+
+        let (x, y) = (input.block_x, input.block_y);
+        if !position.can_place(x, y, args.reach, &terrain, players.iter()) {
+            continue;
+        }
+
+        match inventory.amounts.get_mut(&input.place_block_type) {
+            Some(amount) if *amount > 0 => {
+                match world::server::place_block(x, y, input.place_block_type, &mut terrain) {
+                    Ok(()) => {
+                        *amount -= 1;
+                        stats.blocks_placed += 1;
+                    }
+                    Err(_err) => {
+                        // trace!("player {} unable to place block at ({}, {}): {:?}", addr, x, y, err);
+                    }
+                }
+            }
+            _ => trace!(
+                "player {} has no {:?} to place",
+                addr,
+                input.place_block_type
+            ),
+        }
+    }
+}
+
+/// Apply admin bodies from clients that are on the admin list
+fn process_admin_requests(
+    mut query: Query<(
+        &ClientAddress,
+        &mut ConnectedClientInfo,
+        &mut PlayerPosition,
+    )>,
+    mut terrain: ResMut<Terrain>,
+    args: Res<ServerArgs>,
+    mut paused: ResMut<SimPaused>,
+) {
+    for (addr, mut client, mut position) in query.iter_mut() {
+        for request in std::mem::take(&mut client.admin_requests) {
+            if !args.admins.contains(&addr.addr.ip()) {
+                warn!("ignoring admin request from non-admin {}", addr);
+                continue;
+            }
+
+            match request {
+                ClientBodyElem::AdminFill {
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                    block,
+                } => match world::server::fill_area((x1, y1), (x2, y2), block, &mut terrain) {
+                    Ok(_) => {
+                        info!(
+                            "admin {} filled ({}, {}) to ({}, {}) with {:?}",
+                            addr, x1, y1, x2, y2, block
+                        );
+                    }
+                    Err(e) => warn!("admin {} unable to fill: {:?}", addr, e),
+                },
+                ClientBodyElem::AdminTeleportToOre(ore) => {
+                    match world::nearest_block(&terrain, &position, |b| b == ore) {
+                        // stand on top of the ore, there has to be a row above it
+                        Some((x, y)) if y > 0 => {
+                            // make room, players can't move while inside blocks
+                            let _ = world::server::fill_area(
+                                (x, y - 1),
+                                (x, y - 1),
+                                None,
+                                &mut terrain,
+                            );
+                            *position = PlayerPosition {
+                                x: x as f32,
+                                y: -((y - 1) as f32),
+                            };
+                            info!("admin {} teleported to {:?} at ({}, {})", addr, ore, x, y);
+                        }
+                        _ => warn!("admin {} found no {:?} to teleport to", addr, ore),
+                    }
+                }
+                ClientBodyElem::AdminResendWorld => {
+                    // enqueue_terrain sends a new baseline to clients missing chunks
+                    client.last_confirmed_terrain = Terrain::empty();
+                    info!("admin {} asked for the whole world again", addr);
+                }
+                ClientBodyElem::AdminTogglePause => {
+                    paused.0 = !paused.0;
+                    warn!(
+                        "admin {} {} the simulation",
+                        addr,
+                        if paused.0 { "paused" } else { "resumed" }
+                    );
+                }
+                _ => error!("non-admin body in admin requests: {:?}", request),
+            }
+        }
+    }
 }
 
 /// Server system that runs on _every_ frame
 /// Places messages into Messages resource
-fn retrieve_messages(mut server: ResMut<Server>, mut messages: ResMut<Messages>) {
+fn retrieve_messages(
+    mut server: ResMut<Server>,
+    mut messages: ResMut<Messages>,
+    args: Res<ServerArgs>,
+) {
     // loop until we break (on NoMessage)
     loop {
         // handle all messages on our socket
         match server.get_one_message() {
-            Ok(m) => {
+            Ok(mut m) => {
+                cap_bodies(&m.0, &mut m.1, args.max_bodies);
+
                 // put into resource
 
                 // info!("message queue size: {}", messages.messages.len());
@@ -345,6 +833,9 @@ fn retrieve_messages(mut server: ResMut<Server>, mut messages: ResMut<Messages>)
             Err(ReceiveError::UnknownSender) => {
                 warn!("server recieve error: server is full!");
             }
+            Err(ReceiveError::RateLimited) => {
+                // dropped, already logged by the rate limiter
+            }
             #[cfg(target_os = "windows")]
             Err(ReceiveError::IoError(e)) if e.kind() == std::io::ErrorKind::ConnectionReset => {
                 // ignore
@@ -358,16 +849,25 @@ fn retrieve_messages(mut server: ResMut<Server>, mut messages: ResMut<Messages>)
     }
 }
 
+/// Drop bodies past max_bodies from a client's message, so it can't make us do unbounded work
+fn cap_bodies(addr: &SocketAddr, message: &mut ClientToServer, max_bodies: usize) {
+    if message.bodies.len() > max_bodies {
+        warn!(
+            "message from {} had {} bodies, dropping all past {}",
+            addr,
+            message.bodies.len(),
+            max_bodies
+        );
+        message.bodies.truncate(max_bodies);
+    }
+}
+
 /// System that handles all messages from the Messages resource
 fn handle_messages(
     mut messages: ResMut<Messages>,
     mut commands: Commands,
-    mut query: Query<(
-        Entity,
-        &ClientAddress,
-        Option<&mut ConnectedClientInfo>,
-        &mut PlayerInput,
-    )>,
+    mut query: ClientQuery,
+    terrain: Res<Terrain>,
 ) {
     /*
     We have to handle several different cases and we need immediate access
@@ -380,50 +880,58 @@ fn handle_messages(
     // since entities aren't spawned until next frame
     let mut new_clients: HashMap<SocketAddr, Vec<ClientToServer>> = HashMap::new();
 
-    // for each message
-    while let Some((addr, message)) = messages.messages.pop_front() {
-        let mut entity: Option<Entity> = None;
+    // same for clients coming back after a timeout, so that several messages from them
+    // in one frame all land in the same ConnectedClientInfo
+    let mut reconnected: HashMap<Entity, ConnectedClientInfo> = HashMap::new();
 
-        // check if we have a player at this address already
-        for (e, client_addr, _, _) in query.iter() {
-            if client_addr.addr == addr {
-                entity = Some(e)
-            }
-        }
+    // the one player entity for each address, see find_players
+    let players = find_players(&query, &mut commands);
 
-        match entity {
+    // for each message
+    while let Some((addr, message)) = messages.messages.pop_front() {
+        match players.get(&addr).copied() {
             Some(entity) => {
                 // client is either currently connected or has connected before
                 // unwrap OK since we iterated to find it above
                 let e = query.get_mut(entity).unwrap();
 
                 // unpack tuple here for readability
+                // observers have no input or name, players have no Observer
                 let maybe_connected = e.2;
                 let mut input = e.3;
+                let mut name = e.4;
+                let mut observer = e.5;
 
                 match maybe_connected {
                     Some(mut connected) => {
                         // client is currently connected
 
                         // process the client message
-                        process_client_message(&addr, &mut connected, message, &mut input);
+                        process_client_message(
+                            &addr,
+                            &mut connected,
+                            message,
+                            input.as_deref_mut(),
+                            name.as_deref_mut(),
+                            observer.as_deref_mut(),
+                        );
                     }
                     None => {
                         // client has connected before, but timed out
-                        info!("reconnection from {}", addr);
-                        let mut connected = ConnectedClientInfo::default();
+                        let connected = reconnected.entry(entity).or_insert_with(|| {
+                            info!("reconnection from {}", addr);
+                            ConnectedClientInfo::default()
+                        });
 
                         // process the client message
-                        process_client_message(&addr, &mut connected, message, &mut input);
-
-                        // add connected to the entity
-                        commands.entity(entity).insert(connected);
-
-                        // add other connected-only components to entity
-                        commands
-                            .entity(entity)
-                            .insert(JumpDuration::default())
-                            .insert(JumpState::default());
+                        process_client_message(
+                            &addr,
+                            connected,
+                            message,
+                            input.as_deref_mut(),
+                            name.as_deref_mut(),
+                            observer.as_deref_mut(),
+                        );
                     }
                 };
             }
@@ -439,21 +947,46 @@ fn handle_messages(
         }
     }
 
+    for (entity, connected) in reconnected {
+        // add connected and other connected-only components to the entity
+        commands
+            .entity(entity)
+            .insert(connected)
+            .insert(JumpDuration::default())
+            .insert(JumpState::default())
+            .insert(Velocity::default());
+    }
+
     for (addr, c_messages) in new_clients {
+        // the first message decides whether this client plays or only watches
+        if c_messages.first().is_some_and(|m| m.header.observer) {
+            spawn_observer(&mut commands, ClientAddress { addr }, c_messages);
+            continue;
+        }
+
         // new connection
         let client_addr = ClientAddress { addr };
-        let position = PlayerPosition::default();
+        let position = world::find_spawn_point(&terrain).unwrap_or_default();
         let mut input = PlayerInput::default();
         let jump_dur = JumpDuration::default();
         let jump_state = JumpState::default();
         let inventory = Inventory::default();
+        let stats = PlayerStats::default();
+        let mut name = PlayerName::default();
         let mut connected = ConnectedClientInfo::default();
 
         info!("new connection from {}", client_addr);
 
         for message in c_messages {
             // process the message
-            process_client_message(&client_addr.addr, &mut connected, message, &mut input);
+            process_client_message(
+                &client_addr.addr,
+                &mut connected,
+                message,
+                Some(&mut input),
+                Some(&mut name),
+                None,
+            );
         }
 
         // create entity with components
@@ -466,17 +999,85 @@ fn handle_messages(
             .insert(connected)
             .insert(jump_dur)
             .insert(jump_state)
-            .insert(inventory);
+            .insert(Velocity::default())
+            .insert(inventory)
+            .insert(stats)
+            .insert(name);
+    }
+}
+
+/// Spawn an entity for a new observer, it only gets the components needed to send it things
+fn spawn_observer(
+    commands: &mut Commands,
+    client_addr: ClientAddress,
+    messages: Vec<ClientToServer>,
+) {
+    let mut connected = ConnectedClientInfo::default();
+    let mut observer = Observer::default();
+
+    info!("new observer from {}", client_addr);
+
+    for message in messages {
+        process_client_message(
+            &client_addr.addr,
+            &mut connected,
+            message,
+            None,
+            None,
+            Some(&mut observer),
+        );
+    }
+
+    commands
+        .spawn()
+        .insert(client_addr)
+        .insert(connected)
+        .insert(observer);
+}
+
+/// Map each address to its player entity
+/// There should only ever be one, but if there are more (e.g. from an old save, or a race between
+/// spawning and reconnecting) keep the connected one and despawn the rest, so a client's
+/// messages and state can't be split across entities
+fn find_players(query: &ClientQuery, commands: &mut Commands) -> HashMap<SocketAddr, Entity> {
+    let mut players: HashMap<SocketAddr, (Entity, bool)> = HashMap::new();
+
+    for (entity, addr, connected, _, _, _) in query.iter() {
+        let connected = connected.is_some();
+        match players.get(&addr.addr).copied() {
+            None => {
+                players.insert(addr.addr, (entity, connected));
+            }
+            Some((kept, kept_connected)) => {
+                // prefer the connected one, it has the live state
+                let duplicate = if connected && !kept_connected {
+                    players.insert(addr.addr, (entity, connected));
+                    kept
+                } else {
+                    entity
+                };
+                warn!("duplicate player entity for {}, removing it", addr);
+                commands.entity(duplicate).despawn_recursive();
+            }
+        }
     }
+
+    players
+        .into_iter()
+        .map(|(addr, (entity, _))| (addr, entity))
+        .collect()
 }
 
 /// Process a client's message and push new bodies to the next packet sent to the client
 /// Uses client message info to overwrite player input components
+/// Observers have no input or name, so their player-only bodies are ignored (and vice versa)
 fn process_client_message(
     addr: &SocketAddr,
     client: &mut ConnectedClientInfo,
     message: ClientToServer,
-    input: &mut PlayerInput,
+    mut input: Option<&mut PlayerInput>,
+    mut name: Option<&mut PlayerName>,
+    mut observer: Option<&mut Observer>,
 ) {
     // TODO: just impl Display or Debug instead
     let mut bodies_str = "".to_string();
@@ -484,6 +1085,13 @@ fn process_client_message(
         bodies_str.push_str(match body {
             ClientBodyElem::Ping => "ping,",
             ClientBodyElem::Input(_) => "input,",
+            ClientBodyElem::Disconnect => "disconnect,",
+            ClientBodyElem::AdminFill { .. } => "admin_fill,",
+            ClientBodyElem::AdminTeleportToOre(_) => "admin_teleport,",
+            ClientBodyElem::AdminResendWorld => "admin_resend_world,",
+            ClientBodyElem::AdminTogglePause => "admin_toggle_pause,",
+            ClientBodyElem::SetName(_) => "set_name,",
+            ClientBodyElem::ObserverView(_) => "observer_view,",
         });
     }
     // info!(
@@ -518,18 +1126,32 @@ fn process_client_message(
                             // delete single block
 
                             // find chunk
-                            for mut chunk in &mut client.last_confirmed_terrain.chunks {
-                                if chunk.chunk_number == delete.chunk_number {
-                                    // delete the block
-                                    chunk.blocks[delete.y][delete.x] = None;
-                                }
+                            if let Some(chunk) = client
+                                .last_confirmed_terrain
+                                .get_chunk_mut(delete.chunk_number)
+                            {
+                                // delete the block
+                                chunk.set(delete.x, delete.y, None);
+                            }
+                        }
+                        WorldDelta::BlockPlace {
+                            chunk_number,
+                            x,
+                            y,
+                            block_type,
+                        } => {
+                            if let Some(chunk) =
+                                client.last_confirmed_terrain.get_chunk_mut(*chunk_number)
+                            {
+                                chunk.set(*x, *y, Some(Block::new(*block_type)));
                             }
                         }
                     }
                 }
             }
             None => {
-                error!(
+                // expected if the changes were pruned, a new baseline is on its way
+                warn!(
                     "client ack'd a message that doesn't have a stored changelist?: {}",
                     client.last_ack
                 );
@@ -556,7 +1178,7 @@ fn process_client_message(
         .filter_map(|elem| match elem {
             ClientBodyElem::Ping => Some(ServerBodyElem::Pong(message.header.current_sequence)),
             ClientBodyElem::Input(new_input) => {
-                if in_order {
+                if let (true, Some(input)) = (in_order, input.as_deref_mut()) {
                     // info!("server got inputs for client {}", addr);
                     // add inputs to player entity's input component
                     *input = new_input.clone();
@@ -565,6 +1187,44 @@ fn process_client_message(
                 // never respond directly to input bodies
                 None
             }
+            ClientBodyElem::Disconnect => {
+                info!("client {} disconnected", addr);
+                // drop them on the next network tick
+                client.until_drop = 0;
+                None
+            }
+            ClientBodyElem::SetName(raw) => {
+                let name = name.as_deref_mut()?;
+                let new_name = PlayerName::sanitized(raw);
+                if *name != new_name {
+                    info!(
+                        "{} is now known as {}",
+                        name.or_address(&ClientAddress { addr: *addr }),
+                        new_name.or_address(&ClientAddress { addr: *addr })
+                    );
+                    *name = new_name;
+                }
+
+                // echoed back in PlayerInfo
+                None
+            }
+            ClientBodyElem::AdminFill { .. }
+            | ClientBodyElem::AdminTeleportToOre(_)
+            | ClientBodyElem::AdminResendWorld
+            | ClientBodyElem::AdminTogglePause => {
+                // apply later, once we have access to the terrain
+                // observers can't use admin commands, they have no player to apply them to
+                if in_order && observer.is_none() {
+                    client.admin_requests.push(elem.clone());
+                }
+                None
+            }
+            ClientBodyElem::ObserverView(view) => {
+                if let (true, Some(observer)) = (in_order, observer.as_deref_mut()) {
+                    observer.view = view.clone();
+                }
+                None
+            }
         })
         .collect();
 
@@ -587,25 +1247,97 @@ fn process_client_message(
     });
 }
 
+/// Bodies with a lower priority get dropped first when a message is too large
+fn body_priority(body: &ServerBodyElem) -> u8 {
+    match body {
+        // only used for debugging
+        ServerBodyElem::Pong(_) => 0,
+        // recomputed every tick, so dropping one just delays it
+        ServerBodyElem::WorldDeltas(_) => 1,
+        ServerBodyElem::MiningProgress(_) => 1,
+        ServerBodyElem::Inventory(_) => 2,
+        ServerBodyElem::Stats(_) => 2,
+        ServerBodyElem::PlayerInfo(_) => 3,
+        // tiny, and the client shows it
+        ServerBodyElem::Paused(_) => 3,
+        ServerBodyElem::Reach(_) => 3,
+        // the last thing the client will hear from us
+        ServerBodyElem::ServerShutdown => 4,
+    }
+}
+
+/// Drop the lowest priority bodies from the message until it has at most max_bodies bodies
+/// and encodes to at most max_size bytes
+/// Returns the bodies that were dropped
+fn trim_to_fit(
+    message: &mut ServerToClient,
+    max_size: usize,
+    max_bodies: usize,
+) -> Vec<ServerBodyElem> {
+    let mut dropped = Vec::new();
+
+    while !message.bodies.is_empty() {
+        // no need to check the size while there are too many bodies anyway
+        if message.bodies.len() <= max_bodies {
+            match encoded_size(message) {
+                Ok(size) if size <= max_size => break,
+                Ok(_) => {}
+                Err(e) => {
+                    error!("unable to size message: {}", e);
+                    break;
+                }
+            }
+        }
+
+        // unwrap OK since bodies isn't empty
+        let (index, _) = message
+            .bodies
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, body)| body_priority(body))
+            .unwrap();
+        dropped.push(message.bodies.remove(index));
+    }
+
+    dropped
+}
+
 fn send_all_messages(
     mut server: ResMut<Server>,
     mut query: Query<(&ClientAddress, &mut ConnectedClientInfo)>,
+    args: Res<ServerArgs>,
 ) {
     // loop over clients
-    for (client_addr, client_info) in query.iter_mut() {
-        let message = ServerToClient {
+    for (client_addr, mut client_info) in query.iter_mut() {
+        let mut message = ServerToClient {
             header: ServerHeader {
                 sequence: server.sequence,
             },
             bodies: client_info.bodies.clone(),
         };
 
-        // form message via borrow before consuming it
-        let success_msg = format!("server sent message to {:?}", client_addr);
-        match server.send_message(client_addr.addr, message) {
-            Ok(_) => {
-                // info!("{}", success_msg),
+        let max_size = max_message_size(args.max_packet_size);
+        let dropped = trim_to_fit(&mut message, max_size, args.max_bodies);
+        if !dropped.is_empty() {
+            warn!(
+                "message to {} was over {} bytes or {} bodies, dropped {} bodies",
+                client_addr,
+                max_size,
+                args.max_bodies,
+                dropped.len()
+            );
+
+            // the client will never get these deltas, so don't apply them when it acks this packet
+            if dropped
+                .iter()
+                .any(|b| matches!(b, ServerBodyElem::WorldDeltas(_)))
+            {
+                client_info.deltas.insert(server.sequence, Vec::new());
             }
+        }
+
+        match server.send_message(client_addr.addr, message, args.max_packet_size) {
+            Ok(_) => trace!("server sent message to {}", client_addr),
             Err(e) => error!("server unable to send message: {:?}", e),
         }
     }
@@ -625,104 +1357,151 @@ fn send_all_messages(
 fn enqueue_terrain(
     terrain: Res<Terrain>,
     server: Res<Server>,
-    mut clients: Query<(&ClientAddress, &mut ConnectedClientInfo, &PlayerPosition)>,
+    ready: Res<WorldReady>,
+    mut clients: Query<(
+        &mut ConnectedClientInfo,
+        Option<&PlayerPosition>,
+        Option<&Observer>,
+    )>,
 ) {
-    for (addr, mut client, player_position) in clients.iter_mut() {
-        // the number of the chunk that the player is in
-        let player_chunk = -(player_position.y) as usize / CHUNK_HEIGHT as usize;
-        let chunk_range = if player_chunk == 0 {
-            0..=1
-        } else {
-            (player_chunk - 1)..=(player_chunk + 1)
+    // don't send a half generated or loaded world
+    if !ready.0 {
+        return;
+    }
+
+    for (mut client, player_position, observer) in clients.iter_mut() {
+        // observers get the terrain around their camera instead
+        let player_position = match (player_position, observer) {
+            (Some(position), _) => position,
+            (None, Some(observer)) => &observer.view,
+            (None, None) => continue,
         };
 
-        // info!("enqueuing partial terrain {:?} to {}", chunk_range, addr);
+        // don't hold on to deltas forever for clients that never ack
+        client.prune_deltas(server.sequence);
+
+        let world_changes = compute_world_changes(&terrain, &client, player_position);
+
+        // send client these deltas
+        client
+            .bodies
+            .push(ServerBodyElem::WorldDeltas(world_changes.clone()));
+
+        // keep track of what we've sent so we can update their baseline when they respond
+        client.deltas.insert(server.sequence, world_changes);
+    }
+}
+
+/// Work out what a client needs to get from its last confirmed terrain to ours
+/// This is either a new baseline (if it is missing chunks around the player) or block deletions
+/// and placements
+/// Deltas only leave the diff once the client acks a packet holding them, so anything in a lost
+/// packet is resent every tick, until DELTA_TTL runs out and the client gets a baseline instead
+fn compute_world_changes(
+    terrain: &Terrain,
+    client: &ConnectedClientInfo,
+    player_position: &PlayerPosition,
+) -> Vec<WorldDelta> {
+    // the number of the chunk that the player is in
+    let player_chunk = -(player_position.y) as usize / CHUNK_HEIGHT as usize;
+    let chunk_range = if player_chunk == 0 {
+        0..=1
+    } else {
+        (player_chunk - 1)..=player_chunk.saturating_add(1)
+    };
+
+    // info!("enqueuing partial terrain {:?} to {}", chunk_range, addr);
 
-        // chunks that the client has
-        let client_chunks: Vec<u64> = client
+    // check if the client doesn't have a chunk that it should
+    // if it is missing a chunk, it needs a new baseline
+    let needs_baseline = chunk_range.clone().any(|chunk_num| {
+        client
             .last_confirmed_terrain
-            .chunks
-            .iter()
-            .map(|c| c.chunk_number)
-            .collect();
+            .get_chunk(chunk_num as u64)
+            .is_none()
+    });
+
+    let mut world_changes = Vec::new();
 
-        // check if the client doesn't have a chunk that it should
-        let mut needs_baseline = false;
-        for chunk_num in chunk_range.clone() {
-            // check if client is missing this chunk number
-            let mut filter = client_chunks.iter().filter(|c| **c == chunk_num as u64);
-            if filter.next().is_none() {
-                // if it is missing a chunk, it needs a new baseline
-                needs_baseline = true;
+    if needs_baseline {
+        // resend the entire baseline!
+        // the terrain we will send them
+        let mut baseline = Terrain::empty();
+        // clone in only specified chunks, skipping any that haven't been generated yet
+        for chunk_number in chunk_range {
+            if let Some(chunk) = terrain.get_chunk(chunk_number as u64) {
+                baseline.insert_chunk(chunk.clone())
             }
         }
 
-        let mut world_changes = Vec::new();
-
-        if needs_baseline {
-            // resend the entire baseline!
-            // the terrain we will send them
-            let mut baseline = Terrain::empty();
-            // clone in only specified chunks
-            for chunk_number in chunk_range {
-                baseline.chunks.push(terrain.chunks[chunk_number].clone())
-            }
+        // push it
+        world_changes.push(WorldDelta::NewChunks(baseline));
+    } else {
+        // just calcluate the block deletions and placements
+        for client_chunk in client.last_confirmed_terrain.sorted_chunks() {
+            let chunk_num = client_chunk.chunk_number;
+
+            let server_chunk = terrain.get_chunk(chunk_num);
+            match server_chunk {
+                Some(server_chunk) => {
+                    // loop over blocks in chunk
+                    for y in 0..CHUNK_HEIGHT {
+                        for x in 0..CHUNK_WIDTH {
+                            // if the client chunk has a block here but server doesn't
+                            if client_chunk.blocks[y][x].is_some()
+                                && server_chunk.blocks[y][x].is_none()
+                            {
+                                // create delta (deletion)
+                                let block_deletion = BlockDelete {
+                                    chunk_number: chunk_num,
+                                    x,
+                                    y,
+                                };
+                                // push it to the client
+                                world_changes.push(WorldDelta::BlockDelete(block_deletion));
+                            }
 
-            // push it
-            world_changes.push(WorldDelta::NewChunks(baseline));
-        } else {
-            // just calcluate the block deletions
-            for client_chunk in &mut client.last_confirmed_terrain.chunks {
-                let chunk_num = client_chunk.chunk_number;
-
-                // server chunks are always at their correct index
-                let server_chunk = terrain.chunks.get(chunk_num as usize);
-                match server_chunk {
-                    Some(server_chunk) => {
-                        // loop over blocks in chunk
-                        for y in 0..CHUNK_HEIGHT {
-                            for x in 0..CHUNK_WIDTH {
-                                // if the client chunk has a block here but server doesn't
-                                if client_chunk.blocks[y][x].is_some()
-                                    && server_chunk.blocks[y][x].is_none()
-                                {
-                                    // create delta (deletion)
-                                    let block_deletion = BlockDelete {
+                            // if the server has a block here that the client doesn't (or not this one)
+                            if let Some(block) = server_chunk.blocks[y][x] {
+                                let client_type = client_chunk.blocks[y][x].map(|b| b.block_type);
+                                if client_type != Some(block.block_type) {
+                                    world_changes.push(WorldDelta::BlockPlace {
                                         chunk_number: chunk_num,
                                         x,
                                         y,
-                                    };
-                                    // push it to the client
-                                    world_changes.push(WorldDelta::BlockDelete(block_deletion));
+                                        block_type: block.block_type,
+                                    });
                                 }
                             }
                         }
                     }
-                    None => {
-                        error!(
-                            "client somehow has chunk that server doesn't have: {}",
-                            chunk_num
-                        );
-                    }
+                }
+                None => {
+                    // the server unloaded it since the client got it
+                    debug!("client has chunk that server doesn't have: {}", chunk_num);
                 }
             }
         }
-
-        // send client these deltas
-        client
-            .bodies
-            .push(ServerBodyElem::WorldDeltas(world_changes.clone()));
-
-        // keep track of what we've sent so we can update their baseline when they respond
-        client.deltas.insert(server.sequence, world_changes);
     }
+
+    world_changes
 }
 
 /// Enqueues all player information to each client
 fn enqueue_player_info(
     // With<> for connected players only
-    info: Query<(&ClientAddress, &PlayerPosition), With<ConnectedClientInfo>>,
+    info: Query<
+        (
+            &ClientAddress,
+            &PlayerPosition,
+            &JumpState,
+            &PlayerName,
+            &PlayerInput,
+        ),
+        With<ConnectedClientInfo>,
+    >,
     mut clients: Query<(&ClientAddress, &mut ConnectedClientInfo)>,
+    args: Res<ServerArgs>,
 ) {
     // for each connected client
     for (target_client_addr, mut target_client) in clients.iter_mut() {
@@ -730,10 +1509,13 @@ fn enqueue_player_info(
         let mut players = Vec::new();
 
         // loop over every connected player info
-        for (addr, pos) in info.iter() {
+        for (addr, pos, jump_state, name, input) in info.iter() {
             let player_info = SingleNetPlayerInfo {
                 addr: addr.clone(),
                 position: pos.clone(),
+                grounded: jump_state.is_grounded(),
+                name: name.clone(),
+                target: args.share_targets.then_some((input.block_x, input.block_y)),
             };
 
             if addr.addr == target_client_addr.addr {
@@ -754,6 +1536,20 @@ fn enqueue_player_info(
     }
 }
 
+/// Tell clients whether the simulation is paused, so they can show it
+fn enqueue_pause_state(paused: Res<SimPaused>, mut clients: Query<&mut ConnectedClientInfo>) {
+    for mut client in clients.iter_mut() {
+        client.bodies.push(ServerBodyElem::Paused(paused.0));
+    }
+}
+
+/// Tell clients how far players can reach, so they can show which blocks are in reach
+fn enqueue_reach(args: Res<ServerArgs>, mut clients: Query<&mut ConnectedClientInfo>) {
+    for mut client in clients.iter_mut() {
+        client.bodies.push(ServerBodyElem::Reach(args.reach));
+    }
+}
+
 /// Enqueue player inventory info to each client
 fn enqueue_inventory(mut clients: Query<(&mut ConnectedClientInfo, &Inventory)>) {
     for (mut client, inv) in clients.iter_mut() {
@@ -761,21 +1557,61 @@ fn enqueue_inventory(mut clients: Query<(&mut ConnectedClientInfo, &Inventory)>)
     }
 }
 
+/// Enqueue how far each player is through mining their target block
+fn enqueue_mining_progress(
+    mut clients: Query<(&mut ConnectedClientInfo, &PlayerInput)>,
+    progress: Res<MiningProgress>,
+    terrain: Res<Terrain>,
+) {
+    for (mut client, input) in clients.iter_mut() {
+        let target = (input.block_x, input.block_y);
+        let fraction = match terrain.get_block(target.0, target.1) {
+            Some(block) if input.mine => progress.fraction(target, block.block_type),
+            _ => 0.,
+        };
+        client.bodies.push(ServerBodyElem::MiningProgress(fraction));
+    }
+}
+
+/// Enqueue player statistics to each client
+fn enqueue_stats(mut clients: Query<(&mut ConnectedClientInfo, &PlayerStats)>) {
+    for (mut client, stats) in clients.iter_mut() {
+        client.bodies.push(ServerBodyElem::Stats(stats.clone()));
+    }
+}
+
+/// Add a second of play time to every connected player
+fn count_play_time(mut players: Query<&mut PlayerStats, With<ConnectedClientInfo>>) {
+    for mut stats in players.iter_mut() {
+        stats.play_time_secs += 1;
+    }
+}
+
 /// drop clients (remove ConnectedClientInfo) that haven't responded in a while
+/// observers have nothing worth keeping, so they are despawned instead
 fn drop_disconnected_clients(
-    mut clients: Query<(Entity, &ClientAddress, &mut ConnectedClientInfo)>,
+    mut clients: Query<(
+        Entity,
+        &ClientAddress,
+        &mut ConnectedClientInfo,
+        Option<&Observer>,
+    )>,
     mut commands: Commands,
 ) {
-    for (entity, addr, mut client) in clients.iter_mut() {
+    for (entity, addr, mut client, observer) in clients.iter_mut() {
         // if we need to drop them
-        if client.until_drop == 0 {
+        if client.until_drop == 0 && observer.is_some() {
+            warn!("dropping observer {}", addr);
+            commands.entity(entity).despawn_recursive();
+        } else if client.until_drop == 0 {
             warn!("dropping client {}", addr);
             // remove all connected-only components
             commands
                 .entity(entity)
                 .remove::<ConnectedClientInfo>()
                 .remove::<JumpState>()
-                .remove::<JumpDuration>();
+                .remove::<JumpDuration>()
+                .remove::<Velocity>();
         } else {
             // in else so we never underflow
             client.until_drop -= 1;
@@ -795,3 +1631,904 @@ fn debug_print_players(query: Query<(Entity, &ClientAddress, Option<&ConnectedCl
         );
     }
 }
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::BlockType;
+    use clap::Parser;
+
+    #[test]
+    fn cleared_terrain_gets_baseline() {
+        let terrain = Terrain::new(2);
+        let mut client = ConnectedClientInfo {
+            last_confirmed_terrain: terrain.clone(),
+            ..default()
+        };
+        let position = PlayerPosition::default();
+
+        // in sync, nothing to send
+        let changes = compute_world_changes(&terrain, &client, &position);
+        assert!(changes.is_empty());
+
+        // what AdminResendWorld does
+        client.last_confirmed_terrain = Terrain::empty();
+
+        let changes = compute_world_changes(&terrain, &client, &position);
+        assert_eq!(changes.len(), 1);
+        match &changes[0] {
+            WorldDelta::NewChunks(baseline) => assert_eq!(baseline.chunks.len(), 2),
+            WorldDelta::BlockDelete(_) => panic!("expected a baseline, got a block delete"),
+            WorldDelta::BlockPlace { .. } => panic!("expected a baseline, got a block place"),
+        }
+    }
+
+    #[test]
+    fn admin_fills_are_sent_as_deltas() {
+        let mut world = World::new();
+        let mut terrain = Terrain::new(2);
+        terrain.get_chunk_mut(0).unwrap().set(5, 5, None);
+        world.insert_resource(terrain.clone());
+        world.insert_resource(server_args(&["--admin", "127.0.0.1"]));
+        world.insert_resource(SimPaused(false));
+
+        let admin = world
+            .spawn()
+            .insert(ClientAddress {
+                addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            })
+            .insert(ConnectedClientInfo {
+                last_confirmed_terrain: terrain,
+                admin_requests: vec![ClientBodyElem::AdminFill {
+                    x1: 5,
+                    y1: 5,
+                    x2: 5,
+                    y2: 5,
+                    block: Some(BlockType::Water),
+                }],
+                ..default()
+            })
+            .insert(PlayerPosition::default())
+            .id();
+
+        SystemStage::single_threaded()
+            .with_system(process_admin_requests)
+            .run(&mut world);
+
+        // no new baseline, just the one block
+        let changes = compute_world_changes(
+            world.resource::<Terrain>(),
+            world.get::<ConnectedClientInfo>(admin).unwrap(),
+            &PlayerPosition::default(),
+        );
+        assert!(matches!(
+            changes.as_slice(),
+            [WorldDelta::BlockPlace {
+                chunk_number: 0,
+                x: 5,
+                y: 5,
+                block_type: BlockType::Water,
+            }]
+        ));
+    }
+
+    #[test]
+    fn placed_blocks_are_sent_as_deltas() {
+        let mut world = World::new();
+        let mut terrain = Terrain::new(2);
+        terrain.get_chunk_mut(0).unwrap().set(5, 5, None);
+        terrain.get_chunk_mut(0).unwrap().set(6, 5, None);
+        let client = ConnectedClientInfo {
+            last_confirmed_terrain: terrain.clone(),
+            ..default()
+        };
+        world.insert_resource(terrain);
+        world.insert_resource(server_args(&[]));
+
+        let mut inventory = Inventory::default();
+        inventory.amounts.insert(BlockType::Coal, 2);
+        let placer = |world: &mut World, x, position| {
+            world
+                .spawn()
+                .insert(ClientAddress {
+                    addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+                })
+                .insert(PlayerInput {
+                    place: true,
+                    place_block_type: BlockType::Coal,
+                    block_x: x,
+                    block_y: 5,
+                    ..default()
+                })
+                .insert(inventory.clone())
+                .insert(PlayerStats::default())
+                .insert(ConnectedClientInfo::default())
+                .insert(position)
+                .id()
+        };
+        let player = placer(&mut world, 5, PlayerPosition { x: 5., y: -7. });
+        // can't place a block on top of yourself
+        let blocked = placer(&mut world, 6, PlayerPosition { x: 6.2, y: -5.5 });
+
+        SystemStage::single_threaded()
+            .with_system(process_player_placement)
+            .run(&mut world);
+
+        let amounts = &world.get::<Inventory>(player).unwrap().amounts;
+        assert_eq!(amounts[&BlockType::Coal], 1);
+        // only blocks that were really placed count
+        assert_eq!(world.get::<PlayerStats>(player).unwrap().blocks_placed, 1);
+        assert_eq!(world.get::<PlayerStats>(blocked).unwrap().blocks_placed, 0);
+
+        let changes = compute_world_changes(
+            world.resource::<Terrain>(),
+            &client,
+            &PlayerPosition::default(),
+        );
+        assert!(matches!(
+            changes.as_slice(),
+            [WorldDelta::BlockPlace {
+                chunk_number: 0,
+                x: 5,
+                y: 5,
+                block_type: BlockType::Coal,
+            }]
+        ));
+    }
+
+    #[test]
+    fn dropped_deltas_are_resent_until_acked() {
+        let mut world = World::new();
+        let mut terrain = Terrain::new(2);
+        terrain.set_block(5, 5, Some(Block::new(BlockType::Limestone)));
+        // what the client is showing
+        let mut client_terrain = terrain.clone();
+        let player = world
+            .spawn()
+            .insert(PlayerPosition::default())
+            .insert(ConnectedClientInfo {
+                last_confirmed_terrain: terrain.clone(),
+                ..default()
+            })
+            .id();
+        terrain.set_block(5, 5, None);
+        world.insert_resource(terrain);
+        world.insert_resource(WorldReady(true));
+        world.insert_resource(Server {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            sequence: 1,
+            buffer: [0; BUFFER_SIZE],
+            next_message_id: 0,
+            rate_limiter: RateLimiter::default(),
+        });
+        let mut stage = SystemStage::single_threaded().with_system(enqueue_terrain);
+        // enqueue a tick's terrain, and take what would have been sent
+        let mut send = |world: &mut World| {
+            stage.run(world);
+            world.resource_mut::<Server>().sequence += 1;
+            let mut client = world.get_mut::<ConnectedClientInfo>(player).unwrap();
+            let deltas: Vec<WorldDelta> = std::mem::take(&mut client.bodies)
+                .into_iter()
+                .flat_map(|body| match body {
+                    ServerBodyElem::WorldDeltas(deltas) => deltas,
+                    _ => Vec::new(),
+                })
+                .collect();
+            deltas
+        };
+        let is_delete = |deltas: &[WorldDelta]| {
+            matches!(
+                deltas,
+                [WorldDelta::BlockDelete(BlockDelete {
+                    chunk_number: 0,
+                    x: 5,
+                    y: 5
+                })]
+            )
+        };
+
+        // the first packet is lost, so the deletion goes out again
+        assert!(is_delete(&send(&mut world)));
+        let resent = send(&mut world);
+        assert!(is_delete(&resent));
+
+        // this one arrives and is acked
+        client_terrain.set_block(5, 5, None);
+        let ack = ClientToServer {
+            header: ClientHeader {
+                current_sequence: 1,
+                last_received_sequence: 2,
+                observer: false,
+            },
+            bodies: Vec::new(),
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let mut client = world.get_mut::<ConnectedClientInfo>(player).unwrap();
+        process_client_message(&addr, &mut client, ack, None, None, None);
+        assert!(client.deltas.is_empty());
+        assert_eq!(client.last_confirmed_terrain, client_terrain);
+
+        // nothing left to resend, and the block stays deleted
+        assert!(send(&mut world).is_empty());
+        assert!(client_terrain.get_block(5, 5).is_none());
+    }
+
+    #[test]
+    fn no_terrain_until_world_ready() {
+        let mut world = World::new();
+        world.insert_resource(Terrain::new(2));
+        world.insert_resource(WorldReady(false));
+        world.insert_resource(Server {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            sequence: 1,
+            buffer: [0; BUFFER_SIZE],
+            next_message_id: 0,
+            rate_limiter: RateLimiter::default(),
+        });
+        let player = world
+            .spawn()
+            .insert(ClientAddress {
+                addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            })
+            .insert(ConnectedClientInfo::default())
+            .insert(PlayerPosition::default())
+            .id();
+
+        let mut stage = SystemStage::single_threaded().with_system(enqueue_terrain);
+        stage.run(&mut world);
+        let client = world.get::<ConnectedClientInfo>(player).unwrap();
+        assert!(client.bodies.is_empty());
+        assert!(client.deltas.is_empty());
+
+        world.insert_resource(WorldReady(true));
+        stage.run(&mut world);
+        let client = world.get::<ConnectedClientInfo>(player).unwrap();
+        assert_eq!(client.bodies.len(), 1);
+        assert_eq!(client.deltas.len(), 1);
+    }
+
+    /// ServerArgs as if given on the command line
+    fn server_args(args: &[&str]) -> ServerArgs {
+        let args = ["game", "server"].iter().chain(args);
+        match crate::args::GameArgs::try_parse_from(args).unwrap() {
+            crate::args::GameArgs::Server(args) => args,
+            crate::args::GameArgs::Client(_) => panic!("parsed server args as client args"),
+        }
+    }
+
+    #[test]
+    fn targets_are_shared_when_enabled() {
+        for share in [false, true] {
+            let mut world = World::new();
+            world.insert_resource(server_args(if share { &["--share-targets"] } else { &[] }));
+            for (port, target) in [(1000, (3, 4)), (1001, (50, 900))] {
+                world
+                    .spawn()
+                    .insert(ClientAddress {
+                        addr: SocketAddr::from(([127, 0, 0, 1], port)),
+                    })
+                    .insert(ConnectedClientInfo::default())
+                    .insert(PlayerPosition::default())
+                    .insert(JumpState::default())
+                    .insert(PlayerName::default())
+                    .insert(PlayerInput {
+                        block_x: target.0,
+                        block_y: target.1,
+                        ..default()
+                    });
+            }
+
+            let mut stage = SystemStage::single_threaded().with_system(enqueue_player_info);
+            stage.run(&mut world);
+
+            let mut query = world.query::<&ConnectedClientInfo>();
+            for client in query.iter(&world) {
+                let info = match &client.bodies[..] {
+                    [ServerBodyElem::PlayerInfo(info)] => info,
+                    other => panic!("expected player info, got {:?}", other),
+                };
+                assert_eq!(info.len(), 2);
+                for player in info {
+                    let expected = match player.addr.addr.port() {
+                        1000 => (3, 4),
+                        _ => (50, 900),
+                    };
+                    assert_eq!(player.target, share.then_some(expected));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn names_are_stored_and_echoed() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let mut world = World::new();
+        world.insert_resource(Terrain::new(1));
+        world.insert_resource(server_args(&[]));
+        world.insert_resource(Messages {
+            messages: VecDeque::from([(
+                addr,
+                ClientToServer {
+                    header: ClientHeader {
+                        current_sequence: 1,
+                        last_received_sequence: 0,
+                        observer: false,
+                    },
+                    bodies: vec![ClientBodyElem::SetName(String::from("  Bob!\n"))],
+                },
+            )]),
+        });
+
+        // new player joins with a name
+        let mut stage = SystemStage::single_threaded().with_system(handle_messages);
+        stage.run(&mut world);
+
+        let mut query = world.query::<(&ClientAddress, &PlayerName)>();
+        let (_, name) = query.single(&world);
+        assert_eq!(name.0, "Bob");
+
+        let mut stage = SystemStage::single_threaded().with_system(enqueue_player_info);
+        stage.run(&mut world);
+
+        let mut query = world.query::<&ConnectedClientInfo>();
+        match &query.single(&world).bodies[..] {
+            [ServerBodyElem::PlayerInfo(info)] => {
+                assert_eq!(info[0].name.0, "Bob");
+                assert_eq!(info[0].display_name(), "Bob");
+            }
+            other => panic!("expected player info, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stale_deltas_are_pruned() {
+        let mut client = ConnectedClientInfo {
+            last_confirmed_terrain: Terrain::new(2),
+            ..default()
+        };
+        for seq_num in 1..=DELTA_TTL {
+            client.deltas.insert(seq_num, Vec::new());
+        }
+
+        // everything is still young enough
+        client.prune_deltas(DELTA_TTL);
+        assert_eq!(client.deltas.len() as u64, DELTA_TTL);
+        assert_eq!(client.last_confirmed_terrain.chunks.len(), 2);
+
+        // the oldest ones expire, and the baseline goes with them
+        client.prune_deltas(DELTA_TTL + 5);
+        assert_eq!(client.deltas.len() as u64, DELTA_TTL - 5);
+        assert!(!client.deltas.contains_key(&5));
+        assert!(client.deltas.contains_key(&6));
+        assert!(client.last_confirmed_terrain.chunks.is_empty());
+    }
+
+    #[test]
+    fn oversized_message_is_trimmed() {
+        let player_info = ServerBodyElem::PlayerInfo(vec![SingleNetPlayerInfo {
+            addr: ClientAddress {
+                addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            },
+            position: PlayerPosition::default(),
+            grounded: false,
+            name: PlayerName::sanitized("Bob"),
+            target: None,
+        }]);
+        let mut message = ServerToClient {
+            header: ServerHeader { sequence: 1 },
+            bodies: vec![
+                ServerBodyElem::WorldDeltas(vec![WorldDelta::NewChunks(Terrain::new(2))]),
+                ServerBodyElem::Pong(1),
+                player_info,
+            ],
+        };
+        let max_size = 1000;
+        assert!(encoded_size(&message).unwrap() > max_size);
+
+        let dropped = trim_to_fit(&mut message, max_size, MAX_BODIES_PER_PACKET);
+
+        // pong goes first, then the terrain
+        assert!(matches!(dropped[0], ServerBodyElem::Pong(_)));
+        assert!(matches!(dropped[1], ServerBodyElem::WorldDeltas(_)));
+        assert_eq!(dropped.len(), 2);
+
+        // what's left fits
+        assert!(encoded_size(&message).unwrap() <= max_size);
+        assert!(matches!(
+            message.bodies[..],
+            [ServerBodyElem::PlayerInfo(_)]
+        ));
+    }
+
+    #[test]
+    fn too_many_bodies_are_dropped() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let mut incoming = ClientToServer {
+            header: ClientHeader {
+                current_sequence: 1,
+                last_received_sequence: 0,
+                observer: false,
+            },
+            bodies: vec![ClientBodyElem::Ping; 1000],
+        };
+        cap_bodies(&addr, &mut incoming, 32);
+        assert_eq!(incoming.bodies.len(), 32);
+
+        // few enough is left alone
+        cap_bodies(&addr, &mut incoming, 64);
+        assert_eq!(incoming.bodies.len(), 32);
+
+        // outgoing, the least important go first
+        let mut outgoing = ServerToClient {
+            header: ServerHeader { sequence: 1 },
+            bodies: vec![ServerBodyElem::Pong(1); 10],
+        };
+        outgoing
+            .bodies
+            .push(ServerBodyElem::Stats(PlayerStats::default()));
+        let dropped = trim_to_fit(&mut outgoing, MAX_PACKET_SIZE, 4);
+        assert_eq!(dropped.len(), 7);
+        assert_eq!(outgoing.bodies.len(), 4);
+        assert!(matches!(outgoing.bodies[3], ServerBodyElem::Stats(_)));
+    }
+
+    #[test]
+    fn mining_counts_blocks_mined() {
+        let mut world = World::new();
+        world.insert_resource(Terrain::new(2));
+        world.insert_resource(WorldSeed::default());
+        world.insert_resource(server_args(&[]));
+        world.init_resource::<MiningProgress>();
+
+        // find any block to mine
+        let terrain = world.resource::<Terrain>();
+        let (x, y) = (0..CHUNK_HEIGHT * 2)
+            .flat_map(|y| (0..CHUNK_WIDTH).map(move |x| (x, y)))
+            .find(|&(x, y)| {
+                terrain.chunks[&((y / CHUNK_HEIGHT) as u64)].blocks[y % CHUNK_HEIGHT][x].is_some()
+            })
+            .expect("terrain has no blocks");
+
+        let player = world
+            .spawn()
+            .insert(ClientAddress {
+                addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            })
+            .insert(PlayerInput {
+                mine: true,
+                block_x: x,
+                block_y: y,
+                ..default()
+            })
+            .insert(ConnectedClientInfo::default())
+            .insert(Inventory::default())
+            .insert(PlayerStats::default())
+            .insert(PlayerPosition {
+                x: x as f32,
+                y: -(y as f32) + 1.,
+            })
+            .id();
+
+        let mut stage = SystemStage::single_threaded().with_system(process_player_mining);
+        // the block breaks part way through, after which mining air doesn't count
+        for _ in 0..ticks_to_mine(BlockType::Gabbro) * 2 {
+            stage.run(&mut world);
+        }
+
+        let stats = world.get::<PlayerStats>(player).unwrap();
+        assert_eq!(stats.blocks_mined, 1);
+    }
+
+    /// Network ticks of mining it takes to break a block
+    fn ticks_to_mine(block_type: BlockType) -> usize {
+        (block_type.mine_duration() * NETWORK_TICK_HZ as f32).ceil() as usize
+    }
+
+    #[test]
+    fn blocks_take_time_to_mine() {
+        let mut world = World::new();
+        let mut terrain = Terrain::new(2);
+        terrain.set_block(5, 10, Some(Block::new(BlockType::Limestone)));
+        terrain.set_block(6, 10, Some(Block::new(BlockType::Limestone)));
+        world.insert_resource(terrain);
+        world.insert_resource(WorldSeed::default());
+        world.insert_resource(server_args(&[]));
+        world.init_resource::<MiningProgress>();
+
+        let player = world
+            .spawn()
+            .insert(ClientAddress {
+                addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            })
+            .insert(PlayerInput {
+                mine: true,
+                block_x: 5,
+                block_y: 10,
+                ..default()
+            })
+            .insert(ConnectedClientInfo::default())
+            .insert(Inventory::default())
+            .insert(PlayerStats::default())
+            .insert(PlayerPosition { x: 5., y: -9. })
+            .id();
+
+        let mut stage = SystemStage::single_threaded().with_system(process_player_mining);
+        for _ in 0..ticks_to_mine(BlockType::Limestone) - 1 {
+            stage.run(&mut world);
+        }
+        assert!(world.resource::<Terrain>().get_block(5, 10).is_some());
+        assert_eq!(world.get::<PlayerStats>(player).unwrap().blocks_mined, 0);
+
+        // switching blocks starts over
+        world.get_mut::<PlayerInput>(player).unwrap().block_x = 6;
+        stage.run(&mut world);
+        assert!(world.resource::<Terrain>().get_block(5, 10).is_some());
+        assert!(world.resource::<Terrain>().get_block(6, 10).is_some());
+
+        for _ in 0..ticks_to_mine(BlockType::Limestone) {
+            stage.run(&mut world);
+        }
+        assert!(world.resource::<Terrain>().get_block(6, 10).is_none());
+        assert_eq!(world.get::<PlayerStats>(player).unwrap().blocks_mined, 1);
+    }
+
+    #[test]
+    fn players_mining_together_share_progress() {
+        let mut world = World::new();
+        let mut terrain = Terrain::new(2);
+        terrain.set_block(5, 10, Some(Block::new(BlockType::Limestone)));
+        world.insert_resource(terrain);
+        world.insert_resource(WorldSeed::default());
+        world.insert_resource(server_args(&[]));
+        world.init_resource::<MiningProgress>();
+
+        let mut miner = |port, x| {
+            world
+                .spawn()
+                .insert(ClientAddress {
+                    addr: SocketAddr::from(([127, 0, 0, 1], port)),
+                })
+                .insert(PlayerInput {
+                    mine: true,
+                    block_x: 5,
+                    block_y: 10,
+                    ..default()
+                })
+                .insert(ConnectedClientInfo::default())
+                .insert(Inventory::default())
+                .insert(PlayerStats::default())
+                .insert(PlayerPosition { x, y: -9. })
+                .id()
+        };
+        let first = miner(1234, 4.);
+        let second = miner(5678, 6.);
+
+        // each mines for half as long as it takes one player
+        let mut stage = SystemStage::single_threaded().with_system(process_player_mining);
+        for _ in 0..(ticks_to_mine(BlockType::Limestone) + 1) / 2 {
+            stage.run(&mut world);
+        }
+
+        assert!(world.resource::<Terrain>().get_block(5, 10).is_none());
+        let mined = |player| world.get::<PlayerStats>(player).unwrap().blocks_mined;
+        assert_eq!(mined(first) + mined(second), 1);
+        assert!(world.resource::<MiningProgress>().ticks.is_empty());
+    }
+
+    #[test]
+    fn blocks_out_of_reach_are_not_mined() {
+        let mut world = World::new();
+        let mut terrain = Terrain::new(2);
+        terrain.set_block(5, 10, Some(Block::new(BlockType::Limestone)));
+        world.insert_resource(terrain);
+        world.insert_resource(WorldSeed::default());
+        world.insert_resource(server_args(&["--reach", "4"]));
+        world.init_resource::<MiningProgress>();
+
+        let player = world
+            .spawn()
+            .insert(ClientAddress {
+                addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            })
+            .insert(PlayerInput {
+                mine: true,
+                block_x: 5,
+                block_y: 10,
+                ..default()
+            })
+            .insert(ConnectedClientInfo::default())
+            .insert(Inventory::default())
+            .insert(PlayerStats::default())
+            .insert(PlayerPosition { x: 5., y: -5. })
+            .id();
+
+        SystemStage::single_threaded()
+            .with_system(process_player_mining)
+            .run(&mut world);
+
+        assert!(world.resource::<Terrain>().get_block(5, 10).is_some());
+        let stats = world.get::<PlayerStats>(player).unwrap();
+        assert_eq!(stats.blocks_mined, 0);
+    }
+
+    #[test]
+    fn one_player_entity_per_address() {
+        let mut world = World::new();
+        world.insert_resource(Terrain::new(2));
+        world.init_resource::<Messages>();
+        let timed_out = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let doubled = SocketAddr::from(([127, 0, 0, 1], 5678));
+        let player = |world: &mut World, addr| {
+            world
+                .spawn()
+                .insert(ClientAddress { addr })
+                .insert(PlayerInput::default())
+                .insert(PlayerName::default())
+                .id()
+        };
+
+        // a timed out player sends several messages in one frame
+        player(&mut world, timed_out);
+        // and an address that somehow already has two entities, one still connected
+        player(&mut world, doubled);
+        let live = player(&mut world, doubled);
+        world
+            .entity_mut(live)
+            .insert(ConnectedClientInfo::default());
+
+        let message = |sequence| ClientToServer {
+            header: ClientHeader {
+                current_sequence: sequence,
+                last_received_sequence: sequence,
+                observer: false,
+            },
+            bodies: vec![ClientBodyElem::Ping],
+        };
+        for addr in [timed_out, timed_out, doubled, timed_out] {
+            world
+                .resource_mut::<Messages>()
+                .messages
+                .push_back((addr, message(1)));
+        }
+
+        let mut stage = SystemStage::single_threaded().with_system(handle_messages);
+        stage.run(&mut world);
+        // again, now that the first frame's commands have landed
+        world
+            .resource_mut::<Messages>()
+            .messages
+            .push_back((timed_out, message(2)));
+        stage.run(&mut world);
+
+        // exactly one entity per address, and it is connected
+        let mut players = world.query::<(Entity, &ClientAddress, Option<&ConnectedClientInfo>)>();
+        let mut entities = |world: &World, addr| {
+            players
+                .iter(world)
+                .filter(|(_, a, _)| a.addr == addr)
+                .map(|(e, _, connected)| (e, connected.is_some()))
+                .collect::<Vec<_>>()
+        };
+        let reconnected = entities(&world, timed_out);
+        assert_eq!(reconnected.len(), 1);
+        assert!(reconnected[0].1);
+        // the connected duplicate is the one kept
+        assert_eq!(entities(&world, doubled), vec![(live, true)]);
+    }
+
+    #[test]
+    fn observer_gets_terrain_without_a_player() {
+        let mut world = World::new();
+        world.insert_resource(Terrain::new(2));
+        world.insert_resource(WorldReady(true));
+        world.insert_resource(Server {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            sequence: 1,
+            buffer: [0; BUFFER_SIZE],
+            next_message_id: 0,
+            rate_limiter: RateLimiter::default(),
+        });
+        world.init_resource::<Messages>();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1234));
+
+        // an observer trying to play anyway
+        let message = ClientToServer {
+            header: ClientHeader {
+                current_sequence: 1,
+                last_received_sequence: 1,
+                observer: true,
+            },
+            bodies: vec![
+                ClientBodyElem::Input(PlayerInput {
+                    mine: true,
+                    ..default()
+                }),
+                ClientBodyElem::SetName(String::from("Bob")),
+                ClientBodyElem::ObserverView(PlayerPosition { x: 5., y: -20. }),
+            ],
+        };
+        world
+            .resource_mut::<Messages>()
+            .messages
+            .push_back((addr, message));
+
+        SystemStage::single_threaded()
+            .with_system(handle_messages)
+            .run(&mut world);
+        SystemStage::single_threaded()
+            .with_system(enqueue_terrain)
+            .run(&mut world);
+
+        // no player, so nothing to move, mine with, or list
+        let mut players = world.query_filtered::<(), Or<(
+            With<PlayerPosition>,
+            With<PlayerInput>,
+            With<PlayerName>,
+            With<Inventory>,
+        )>>();
+        assert_eq!(players.iter(&world).count(), 0);
+
+        let (observer, client) = world
+            .query::<(&Observer, &ConnectedClientInfo)>()
+            .single(&world);
+        assert_eq!(observer.view.x, 5.);
+        assert!(client.admin_requests.is_empty());
+        assert!(client.bodies.iter().any(|body| matches!(
+            body,
+            ServerBodyElem::WorldDeltas(deltas)
+                if matches!(deltas.as_slice(), [WorldDelta::NewChunks(_)])
+        )));
+    }
+
+    #[test]
+    fn paused_sim_stops_movement() {
+        let mut world = World::new();
+        world.insert_resource(Terrain::new(2));
+        world.insert_resource(Time::default());
+        world.insert_resource(server_args(&[]));
+        world.insert_resource(SimPaused(true));
+
+        // in the air, so gravity would move them
+        let player = world
+            .spawn()
+            .insert(PlayerPosition { x: 5., y: 10. })
+            .insert(JumpDuration::default())
+            .insert(JumpState::default())
+            .insert(Velocity::default())
+            .insert(PlayerInput {
+                right: true,
+                ..default()
+            })
+            .insert(ConnectedClientInfo::default())
+            .id();
+        let mut stage =
+            SystemStage::single_threaded().with_system(handle_movement.run_if(sim_running));
+        let position = |world: &World| {
+            let p = world.get::<PlayerPosition>(player).unwrap();
+            (p.x, p.y)
+        };
+
+        for _ in 0..10 {
+            stage.run(&mut world);
+        }
+        assert_eq!(position(&world), (5., 10.));
+
+        world.resource_mut::<SimPaused>().0 = false;
+        stage.run(&mut world);
+        assert_ne!(position(&world), (5., 10.));
+    }
+
+    #[test]
+    fn connected_clients_condition() {
+        let mut world = World::new();
+        let mut condition = IntoSystem::into_system(has_connected_clients);
+        condition.initialize(&mut world);
+
+        // nobody at all
+        assert!(!condition.run((), &mut world));
+
+        // a player that has disconnected doesn't count
+        let player = world
+            .spawn()
+            .insert(ClientAddress {
+                addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            })
+            .id();
+        assert!(!condition.run((), &mut world));
+
+        world
+            .entity_mut(player)
+            .insert(ConnectedClientInfo::default());
+        assert!(condition.run((), &mut world));
+    }
+
+    #[test]
+    fn reach_is_sent_to_clients() {
+        let mut world = World::new();
+        world.insert_resource(server_args(&["--reach", "5"]));
+        let client = world.spawn().insert(ConnectedClientInfo::default()).id();
+        SystemStage::single_threaded()
+            .with_system(enqueue_reach)
+            .run(&mut world);
+
+        assert!(matches!(
+            world.get::<ConnectedClientInfo>(client).unwrap().bodies[..],
+            [ServerBodyElem::Reach(reach)] if reach == 5.
+        ));
+    }
+
+    #[test]
+    fn shutdown_is_sent_to_every_client() {
+        let mut world = World::new();
+        world.init_resource::<Events<AppExit>>();
+        let clients: Vec<Entity> = (0..3)
+            .map(|_| world.spawn().insert(ConnectedClientInfo::default()).id())
+            .collect();
+        let mut stage =
+            SystemStage::single_threaded().with_system(enqueue_shutdown.run_if(exit_requested));
+        let shutdowns = |world: &World| {
+            clients
+                .iter()
+                .filter(|c| {
+                    world
+                        .get::<ConnectedClientInfo>(**c)
+                        .unwrap()
+                        .bodies
+                        .iter()
+                        .any(|b| matches!(b, ServerBodyElem::ServerShutdown))
+                })
+                .count()
+        };
+
+        // still running
+        stage.run(&mut world);
+        assert_eq!(shutdowns(&world), 0);
+
+        world.send_event(AppExit);
+        stage.run(&mut world);
+        assert_eq!(shutdowns(&world), 3);
+    }
+
+    #[test]
+    fn rate_limiter_drops_excess() {
+        let mut limiter = RateLimiter::default();
+        let flooder = SocketAddr::from(([10, 0, 0, 1], 1234));
+        let normal = SocketAddr::from(([10, 0, 0, 2], 1234));
+        let start = Instant::now();
+
+        for _ in 0..RATE_LIMIT_PACKETS {
+            assert!(limiter.allow(flooder, start));
+        }
+        // over the limit
+        assert!(!limiter.allow(flooder, start));
+        assert!(!limiter.allow(flooder, start + RATE_LIMIT_WINDOW / 2));
+
+        // other addresses are unaffected
+        assert!(limiter.allow(normal, start));
+
+        // the next window starts fresh
+        assert!(limiter.allow(flooder, start + RATE_LIMIT_WINDOW));
+    }
+
+    #[test]
+    fn rate_limiter_warns_once_per_window() {
+        let mut limiter = RateLimiter::default();
+        let flooder = SocketAddr::from(([10, 0, 0, 1], 1234));
+        let start = Instant::now();
+
+        for _ in 0..=RATE_LIMIT_PACKETS {
+            limiter.allow(flooder, start);
+        }
+        assert!(limiter.warned.contains(&flooder));
+
+        // forgotten with the window, so flooding again is logged again
+        let next = start + RATE_LIMIT_WINDOW;
+        assert!(limiter.allow(flooder, next));
+        assert!(limiter.warned.is_empty());
+        for _ in 0..RATE_LIMIT_PACKETS {
+            limiter.allow(flooder, next);
+        }
+        assert!(limiter.warned.contains(&flooder));
+    }
+}
@@ -1,16 +1,12 @@
 use crate::world::BlockType;
-use bevy::{
-    prelude::*,
-    sprite::collide_aabb::{collide, Collision},
-    time::Stopwatch,
-};
+use bevy::{prelude::*, sprite::collide_aabb::collide};
 use iyes_loopless::prelude::*;
 use std::{cmp, collections::HashMap, time::Duration};
 use strum::IntoEnumIterator;
 
 use bincode::{Decode, Encode};
 
-use crate::network::ClientAddress;
+use crate::network::{ClientAddress, PlayerName, NETWORK_TICK_HZ};
 use crate::{
     states::client::GameState,
     world::{
@@ -24,13 +20,40 @@ const PLAYER_ASSET: &str = "Ferris.png";
 pub const PLAYER_AND_BLOCK_SIZE: f32 = 32.;
 const PLAYER_START_POS: PlayerPosition = PlayerPosition { x: 0., y: 0. };
 const PLAYER_SPEED: f32 = 20.;
+/// Blocks per second per second gained while walking, with MovementModel::Accelerated
+pub const PLAYER_ACCELERATION: f32 = 80.;
+/// Blocks per second per second lost once no direction is held, with MovementModel::Accelerated
+pub const PLAYER_FRICTION: f32 = 60.;
 const PLAYER_JUMP_DURATION: f32 = 0.3; //seconds
-const PLAYER_MINE_DURATION: f32 = 2.; //seconds
-const PLAYER_MINE_RADIUS: f32 = 3.; //number of blocks
+/// Seconds of mining the same block it takes to break it
+pub const PLAYER_MINE_DURATION: f32 = 2.;
+/// Default for how far (in blocks) from a player the server lets them mine or place
+pub const PLAYER_MINE_RADIUS: f32 = 3.;
 const GRAVITY: f32 = -10.0;
+/// Default size of the camera bounds box, see client::CameraBounds
 pub const CAMERA_BOUNDS_SIZE: [f32; 2] = [1000., 500.];
+/// Closest the camera can zoom in, as a camera scale
+const CAMERA_ZOOM_MIN: f32 = 0.5;
+/// Furthest the camera can zoom out, as a camera scale
+const CAMERA_ZOOM_MAX: f32 = 3.;
+/// How much one notch of the mouse wheel changes the camera scale
+const CAMERA_ZOOM_STEP: f32 = 0.1;
 const PLAYER_Z: f32 = 2.0;
+/// Between blocks and players
+const TARGET_MARKER_Z: f32 = 1.5;
+/// How see-through the marker on another player's target is
+const TARGET_MARKER_ALPHA: f32 = 0.35;
+/// Tint of the ghost of the selected block under our cursor when it can be placed there
+const GHOST_COLOR: Color = Color::rgba(1., 1., 1., 0.5);
+/// Tint of the ghost when the block can't go there, see PlayerPosition::can_place
+const GHOST_BLOCKED_COLOR: Color = Color::rgba(1., 0., 0., 0.5);
+/// In front of the cursor highlight
+const MINING_OVERLAY_Z: f32 = 1.6;
+/// Colour of the square that grows over a block as we mine it
+const MINING_OVERLAY_COLOR: Color = Color::rgba(0., 0., 0., 0.5);
 const INV_ICON_SIZE: f32 = 48.0;
+/// Tint of every hotbar cell but the selected one
+const HOTBAR_UNSELECTED_COLOR: Color = Color::rgba(1., 1., 1., 0.4);
 
 #[derive(Component, Default, Debug, Encode, Decode, Clone)]
 pub struct PlayerPosition {
@@ -38,6 +61,126 @@ pub struct PlayerPosition {
     pub y: f32,
 }
 
+/// How far above the surface (in blocks) a player may be before we consider the position broken
+const MAX_HEIGHT_ABOVE_SURFACE: f32 = CHUNK_HEIGHT as f32;
+
+impl PlayerPosition {
+    /// Whether the position is a real place in the world, i.e. not NaN, infinite,
+    /// past the side walls, far up in the sky, or below the generated terrain
+    pub fn is_valid(&self, terrain: &Terrain) -> bool {
+        // chunks above the bottom may be unloaded, so go by the deepest chunk's number
+        let chunks_deep = terrain.chunks.keys().map(|n| n + 1).max();
+        let depth = (chunks_deep.unwrap_or(0) as usize * CHUNK_HEIGHT) as f32;
+        self.x.is_finite()
+            && self.y.is_finite()
+            && (0.0..=(CHUNK_WIDTH - 1) as f32).contains(&self.x)
+            && self.y <= MAX_HEIGHT_ABOVE_SURFACE
+            && -self.y < depth
+    }
+
+    /// Whether the block at global (x, y) is within reach blocks of this position
+    pub fn can_reach(&self, x: usize, y: usize, reach: f32) -> bool {
+        let dx = x as f32 - self.x;
+        let dy = y as f32 + self.y;
+        dx * dx + dy * dy <= reach * reach
+    }
+
+    /// Whether a player here could place a block at global (x, y): within reach, into an empty
+    /// cell of a loaded chunk, and not overlapping any of players
+    /// The server checks this before placing, and the client to tint its placement ghost
+    pub fn can_place<'a>(
+        &self,
+        x: usize,
+        y: usize,
+        reach: f32,
+        terrain: &Terrain,
+        players: impl IntoIterator<Item = &'a PlayerPosition>,
+    ) -> bool {
+        let loaded = x < CHUNK_WIDTH && terrain.get_chunk((y / CHUNK_HEIGHT) as u64).is_some();
+        let overlaps_player = players
+            .into_iter()
+            .any(|p| (p.x - x as f32).abs() < 1. && (-p.y - y as f32).abs() < 1.);
+        self.can_reach(x, y, reach)
+            && loaded
+            && terrain.get_block(x, y).is_none()
+            && !overlaps_player
+    }
+}
+
+/// How left/right input turns into horizontal movement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MovementModel {
+    /// Full speed as soon as a direction is held, stops as soon as it is released
+    Classic,
+    /// Ramps up to full speed and coasts to a stop, rates in blocks per second per second
+    Accelerated { acceleration: f32, friction: f32 },
+}
+
+impl MovementModel {
+    /// Horizontal velocity after time_delta seconds of holding `direction` (-1, 0 or 1)
+    pub fn next_velocity(&self, velocity: f32, direction: f32, time_delta: f32) -> f32 {
+        match *self {
+            MovementModel::Classic => direction * PLAYER_SPEED,
+            MovementModel::Accelerated {
+                acceleration,
+                friction,
+            } => {
+                if direction != 0. {
+                    (velocity + direction * acceleration * time_delta)
+                        .clamp(-PLAYER_SPEED, PLAYER_SPEED)
+                } else {
+                    // slow down toward 0 without overshooting
+                    let slowed = velocity.abs() - friction * time_delta;
+                    velocity.signum() * slowed.max(0.)
+                }
+            }
+        }
+    }
+}
+
+/// What players run into where a chunk isn't loaded, e.g. one that was unloaded or hasn't
+/// arrived yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MissingChunks {
+    /// Nothing, players fall through
+    #[default]
+    Empty,
+    /// Solid ground, players stop at its edge
+    Solid,
+}
+
+/// Which way players fall, the same for everyone in a world
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum GravityDirection {
+    #[default]
+    Down,
+    Up,
+    Left,
+    Right,
+}
+
+impl GravityDirection {
+    /// Unit vector that players fall along
+    pub fn vector(&self) -> Vec2 {
+        match self {
+            GravityDirection::Down => Vec2::NEG_Y,
+            GravityDirection::Up => Vec2::Y,
+            GravityDirection::Left => Vec2::NEG_X,
+            GravityDirection::Right => Vec2::X,
+        }
+    }
+
+    /// Unit vector that holding right walks along, the other axis from gravity
+    /// Stays as on screen: right is right when falling up, and up when falling sideways
+    pub fn walk_axis(&self) -> Vec2 {
+        if self.vector().x == 0. {
+            Vec2::X
+        } else {
+            Vec2::Y
+        }
+    }
+}
+
 /// Contains all inputs that the client needs to tell the server
 #[derive(Component, Encode, Decode, Clone, Debug, Default)]
 pub struct PlayerInput {
@@ -47,14 +190,45 @@ pub struct PlayerInput {
     pub mine: bool, //true means the block at block_x, block_y was clicked on.
     pub block_x: usize,
     pub block_y: usize,
+    /// place a place_block_type block from the inventory at block_x, block_y
+    pub place: bool,
+    pub place_block_type: BlockType,
 }
 
 /// Represents the entire inventory for a player
-#[derive(Component, Debug, Encode, Decode, Clone)]
+#[derive(Component, Debug, Clone)]
 pub struct Inventory {
     pub amounts: HashMap<BlockType, usize>,
 }
 
+// encode sorted by block type so the same inventory always gives the same bytes
+// the layout is the same as an encoded HashMap
+impl Encode for Inventory {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        let mut amounts: Vec<(&BlockType, &usize)> = self.amounts.iter().collect();
+        amounts.sort();
+        bincode::Encode::encode(&amounts, encoder)?;
+        Ok(())
+    }
+}
+
+// block types added since the inventory was saved start at 0, like in a new inventory
+impl Decode for Inventory {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let amounts: HashMap<BlockType, usize> = bincode::Decode::decode(decoder)?;
+        let mut inventory = Inventory::default();
+        inventory.amounts.extend(amounts);
+        Ok(inventory)
+    }
+}
+
+bincode::impl_borrow_decode!(Inventory);
+
 impl Default for Inventory {
     fn default() -> Self {
         // start with 0 of every block
@@ -63,8 +237,25 @@ impl Default for Inventory {
     }
 }
 
+/// Whether a player is standing on something, worked out by the server when moving them
+/// TODO: use it to pick between standing and falling sprites once players are animated
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Grounded(pub bool);
+
+/// Lifetime statistics for a player, kept by the server and saved with the player
+#[derive(Component, Debug, Encode, Decode, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json-export", derive(serde::Serialize))]
+pub struct PlayerStats {
+    pub blocks_mined: u64,
+    pub blocks_placed: u64,
+    /// TODO: increment once players can die
+    pub deaths: u64,
+    /// Seconds spent connected to the server
+    pub play_time_secs: u64,
+}
+
 pub mod server {
-    use crate::network::server::ConnectedClientInfo;
+    use crate::{args::ServerArgs, network::server::ConnectedClientInfo, world};
 
     use super::*;
 
@@ -81,9 +272,25 @@ pub mod server {
         }
     }
 
-    #[derive(Component)]
-    struct MineDuration {
-        timer: Stopwatch,
+    /// Network ticks of mining each block being mined has had, by block coordinate; should be a
+    /// resource
+    /// Shared between players, so everyone mining the same block adds to the same progress
+    /// Counted in whole ticks, since adding up fractions of a second falls just short of a duration
+    #[derive(Default, Debug)]
+    pub struct MiningProgress {
+        pub ticks: HashMap<(usize, usize), u32>,
+    }
+
+    impl MiningProgress {
+        /// Seconds the block at target has been mined for
+        pub fn elapsed(&self, target: (usize, usize)) -> f32 {
+            self.ticks.get(&target).copied().unwrap_or_default() as f32 / NETWORK_TICK_HZ as f32
+        }
+
+        /// Fraction of the way to breaking the block at target, a block_type, from 0 to 1
+        pub fn fraction(&self, target: (usize, usize), block_type: world::BlockType) -> f32 {
+            (self.elapsed(target) / block_type.mine_duration()).min(1.)
+        }
     }
 
     #[derive(Eq, PartialEq)]
@@ -102,31 +309,35 @@ pub mod server {
     #[derive(Component, Default)]
     pub struct JumpState {
         state: PlayerJumpState,
+        /// Whether the last move ended standing on a block
+        grounded: bool,
     }
 
-    #[derive(Component, Debug)]
-    struct PlayerCollision {
-        any: bool,
-        top: Option<f32>,
-        right: Option<f32>,
-        bottom: Option<f32>,
-        left: Option<f32>,
-        inside: bool,
-    }
-
-    impl Default for PlayerCollision {
-        fn default() -> PlayerCollision {
-            PlayerCollision {
-                any: false,
-                top: None,
-                right: None,
-                bottom: None,
-                left: None,
-                inside: false,
+    impl JumpState {
+        pub fn is_grounded(&self) -> bool {
+            self.grounded
+        }
+
+        /// State for picking up a player's movement part way, knowing only whether they were
+        /// on the ground; a player in the air is treated as falling, so they can't start a jump
+        pub fn resumed(grounded: bool) -> Self {
+            Self {
+                state: if grounded {
+                    PlayerJumpState::NonJumping
+                } else {
+                    PlayerJumpState::Falling
+                },
+                grounded,
             }
         }
     }
 
+    /// Horizontal speed in blocks per second, only used by MovementModel::Accelerated
+    #[derive(Component, Default, Debug)]
+    pub struct Velocity {
+        pub x: f32,
+    }
+
     //Handles player movement, gravity, jumpstate
     pub fn handle_movement(
         mut query: Query<
@@ -134,135 +345,240 @@ pub mod server {
                 &mut PlayerPosition,
                 &mut JumpDuration,
                 &mut JumpState,
+                &mut Velocity,
                 &PlayerInput,
             ),
             With<ConnectedClientInfo>,
         >,
         _time: Res<Time>,
         terrain: Res<Terrain>,
+        args: Res<ServerArgs>,
     ) {
-        const DEBUG_COLLISIONS: bool = false;
-
         // timers don't work with iyes_loopless?
         // TODO: maybe make this system run _not_ on a fixed timestep and user a timer
         let time_delta = 1f32 / 60f32;
 
-        for (mut player_position, mut player_jump_timer, mut player_jump_state, input) in
-            query.iter_mut()
+        let model = args.movement_model();
+
+        for (
+            mut player_position,
+            mut player_jump_timer,
+            mut player_jump_state,
+            mut velocity,
+            input,
+        ) in query.iter_mut()
         {
-            player_jump_timer
-                .timer
-                .tick(Duration::from_secs_f32(time_delta));
+            sanitize_position(&mut player_position, &terrain);
+
+            move_player(
+                &mut player_position,
+                &mut player_jump_timer,
+                &mut player_jump_state,
+                &mut velocity,
+                input,
+                &terrain,
+                model,
+                args.gravity,
+                args.missing_chunks,
+                time_delta,
+            );
+        }
+    }
 
-            let mut x_diff = 0.;
-            let mut y_diff = 0.;
+    /// Puts players with a broken position (see PlayerPosition::is_valid) back at the spawn point
+    pub fn sanitize_position(player_position: &mut PlayerPosition, terrain: &Terrain) {
+        if !player_position.is_valid(terrain) {
+            let spawn = world::find_spawn_point(terrain).unwrap_or_default();
+            warn!(
+                "player at invalid position {:?}, moving to {:?}",
+                player_position, spawn
+            );
+            *player_position = spawn;
+        }
+    }
 
-            let prev_x = player_position.x;
-            let prev_y = player_position.y;
+    /// Pushes a player stuck inside solid blocks straight up until they are free,
+    /// e.g. when a save is loaded into terrain that has since changed
+    /// Returns whether the player was moved
+    pub fn unstick_position(player_position: &mut PlayerPosition, terrain: &Terrain) -> bool {
+        let start = player_position.clone();
+
+        // each step lands just above the highest block we overlap, so this always ends,
+        // at the latest once we're above the surface
+        loop {
+            // missing chunks are never solid here, or this would climb forever inside one
+            let blocks = get_collisions(player_position, terrain, MissingChunks::Empty, false);
+            if blocks.is_empty() {
+                break;
+            }
+            player_position.y = blocks.iter().map(|b| b.y).fold(f32::MIN, f32::max) + 1.;
+        }
 
-            // info!("movement calc, starting: ({}, {})", prev_x, prev_y);
+        let moved = player_position.y != start.y;
+        if moved {
+            warn!(
+                "player inside blocks at {:?}, moving up to {:?}",
+                start, player_position
+            );
+        }
+        moved
+    }
 
-            //Player moves left
-            if input.left {
-                x_diff -= PLAYER_SPEED * time_delta;
-            }
+    /// Moves a single player forward by time_delta seconds, then resolves collisions
+    #[allow(clippy::too_many_arguments)]
+    pub fn move_player(
+        player_position: &mut PlayerPosition,
+        player_jump_timer: &mut JumpDuration,
+        player_jump_state: &mut JumpState,
+        velocity: &mut Velocity,
+        input: &PlayerInput,
+        terrain: &Terrain,
+        model: MovementModel,
+        gravity: GravityDirection,
+        missing_chunks: MissingChunks,
+        time_delta: f32,
+    ) {
+        player_jump_timer
+            .timer
+            .tick(Duration::from_secs_f32(time_delta));
 
-            //Player moves right
-            if input.right {
-                x_diff += PLAYER_SPEED * time_delta;
-            }
+        // how far to move against gravity this tick
+        let mut rise = 0.;
 
-            //When space pressed, set player to jumping and start timer
-            if input.jump && player_jump_state.state == PlayerJumpState::NonJumping {
-                player_jump_timer.timer.reset();
-                player_jump_state.state = PlayerJumpState::Jumping;
-                // info!("player starting jump");
-            }
+        let prev_x = player_position.x;
+        let prev_y = player_position.y;
 
-            //Player jumps (increases in height) for PLAYER_JUMP_DURATION seconds
-            if !player_jump_timer.timer.finished()
-                && player_jump_state.state == PlayerJumpState::Jumping
-            {
-                y_diff += PLAYER_SPEED * time_delta;
-                // info!("player is jumping");
-            }
+        // info!("movement calc, starting: ({}, {})", prev_x, prev_y);
 
-            //sets jump state as player falling
-            if player_jump_state.state == PlayerJumpState::Jumping
-                && player_jump_timer.timer.finished()
-            {
-                player_jump_state.state = PlayerJumpState::Falling;
-                // info!("player is falling");
-            }
+        //Player moves left and/or right
+        let mut direction = 0.;
+        if input.left {
+            direction -= 1.;
+        }
+        if input.right {
+            direction += 1.;
+        }
+        velocity.x = model.next_velocity(velocity.x, direction, time_delta);
+        let walk_diff = gravity.walk_axis() * velocity.x * time_delta;
+
+        //When space pressed, set player to jumping and start timer
+        if input.jump && player_jump_state.state == PlayerJumpState::NonJumping {
+            player_jump_timer.timer.reset();
+            player_jump_state.state = PlayerJumpState::Jumping;
+            // info!("player starting jump");
+        }
 
-            // gravity already negative
-            y_diff += GRAVITY * time_delta;
+        //Releasing space early cuts the jump short for a smaller hop
+        if !input.jump && player_jump_state.state == PlayerJumpState::Jumping {
+            player_jump_state.state = PlayerJumpState::Falling;
+        }
 
-            // info!(
-            //     "moving player, time_delta:{:.5} x_diff:{:.2}, y_diff:{:.2}",
-            //     time_delta, x_diff, y_diff
-            // );
+        //Player jumps (moves away from gravity) while space is held, for up to PLAYER_JUMP_DURATION seconds
+        if !player_jump_timer.timer.finished()
+            && player_jump_state.state == PlayerJumpState::Jumping
+        {
+            rise += PLAYER_SPEED * time_delta;
+            // info!("player is jumping");
+        }
 
-            player_position.x += x_diff as f32;
-            player_position.y += y_diff as f32;
+        //sets jump state as player falling
+        if player_jump_state.state == PlayerJumpState::Jumping && player_jump_timer.timer.finished()
+        {
+            player_jump_state.state = PlayerJumpState::Falling;
+            // info!("player is falling");
+        }
 
-            // prevent going past horizontal world boundaries
-            player_position.x =
-                f32::min(f32::max(player_position.x, 0.0), (CHUNK_WIDTH - 1) as f32);
+        // GRAVITY is negative, i.e. the fall speed against the "up" direction
+        let fall_diff = gravity.vector() * (-GRAVITY * time_delta - rise);
 
-            loop {
-                let player_collision = get_collisions(&player_position, &terrain, DEBUG_COLLISIONS);
-                if !player_collision.any {
-                    break;
-                }
+        // info!(
+        //     "moving player, time_delta:{:.5} walk_diff:{:?}, fall_diff:{:?}",
+        //     time_delta, walk_diff, fall_diff
+        // );
 
-                // info!("There's a collision: {:?}", player_collision);
-                // Check for "inside" conditions that can occur and just reset in those scenarios
-                if (player_collision.left.is_some() && player_collision.right.is_some())
-                    || (player_collision.top.is_some() && player_collision.bottom.is_some())
-                    || player_collision.inside
-                {
-                    player_position.x = prev_x;
-                    player_position.y = prev_y;
-                    // info!("Inside collision");
-
-                    continue;
-                }
+        // move one axis at a time, so we always know which way to push out of blocks
+        // gravity first, so standing on something stops us before walking
+        player_jump_state.grounded = false;
+        if move_along_axis(player_position, fall_diff, terrain, missing_chunks)
+            && fall_diff.dot(gravity.vector()) > 0.
+        {
+            // landed on whatever is below us, in gravity's terms
+            player_jump_state.state = PlayerJumpState::NonJumping;
+            player_jump_state.grounded = true;
+            // info!("player hit ground");
+        }
 
-                if player_collision.left.is_some() {
-                    player_position.x = player_collision.left.unwrap();
-                    // info!("Left collision");
-                    continue;
-                } else if player_collision.right.is_some() {
-                    player_position.x = player_collision.right.unwrap();
-                    // info!("Right collision");]
-                    continue;
-                }
+        if move_along_axis(player_position, walk_diff, terrain, missing_chunks) {
+            velocity.x = 0.;
+        }
 
-                if player_collision.top.is_some() {
-                    player_position.y = player_collision.top.unwrap();
-                    // info!("Top collision");
-                    continue;
-                } else if player_collision.bottom.is_some() {
-                    player_position.y = player_collision.bottom.unwrap();
-                    // info!("Bottom collision");
-                    player_jump_state.state = PlayerJumpState::NonJumping;
-                    // info!("player hit ground");
-
-                    continue;
-                }
+        // each axis pushed us back out the way we came, so we're only still inside something
+        // if we started in it, e.g. a block was placed on us; climb out instead of freezing there
+        unstick_position(player_position, terrain);
+
+        // fell out of the world, so don't move at all
+        if !player_position.is_valid(terrain) {
+            player_position.x = prev_x;
+            player_position.y = prev_y;
+        }
+    }
+
+    /// Move the player by diff, which must only be along x or only along y,
+    /// then push them back out of any blocks (or world wall) they moved into
+    /// Returns whether something stopped them
+    fn move_along_axis(
+        player_position: &mut PlayerPosition,
+        diff: Vec2,
+        terrain: &Terrain,
+        missing_chunks: MissingChunks,
+    ) -> bool {
+        const DEBUG_COLLISIONS: bool = false;
+
+        if diff.x != 0. {
+            player_position.x += diff.x;
+
+            // prevent going past horizontal world boundaries
+            let clamped = player_position.x.clamp(0.0, (CHUNK_WIDTH - 1) as f32);
+            if clamped != player_position.x {
+                player_position.x = clamped;
+                return true;
             }
+        } else if diff.y != 0. {
+            player_position.y += diff.y;
+        } else {
+            return false;
+        }
+
+        let blocks = get_collisions(player_position, terrain, missing_chunks, DEBUG_COLLISIONS);
+        if blocks.is_empty() {
+            return false;
+        }
+
+        // stop just short of the nearest block in the direction we moved
+        if diff.x > 0. {
+            player_position.x = blocks.iter().map(|b| b.x).fold(f32::MAX, f32::min) - 1.;
+        } else if diff.x < 0. {
+            player_position.x = blocks.iter().map(|b| b.x).fold(f32::MIN, f32::max) + 1.;
+        } else if diff.y > 0. {
+            player_position.y = blocks.iter().map(|b| b.y).fold(f32::MAX, f32::min) - 1.;
+        } else {
+            player_position.y = blocks.iter().map(|b| b.y).fold(f32::MIN, f32::max) + 1.;
         }
+        true
     }
 
+    /// Positions of all the blocks that the player overlaps
+    /// Chunks that aren't loaded count as empty or solid depending on missing_chunks
     fn get_collisions(
-        player_position: &Mut<PlayerPosition>,
+        player_position: &PlayerPosition,
         terrain: &Terrain,
+        missing_chunks: MissingChunks,
         debug: bool,
-    ) -> PlayerCollision {
+    ) -> Vec<Vec2> {
         // Get block indices we need to check
 
         // how many blocks to the right the player is
+        // casts saturate, so NaN and negatives become 0 and huge values usize::MAX
         let player_x_block = (player_position.x) as usize;
         // how many blocks down the player is
         let player_y_block = -(player_position.y) as usize;
@@ -271,26 +587,34 @@ pub mod server {
 
         let sizes = Vec2 { x: 1., y: 1. };
 
-        let mut collisions = PlayerCollision::default();
+        let mut collisions = Vec::new();
 
-        for x_index in
-            (cmp::max(1, player_x_block) - 1)..=(cmp::min(player_x_block + 1, CHUNK_WIDTH - 1))
+        for x_index in (cmp::max(1, player_x_block) - 1)
+            ..=(cmp::min(player_x_block.saturating_add(1), CHUNK_WIDTH - 1))
         {
-            for y_index in (cmp::max(1, player_y_block) - 1)..=player_y_block + 1 {
+            for y_index in (cmp::max(1, player_y_block) - 1)..=player_y_block.saturating_add(1) {
                 let chunk_number = y_index / CHUNK_HEIGHT;
                 // index inside the chunk
                 let chunk_y_index = y_index - (chunk_number * CHUNK_HEIGHT);
 
-                let block = terrain.chunks[chunk_number].blocks[chunk_y_index][x_index];
+                let solid = match terrain.get_chunk(chunk_number as u64) {
+                    Some(chunk) => chunk
+                        .get(x_index, chunk_y_index)
+                        .filter(|b| b.block_type.is_solid())
+                        .is_some(),
+                    None => missing_chunks == MissingChunks::Solid,
+                };
 
-                // info!("checking chunk: {}, x: {}, y: {}, block = {:?}", chunk_number, x_index, chunk_y_index, block);
-                if block.is_some() {
+                // info!("checking chunk: {}, x: {}, y: {}, solid = {:?}", chunk_number, x_index, chunk_y_index, solid);
+                if solid {
                     let z = PLAYER_Z; // always collide on same z plane
                     let block_pos = Vec3 {
                         x: x_index as f32,
                         y: -(chunk_y_index as f32 + (chunk_number * CHUNK_HEIGHT) as f32) as f32,
                         z: z,
                     };
+                    // only used to check for overlap, the side it picks is based on the smallest
+                    // overlap which is wrong for small moves
                     let collision = collide(
                         Vec3::new(player_position.x as f32, player_position.y as f32, z),
                         sizes,
@@ -298,15 +622,7 @@ pub mod server {
                         sizes,
                     );
                     if collision.is_some() {
-                        collisions.any = true;
-                        match collision {
-                            Some(Collision::Top) => collisions.bottom = Some(block_pos.y + sizes.y),
-                            Some(Collision::Left) => collisions.right = Some(block_pos.x - sizes.x),
-                            Some(Collision::Bottom) => collisions.top = Some(block_pos.y - sizes.y),
-                            Some(Collision::Right) => collisions.left = Some(block_pos.x + sizes.x),
-                            Some(Collision::Inside) => collisions.inside = true,
-                            None => (),
-                        }
+                        collisions.push(block_pos.truncate());
                     }
                     if debug {
                         info!("Block x: {}, y: {}, chunk: {}, collision: {:?}, playerxy: {:?}, blockxy: {},{}", x_index, chunk_y_index, chunk_number, collision, player_position, block_pos.x, block_pos.y);
@@ -320,6 +636,13 @@ pub mod server {
 }
 
 pub mod client {
+    use crate::{
+        args::ClientArgs,
+        hud::Hud,
+        keybindings::KeyBindings,
+        network::client::{ServerPaused, ServerReach},
+    };
+    use bevy::input::mouse::MouseWheel;
     use strum::IntoEnumIterator;
 
     use super::*;
@@ -328,22 +651,61 @@ pub mod client {
 
     impl Plugin for PlayerPlugin {
         fn build(&self, app: &mut App) {
-            app.add_system(
-                move_players_sprites_to_position
-                    .run_in_state(GameState::InGame)
-                    .label("move_players_sprites_to_position"),
-            )
-            .add_system(
-                handle_camera_movement
-                    .run_in_state(GameState::InGame)
-                    .after("move_players_sprites_to_position")
-                    .label("handle_camera_movement"),
-            )
-            .add_system(re_render_inventory.run_in_state(GameState::InGame))
-            .add_enter_system(GameState::InGame, init_spawn_local_player)
-            .add_enter_system(GameState::InGame, create_inventory_ui)
-            .add_exit_system(GameState::InGame, destroy_inventory_ui)
-            .add_exit_system(GameState::InGame, destroy_all_players);
+            app.init_resource::<CameraBounds>()
+                .init_resource::<CameraZoom>()
+                .init_resource::<SelectedSlot>()
+                .init_resource::<BuildMode>()
+                .init_resource::<MiningEstimate>()
+                .add_system(
+                    move_players_sprites_to_position
+                        .run_in_state(GameState::InGame)
+                        .label("move_players_sprites_to_position"),
+                )
+                .add_system(
+                    handle_camera_movement
+                        .run_in_state(GameState::InGame)
+                        .after("move_players_sprites_to_position")
+                        .label("handle_camera_movement"),
+                )
+                .add_system(
+                    place_target_markers
+                        .run_in_state(GameState::InGame)
+                        .after("move_players_sprites_to_position"),
+                )
+                .add_system(
+                    place_cursor_highlight
+                        .run_in_state(GameState::InGame)
+                        .after("handle_camera_movement"),
+                )
+                .add_system(
+                    estimate_mining_progress
+                        .run_in_state(GameState::InGame)
+                        .label("estimate_mining_progress"),
+                )
+                .add_system(
+                    place_mining_overlay
+                        .run_in_state(GameState::InGame)
+                        .after("handle_camera_movement")
+                        .after("estimate_mining_progress"),
+                )
+                .add_system(re_render_inventory.run_in_state(GameState::InGame))
+                .add_system(
+                    select_hotbar_slot
+                        .run_in_state(GameState::InGame)
+                        .label("select_hotbar_slot"),
+                )
+                .add_system(toggle_build_mode.run_in_state(GameState::InGame))
+                .add_system(
+                    highlight_selected_slot
+                        .run_in_state(GameState::InGame)
+                        .after("select_hotbar_slot"),
+                )
+                .add_enter_system(GameState::InGame, init_spawn_local_player)
+                .add_enter_system(GameState::InGame, create_inventory_ui)
+                .add_exit_system(GameState::InGame, destroy_inventory_ui)
+                .add_exit_system(GameState::InGame, destroy_all_players)
+                .add_exit_system(GameState::Paused, destroy_inventory_ui)
+                .add_exit_system(GameState::Paused, destroy_all_players);
         }
     }
 
@@ -355,20 +717,239 @@ pub mod client {
     #[derive(Component)]
     pub struct Player;
 
+    /// Query filter for players other than our own
+    pub type RemotePlayers = (With<Player>, Without<LocalPlayer>);
+
+    /// Longest gap in server updates (in seconds) that the local player keeps moving through
+    pub const MAX_EXTRAPOLATION_SECS: f32 = 0.25;
+
+    /// Keeps the local player moving from their last input while PlayerInfo updates are missing,
+    /// e.g. on a lossy link, until the next update snaps them back to where the server has them
+    /// Uses the default movement model and gravity, since the server doesn't tell us its own
+    #[derive(Component, Default)]
+    pub struct Extrapolation {
+        /// The last input sent to the server
+        pub input: PlayerInput,
+        /// Seconds since the last PlayerInfo update
+        gap_secs: f32,
+        /// Whether an update came in since the last step
+        fresh: bool,
+        jump_duration: server::JumpDuration,
+        jump_state: server::JumpState,
+        velocity: server::Velocity,
+    }
+
+    impl Extrapolation {
+        /// Take the server's position for the local player, and start any gap over from there
+        pub fn reconcile(
+            &mut self,
+            position: &mut PlayerPosition,
+            server_position: &PlayerPosition,
+            grounded: bool,
+        ) {
+            *position = server_position.clone();
+            self.gap_secs = 0.;
+            self.fresh = true;
+            self.jump_duration = server::JumpDuration::default();
+            self.jump_state = server::JumpState::resumed(grounded);
+            self.velocity = server::Velocity::default();
+        }
+
+        /// Move the player time_delta seconds further, unless an update came in since the last step
+        /// Stops once the gap reaches MAX_EXTRAPOLATION_SECS, so a dead link doesn't walk us off
+        pub fn step(&mut self, position: &mut PlayerPosition, terrain: &Terrain, time_delta: f32) {
+            if std::mem::take(&mut self.fresh) {
+                return;
+            }
+
+            let time_delta = time_delta.min(MAX_EXTRAPOLATION_SECS - self.gap_secs);
+            if time_delta <= 0. {
+                return;
+            }
+            self.gap_secs += time_delta;
+
+            server::move_player(
+                position,
+                &mut self.jump_duration,
+                &mut self.jump_state,
+                &mut self.velocity,
+                &self.input,
+                terrain,
+                MovementModel::Classic,
+                GravityDirection::Down,
+                MissingChunks::Empty,
+                time_delta,
+            );
+        }
+    }
+
+    /// Where a remote player's sprite is drawn, sliding from their previous PlayerInfo position
+    /// to the latest one over a network tick, so they don't jump from update to update
+    /// The local player doesn't have one, it is drawn exactly where it is
+    #[derive(Component, Debug, Default)]
+    pub struct InterpolatedPosition {
+        pub previous: PlayerPosition,
+        pub target: PlayerPosition,
+        /// Seconds since target was set
+        pub elapsed: f32,
+    }
+
+    impl InterpolatedPosition {
+        /// Start out standing still at position
+        pub fn new(position: &PlayerPosition) -> Self {
+            Self {
+                previous: position.clone(),
+                target: position.clone(),
+                elapsed: 0.,
+            }
+        }
+
+        /// Head for a new position, starting from wherever the sprite is now
+        pub fn retarget(&mut self, target: &PlayerPosition) {
+            self.previous = self.position();
+            self.target = target.clone();
+            self.elapsed = 0.;
+        }
+
+        /// Where to draw the player, stays at the target once a whole network tick has passed
+        pub fn position(&self) -> PlayerPosition {
+            let t = (self.elapsed * NETWORK_TICK_HZ as f32).min(1.);
+            PlayerPosition {
+                x: self.previous.x + (self.target.x - self.previous.x) * t,
+                y: self.previous.y + (self.target.y - self.previous.y) * t,
+            }
+        }
+    }
+
+    /// The block another player is pointing at, None unless the server shares targets
+    #[derive(Component, Default)]
+    pub struct RemoteTarget(pub Option<(usize, usize)>);
+
+    /// Faint square over a remote player's target, a child of that player
+    #[derive(Component)]
+    struct TargetMarker;
+
+    /// Query filter for target markers, which are never players themselves
+    type OnlyTargetMarkers = (With<TargetMarker>, Without<Player>);
+
+    /// Translucent ghost of the selected block over the block under our cursor, a child of the
+    /// local player
+    #[derive(Component)]
+    struct CursorHighlight;
+
+    /// Query filter for our cursor highlight, which is never a player itself
+    type OnlyCursorHighlights = (With<CursorHighlight>, Without<Player>);
+
+    /// Dark square over the block we're mining, growing as it nears breaking, a child of the
+    /// local player
+    #[derive(Component)]
+    struct MiningOverlay;
+
+    /// Query filter for our mining overlay, which is never a player or cursor highlight itself
+    type OnlyMiningOverlays = (
+        With<MiningOverlay>,
+        Without<Player>,
+        Without<CursorHighlight>,
+    );
+
+    /// How far through mining its target block we think the local player is; should be a resource
+    /// Moved on every frame the mine input is held, so the overlay doesn't wait for the server,
+    /// and snapped to the server's progress whenever that arrives
+    #[derive(Debug, Default, PartialEq)]
+    pub struct MiningEstimate {
+        pub target: Option<(usize, usize)>,
+        /// From 0 to 1, breaking at 1
+        pub fraction: f32,
+    }
+
+    impl MiningEstimate {
+        /// Mine target, a block of mined's type, for time_delta more seconds; None stops mining
+        /// Moving on to another block starts over, like on the server
+        pub fn advance(&mut self, mined: Option<((usize, usize), BlockType)>, time_delta: f32) {
+            let (target, block_type) = match mined {
+                Some(mined) => mined,
+                None => {
+                    *self = Self::default();
+                    return;
+                }
+            };
+            if self.target != Some(target) {
+                self.target = Some(target);
+                self.fraction = 0.;
+            }
+            self.fraction = (self.fraction + time_delta / block_type.mine_duration()).min(1.);
+        }
+
+        /// Take the server's progress on our target, see ServerBodyElem::MiningProgress
+        pub fn snap(&mut self, fraction: f32) {
+            self.fraction = fraction;
+        }
+
+        /// The block at (x, y) was removed, so if it was our target it's finished
+        pub fn block_deleted(&mut self, x: usize, y: usize) {
+            if self.target == Some((x, y)) {
+                self.fraction = 0.;
+            }
+        }
+    }
+
     #[derive(Component)]
     pub struct CameraBoundsBox {
         pub center_coord: Vec3,
     }
 
-    /// Moves the transform of player entities to their stored PlayerPosition
+    /// Size in pixels of the area the player can move in before the camera follows; should be a resource
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct CameraBounds {
+        pub size: Vec2,
+    }
+
+    impl Default for CameraBounds {
+        fn default() -> Self {
+            Self {
+                size: Vec2::from(CAMERA_BOUNDS_SIZE),
+            }
+        }
+    }
+
+    /// Scale of the camera, above 1 zooms out; should be a resource
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct CameraZoom(pub f32);
+
+    impl Default for CameraZoom {
+        fn default() -> Self {
+            Self(1.)
+        }
+    }
+
+    /// Keeps a camera scale between how far the camera can zoom in and out
+    pub fn clamp_zoom(scale: f32) -> f32 {
+        scale.clamp(CAMERA_ZOOM_MIN, CAMERA_ZOOM_MAX)
+    }
+
+    /// Moves the transform of player entities to their stored PlayerPosition,
+    /// or along the way to it for remote players
     fn move_players_sprites_to_position(
+        time: Res<Time>,
         mut query: Query<
-            (&mut Transform, &PlayerPosition, Option<&LocalPlayer>),
+            (
+                &mut Transform,
+                &PlayerPosition,
+                Option<&mut InterpolatedPosition>,
+            ),
             Without<CharacterCamera>,
         >,
         mut camera: Query<&mut Transform, With<CharacterCamera>>,
     ) {
-        for (mut render_pos, game_pos, local) in query.iter_mut() {
+        for (mut render_pos, game_pos, interpolation) in query.iter_mut() {
+            let game_pos = match interpolation {
+                Some(mut interpolation) => {
+                    interpolation.elapsed += time.delta_seconds();
+                    interpolation.position()
+                }
+                None => game_pos.clone(),
+            };
+
             let bevy_x = game_pos.x as f32 * PLAYER_AND_BLOCK_SIZE as f32;
             let bevy_y = game_pos.y as f32 * PLAYER_AND_BLOCK_SIZE as f32;
 
@@ -383,7 +964,17 @@ pub mod client {
 
     /// creates local player at starting position,
     /// sprite will be moved to correct location in other system
-    fn init_spawn_local_player(mut commands: Commands, assets: Res<AssetServer>) {
+    /// observers don't get one, they only have the camera
+    fn init_spawn_local_player(
+        mut commands: Commands,
+        assets: Res<AssetServer>,
+        args: Res<ClientArgs>,
+    ) {
+        if args.observe {
+            info!("observing, not spawning a player");
+            return;
+        }
+
         let game_position = PLAYER_START_POS;
         info!(
             "spawning player at game position=({}, {})",
@@ -412,7 +1003,34 @@ pub mod client {
             .insert(CameraBoundsBox {
                 center_coord: bevy_position.clone(),
             })
-            .insert(Inventory::default());
+            .insert(Inventory::default())
+            .insert(PlayerStats::default())
+            .insert(Grounded::default())
+            .insert(PlayerName::default())
+            .insert(Extrapolation::default())
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite {
+                            custom_size: Some(Vec2::splat(PLAYER_AND_BLOCK_SIZE)),
+                            color: GHOST_BLOCKED_COLOR,
+                            ..default()
+                        },
+                        visibility: Visibility { is_visible: false },
+                        ..default()
+                    })
+                    .insert(CursorHighlight);
+                parent
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite {
+                            color: MINING_OVERLAY_COLOR,
+                            ..default()
+                        },
+                        visibility: Visibility { is_visible: false },
+                        ..default()
+                    })
+                    .insert(MiningOverlay);
+            });
         // TODO: reset camera
     }
 
@@ -423,8 +1041,87 @@ pub mod client {
     #[derive(Component)]
     struct InventorySlot(BlockType);
 
-    /// Spawns the inventory UI
-    fn create_inventory_ui(assets: Res<AssetServer>, mut commands: Commands) {
+    /// Keys that pick a hotbar cell, counted from the left
+    const HOTBAR_KEYS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+
+    /// The block types in the hotbar, left to right
+    pub fn hotbar_blocks() -> Vec<BlockType> {
+        // skip "fake" blocks
+        BlockType::iter().filter(|b| b.is_real_block()).collect()
+    }
+
+    /// Which hotbar cell right clicking places from, counted from the left
+    #[derive(Debug, Default, PartialEq)]
+    pub struct SelectedSlot(pub usize);
+
+    impl SelectedSlot {
+        /// The type of block in the selected cell
+        pub fn block_type(&self) -> BlockType {
+            let blocks = hotbar_blocks();
+            blocks[self.0 % blocks.len()]
+        }
+
+        /// Move the selection `by` cells to the right, wrapping around at either end
+        pub fn step(&mut self, by: isize) {
+            let len = hotbar_blocks().len() as isize;
+            self.0 = (self.0 as isize + by).rem_euclid(len) as usize;
+        }
+    }
+
+    /// Whether the mine binding places blocks instead of mining them; should be a resource
+    /// The place binding places either way
+    #[derive(Debug, Default, PartialEq)]
+    pub struct BuildMode {
+        pub placing: bool,
+    }
+
+    impl BuildMode {
+        /// Whether to mine and whether to place, given whether the mine and place bindings are held
+        pub fn actions(&self, mine_held: bool, place_held: bool) -> (bool, bool) {
+            if self.placing {
+                (false, mine_held || place_held)
+            } else {
+                (mine_held, place_held)
+            }
+        }
+    }
+
+    /// Switch between mining and building when the toggle_build_mode binding is pressed
+    fn toggle_build_mode(
+        mut mode: ResMut<BuildMode>,
+        bindings: Res<KeyBindings>,
+        keys: Res<Input<KeyCode>>,
+        mouse: Res<Input<MouseButton>>,
+    ) {
+        if bindings.toggle_build_mode.just_pressed(&keys, &mouse) {
+            mode.placing = !mode.placing;
+            info!(
+                "build mode: {}",
+                if mode.placing { "placing" } else { "mining" }
+            );
+        }
+    }
+
+    /// Spawns the inventory UI, unless we are observing and have no inventory
+    fn create_inventory_ui(
+        assets: Res<AssetServer>,
+        mut commands: Commands,
+        args: Res<ClientArgs>,
+    ) {
+        if args.observe {
+            return;
+        }
+
         let inventory_text_style = TextStyle {
             font: assets.load("fonts/milky_coffee.ttf"),
             font_size: 32.0,
@@ -444,14 +1141,10 @@ pub mod client {
                 color: Color::NONE.into(),
                 ..default()
             })
-            .insert(InventoryUi);
+            .insert(InventoryUi)
+            .insert(Hud);
 
-        let mut n = 0;
-        for block_type in BlockType::iter() {
-            // skip "fake" blocks
-            if !block_type.is_real_block() {
-                continue;
-            }
+        for (n, block_type) in hotbar_blocks().into_iter().enumerate() {
             inventory_root_entity.with_children(|parent| {
                 let location = UiRect {
                     left: Val::Px(n as f32 * INV_ICON_SIZE),
@@ -486,7 +1179,59 @@ pub mod client {
                     // add type marker
                     .insert(InventorySlot(block_type));
             });
-            n = n + 1;
+        }
+    }
+
+    /// 1-9 pick a hotbar cell, E and scrolling down go right, scrolling up goes left
+    /// Scrolling with control held zooms the camera instead
+    fn select_hotbar_slot(
+        mut selected: ResMut<SelectedSlot>,
+        input: Res<Input<KeyCode>>,
+        mut wheel: EventReader<MouseWheel>,
+    ) {
+        let before = selected.0;
+
+        let hotbar_len = hotbar_blocks().len();
+        for (slot, key) in HOTBAR_KEYS.iter().enumerate().take(hotbar_len) {
+            if input.just_pressed(*key) {
+                selected.0 = slot;
+            }
+        }
+
+        if input.just_pressed(KeyCode::E) {
+            selected.step(1);
+        }
+
+        let scrolled: f32 = wheel.iter().map(|event| event.y).sum();
+        if input.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+            // zooming
+        } else if scrolled > 0. {
+            selected.step(-1);
+        } else if scrolled < 0. {
+            selected.step(1);
+        }
+
+        if selected.0 != before {
+            info!("placing {:?}", selected.block_type());
+        }
+    }
+
+    /// Dims every hotbar cell except the selected one
+    fn highlight_selected_slot(
+        selected: Res<SelectedSlot>,
+        mut slots: Query<(&InventorySlot, &mut UiColor)>,
+    ) {
+        let selected_type = selected.block_type();
+        for (slot, mut color) in slots.iter_mut() {
+            let tint = if slot.0 == selected_type {
+                Color::WHITE
+            } else {
+                HOTBAR_UNSELECTED_COLOR
+            };
+            // only touch it when it changes
+            if color.0 != tint {
+                color.0 = tint;
+            }
         }
     }
 
@@ -494,7 +1239,11 @@ pub mod client {
         query_inv: Query<&Inventory>,
         mut query_inv_text: Query<(&mut Text, &InventorySlot)>,
     ) {
-        let inv = query_inv.single();
+        let inv = match query_inv.get_single() {
+            Ok(inv) => inv,
+            // observing, there is no inventory to show
+            Err(_) => return,
+        };
         for (mut text, slot) in query_inv_text.iter_mut() {
             match inv.amounts.get(&slot.0) {
                 Some(amount) => {
@@ -530,24 +1279,28 @@ pub mod client {
     fn destroy_all_players(
         players: Query<Entity, With<Player>>,
         mut camera_query: Query<&mut Transform, (With<CharacterCamera>, Without<Player>)>,
+        mut zoom: ResMut<CameraZoom>,
         mut commands: Commands,
     ) {
         // despawn all players
         for entity in players.iter() {
-            commands.entity(entity).despawn();
+            commands.entity(entity).despawn_recursive();
         }
 
         // move camera to start position
         for mut camera in camera_query.iter_mut() {
             camera.translation.x = PLAYER_START_POS.x as f32;
             camera.translation.y = PLAYER_START_POS.y as f32;
+            camera.scale = Vec3::ONE;
         }
+        *zoom = CameraZoom::default();
     }
 
     pub fn spawn_other_player_at(
         commands: &mut Commands,
         assets: &AssetServer,
         addr: &ClientAddress,
+        name: &PlayerName,
         position: &PlayerPosition,
     ) {
         // color based on address
@@ -575,67 +1328,295 @@ pub mod client {
             })
             .insert(Player)
             .insert(position.clone())
-            .insert(addr.clone());
+            .insert(Grounded::default())
+            .insert(addr.clone())
+            .insert(name.clone())
+            .insert(RemoteTarget::default())
+            .insert(InterpolatedPosition::new(position))
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite {
+                            custom_size: Some(Vec2::splat(PLAYER_AND_BLOCK_SIZE)),
+                            color: *color.clone().set_a(TARGET_MARKER_ALPHA),
+                            ..default()
+                        },
+                        visibility: Visibility { is_visible: false },
+                        ..default()
+                    })
+                    .insert(TargetMarker);
+            });
     }
 
-    fn handle_camera_movement(
-        mut query: Query<(&Transform, &mut CameraBoundsBox, With<LocalPlayer>)>,
-        mut camera_query: Query<(&mut Transform, With<CharacterCamera>, Without<LocalPlayer>)>,
-        input: Res<Input<KeyCode>>,
+    /// Moves each remote player's marker onto the block they're pointing at
+    /// The marker is a child, so it's placed relative to the player's sprite
+    fn place_target_markers(
+        players: Query<(&Transform, &RemoteTarget, &Children), RemotePlayers>,
+        mut markers: Query<(&mut Transform, &mut Visibility), OnlyTargetMarkers>,
     ) {
-        for (player_transform, mut camera_box, _player) in query.iter_mut() {
-            //Likely has to be changed when multiplayer is added
-            let mut camera = camera_query.single_mut();
-
-            //Calculate distance from center based on box size
-            let horizontal_dist = CAMERA_BOUNDS_SIZE[0] / 2.;
-            let vert_dist = CAMERA_BOUNDS_SIZE[1] / 2.;
-
-            //Calculates coordinates of bounds based on distance from center of camera box
-            let cam_x = camera_box.center_coord[0];
-            let cam_y = camera_box.center_coord[1];
-
-            let right_bound = cam_x + horizontal_dist;
-            let left_bound = cam_x - horizontal_dist;
-            let top_bound = cam_y + vert_dist;
-            let bottom_bound = cam_y - vert_dist;
-
-            //Checks if player is hitting boundaries of camera box
-            if player_transform.translation.x >= right_bound {
-                //moves center of camera box by how far player is past boundary
-                camera_box.center_coord[0] += player_transform.translation.x - right_bound;
-                //moves camera accordingly
-                camera.0.translation.x += player_transform.translation.x - right_bound;
+        for (player, target, children) in players.iter() {
+            for child in children.iter() {
+                if let Ok((mut transform, mut visibility)) = markers.get_mut(*child) {
+                    visibility.is_visible = target.0.is_some();
+                    if let Some((x, y)) = target.0 {
+                        let block = Vec3::new(
+                            x as f32 * PLAYER_AND_BLOCK_SIZE,
+                            -(y as f32) * PLAYER_AND_BLOCK_SIZE,
+                            TARGET_MARKER_Z,
+                        );
+                        transform.translation = block - player.translation;
+                    }
+                }
             }
+        }
+    }
 
-            if player_transform.translation.x <= left_bound {
-                camera_box.center_coord[0] += player_transform.translation.x - left_bound;
-                camera.0.translation.x += player_transform.translation.x - left_bound;
-            }
+    /// Where the cursor is in the window, scaled as if the window were WIN_W by WIN_H
+    /// The camera always shows that much of the world, however big the window is
+    pub fn cursor_in_view(window: &Window) -> Option<Vec2> {
+        let size = Vec2::new(window.width(), window.height());
+        let scale = (WIN_W / size.x).max(WIN_H / size.y);
+        window
+            .cursor_position()
+            .map(|cursor| Vec2::new(WIN_W, WIN_H) / 2. + (cursor - size / 2.) * scale)
+    }
 
-            if player_transform.translation.y >= top_bound {
-                camera_box.center_coord[1] += player_transform.translation.y - top_bound;
-                camera.0.translation.y += player_transform.translation.y - top_bound;
-            }
+    /// The global (x, y) of the block under the cursor, given its position in the view
+    pub fn block_under_cursor(
+        cursor: Vec2,
+        camera_box: &CameraBoundsBox,
+        zoom: &CameraZoom,
+    ) -> (usize, usize) {
+        //calculate distance of click from camera center
+        let dist_x = (cursor.x - (WIN_W / 2.)) * zoom.0;
+        let dist_y = (cursor.y - (WIN_H / 2.)) * zoom.0;
+
+        //calculate bevy coords of click
+        let game_x = camera_box.center_coord.x + dist_x;
+        let game_y = camera_box.center_coord.y + dist_y;
+
+        //calculate block coords from bevy coords
+        (
+            (game_x / PLAYER_AND_BLOCK_SIZE).round() as usize,
+            (-game_y / PLAYER_AND_BLOCK_SIZE).round() as usize,
+        )
+    }
 
-            if player_transform.translation.y <= bottom_bound {
-                camera_box.center_coord[1] += player_transform.translation.y - bottom_bound;
-                camera.0.translation.y += player_transform.translation.y - bottom_bound;
-            }
+    /// Moves our cursor highlight onto the block under the cursor, showing the selected block as
+    /// it would be placed, tinted red if it can't be
+    /// Hidden while the cursor is outside the window
+    fn place_cursor_highlight(
+        windows: Res<Windows>,
+        zoom: Res<CameraZoom>,
+        reach: Res<ServerReach>,
+        terrain: Res<Terrain>,
+        selected: Res<SelectedSlot>,
+        assets: Res<AssetServer>,
+        player: Query<
+            (&Transform, &PlayerPosition, &CameraBoundsBox, &Children),
+            With<LocalPlayer>,
+        >,
+        players: Query<&PlayerPosition, With<Player>>,
+        mut highlights: Query<
+            (
+                &mut Transform,
+                &mut Visibility,
+                &mut Sprite,
+                &mut Handle<Image>,
+            ),
+            OnlyCursorHighlights,
+        >,
+    ) {
+        let (player_transform, position, camera_box, children) = match player.get_single() {
+            Ok(player) => player,
+            Err(_) => return,
+        };
+        let cursor = windows.get_primary().and_then(cursor_in_view);
 
-            //DEBUGGING: Free Roam Camera with Arrow Keys
-            if input.pressed(KeyCode::Right) {
-                camera.0.translation.x += 25.;
-            }
-            if input.pressed(KeyCode::Left) {
-                camera.0.translation.x -= 25.;
-            }
-            if input.pressed(KeyCode::Up) {
-                camera.0.translation.y += 25.;
+        for child in children.iter() {
+            if let Ok((mut transform, mut visibility, mut sprite, mut texture)) =
+                highlights.get_mut(*child)
+            {
+                visibility.is_visible = cursor.is_some();
+                if let Some(cursor) = cursor {
+                    let (x, y) = block_under_cursor(cursor, camera_box, &zoom);
+                    let block = Vec3::new(
+                        x as f32 * PLAYER_AND_BLOCK_SIZE,
+                        -(y as f32) * PLAYER_AND_BLOCK_SIZE,
+                        TARGET_MARKER_Z,
+                    );
+                    transform.translation = block - player_transform.translation;
+                    sprite.color = if position.can_place(x, y, reach.0, &terrain, players.iter()) {
+                        GHOST_COLOR
+                    } else {
+                        GHOST_BLOCKED_COLOR
+                    };
+                    // only touch it when the selection changes
+                    let ghost = assets.load(selected.block_type().image_file_path());
+                    if *texture != ghost {
+                        *texture = ghost;
+                    }
+                }
             }
-            if input.pressed(KeyCode::Down) {
-                camera.0.translation.y -= 25.;
+        }
+    }
+
+    /// Moves our mining estimate on a frame while the mine input is held on a block in reach
+    fn estimate_mining_progress(
+        time: Res<Time>,
+        terrain: Res<Terrain>,
+        reach: Res<ServerReach>,
+        paused: Res<ServerPaused>,
+        player: Query<(&PlayerPosition, &Extrapolation), With<LocalPlayer>>,
+        mut estimate: ResMut<MiningEstimate>,
+    ) {
+        // the server doesn't mine while paused either
+        if paused.0 {
+            return;
+        }
+
+        let mined = player
+            .get_single()
+            .ok()
+            .and_then(|(position, extrapolation)| {
+                let input = &extrapolation.input;
+                match terrain.get_block(input.block_x, input.block_y) {
+                    Some(block)
+                        if input.mine
+                            && position.can_reach(input.block_x, input.block_y, reach.0) =>
+                    {
+                        Some(((input.block_x, input.block_y), block.block_type))
+                    }
+                    _ => None,
+                }
+            });
+        estimate.advance(mined, time.delta_seconds());
+    }
+
+    /// Moves our mining overlay onto the block being mined, sized by how far through it we are
+    fn place_mining_overlay(
+        estimate: Res<MiningEstimate>,
+        player: Query<(&Transform, &Children), With<LocalPlayer>>,
+        mut overlays: Query<(&mut Transform, &mut Visibility, &mut Sprite), OnlyMiningOverlays>,
+    ) {
+        let (player_transform, children) = match player.get_single() {
+            Ok(player) => player,
+            Err(_) => return,
+        };
+
+        for child in children.iter() {
+            if let Ok((mut transform, mut visibility, mut sprite)) = overlays.get_mut(*child) {
+                let target = estimate.target.filter(|_| estimate.fraction > 0.);
+                visibility.is_visible = target.is_some();
+                if let Some((x, y)) = target {
+                    let block = Vec3::new(
+                        x as f32 * PLAYER_AND_BLOCK_SIZE,
+                        -(y as f32) * PLAYER_AND_BLOCK_SIZE,
+                        MINING_OVERLAY_Z,
+                    );
+                    transform.translation = block - player_transform.translation;
+                    sprite.custom_size =
+                        Some(Vec2::splat(PLAYER_AND_BLOCK_SIZE * estimate.fraction));
+                }
             }
+        }
+    }
+
+    /// Moves the camera bounds box so that the player stays inside it
+    /// Returns how far the box (and so the camera) moved
+    pub fn follow_player(
+        camera_box: &mut CameraBoundsBox,
+        player: Vec3,
+        bounds: &CameraBounds,
+    ) -> Vec2 {
+        //Calculate distance from center based on box size
+        let horizontal_dist = bounds.size.x / 2.;
+        let vert_dist = bounds.size.y / 2.;
+
+        //Calculates coordinates of bounds based on distance from center of camera box
+        let cam_x = camera_box.center_coord[0];
+        let cam_y = camera_box.center_coord[1];
+
+        let right_bound = cam_x + horizontal_dist;
+        let left_bound = cam_x - horizontal_dist;
+        let top_bound = cam_y + vert_dist;
+        let bottom_bound = cam_y - vert_dist;
+
+        let mut moved = Vec2::ZERO;
+
+        //Checks if player is hitting boundaries of camera box
+        //moves center of camera box by how far player is past boundary
+        if player.x >= right_bound {
+            moved.x += player.x - right_bound;
+        }
+
+        if player.x <= left_bound {
+            moved.x += player.x - left_bound;
+        }
+
+        if player.y >= top_bound {
+            moved.y += player.y - top_bound;
+        }
+
+        if player.y <= bottom_bound {
+            moved.y += player.y - bottom_bound;
+        }
+
+        camera_box.center_coord += moved.extend(0.);
+        moved
+    }
+
+    fn handle_camera_movement(
+        mut query: Query<(&Transform, &mut CameraBoundsBox, With<LocalPlayer>)>,
+        mut camera_query: Query<(&mut Transform, With<CharacterCamera>, Without<LocalPlayer>)>,
+        input: Res<Input<KeyCode>>,
+        mut wheel: EventReader<MouseWheel>,
+        bounds: Res<CameraBounds>,
+        mut zoom: ResMut<CameraZoom>,
+    ) {
+        //Likely has to be changed when multiplayer is added
+        let mut camera = camera_query.single_mut();
+
+        //Scrolling with control held zooms, scaling the transform like the surface view
+        //so camera children keep covering the screen
+        let scrolled: f32 = wheel.iter().map(|event| event.y).sum();
+        if input.any_pressed([KeyCode::LControl, KeyCode::RControl]) && scrolled != 0. {
+            // scrolling up zooms in
+            zoom.0 = clamp_zoom(zoom.0 - scrolled.signum() * CAMERA_ZOOM_STEP);
+        }
+        //Pressing R also resets the zoom
+        if input.pressed(KeyCode::R) {
+            zoom.0 = 1.;
+        }
+        // only while it changes, so the surface view can animate the scale
+        if zoom.is_changed() {
+            camera.0.scale = Vec3::new(zoom.0, zoom.0, 1.);
+        }
+
+        //the view grows with the zoom, so the dead zone does too
+        let bounds = CameraBounds {
+            size: bounds.size * zoom.0,
+        };
+
+        //DEBUGGING: Free Roam Camera with Arrow Keys
+        //this is also how observers, who have no local player, look around
+        if input.pressed(KeyCode::Right) {
+            camera.0.translation.x += 25.;
+        }
+        if input.pressed(KeyCode::Left) {
+            camera.0.translation.x -= 25.;
+        }
+        if input.pressed(KeyCode::Up) {
+            camera.0.translation.y += 25.;
+        }
+        if input.pressed(KeyCode::Down) {
+            camera.0.translation.y -= 25.;
+        }
+
+        for (player_transform, mut camera_box, _player) in query.iter_mut() {
+            //moves camera along with the box
+            let moved = follow_player(&mut camera_box, player_transform.translation, &bounds);
+            camera.0.translation += moved.extend(0.);
 
             //Pressing R returns camera to player after free roam
             if input.pressed(KeyCode::R) {
@@ -652,3 +1633,707 @@ pub mod client {
         camera_transform.translation.y = camera_bounds.center_coord[1];
     }
 }
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::server::*;
+    use super::*;
+    use crate::world::{Block, Chunk};
+
+    /// Empty terrain with a solid floor across all of row `floor_y` in chunk 0
+    fn floor_terrain(floor_y: usize) -> Terrain {
+        let mut terrain: Terrain = [Chunk::empty(0), Chunk::empty(1)].into_iter().collect();
+        for x in 0..CHUNK_WIDTH {
+            terrain.get_chunk_mut(0).unwrap().blocks[floor_y][x] = Some(Block {
+                block_type: BlockType::Limestone,
+            });
+        }
+        terrain
+    }
+
+    /// Stand on the floor, then jump while holding jump for `hold_ticks` ticks
+    /// Returns the highest y position reached
+    fn jump_peak(hold_ticks: usize) -> f32 {
+        let terrain = floor_terrain(10);
+        let mut position = PlayerPosition { x: 5., y: -9. };
+        let mut jump_duration = JumpDuration::default();
+        let mut jump_state = JumpState::default();
+        let mut velocity = Velocity::default();
+        let mut peak = position.y;
+
+        for tick in 0..120 {
+            let input = PlayerInput {
+                jump: tick < hold_ticks,
+                ..default()
+            };
+            move_player(
+                &mut position,
+                &mut jump_duration,
+                &mut jump_state,
+                &mut velocity,
+                &input,
+                &terrain,
+                MovementModel::Classic,
+                GravityDirection::Down,
+                MissingChunks::Empty,
+                1. / 60.,
+            );
+            peak = peak.max(position.y);
+        }
+
+        // back on the ground afterwards
+        assert_eq!(position.y, -9.);
+        peak
+    }
+
+    #[test]
+    fn remote_players_slide_between_updates() {
+        use super::client::InterpolatedPosition;
+        let tick = 1. / NETWORK_TICK_HZ as f32;
+
+        let mut interpolation = InterpolatedPosition::new(&PlayerPosition { x: 2., y: -4. });
+        interpolation.retarget(&PlayerPosition { x: 4., y: -8. });
+        assert_eq!(interpolation.position().x, 2.);
+
+        interpolation.elapsed = tick / 2.;
+        let halfway = interpolation.position();
+        assert!((halfway.x - 3.).abs() < 0.001);
+        assert!((halfway.y + 6.).abs() < 0.001);
+
+        // waits at the target if the next update is late
+        interpolation.elapsed = tick * 3.;
+        assert_eq!(interpolation.position().x, 4.);
+
+        // an early update carries on from where the sprite is, instead of jumping back
+        interpolation.elapsed = tick / 2.;
+        interpolation.retarget(&PlayerPosition { x: 10., y: -6. });
+        assert!((interpolation.position().x - 3.).abs() < 0.001);
+    }
+
+    #[test]
+    fn inventory_round_trips() {
+        let mut inventory = Inventory::default();
+        *inventory.amounts.get_mut(&BlockType::Coal).unwrap() = 12;
+        *inventory.amounts.get_mut(&BlockType::Sand).unwrap() = 3;
+
+        let encoded = bincode::encode_to_vec(&inventory, crate::network::BINCODE_CONFIG).unwrap();
+        let (decoded, _): (Inventory, usize) =
+            bincode::decode_from_slice(&encoded, crate::network::BINCODE_CONFIG).unwrap();
+
+        assert_eq!(decoded.amounts, inventory.amounts);
+    }
+
+    #[test]
+    fn old_inventories_get_new_block_types() {
+        // saved before saplings existed
+        let old: HashMap<BlockType, usize> = [(BlockType::Coal, 4)].into_iter().collect();
+        let encoded = bincode::encode_to_vec(&old, crate::network::BINCODE_CONFIG).unwrap();
+        let (decoded, _): (Inventory, usize) =
+            bincode::decode_from_slice(&encoded, crate::network::BINCODE_CONFIG).unwrap();
+
+        assert_eq!(decoded.amounts[&BlockType::Coal], 4);
+        assert_eq!(decoded.amounts[&BlockType::Sapling], 0);
+        assert_eq!(decoded.amounts.len(), Inventory::default().amounts.len());
+    }
+
+    #[test]
+    fn inventory_encoding_is_deterministic() {
+        // same contents, built in opposite orders
+        let mut forward = Inventory {
+            amounts: HashMap::new(),
+        };
+        for (i, block_type) in BlockType::iter().enumerate() {
+            forward.amounts.insert(block_type, i);
+        }
+        let mut backward = Inventory {
+            amounts: HashMap::new(),
+        };
+        for (i, block_type) in BlockType::iter()
+            .enumerate()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+        {
+            backward.amounts.insert(block_type, i);
+        }
+
+        let config = crate::network::BINCODE_CONFIG;
+        let forward_bytes = bincode::encode_to_vec(&forward, config).unwrap();
+        let backward_bytes = bincode::encode_to_vec(&backward, config).unwrap();
+        assert_eq!(forward_bytes, backward_bytes);
+
+        // and it's still readable as a map
+        let (decoded, _): (HashMap<BlockType, usize>, usize) =
+            bincode::decode_from_slice(&forward_bytes, config).unwrap();
+        assert_eq!(decoded, forward.amounts);
+    }
+
+    #[test]
+    fn holding_jump_reaches_max_height() {
+        let max_rise = (PLAYER_SPEED + GRAVITY) * PLAYER_JUMP_DURATION;
+        let peak = jump_peak(60);
+        assert!((peak - (-9. + max_rise)).abs() < 0.2, "peak was {}", peak);
+    }
+
+    /// Walk right on the floor for `hold_ticks` ticks, then let go
+    /// Returns the x position after every tick
+    fn walk_right(model: MovementModel, hold_ticks: usize, total_ticks: usize) -> Vec<f32> {
+        let terrain = floor_terrain(10);
+        let mut position = PlayerPosition { x: 5., y: -9. };
+        let mut jump_duration = JumpDuration::default();
+        let mut jump_state = JumpState::default();
+        let mut velocity = Velocity::default();
+
+        (0..total_ticks)
+            .map(|tick| {
+                let input = PlayerInput {
+                    right: tick < hold_ticks,
+                    ..default()
+                };
+                move_player(
+                    &mut position,
+                    &mut jump_duration,
+                    &mut jump_state,
+                    &mut velocity,
+                    &input,
+                    &terrain,
+                    model,
+                    GravityDirection::Down,
+                    MissingChunks::Empty,
+                    1. / 60.,
+                );
+                position.x
+            })
+            .collect()
+    }
+
+    #[test]
+    fn classic_movement_is_instant() {
+        let xs = walk_right(MovementModel::Classic, 10, 20);
+        let full_step = PLAYER_SPEED / 60.;
+        assert!((xs[0] - (5. + full_step)).abs() < 0.001);
+        // stops dead once released
+        assert_eq!(xs[10], xs[9]);
+        assert_eq!(xs[19], xs[9]);
+    }
+
+    #[test]
+    fn accelerated_movement_ramps_and_coasts() {
+        let model = MovementModel::Accelerated {
+            acceleration: PLAYER_ACCELERATION,
+            friction: PLAYER_FRICTION,
+        };
+        let xs = walk_right(model, 30, 90);
+        let full_step = PLAYER_SPEED / 60.;
+
+        // slower than full speed at first, then gets there
+        assert!(xs[0] - 5. < full_step);
+        assert!(xs[0] > 5.);
+        assert!((xs[29] - xs[28] - full_step).abs() < 0.001);
+
+        // keeps going for a bit after letting go, then stops
+        assert!(xs[30] > xs[29]);
+        assert!(xs[35] > xs[34]);
+        assert_eq!(xs[89], xs[88]);
+    }
+
+    #[test]
+    fn inside_floor_is_pushed_out() {
+        let terrain = floor_terrain(10);
+        // inside the floor
+        let mut position = PlayerPosition { x: 5., y: -10. };
+        move_player(
+            &mut position,
+            &mut JumpDuration::default(),
+            &mut JumpState::default(),
+            &mut Velocity::default(),
+            &PlayerInput {
+                right: true,
+                ..default()
+            },
+            &terrain,
+            MovementModel::Classic,
+            GravityDirection::Down,
+            MissingChunks::Empty,
+            1. / 60.,
+        );
+        // no endless loop, and we end up standing on the floor
+        assert_eq!(position.y, -9.);
+    }
+
+    #[test]
+    fn extrapolation_is_bounded_and_reconciles() {
+        use super::client::{Extrapolation, MAX_EXTRAPOLATION_SECS};
+
+        let terrain = floor_terrain(10);
+        let mut position = PlayerPosition::default();
+        let mut extrapolation = Extrapolation::default();
+        extrapolation.input.right = true;
+        let dt = 1. / 60.;
+
+        // an update just came in, so the first step leaves the player where the server put them
+        extrapolation.reconcile(&mut position, &PlayerPosition { x: 5., y: -9. }, true);
+        extrapolation.step(&mut position, &terrain, dt);
+        assert_eq!(position.x, 5.);
+
+        // then no updates for a second: keep walking, but only for so long
+        let mut xs = Vec::new();
+        for _ in 0..60 {
+            extrapolation.step(&mut position, &terrain, dt);
+            xs.push(position.x);
+        }
+        let bound = (MAX_EXTRAPOLATION_SECS / dt).round() as usize;
+        assert!(xs[0] > 5.);
+        assert!(xs[bound - 1] > xs[bound / 2]);
+        assert_eq!(xs[59], xs[bound - 1]);
+        assert_eq!(position.y, -9.);
+
+        // updates resume, snap to the server and start over
+        extrapolation.reconcile(&mut position, &PlayerPosition { x: 7., y: -9. }, true);
+        assert_eq!((position.x, position.y), (7., -9.));
+        extrapolation.step(&mut position, &terrain, dt);
+        assert_eq!(position.x, 7.);
+        extrapolation.step(&mut position, &terrain, dt);
+        assert!(position.x > 7.);
+    }
+
+    #[test]
+    fn upward_gravity_lands_on_ceilings() {
+        // the floor row works just as well as a ceiling
+        let terrain = floor_terrain(5);
+        let mut position = PlayerPosition { x: 5., y: -8. };
+        let mut jump_duration = JumpDuration::default();
+        let mut jump_state = JumpState::default();
+        let mut velocity = Velocity::default();
+        let mut step = |position: &mut PlayerPosition| {
+            move_player(
+                position,
+                &mut jump_duration,
+                &mut jump_state,
+                &mut velocity,
+                &PlayerInput::default(),
+                &terrain,
+                MovementModel::Classic,
+                GravityDirection::Up,
+                MissingChunks::Empty,
+                1. / 60.,
+            );
+        };
+
+        step(&mut position);
+        assert!(position.y > -8.);
+        for _ in 0..120 {
+            step(&mut position);
+        }
+        // resting against the underside of the block row at y = -5
+        assert_eq!(position.y, -6.);
+        assert!(jump_state.is_grounded());
+    }
+
+    #[test]
+    fn broken_positions_are_reset() {
+        let terrain = floor_terrain(10);
+        let spawn = PlayerPosition { x: 0., y: -9. };
+
+        for (x, y) in [
+            (f32::NAN, -5.),
+            (5., f32::NAN),
+            (f32::INFINITY, -5.),
+            (5., -1e30),
+            (5., 1e30),
+            (-3., -5.),
+        ] {
+            let mut position = PlayerPosition { x, y };
+            sanitize_position(&mut position, &terrain);
+            assert_eq!((position.x, position.y), (spawn.x, spawn.y));
+
+            // and moving from there doesn't panic
+            move_player(
+                &mut position,
+                &mut JumpDuration::default(),
+                &mut JumpState::default(),
+                &mut Velocity::default(),
+                &PlayerInput::default(),
+                &terrain,
+                MovementModel::Classic,
+                GravityDirection::Down,
+                MissingChunks::Empty,
+                1. / 60.,
+            );
+            assert!(position.is_valid(&terrain));
+        }
+
+        // collision lookups survive broken positions too
+        for (x, y) in [(f32::NAN, f32::NAN), (1e30, -1e30), (-1e30, 1e30)] {
+            move_player(
+                &mut PlayerPosition { x, y },
+                &mut JumpDuration::default(),
+                &mut JumpState::default(),
+                &mut Velocity::default(),
+                &PlayerInput::default(),
+                &terrain,
+                MovementModel::Classic,
+                GravityDirection::Down,
+                MissingChunks::Empty,
+                1. / 60.,
+            );
+        }
+
+        // a valid position is left alone
+        let mut position = PlayerPosition { x: 5., y: -9. };
+        sanitize_position(&mut position, &terrain);
+        assert_eq!((position.x, position.y), (5., -9.));
+    }
+
+    #[test]
+    fn grounded_follows_landing() {
+        let terrain = floor_terrain(10);
+        let mut position = PlayerPosition { x: 5., y: -7. };
+        let mut jump_duration = JumpDuration::default();
+        let mut jump_state = JumpState::default();
+        let mut velocity = Velocity::default();
+        let mut step = |position: &mut PlayerPosition, jump_state: &mut JumpState, jump: bool| {
+            move_player(
+                position,
+                &mut jump_duration,
+                jump_state,
+                &mut velocity,
+                &PlayerInput { jump, ..default() },
+                &terrain,
+                MovementModel::Classic,
+                GravityDirection::Down,
+                MissingChunks::Empty,
+                1. / 60.,
+            )
+        };
+
+        // two blocks up, falling
+        step(&mut position, &mut jump_state, false);
+        assert!(!jump_state.is_grounded());
+
+        // land on the floor
+        for _ in 0..60 {
+            step(&mut position, &mut jump_state, false);
+        }
+        assert_eq!(position.y, -9.);
+        assert!(jump_state.is_grounded());
+
+        // and leave it again
+        step(&mut position, &mut jump_state, true);
+        assert!(position.y > -9.);
+        assert!(!jump_state.is_grounded());
+    }
+
+    /// Run move_player for `ticks` ticks with the same input, returning every position on the way
+    fn walk(
+        position: &mut PlayerPosition,
+        input: &PlayerInput,
+        terrain: &Terrain,
+        ticks: usize,
+    ) -> Vec<PlayerPosition> {
+        let mut jump_duration = JumpDuration::default();
+        let mut jump_state = JumpState::default();
+        let mut velocity = Velocity::default();
+        (0..ticks)
+            .map(|_| {
+                move_player(
+                    position,
+                    &mut jump_duration,
+                    &mut jump_state,
+                    &mut velocity,
+                    input,
+                    terrain,
+                    MovementModel::Classic,
+                    GravityDirection::Down,
+                    MissingChunks::Empty,
+                    1. / 60.,
+                );
+                position.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn jumping_past_a_corner_slides_under_it() {
+        let mut terrain = floor_terrain(20);
+        terrain.set_block(6, 10, Some(Block::new(BlockType::Limestone)));
+        // below and to the left of the block, heading up and right into its corner
+        let mut position = PlayerPosition { x: 4.6, y: -12. };
+        let input = PlayerInput {
+            right: true,
+            jump: true,
+            ..default()
+        };
+
+        let path = walk(&mut position, &input, &terrain, 20);
+
+        // never caught on the corner, walking on while the block stops the jump
+        for (before, after) in path.iter().zip(&path[1..]) {
+            assert!(after.x > before.x, "stuck at {:?}", after);
+        }
+        let under_block = path.iter().filter(|p| (p.x - 6.).abs() < 1.);
+        assert!(under_block.clone().count() > 0);
+        for p in under_block {
+            assert!(p.y <= -11., "inside the block at {:?}", p);
+        }
+        assert!(position.x > 7.);
+    }
+
+    #[test]
+    fn walking_into_a_gap_drops_down_it() {
+        // a 1 wide hole in the floor, right before a wall
+        let mut terrain = floor_terrain(10);
+        terrain.set_block(5, 10, None);
+        terrain.set_block(6, 9, Some(Block::new(BlockType::Limestone)));
+        let mut position = PlayerPosition { x: 2.2, y: -9. };
+        let input = PlayerInput {
+            right: true,
+            ..default()
+        };
+
+        let path = walk(&mut position, &input, &terrain, 15);
+
+        // the wall lines us up with the hole, then we fall through it
+        assert!(
+            path.iter().any(|p| p.x == 5. && p.y < -9.5),
+            "never fell in: {:?}",
+            path
+        );
+    }
+
+    #[test]
+    fn blocks_placed_on_a_player_push_them_out() {
+        let mut terrain = floor_terrain(10);
+        terrain.set_block(5, 9, Some(Block::new(BlockType::Limestone)));
+        // standing where the new block is
+        let mut position = PlayerPosition { x: 5., y: -9. };
+
+        walk(&mut position, &PlayerInput::default(), &terrain, 1);
+
+        assert_eq!((position.x, position.y), (5., -8.));
+    }
+
+    #[test]
+    fn unloaded_chunks_are_fallen_through() {
+        // chunk 1 was unloaded, chunk 2 below it is still around
+        let terrain: Terrain = [Chunk::empty(0), Chunk::empty(2)].into_iter().collect();
+        let start = -(CHUNK_HEIGHT as f32 + 5.);
+        let mut position = PlayerPosition { x: 5., y: start };
+
+        walk(&mut position, &PlayerInput::default(), &terrain, 10);
+
+        assert_eq!(position.x, 5.);
+        assert!(position.y < start);
+    }
+
+    #[test]
+    fn unloaded_chunks_can_be_solid() {
+        // standing in chunk 0 right above the unloaded chunk 1
+        let terrain: Terrain = [Chunk::empty(0), Chunk::empty(2)].into_iter().collect();
+        let start = -(CHUNK_HEIGHT as f32 - 1.);
+
+        let mut position = PlayerPosition { x: 5., y: start };
+        let mut jump_duration = JumpDuration::default();
+        let mut jump_state = JumpState::default();
+        let mut velocity = Velocity::default();
+        for _ in 0..10 {
+            move_player(
+                &mut position,
+                &mut jump_duration,
+                &mut jump_state,
+                &mut velocity,
+                &PlayerInput::default(),
+                &terrain,
+                MovementModel::Classic,
+                GravityDirection::Down,
+                MissingChunks::Solid,
+                1. / 60.,
+            );
+        }
+
+        assert_eq!((position.x, position.y), (5., start));
+        assert!(jump_state.is_grounded());
+    }
+
+    #[test]
+    fn tapping_jump_is_lower() {
+        let full = jump_peak(60);
+        let tap = jump_peak(3);
+        assert!(tap > -9.);
+        assert!(tap < full - 1., "tap {} vs full {}", tap, full);
+    }
+
+    #[test]
+    fn camera_bounds_control_tracking() {
+        use super::client::{follow_player, CameraBounds, CameraBoundsBox};
+
+        let player = Vec3::new(300., 0., 0.);
+
+        // the default box is 1000 wide, so 300 to the right is still inside
+        let mut camera_box = CameraBoundsBox {
+            center_coord: Vec3::ZERO,
+        };
+        let moved = follow_player(&mut camera_box, player, &CameraBounds::default());
+        assert_eq!(moved, Vec2::ZERO);
+        assert_eq!(camera_box.center_coord, Vec3::ZERO);
+
+        // a 400 wide box starts tracking at 200
+        let tight = CameraBounds {
+            size: Vec2::new(400., 400.),
+        };
+        let moved = follow_player(&mut camera_box, player, &tight);
+        assert_eq!(moved, Vec2::new(100., 0.));
+        assert_eq!(camera_box.center_coord, Vec3::new(100., 0., 0.));
+
+        // and also vertically
+        let moved = follow_player(&mut camera_box, Vec3::new(100., -250., 0.), &tight);
+        assert_eq!(moved, Vec2::new(0., -50.));
+    }
+
+    #[test]
+    fn placing_needs_an_empty_cell_in_reach() {
+        use crate::world::FLAT_SURFACE_HEIGHT;
+
+        // everything above row FLAT_SURFACE_HEIGHT is sky
+        let mut terrain = Terrain::new_flat(2, FLAT_SURFACE_HEIGHT);
+        let position = PlayerPosition { x: 5., y: -10. };
+        let can_place = |x, y, terrain: &Terrain, others: &[PlayerPosition]| -> bool {
+            position.can_place(x, y, 3., terrain, others)
+        };
+
+        // the sky above a flat world
+        assert!(can_place(6, 10, &terrain, &[]));
+        // too far
+        assert!(!can_place(9, 10, &terrain, &[]));
+        // already a block there
+        terrain.set_block(6, 10, Some(Block::new(BlockType::Sand)));
+        assert!(!can_place(6, 10, &terrain, &[]));
+        // the player themselves, or someone else standing there
+        assert!(!can_place(5, 10, &terrain, &[position.clone()]));
+        let other = PlayerPosition { x: 4.5, y: -9. };
+        assert!(!can_place(4, 9, &terrain, &[other]));
+        // nowhere to put it
+        let far_down = PlayerPosition {
+            x: 5.,
+            y: -(2. * CHUNK_HEIGHT as f32),
+        };
+        assert!(!far_down.can_place(5, 2 * CHUNK_HEIGHT, 3., &terrain, []));
+    }
+
+    #[test]
+    fn build_mode_picks_mining_or_placing() {
+        use super::client::BuildMode;
+
+        let mining = BuildMode { placing: false };
+        assert_eq!(mining.actions(true, false), (true, false));
+        assert_eq!(mining.actions(false, true), (false, true));
+
+        // the mine binding places, and nothing mines
+        let placing = BuildMode { placing: true };
+        assert_eq!(placing.actions(true, false), (false, true));
+        assert_eq!(placing.actions(false, true), (false, true));
+        assert_eq!(placing.actions(false, false), (false, false));
+    }
+
+    #[test]
+    fn hotbar_selection_wraps() {
+        use super::client::{hotbar_blocks, SelectedSlot};
+
+        let blocks = hotbar_blocks();
+        let last = blocks.len() - 1;
+        let mut selected = SelectedSlot::default();
+        assert_eq!(selected.block_type(), blocks[0]);
+
+        // left of the first cell is the last
+        selected.step(-1);
+        assert_eq!(selected, SelectedSlot(last));
+        assert_eq!(selected.block_type(), blocks[last]);
+
+        // and right of the last is the first
+        selected.step(1);
+        assert_eq!(selected, SelectedSlot(0));
+        selected.step(2);
+        assert_eq!(selected.block_type(), blocks[2]);
+    }
+
+    #[test]
+    fn cursor_picks_the_block_under_it() {
+        use super::client::{block_under_cursor, CameraBoundsBox, CameraZoom};
+
+        let camera_box = CameraBoundsBox {
+            center_coord: Vec3::new(10. * PLAYER_AND_BLOCK_SIZE, -5. * PLAYER_AND_BLOCK_SIZE, 0.),
+        };
+        let center = Vec2::new(WIN_W / 2., WIN_H / 2.);
+
+        // the middle of the window is where the camera is
+        let zoom = CameraZoom::default();
+        assert_eq!(block_under_cursor(center, &camera_box, &zoom), (10, 5));
+        // window y points up, block y points down
+        let offset = Vec2::new(PLAYER_AND_BLOCK_SIZE, PLAYER_AND_BLOCK_SIZE);
+        assert_eq!(
+            block_under_cursor(center + offset, &camera_box, &zoom),
+            (11, 4)
+        );
+        // less than half a block away still picks the same block
+        let nudge = Vec2::splat(PLAYER_AND_BLOCK_SIZE * 0.4);
+        assert_eq!(
+            block_under_cursor(center - nudge, &camera_box, &zoom),
+            (10, 5)
+        );
+        // zoomed out, the same distance on screen covers more blocks
+        let zoom = CameraZoom(2.);
+        assert_eq!(
+            block_under_cursor(center + offset, &camera_box, &zoom),
+            (12, 3)
+        );
+    }
+
+    #[test]
+    fn mining_estimate_follows_the_server() {
+        use super::client::MiningEstimate;
+
+        let mut estimate = MiningEstimate::default();
+        let limestone = Some(((5, 10), BlockType::Limestone));
+        let tick = 1. / NETWORK_TICK_HZ as f32;
+
+        // one tick of guessing moves as far as one tick of mining on the server
+        estimate.advance(limestone, tick);
+        let server_fraction = tick / BlockType::Limestone.mine_duration();
+        assert!((estimate.fraction - server_fraction).abs() < 1e-6);
+
+        // the server's value wins, e.g. when someone else is mining the same block
+        estimate.snap(0.5);
+        assert_eq!(estimate.fraction, 0.5);
+        estimate.advance(limestone, tick);
+        assert!(estimate.fraction > 0.5);
+
+        // the block broke
+        estimate.block_deleted(5, 10);
+        assert_eq!(estimate.fraction, 0.);
+
+        // moving on or letting go starts over
+        estimate.advance(limestone, 1.);
+        estimate.advance(Some(((6, 10), BlockType::Limestone)), tick);
+        assert_eq!(estimate.target, Some((6, 10)));
+        assert!(estimate.fraction < 0.5);
+        estimate.advance(None, tick);
+        assert_eq!(estimate, MiningEstimate::default());
+    }
+
+    #[test]
+    fn zoom_is_clamped() {
+        use super::client::clamp_zoom;
+
+        assert_eq!(clamp_zoom(1.), 1.);
+        assert_eq!(
+            clamp_zoom(CAMERA_ZOOM_MIN - CAMERA_ZOOM_STEP),
+            CAMERA_ZOOM_MIN
+        );
+        assert_eq!(
+            clamp_zoom(CAMERA_ZOOM_MAX + CAMERA_ZOOM_STEP),
+            CAMERA_ZOOM_MAX
+        );
+        assert_eq!(clamp_zoom(0.), CAMERA_ZOOM_MIN);
+    }
+}
@@ -1,14 +1,17 @@
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
+    sync::OnceLock,
 };
 
 use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use rand_distr::{Binomial, Distribution};
 
-use crate::world::{BiomeType, BlockType, Cave, OreType, Vein, CHUNK_HEIGHT, CHUNK_WIDTH};
+use crate::world::{BiomeType, BlockType, Cave, Vein, CHUNK_HEIGHT, CHUNK_WIDTH};
 
 const FREQUENCY: f32 = 4.;
+/// Octaves of noise summed for caves, each at twice the frequency and half the weight of the one before
+pub const CAVE_NOISE_OCTAVES: u32 = 3;
 
 pub fn generate_seed(base_seed: u64, additional_data: Vec<u64>) -> u64 {
     let mut s = DefaultHasher::new();
@@ -40,22 +43,36 @@ pub fn perlin_slice(seed: u64, density: usize, width: usize, height: usize) -> V
     return slice;
 }
 
+/// Roughly how many veins start in each chunk
+const APPROX_VEINS_PER_CHUNK: f64 = 16.0;
+
+// Treat it as if every block of a chunk has a % chance of originating an ore vein
+// only depends on constants, so build it once instead of on every call
+fn vein_count_distribution() -> &'static Binomial {
+    static DISTRIBUTION: OnceLock<Binomial> = OnceLock::new();
+    DISTRIBUTION.get_or_init(|| {
+        Binomial::new(
+            (CHUNK_WIDTH * CHUNK_HEIGHT) as u64,
+            APPROX_VEINS_PER_CHUNK / (CHUNK_WIDTH * CHUNK_HEIGHT) as f64,
+        )
+        .unwrap()
+    })
+}
+
 //Generates a random count of veins for a chunk using a normal distribution
 pub fn generate_random_vein_count(seed: u64, chunk_number: u64) -> u64 {
-    let approx_veins_per_chunk = 16.0;
-    // Treat it as if every block of a chunk has a % chance of originating an ore vein
     let mut rand = StdRng::seed_from_u64(generate_seed(seed, vec![chunk_number]));
-    let bindist = Binomial::new(
-        (CHUNK_WIDTH * CHUNK_HEIGHT) as u64,
-        approx_veins_per_chunk / (CHUNK_WIDTH * CHUNK_HEIGHT) as f64,
-    )
-    .unwrap();
-    let value = bindist.sample(&mut rand);
-    value
+    vein_count_distribution().sample(&mut rand)
 }
 
 //Generates random vein with a random start coordinate, end coordinate, and thickness
-pub fn generate_random_vein(seed: u64, chunk_number: u64, vein_number: u64) -> Vein {
+//Its ore is picked from biome_at the start, which gives the biome at an x, y in the chunk
+pub fn generate_random_vein(
+    seed: u64,
+    chunk_number: u64,
+    vein_number: u64,
+    biome_at: &impl Fn(usize, usize) -> BiomeType,
+) -> Vein {
     let mut rand = StdRng::seed_from_u64(generate_seed(seed, vec![chunk_number, vein_number]));
 
     // Generate random start coordinate
@@ -70,6 +87,8 @@ pub fn generate_random_vein(seed: u64, chunk_number: u64, vein_number: u64) -> V
 
     let thickness_sq: f32 = rand.gen_range(1.0..3.0);
 
+    let ore = biome_at(start_x, start_y).pick_ore(rand.gen(), chunk_number);
+
     /* info!(
         "Generated vein from {},{} to {},{} in chunk {} with thickness_sq {}",
         start_x,
@@ -81,16 +100,59 @@ pub fn generate_random_vein(seed: u64, chunk_number: u64, vein_number: u64) -> V
     ); */
 
     Vein {
-        ore_type: OreType::Primary,
         chunk_number,
         start_x,
         start_y,
         end_x,
         end_y,
         thickness_sq,
+        ore,
     }
 }
 
+/// Closest that two veins in the same chunk are allowed to start, in blocks
+pub const MIN_VEIN_SPACING: f32 = 8.;
+/// How many times a vein that starts too close to another is rerolled before it's left out
+const MAX_VEIN_REROLLS: u64 = 8;
+
+/// Generates count veins for a chunk, rerolling any vein that starts within MIN_VEIN_SPACING of
+/// an earlier one so they don't clump; a vein that is still too close after MAX_VEIN_REROLLS is
+/// dropped, so there can be fewer than count
+pub fn generate_spaced_veins(
+    seed: u64,
+    chunk_number: u64,
+    count: u64,
+    biome_at: &impl Fn(usize, usize) -> BiomeType,
+) -> Vec<Vein> {
+    let too_close = |veins: &[Vein], vein: &Vein| {
+        veins.iter().any(|other| {
+            let dx = other.start_x as f32 - vein.start_x as f32;
+            let dy = other.start_y as f32 - vein.start_y as f32;
+            dx * dx + dy * dy < MIN_VEIN_SPACING * MIN_VEIN_SPACING
+        })
+    };
+
+    let mut veins: Vec<Vein> = Vec::new();
+    for vein_number in 0..count {
+        // the first try uses the plain seed, so well spaced veins are the same as before
+        let spaced = (0..=MAX_VEIN_REROLLS)
+            .map(|attempt| {
+                let attempt_seed = if attempt == 0 {
+                    seed
+                } else {
+                    generate_seed(seed, vec![attempt])
+                };
+                generate_random_vein(attempt_seed, chunk_number, vein_number, biome_at)
+            })
+            .find(|vein| !too_close(&veins, vein));
+
+        if let Some(vein) = spaced {
+            veins.push(vein);
+        }
+    }
+    veins
+}
+
 //Get the value (float) of a position X
 pub fn slice_pos_x(x: usize, r: &Vec<i32>) -> f32 {
     //Do this so we can generate spaces between points
@@ -110,7 +172,8 @@ fn dist_sq(x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
     ((x1 - x2).powf(2.0) + (y1 - y2).powf(2.0)).into()
 }
 
-pub fn dist_to_vein(vein: &Vein, x: f32, y: f32) -> f32 {
+/// Squared distance from a point to the vein's center line, compare it against Vein::radius_sq
+pub fn dist_sq_to_vein(vein: &Vein, x: f32, y: f32) -> f32 {
     // Get distance from point to line segment
     // Adapted from https://stackoverflow.com/a/1501725/1474787
     // Do all necessary casting first for readability's sake
@@ -188,7 +251,7 @@ pub fn generate_chunk_biome_change(seed: u64, chunk_number: u64) -> Option<Biome
 }
 
 pub fn generate_random_cave(seed: u64, chunk_number: u64) -> Cave {
-    let cave_map = generate_perlin_noise(chunk_number, seed);
+    let cave_map = generate_fractal_noise(chunk_number, seed, CAVE_NOISE_OCTAVES);
 
     return Cave {
         block_type: BlockType::CaveVoid,
@@ -197,28 +260,47 @@ pub fn generate_random_cave(seed: u64, chunk_number: u64) -> Cave {
     };
 }
 
-pub fn generate_perlin_noise(chunk_number: u64, seed: u64) -> [[f32; CHUNK_WIDTH]; CHUNK_HEIGHT] {
+/// Perlin noise summed over octaves (fBm), each octave at twice the frequency and half the
+/// weight of the one before, scaled back to the range of a single octave
+/// Sampled by position in the whole world rather than in the chunk, so the noise carries on
+/// smoothly from the bottom row of one chunk into the top row of the next
+pub fn generate_fractal_noise(
+    chunk_number: u64,
+    seed: u64,
+    octaves: u32,
+) -> [[f32; CHUNK_WIDTH]; CHUNK_HEIGHT] {
     let mut noise_map = [[0. as f32; CHUNK_WIDTH]; CHUNK_HEIGHT];
 
     let p = generate_perlin_hash_table(seed);
+    let total_weight: f32 = (0..octaves).map(|octave| 0.5f32.powi(octave as i32)).sum();
 
     for chunk_x in 0..CHUNK_WIDTH {
         for chunk_y in 0..CHUNK_HEIGHT {
             let phys_y = (chunk_number as usize * CHUNK_HEIGHT) + chunk_y;
 
-            let x = chunk_x as f32 / CHUNK_WIDTH as f32;
-            let y = phys_y as f32 / CHUNK_HEIGHT as f32;
+            let n: f32 = (0..octaves)
+                .map(|octave| {
+                    let frequency = FREQUENCY * 2f32.powi(octave as i32);
+                    0.5f32.powi(octave as i32) * noise_at(chunk_x, phys_y, frequency, &p)
+                })
+                .sum();
 
-            let n = noise((x * FREQUENCY) as f32, (y * FREQUENCY) as f32, p);
-
-            noise_map[chunk_y][chunk_x] = n;
+            noise_map[chunk_y][chunk_x] = n / total_weight;
         }
     }
 
     return noise_map;
 }
 
-pub fn noise(x: f32, y: f32, p: [usize; 512]) -> f32 {
+/// Noise at a block, by its x and its y counted down from the top of the world
+/// frequency is how many noise cells fit across a chunk
+fn noise_at(x: usize, phys_y: usize, frequency: f32, p: &[usize; 512]) -> f32 {
+    let x = x as f32 / CHUNK_WIDTH as f32;
+    let y = phys_y as f32 / CHUNK_HEIGHT as f32;
+    noise(x * frequency, y * frequency, p)
+}
+
+pub fn noise(x: f32, y: f32, p: &[usize; 512]) -> f32 {
     let xi = x.floor() as usize & 255;
     let yi = y.floor() as usize & 255;
 
@@ -282,3 +364,78 @@ pub fn generate_perlin_hash_table(seed: u64) -> [usize; 512] {
 
     return hash_table;
 }
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cave_noise_is_continuous_across_chunks() {
+        let seed = 82981925813;
+        for octaves in [1, CAVE_NOISE_OCTAVES] {
+            for chunk_number in 0..6 {
+                let above = generate_fractal_noise(chunk_number, seed, octaves);
+                let below = generate_fractal_noise(chunk_number + 1, seed, octaves);
+
+                // stepping over the boundary changes the noise no more than stepping between
+                // any two rows inside a chunk does
+                let biggest_step = |map: &[[f32; CHUNK_WIDTH]; CHUNK_HEIGHT]| {
+                    map.windows(2)
+                        .flat_map(|rows| rows[0].iter().zip(rows[1].iter()))
+                        .map(|(a, b)| (a - b).abs())
+                        .fold(0., f32::max)
+                };
+                let boundary_step = above[CHUNK_HEIGHT - 1]
+                    .iter()
+                    .zip(below[0].iter())
+                    .map(|(a, b)| (a - b).abs())
+                    .fold(0., f32::max);
+                assert!(boundary_step <= biggest_step(&above).max(biggest_step(&below)));
+            }
+        }
+    }
+
+    #[test]
+    fn vein_count_matches_per_call_distribution() {
+        for seed in [0, 1, 82981925813, u64::MAX] {
+            for chunk_number in 0..20 {
+                // how the count used to be computed, with a new distribution every call
+                let mut rand = StdRng::seed_from_u64(generate_seed(seed, vec![chunk_number]));
+                let expected = Binomial::new(
+                    (CHUNK_WIDTH * CHUNK_HEIGHT) as u64,
+                    16.0 / (CHUNK_WIDTH * CHUNK_HEIGHT) as f64,
+                )
+                .unwrap()
+                .sample(&mut rand);
+
+                assert_eq!(generate_random_vein_count(seed, chunk_number), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn veins_keep_their_distance() {
+        let sedimentary = |_: usize, _: usize| BiomeType::Sedimentary;
+        for chunk_number in 0..20 {
+            let count = generate_random_vein_count(82981925813, chunk_number);
+            let veins = generate_spaced_veins(82981925813, chunk_number, count, &sedimentary);
+            assert!(veins.len() as u64 <= count);
+            assert!(!veins.is_empty());
+
+            for (i, a) in veins.iter().enumerate() {
+                for b in &veins[i + 1..] {
+                    let dx = a.start_x as f32 - b.start_x as f32;
+                    let dy = a.start_y as f32 - b.start_y as f32;
+                    assert!((dx * dx + dy * dy).sqrt() >= MIN_VEIN_SPACING);
+                }
+            }
+
+            // still deterministic
+            assert_eq!(
+                veins,
+                generate_spaced_veins(82981925813, chunk_number, count, &sedimentary)
+            );
+        }
+    }
+}
@@ -1,23 +1,51 @@
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+};
 use bincode::{Decode, Encode};
+use futures_lite::future;
 use iyes_loopless::prelude::*;
 use std::{
-    fs::{create_dir_all, read, File},
-    io::Write,
+    fs::{create_dir_all, read, read_dir, remove_file, rename, File},
+    io::{ErrorKind, Write},
     net::SocketAddr,
     path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
     args::ServerArgs,
-    network::{ClientAddress, BINCODE_CONFIG},
-    player::{Inventory, PlayerInput, PlayerPosition},
+    network::{ClientAddress, PlayerName, BINCODE_CONFIG},
+    player::{server::unstick_position, Inventory, PlayerInput, PlayerPosition, PlayerStats},
     states,
-    world::Terrain,
+    world::{server::generation_settings, BiomeCache, Block, BlockType, Chunk, Terrain, WorldSeed},
 };
 
 pub const DEFAULT_SAVE_DIR: &str = "savedata";
 pub const DEFAULT_SAVE_FILE_SERVER: &str = "server.sav";
+/// Save slots are files in DEFAULT_SAVE_DIR with this extension
+pub const SAVE_EXTENSION: &str = "sav";
+/// Copies of the previous save kept next to it, the oldest is deleted to make room
+pub const MAX_SAVE_BACKUPS: usize = 3;
+const BACKUP_EXTENSION: &str = "bak";
+/// Saves are written to a file with this extension next to the save, then moved over it
+const TEMP_EXTENSION: &str = "tmp";
+/// Seconds between autosaves
+pub const DEFAULT_SAVE_INTERVAL_SECS: u64 = 5;
+/// Longest allowed time between autosaves, an hour of lost progress is plenty
+pub const MAX_SAVE_INTERVAL_SECS: u64 = 60 * 60;
+/// Autosaves that can fail in a row before autosave gives up
+pub const MAX_SAVE_FAILURES: u32 = 3;
+/// First thing in every save file, "KKSV"
+pub const SAVE_MAGIC: u32 = u32::from_be_bytes(*b"KKSV");
+/// Bump whenever the encoding of SaveFile changes, and teach migrate to read the old one
+pub const SAVE_VERSION: u16 = 2;
+/// Most memory decoding a save may claim, so that a file that isn't one can't make us
+/// allocate gigabytes when we try to read it as an old save
+const LOAD_LIMIT: usize = 1 << 30;
+/// Saves from before the header start with their number of players where SAVE_MAGIC would be,
+/// which is never anywhere near this many
+const MAX_LEGACY_PLAYERS: u32 = u16::MAX as u32;
 
 pub fn default_save_path_server() -> PathBuf {
     Path::new(".")
@@ -25,22 +53,68 @@ pub fn default_save_path_server() -> PathBuf {
         .join(DEFAULT_SAVE_FILE_SERVER)
 }
 
+/// Where the save slot with this name is kept
+pub fn slot_path(name: &str) -> PathBuf {
+    Path::new(".")
+        .join(DEFAULT_SAVE_DIR)
+        .join(format!("{}.{}", name, SAVE_EXTENSION))
+}
+
+/// Names of the save slots in the save folder, sorted
+pub fn list_saves() -> Vec<String> {
+    list_saves_in(&Path::new(".").join(DEFAULT_SAVE_DIR))
+}
+
+/// Names of the save files in dir, sorted; empty if it can't be read
+fn list_saves_in(dir: &Path) -> Vec<String> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|e| e == SAVE_EXTENSION))
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect();
+    names.sort();
+    names
+}
+
 pub mod server {
     use super::*;
 
-    pub struct SaveLoadPlugin;
+    pub struct SaveLoadPlugin {
+        /// seconds between autosaves, 0 disables autosaving
+        pub save_interval: u64,
+    }
 
     impl Plugin for SaveLoadPlugin {
         fn build(&self, app: &mut App) {
+            app.init_resource::<SaveFailures>()
+                .init_resource::<SaveInProgress>()
+                .add_system(
+                    finish_save
+                        .run_in_state(states::server::GameState::Running)
+                        .label("finish_save"),
+                );
+
             // save
-            app.add_fixed_timestep(std::time::Duration::from_secs(5), "SAVE_INTERVAL");
-            app.add_fixed_timestep_system(
-                "SAVE_INTERVAL",
-                0,
-                save_server
-                    .run_in_state(states::server::GameState::Running)
-                    .label("save_server"),
-            );
+            if self.save_interval > 0 {
+                app.add_fixed_timestep(
+                    std::time::Duration::from_secs(self.save_interval),
+                    "SAVE_INTERVAL",
+                );
+                app.add_fixed_timestep_system(
+                    "SAVE_INTERVAL",
+                    0,
+                    save_server
+                        .run_in_state(states::server::GameState::Running)
+                        .label("save_server"),
+                );
+            } else {
+                warn!("autosave is disabled, the world is only saved when the server stops");
+            }
 
             // load on start
             app.add_enter_system(
@@ -51,105 +125,460 @@ pub mod server {
     }
 }
 
+/// Counts saves failing in a row, so that an unwritable save path doesn't fill the log
+/// Should be a resource on the server
+#[derive(Default, Debug)]
+pub struct SaveFailures {
+    consecutive: u32,
+    /// Autosave gave up after MAX_SAVE_FAILURES failures in a row
+    pub autosave_disabled: bool,
+}
+
+impl SaveFailures {
+    /// Record whether a save worked, a successful save turns autosave back on
+    /// Returns true only for the failure that disables autosave
+    pub fn record(&mut self, saved: bool) -> bool {
+        if saved {
+            self.consecutive = 0;
+            self.autosave_disabled = false;
+            return false;
+        }
+
+        self.consecutive = self.consecutive.saturating_add(1);
+        if !self.autosave_disabled && self.consecutive >= MAX_SAVE_FAILURES {
+            self.autosave_disabled = true;
+            return true;
+        }
+        false
+    }
+}
+
+/// The save being encoded and written in the background, so a big world doesn't stall the tick
+/// Should be a resource on the server
+#[derive(Default, Debug)]
+pub struct SaveInProgress {
+    /// Whether the save worked, once it finishes
+    task: Option<Task<bool>>,
+    /// Autosaves skipped because the one before was still being written
+    skipped: u32,
+}
+
 /// Helper struct to save and load players
 #[derive(Debug, Encode, Decode)]
 struct PlayerInFile {
     addr: SocketAddr,
     position: PlayerPosition,
     inventory: Inventory,
+    stats: PlayerStats,
+}
+
+/// Written at the start of a save file, so we know how to read the rest
+#[derive(Debug, Encode, Decode)]
+struct SaveHeader {
+    magic: u32,
+    version: u16,
+}
+
+impl Default for SaveHeader {
+    fn default() -> Self {
+        Self {
+            magic: SAVE_MAGIC,
+            version: SAVE_VERSION,
+        }
+    }
 }
 
 /// Struct that get serialized to save the world
 #[derive(Debug, Encode)]
 pub struct SaveFile<'a> {
+    header: SaveHeader,
+    /// so chunks generated after loading match the ones that were saved
+    seed: WorldSeed,
     players: Vec<PlayerInFile>,
     /// reference to the terrain resource
     terrain: &'a Terrain,
 }
 
 /// Struct that gets created whenever we deserialize the save file
-#[derive(Debug, Decode)]
+/// The same as SaveFile after its header
+#[derive(Debug)]
 pub struct LoadFile {
+    /// None for saves from before the seed was saved
+    seed: Option<WorldSeed>,
     players: Vec<PlayerInFile>,
     /// owns a terrain that gets created from the file
     terrain: Terrain,
 }
 
+/// A player as saved before there was a header, before stats were kept
+#[derive(Debug, Decode)]
+struct LegacyPlayerInFile {
+    addr: SocketAddr,
+    position: PlayerPosition,
+    inventory: Inventory,
+}
+
+/// A chunk as saved before there was a header, one Option<BlockType> per cell
+struct LegacyChunk(Chunk);
+
+impl Decode for LegacyChunk {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let mut chunk = Chunk::empty(0);
+        for cell in chunk.blocks.iter_mut().flatten() {
+            let block_type: Option<BlockType> = Decode::decode(decoder)?;
+            *cell = block_type.map(Block::new);
+        }
+        chunk.chunk_number = Decode::decode(decoder)?;
+        Ok(Self(chunk))
+    }
+}
+
+impl Decode for LoadFile {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        Ok(Self {
+            seed: Some(Decode::decode(decoder)?),
+            players: Decode::decode(decoder)?,
+            terrain: Decode::decode(decoder)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// Starts with something other than SAVE_MAGIC that is too big to be a save from before
+    /// there was a header, so it is some other kind of file
+    BadMagic,
+    /// Saved by a newer version of the game, or an old one migrate can't read anymore
+    UnsupportedVersion(u16),
+    DecodeError(bincode::error::DecodeError),
+}
+
+/// Read a save file, migrating it from an older version if needed
+fn decode_save(bytes: &[u8]) -> Result<LoadFile, LoadError> {
+    let (header, header_size) =
+        match bincode::decode_from_slice::<SaveHeader, _>(bytes, BINCODE_CONFIG) {
+            Ok((header, size)) if header.magic == SAVE_MAGIC => (header, size),
+            Ok((header, _)) if header.magic > MAX_LEGACY_PLAYERS => {
+                return Err(LoadError::BadMagic)
+            }
+            // saves from before the header start straight away with the players
+            _ => return migrate(0, bytes),
+        };
+    let body = &bytes[header_size..];
+
+    match header.version {
+        SAVE_VERSION => bincode::decode_from_slice(body, BINCODE_CONFIG.with_limit::<LOAD_LIMIT>())
+            .map(|(load, _size)| load)
+            .map_err(LoadError::DecodeError),
+        version if version < SAVE_VERSION => migrate(version, body),
+        version => Err(LoadError::UnsupportedVersion(version)),
+    }
+}
+
+/// Read the part of a save file after its header, as written by an older version
+fn migrate(version: u16, bytes: &[u8]) -> Result<LoadFile, LoadError> {
+    match version {
+        // no header, players without stats and every cell of a chunk stored on its own
+        0 => bincode::decode_from_slice::<(Vec<LegacyPlayerInFile>, Vec<LegacyChunk>), _>(
+            bytes,
+            BINCODE_CONFIG.with_limit::<LOAD_LIMIT>(),
+        )
+        .map(|((players, chunks), _size)| LoadFile {
+            seed: None,
+            players: players
+                .into_iter()
+                .map(|player| PlayerInFile {
+                    addr: player.addr,
+                    position: player.position,
+                    inventory: player.inventory,
+                    stats: PlayerStats::default(),
+                })
+                .collect(),
+            terrain: chunks.into_iter().map(|chunk| chunk.0).collect(),
+        })
+        .map_err(LoadError::DecodeError),
+        // version 1 is version 2 without the seed
+        1 => bincode::decode_from_slice::<(Vec<PlayerInFile>, Terrain), _>(
+            bytes,
+            BINCODE_CONFIG.with_limit::<LOAD_LIMIT>(),
+        )
+        .map(|((players, terrain), _size)| LoadFile {
+            seed: None,
+            players,
+            terrain,
+        })
+        .map_err(LoadError::DecodeError),
+        version => Err(LoadError::UnsupportedVersion(version)),
+    }
+}
+
+/// Start writing the world to the save file in the background, unless the last save is still going
+/// finish_save picks up the result
 fn save_server(
     terrain: Res<Terrain>,
-    query: Query<(&PlayerPosition, &ClientAddress, &Inventory)>,
+    seed: Res<WorldSeed>,
+    query: Query<(&PlayerPosition, &ClientAddress, &Inventory, &PlayerStats)>,
     args: Res<ServerArgs>,
+    failures: Res<SaveFailures>,
+    mut in_progress: ResMut<SaveInProgress>,
 ) {
-    let mut players_in_file = Vec::<PlayerInFile>::new();
-    for (position, addr, inv) in query.iter() {
-        let player = PlayerInFile {
+    if failures.autosave_disabled {
+        return;
+    }
+    if in_progress.task.is_some() {
+        in_progress.skipped += 1;
+        warn!(
+            "the last save is still being written, skipped {} autosaves so far; consider a longer save interval with -s",
+            in_progress.skipped
+        );
+        return;
+    }
+
+    let players = players_in_file(&query);
+    // a copy, so the world can keep changing while it's written
+    let terrain = terrain.clone();
+    let path = args.save_path();
+    let seed = *seed;
+    let task = AsyncComputeTaskPool::get()
+        .spawn(async move { write_save(&path, seed, players, &terrain) });
+    in_progress.task = Some(task);
+}
+
+/// Save the world one last time as the server stops, waiting until it is written
+/// Runs even with autosave disabled, so nothing since the last autosave is lost
+pub fn save_on_exit(
+    terrain: Res<Terrain>,
+    seed: Res<WorldSeed>,
+    query: Query<(&PlayerPosition, &ClientAddress, &Inventory, &PlayerStats)>,
+    args: Res<ServerArgs>,
+    mut in_progress: ResMut<SaveInProgress>,
+) {
+    // an autosave still being written would race this one for the file
+    if let Some(task) = in_progress.task.take() {
+        future::block_on(task);
+    }
+
+    let path = args.save_path();
+    info!("saving to {} before stopping", path.display());
+    write_save(&path, *seed, players_in_file(&query), &terrain);
+}
+
+/// The players to put in a save file
+fn players_in_file(
+    query: &Query<(&PlayerPosition, &ClientAddress, &Inventory, &PlayerStats)>,
+) -> Vec<PlayerInFile> {
+    query
+        .iter()
+        .map(|(position, addr, inv, stats)| PlayerInFile {
             addr: addr.addr,
             position: position.clone(),
             inventory: inv.clone(),
-        };
-        players_in_file.push(player);
-    }
+            stats: stats.clone(),
+        })
+        .collect()
+}
 
+/// Encode and write a save to path, backing up the one it replaces
+/// Returns whether it worked, errors are logged
+fn write_save(path: &Path, seed: WorldSeed, players: Vec<PlayerInFile>, terrain: &Terrain) -> bool {
     let save_file = SaveFile {
-        players: players_in_file,
-        terrain: terrain.as_ref(),
+        header: SaveHeader::default(),
+        seed,
+        players,
+        terrain,
     };
     // try to encode, allocating a vec
-    // in a real packet, we should use a pre-allocated array and encode into its slice
-    match bincode::encode_to_vec(save_file, BINCODE_CONFIG) {
-        Ok(encoded_vec) => {
-            // creates the savedata folder if it is missing
-            if let Err(e) = create_dir_all(DEFAULT_SAVE_DIR) {
-                error!("unable to create save dir, {}", e);
-                return;
-            }
-            // else it was successful
-
-            // open file in write-mode
-            match File::create(&args.save_file) {
-                Ok(mut file) => {
-                    // write the bytes to file
-                    match file.write_all(&encoded_vec) {
-                        Ok(_) => {
-                            // info!("saved to file!"),
-                        }
-                        Err(e) => error!("could not write to save file, {}", e),
-                    }
-                }
-                Err(e) => {
-                    error!("could not create save file, {}", e);
-                }
-            }
-        }
+    let encoded_vec = match bincode::encode_to_vec(save_file, BINCODE_CONFIG) {
+        Ok(encoded_vec) => encoded_vec,
         Err(e) => {
             error!("unable to encode terrain, {}", e);
+            return false;
+        }
+    };
+
+    // the save being replaced is all there is to go back to if this write is cut short
+    if let Err(e) = backup_save_file(path) {
+        warn!("could not back up {}, {}", path.display(), e);
+    }
+    let result = write_save_file(path, &encoded_vec);
+    if let Err(e) = &result {
+        error!("could not save to {}, {}", path.display(), e);
+    }
+    result.is_ok()
+}
+
+/// Check on the save being written in the background, counting it once it's done
+fn finish_save(
+    mut in_progress: ResMut<SaveInProgress>,
+    mut failures: ResMut<SaveFailures>,
+    args: Res<ServerArgs>,
+) {
+    let saved = match &mut in_progress.task {
+        Some(task) => match future::block_on(future::poll_once(task)) {
+            Some(saved) => saved,
+            // still going
+            None => return,
+        },
+        None => return,
+    };
+    in_progress.task = None;
+
+    if failures.record(saved) {
+        warn!(
+            "saving failed {} times in a row, autosave is now disabled; fix the permissions of {} or pick another save file with -f or --slot",
+            MAX_SAVE_FAILURES,
+            args.save_path().display()
+        );
+    }
+}
+
+/// Write an encoded save to path, creating its folder if it is missing
+/// The save only replaces the old one once it is completely written, so a save that is cut
+/// short never leaves a damaged file at path
+fn write_save_file(path: &Path, encoded: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let temp = temp_path(path);
+    let mut file = File::create(&temp)?;
+    file.write_all(encoded)?;
+    file.sync_all()?;
+    rename(&temp, path)
+}
+
+/// Where the save at path is written before it replaces it, e.g. savedata/server.sav.tmp
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}", name, TEMP_EXTENSION))
+}
+
+/// Where the backup of the save at path taken at this time (milliseconds since the epoch) goes,
+/// e.g. savedata/server.sav.1667000000000.bak
+fn backup_path(path: &Path, millis: u128) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.{}", name, millis, BACKUP_EXTENSION))
+}
+
+/// Backups of the save at path, oldest first
+fn list_backups(path: &Path) -> Vec<PathBuf> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut backups: Vec<(u128, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let millis = file_name
+                .to_str()?
+                .strip_prefix(name.as_ref())?
+                .strip_prefix('.')?
+                .strip_suffix(BACKUP_EXTENSION)?
+                .strip_suffix('.')?
+                .parse()
+                .ok()?;
+            Some((millis, entry.path()))
+        })
+        .collect();
+    backups.sort();
+    backups.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Copy the save at path, if there is one, to a new backup, then delete the oldest backups
+/// so there are at most MAX_SAVE_BACKUPS
+/// A save that can't be read isn't backed up, so it can't push out the backups that can
+fn backup_save_file(path: &Path) -> std::io::Result<()> {
+    let encoded = match read(path) {
+        Ok(encoded) => encoded,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if decode_save(&encoded).is_err() {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "the save there is damaged, keeping the old backups",
+        ));
+    }
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    File::create(backup_path(path, millis))?.write_all(&encoded)?;
+
+    let backups = list_backups(path);
+    for old in &backups[..backups.len().saturating_sub(MAX_SAVE_BACKUPS)] {
+        remove_file(old)?;
+    }
+    Ok(())
+}
+
+/// Log why the save at path couldn't be read
+fn log_load_error(path: &Path, error: LoadError) {
+    let path = path.display();
+    match error {
+        LoadError::BadMagic => error!("{} is not a save file", path),
+        LoadError::UnsupportedVersion(version) => error!(
+            "{} is a version {} save, this server reads up to version {}",
+            path, version, SAVE_VERSION
+        ),
+        LoadError::DecodeError(e) => error!("unable to decode save file {}: {}", path, e),
+    }
+}
+
+/// Read the newest backup of the save at path that can still be read
+fn load_newest_backup(path: &Path) -> Option<LoadFile> {
+    for backup in list_backups(path).iter().rev() {
+        let decoded = match read(backup) {
+            Ok(encoded_vec) => decode_save(&encoded_vec),
+            Err(e) => {
+                error!("could not read backup {}, {}", backup.display(), e);
+                continue;
+            }
+        };
+        match decoded {
+            Ok(load) => {
+                warn!("loaded the backup {} instead", backup.display());
+                return Some(load);
+            }
+            Err(e) => log_load_error(backup, e),
         }
     }
+    error!("no usable backup of {}", path.display());
+    None
 }
 
-/// Load the file
+/// Load the file, the freshly generated world is kept if there isn't one yet
+/// If the file is damaged, its newest working backup is loaded instead
 fn load_server(
     mut commands: Commands,
     players: Query<Entity, With<ClientAddress>>,
     args: Res<ServerArgs>,
 ) {
-    match read(&args.save_file) {
+    let save_path = args.save_path();
+    match read(&save_path) {
         Ok(encoded_vec) => {
             // try to load the world and player
-            let decoded: LoadFile = match bincode::decode_from_slice(&encoded_vec, BINCODE_CONFIG) {
-                Ok((load, _size)) => load,
+            let decoded = match decode_save(&encoded_vec) {
+                Ok(load) => load,
                 Err(e) => {
-                    error!("unable to decode save file: {}", e);
-                    return;
+                    log_load_error(&save_path, e);
+                    match load_newest_backup(&save_path) {
+                        Some(load) => load,
+                        None => return,
+                    }
                 }
             };
 
-            // delete old terrain
-            commands.remove_resource::<Terrain>();
-
-            // insert new terrain
-            commands.insert_resource(decoded.terrain);
-
             // delete all player entities
             for entity in players.iter() {
                 commands.entity(entity).despawn();
@@ -157,11 +586,36 @@ fn load_server(
 
             // spawn entities for each player that we loaded from file
             for player in decoded.players {
-                spawn_player(&mut commands, &player)
+                spawn_player(&mut commands, &player, &decoded.terrain)
+            }
+
+            // delete old terrain
+            commands.remove_resource::<Terrain>();
+
+            // insert new terrain
+            commands.insert_resource(decoded.terrain);
+
+            // keep generating the world the save came from
+            match decoded.seed {
+                Some(seed) => {
+                    info!("loaded world seed {}", seed.0);
+                    let settings = generation_settings(&args, seed);
+                    commands.insert_resource(BiomeCache::new(&settings));
+                    commands.insert_resource(settings);
+                    commands.insert_resource(seed);
+                }
+                None => warn!("save has no seed, new chunks are generated with the current one"),
             }
 
             warn!("loaded from file!");
         }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            info!(
+                "no save at {} yet, starting a new world; saves found: {:?}",
+                save_path.display(),
+                list_saves()
+            );
+        }
         Err(e) => {
             error!("could not read save file, {}", e);
         }
@@ -169,11 +623,336 @@ fn load_server(
 }
 
 /// Spawn in a previously-connected player (from a file)
-fn spawn_player(commands: &mut Commands, player: &PlayerInFile) {
+/// If the terrain has changed under their saved position, they are moved out of it
+fn spawn_player(commands: &mut Commands, player: &PlayerInFile, terrain: &Terrain) {
+    let mut position = player.position.clone();
+    unstick_position(&mut position, terrain);
+
     commands
         .spawn()
         .insert(ClientAddress { addr: player.addr })
-        .insert(player.position.clone())
+        .insert(position)
         .insert(PlayerInput::default())
-        .insert(player.inventory.clone());
+        .insert(player.inventory.clone())
+        .insert(player.stats.clone())
+        // names aren't saved, clients send theirs again when they reconnect
+        .insert(PlayerName::default());
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{CHUNK_HEIGHT, CHUNK_WIDTH};
+
+    #[test]
+    fn stats_survive_save_and_load() {
+        let stats = PlayerStats {
+            blocks_mined: 42,
+            blocks_placed: 3,
+            deaths: 1,
+            play_time_secs: 3600,
+        };
+        let terrain = Terrain::new(1);
+        let save_file = SaveFile {
+            header: SaveHeader::default(),
+            seed: WorldSeed(1234),
+            players: vec![PlayerInFile {
+                addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+                position: PlayerPosition::default(),
+                inventory: Inventory::default(),
+                stats: stats.clone(),
+            }],
+            terrain: &terrain,
+        };
+
+        let encoded = bincode::encode_to_vec(save_file, BINCODE_CONFIG).unwrap();
+        let loaded = decode_save(&encoded).unwrap();
+
+        assert_eq!(loaded.players.len(), 1);
+        assert_eq!(loaded.players[0].stats, stats);
+        assert_eq!(loaded.seed, Some(WorldSeed(1234)));
+    }
+
+    /// An encoded save of a small world with the given header
+    fn encoded_save(header: SaveHeader) -> Vec<u8> {
+        let terrain = Terrain::new(1);
+        let players = vec![PlayerInFile {
+            addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            position: PlayerPosition::default(),
+            inventory: Inventory::default(),
+            stats: PlayerStats::default(),
+        }];
+        // saves only have a seed since version 2
+        let has_seed = header.version >= 2;
+        let mut encoded = bincode::encode_to_vec(header, BINCODE_CONFIG).unwrap();
+        if has_seed {
+            encoded.extend(bincode::encode_to_vec(WorldSeed(1234), BINCODE_CONFIG).unwrap());
+        }
+        encoded.extend(bincode::encode_to_vec(&players, BINCODE_CONFIG).unwrap());
+        encoded.extend(bincode::encode_to_vec(&terrain, BINCODE_CONFIG).unwrap());
+        encoded
+    }
+
+    #[test]
+    fn save_versions_are_checked() {
+        let loaded = decode_save(&encoded_save(SaveHeader::default())).unwrap();
+        assert_eq!(loaded.players.len(), 1);
+        assert_eq!(loaded.terrain.chunks.len(), 1);
+        assert_eq!(loaded.seed, Some(WorldSeed(1234)));
+
+        // from before saves had a seed
+        let version_1 = SaveHeader {
+            version: 1,
+            ..default()
+        };
+        let loaded = decode_save(&encoded_save(version_1)).unwrap();
+        assert_eq!(loaded.players.len(), 1);
+        assert_eq!(loaded.terrain.chunks.len(), 1);
+        assert_eq!(loaded.seed, None);
+
+        // from before saves had a header, see legacy_saves_are_migrated
+        let wrong_magic = SaveHeader {
+            magic: u32::from_be_bytes(*b"PNG\n"),
+            ..default()
+        };
+        assert!(matches!(
+            decode_save(&encoded_save(wrong_magic)),
+            Err(LoadError::BadMagic)
+        ));
+        // starts with a small number, so it is read as a save from before the header
+        assert!(matches!(
+            decode_save(b"not a save file at all"),
+            Err(LoadError::DecodeError(_))
+        ));
+
+        let future = SaveHeader {
+            version: SAVE_VERSION + 1,
+            ..default()
+        };
+        assert!(matches!(
+            decode_save(&encoded_save(future)),
+            Err(LoadError::UnsupportedVersion(version)) if version == SAVE_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn legacy_saves_are_migrated() {
+        // the layout from before the header: players without stats, then every cell of every chunk
+        let mut inventory = Inventory::default();
+        inventory.amounts.insert(BlockType::Coal, 5);
+        let players = vec![(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            PlayerPosition { x: 3., y: -2. },
+            inventory,
+        )];
+        let mut encoded = bincode::encode_to_vec(&players, BINCODE_CONFIG).unwrap();
+        let chunks: u64 = 2;
+        encoded.extend(bincode::encode_to_vec(chunks, BINCODE_CONFIG).unwrap());
+        for chunk_number in 0..chunks {
+            for i in 0..CHUNK_WIDTH * CHUNK_HEIGHT {
+                let cell = (i == 7).then_some(BlockType::Granite);
+                encoded.extend(bincode::encode_to_vec(cell, BINCODE_CONFIG).unwrap());
+            }
+            encoded.extend(bincode::encode_to_vec(chunk_number, BINCODE_CONFIG).unwrap());
+        }
+
+        let loaded = decode_save(&encoded).unwrap();
+        assert_eq!(loaded.seed, None);
+        assert_eq!(loaded.players.len(), 1);
+        assert_eq!(loaded.players[0].inventory.amounts[&BlockType::Coal], 5);
+        assert_eq!(loaded.players[0].stats, PlayerStats::default());
+        assert_eq!(loaded.terrain.chunks.len(), 2);
+        assert_eq!(
+            loaded.terrain.get_block(7, CHUNK_HEIGHT),
+            Some(&Block::new(BlockType::Granite))
+        );
+        assert_eq!(loaded.terrain.get_block(8, CHUNK_HEIGHT), None);
+
+        // cut short, the error says why
+        assert!(matches!(
+            decode_save(&encoded[..encoded.len() / 2]),
+            Err(LoadError::DecodeError(_))
+        ));
+    }
+
+    #[test]
+    fn players_loaded_inside_blocks_are_moved_up() {
+        // solid from row 5 to row 9
+        let mut terrain: Terrain = [Chunk::empty(0)].into_iter().collect();
+        for row in &mut terrain.get_chunk_mut(0).unwrap().blocks[5..10] {
+            *row = [Some(Block {
+                block_type: BlockType::Limestone,
+            }); CHUNK_WIDTH];
+        }
+        let player = PlayerInFile {
+            addr: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            position: PlayerPosition { x: 3., y: -7. },
+            inventory: Inventory::default(),
+            stats: PlayerStats::default(),
+        };
+
+        let mut world = World::new();
+        SystemStage::single_threaded()
+            .with_system(move |mut commands: Commands| {
+                spawn_player(&mut commands, &player, &terrain)
+            })
+            .run(&mut world);
+
+        // standing on top of the blocks, in the first free cell
+        let position = world.query::<&PlayerPosition>().single(&world);
+        assert_eq!((position.x, position.y), (3., -4.));
+    }
+
+    /// ServerArgs saving to path
+    fn args_saving_to(path: &Path) -> ServerArgs {
+        use clap::Parser;
+
+        let args = ["game", "server", "-f", path.to_str().unwrap()];
+        match crate::args::GameArgs::try_parse_from(args).unwrap() {
+            crate::args::GameArgs::Server(args) => args,
+            crate::args::GameArgs::Client(_) => panic!("parsed server args as client args"),
+        }
+    }
+
+    #[test]
+    fn saves_dont_overlap() {
+        let path = std::env::temp_dir().join(format!("game-overlap-{}.sav", std::process::id()));
+        let args = args_saving_to(&path);
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(Terrain::new(1))
+            .insert_resource(WorldSeed::default())
+            .insert_resource(args)
+            .init_resource::<SaveFailures>()
+            .init_resource::<SaveInProgress>();
+        let mut stage = SystemStage::single_threaded().with_system(save_server);
+
+        // the second comes along before anything checks on the first
+        stage.run(&mut app.world);
+        stage.run(&mut app.world);
+        let mut in_progress = app.world.resource_mut::<SaveInProgress>();
+        assert_eq!(in_progress.skipped, 1);
+        let task = in_progress.task.take().expect("no save started");
+
+        assert!(future::block_on(task));
+        let loaded = decode_save(&read(&path).unwrap()).unwrap();
+        assert_eq!(loaded.terrain.chunks.len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn exiting_saves_even_without_autosave() {
+        let path = std::env::temp_dir().join(format!("game-exit-{}.sav", std::process::id()));
+        let mut args = args_saving_to(&path);
+        args.save_interval = 0;
+
+        let mut world = World::new();
+        world.insert_resource(Terrain::new(2));
+        world.insert_resource(WorldSeed::default());
+        world.insert_resource(args);
+        world.init_resource::<SaveInProgress>();
+        SystemStage::single_threaded()
+            .with_system(save_on_exit)
+            .run(&mut world);
+
+        // written by the time the system returns
+        let loaded = decode_save(&read(&path).unwrap()).unwrap();
+        assert_eq!(loaded.terrain.chunks.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn seed_survives_save_and_load() {
+        use crate::world::GenerationSettings;
+
+        let path = std::env::temp_dir().join(format!("game-seed-{}.sav", std::process::id()));
+        assert!(write_save(
+            &path,
+            WorldSeed(77),
+            Vec::new(),
+            &Terrain::new(1)
+        ));
+
+        let mut world = World::new();
+        world.insert_resource(args_saving_to(&path));
+        SystemStage::single_threaded()
+            .with_system(load_server)
+            .run(&mut world);
+
+        assert_eq!(*world.resource::<WorldSeed>(), WorldSeed(77));
+        assert_eq!(world.resource::<GenerationSettings>().seed, 77);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_saves_are_recovered_from_backups() {
+        let dir = std::env::temp_dir().join(format!("game-backups-{}", std::process::id()));
+        let path = dir.join("world.sav");
+        let encoded = encoded_save(SaveHeader::default());
+        write_save_file(&path, &encoded).unwrap();
+        assert!(!temp_path(&path).exists());
+
+        // only the newest few are kept
+        for _ in 0..MAX_SAVE_BACKUPS + 2 {
+            backup_save_file(&path).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let backups = list_backups(&path);
+        assert_eq!(backups.len(), MAX_SAVE_BACKUPS);
+
+        // as if the disk was damaged after saving
+        write_save_file(&path, &encoded[..encoded.len() / 2]).unwrap();
+
+        // the damaged save doesn't push out a working backup
+        assert!(backup_save_file(&path).is_err());
+        assert_eq!(list_backups(&path), backups);
+
+        let mut world = World::new();
+        world.insert_resource(args_saving_to(&path));
+        SystemStage::single_threaded()
+            .with_system(load_server)
+            .run(&mut world);
+
+        assert_eq!(world.resource::<Terrain>().chunks.len(), 1);
+        assert_eq!(world.query::<&ClientAddress>().iter(&world).count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saves_are_listed_by_name() {
+        let dir = std::env::temp_dir().join(format!("game-slots-{}", std::process::id()));
+        assert!(list_saves_in(&dir).is_empty());
+
+        create_dir_all(dir.join("folder.sav")).unwrap();
+        for file in ["world.sav", "caves.sav", "notes.txt"] {
+            File::create(dir.join(file)).unwrap();
+        }
+        assert_eq!(list_saves_in(&dir), ["caves", "world"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn repeated_save_failures_disable_autosave() {
+        let mut failures = SaveFailures::default();
+
+        // warns once, on the failure that crosses the threshold
+        for _ in 1..MAX_SAVE_FAILURES {
+            assert!(!failures.record(false));
+            assert!(!failures.autosave_disabled);
+        }
+        assert!(failures.record(false));
+        assert!(failures.autosave_disabled);
+        assert!(!failures.record(false));
+        assert!(failures.autosave_disabled);
+
+        // a save that works turns it back on and starts counting again
+        assert!(!failures.record(true));
+        assert!(!failures.autosave_disabled);
+        assert!(!failures.record(false));
+        assert!(!failures.autosave_disabled);
+    }
 }
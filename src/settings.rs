@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+use crate::{WIN_H, WIN_W};
+
+/// File the client keeps its settings in, in the working directory
+pub const SETTINGS_FILE: &str = "settings.toml";
+
+/// Window sizes the options menu cycles through, all 16:9 so they show the same part of the world
+pub const RESOLUTIONS: [[u32; 2]; 4] = [[960, 540], [1280, 720], [1600, 900], [1920, 1080]];
+
+/// How much one press of the volume buttons changes the volume
+const VOLUME_STEP: f32 = 0.1;
+
+/// Client settings picked in the options menu; should be a resource
+/// Settings missing from the file keep their defaults
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Window width and height in logical pixels
+    pub resolution: [u32; 2],
+    /// Master volume from 0 (muted) to 1, for sounds to scale themselves by
+    pub volume: f32,
+    /// Darken the edges of the screen while underground, see DepthOverlayPlugin
+    pub depth_overlay: bool,
+    /// Wait for the display to refresh before showing a frame, unless --vsync is given
+    pub vsync: bool,
+    /// Most frames to render per second, unless --fps-cap is given
+    pub fps_cap: Option<u32>,
+    /// Name other players see, unless --name is given
+    pub name: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            resolution: [WIN_W as u32, WIN_H as u32],
+            volume: 1.,
+            depth_overlay: true,
+            vsync: false,
+            fps_cap: None,
+            name: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl Settings {
+    /// Read settings from a TOML file
+    pub fn load(path: &Path) -> Result<Self, SettingsError> {
+        let text = fs::read_to_string(path).map_err(SettingsError::Io)?;
+        toml::from_str(&text).map_err(SettingsError::Parse)
+    }
+
+    /// Settings from `path`, the defaults if it doesn't exist or can't be read
+    pub fn load_or_default(path: &Path) -> Self {
+        match Self::load(path) {
+            Ok(settings) => settings,
+            Err(SettingsError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(SettingsError::Io(e)) => {
+                warn!(
+                    "could not read settings from {}: {}, using the defaults",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }
+            Err(SettingsError::Parse(e)) => {
+                warn!(
+                    "bad settings in {}: {}, using the defaults",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Write settings to a TOML file, replacing it
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self).expect("settings are plain values, always valid TOML");
+        fs::write(path, text)
+    }
+
+    /// Write settings to a TOML file, only logging if that fails
+    pub fn save_or_warn(&self, path: &Path) {
+        match self.save(path) {
+            Ok(()) => info!("saved settings to {}", path.display()),
+            Err(e) => warn!("could not write settings to {}: {}", path.display(), e),
+        }
+    }
+
+    /// Switch to the next size in RESOLUTIONS, going back to the first after the last
+    pub fn next_resolution(&mut self) {
+        let next = RESOLUTIONS
+            .iter()
+            .position(|r| *r == self.resolution)
+            .map_or(0, |i| (i + 1) % RESOLUTIONS.len());
+        self.resolution = RESOLUTIONS[next];
+    }
+
+    /// Change the volume by `steps` presses of the volume buttons, staying between 0 and 1
+    pub fn step_volume(&mut self, steps: i32) {
+        let volume = self.volume + steps as f32 * VOLUME_STEP;
+        // whole steps, so it doesn't drift away from round percentages
+        self.volume = ((volume / VOLUME_STEP).round() * VOLUME_STEP).clamp(0., 1.);
+    }
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_survive_saving() {
+        let path = std::env::temp_dir().join(format!("game-settings-{}.toml", std::process::id()));
+        let settings = Settings {
+            resolution: [1920, 1080],
+            volume: 0.5,
+            depth_overlay: false,
+            vsync: true,
+            fps_cap: Some(30),
+            name: Some(String::from("digger")),
+        };
+
+        settings.save(&path).unwrap();
+        assert_eq!(Settings::load(&path).unwrap(), settings);
+        fs::remove_file(&path).unwrap();
+
+        // nothing saved yet
+        assert_eq!(Settings::load_or_default(&path), Settings::default());
+        // older files might not have everything
+        let partial: Settings = toml::from_str("volume = 0.3").unwrap();
+        assert_eq!(partial.resolution, Settings::default().resolution);
+    }
+
+    #[test]
+    fn options_wrap_and_clamp() {
+        let mut settings = Settings::default();
+
+        // the default is in the list, and the list goes round
+        for _ in 0..RESOLUTIONS.len() {
+            settings.next_resolution();
+        }
+        assert_eq!(settings.resolution, Settings::default().resolution);
+
+        settings.step_volume(1);
+        assert_eq!(settings.volume, 1.);
+        settings.step_volume(-3);
+        assert!((settings.volume - 0.7).abs() < 1e-6);
+        settings.step_volume(-20);
+        assert_eq!(settings.volume, 0.);
+    }
+}
@@ -0,0 +1,197 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+    hud::Hud,
+    network::{ClientAddress, PlayerName},
+    player::client::{Player, RemotePlayers},
+    states::client::GameState,
+    CharacterCamera,
+};
+
+/// Lets the camera follow other players, cycling through them with [ and ]
+pub struct SpectatePlugin;
+
+impl Plugin for SpectatePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Spectate::default())
+            .add_enter_system(GameState::InGame, spawn_spectate_label)
+            .add_system(brackets_cycle_spectate.run_in_state(GameState::InGame))
+            .add_system(
+                follow_spectated
+                    .run_in_state(GameState::InGame)
+                    .after("handle_camera_movement"),
+            )
+            .add_exit_system(GameState::InGame, reset_spectate)
+            .add_exit_system(GameState::Paused, reset_spectate);
+    }
+}
+
+/// Who the camera is following; should be a resource
+#[derive(Default)]
+pub struct Spectate {
+    /// The remote player being followed, None when the camera is on the local player
+    pub target: Option<ClientAddress>,
+}
+
+/// Marker for the "spectating" text
+#[derive(Component)]
+struct SpectateLabel;
+
+/// Next index when stepping through a list of `len` items, wrapping around at both ends
+/// Starts at the first (forward) or last (backward) item when nothing is selected
+pub fn cycle_index(current: Option<usize>, len: usize, forward: bool) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+
+    Some(match current {
+        // the list may have shrunk since we picked this one
+        Some(i) if i < len => {
+            if forward {
+                (i + 1) % len
+            } else {
+                (i + len - 1) % len
+            }
+        }
+        _ if forward => 0,
+        _ => len - 1,
+    })
+}
+
+fn spawn_spectate_label(mut commands: Commands, assets: Res<AssetServer>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: assets.load("fonts/milky_coffee.ttf"),
+                    font_size: 32.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(16.),
+                    bottom: Val::Px(16.),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(SpectateLabel)
+        .insert(Hud);
+}
+
+/// Make ] and [ follow the next and previous remote player, R goes back to the local player
+fn brackets_cycle_spectate(
+    input: Res<Input<KeyCode>>,
+    mut spectate: ResMut<Spectate>,
+    players: Query<(&ClientAddress, &PlayerName), RemotePlayers>,
+    mut labels: Query<&mut Text, With<SpectateLabel>>,
+) {
+    let forward = input.just_pressed(KeyCode::RBracket);
+    let backward = input.just_pressed(KeyCode::LBracket);
+    let stop = input.just_pressed(KeyCode::R);
+    if !forward && !backward && !stop {
+        return;
+    }
+
+    if stop {
+        spectate.target = None;
+    } else {
+        // sort so that the order doesn't depend on when players were spawned
+        let mut addrs: Vec<&ClientAddress> = players.iter().map(|(addr, _)| addr).collect();
+        addrs.sort_by_key(|a| a.addr);
+
+        let current = spectate
+            .target
+            .as_ref()
+            .and_then(|target| addrs.iter().position(|a| *a == target));
+        spectate.target = cycle_index(current, addrs.len(), forward).map(|i| addrs[i].clone());
+    }
+
+    let label = match &spectate.target {
+        Some(target) => {
+            let name = players
+                .iter()
+                .find(|(addr, _)| *addr == target)
+                .map_or_else(|| target.to_string(), |(addr, name)| name.or_address(addr));
+            info!("spectating {}", name);
+            format!("Spectating {}", name)
+        }
+        None => String::new(),
+    };
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = label.clone();
+    }
+}
+
+/// Center the camera on the spectated player, stops spectating if they left
+fn follow_spectated(
+    mut spectate: ResMut<Spectate>,
+    players: Query<(&ClientAddress, &Transform), RemotePlayers>,
+    mut camera: Query<&mut Transform, (With<CharacterCamera>, Without<Player>)>,
+    mut labels: Query<&mut Text, With<SpectateLabel>>,
+) {
+    let target = match &spectate.target {
+        Some(target) => target,
+        None => return,
+    };
+
+    match players.iter().find(|(addr, _)| *addr == target) {
+        Some((_, player)) => {
+            for mut transform in camera.iter_mut() {
+                transform.translation.x = player.translation.x;
+                transform.translation.y = player.translation.y;
+            }
+        }
+        None => {
+            info!("{} is gone, no longer spectating", target);
+            spectate.target = None;
+            for mut text in labels.iter_mut() {
+                text.sections[0].value.clear();
+            }
+        }
+    }
+}
+
+fn reset_spectate(
+    mut commands: Commands,
+    mut spectate: ResMut<Spectate>,
+    query: Query<Entity, With<SpectateLabel>>,
+) {
+    *spectate = Spectate::default();
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_index_wraps() {
+        // nobody to spectate
+        assert_eq!(cycle_index(None, 0, true), None);
+        assert_eq!(cycle_index(Some(2), 0, false), None);
+
+        // start at either end
+        assert_eq!(cycle_index(None, 3, true), Some(0));
+        assert_eq!(cycle_index(None, 3, false), Some(2));
+
+        // step and wrap around
+        assert_eq!(cycle_index(Some(0), 3, true), Some(1));
+        assert_eq!(cycle_index(Some(2), 3, true), Some(0));
+        assert_eq!(cycle_index(Some(1), 3, false), Some(0));
+        assert_eq!(cycle_index(Some(0), 3, false), Some(2));
+        assert_eq!(cycle_index(Some(0), 1, true), Some(0));
+
+        // the list shrank under us
+        assert_eq!(cycle_index(Some(5), 3, true), Some(0));
+        assert_eq!(cycle_index(Some(5), 3, false), Some(2));
+    }
+}
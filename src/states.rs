@@ -35,10 +35,21 @@ pub mod client {
     pub enum GameState {
         ///menu
         Menu,
+        /// Connected to the server, waiting for it to send the world
+        Loading,
         /// Main game loop, game world exists with player
         InGame,
+        /// Pause menu over the game, which keeps running underneath
+        /// Set and unset through CurrentState directly, so InGame's enter and exit systems
+        /// don't run on pausing and resuming; anything torn down on leaving InGame has to be
+        /// on leaving Paused too
+        Paused,
         /// Credits screen
         Credits,
+        /// Options screen, opened from the main menu
+        Options,
+        /// Picking the server to connect to, between the main menu and loading
+        Connect,
     }
 
     /// Initial GameState
@@ -59,6 +70,19 @@ pub mod client {
         }
     }
 
+    /// Run condition for systems that talk to the server, which happens while loading and in game
+    pub fn connected_to_server(state: Res<CurrentState<GameState>>) -> bool {
+        matches!(
+            state.0,
+            GameState::Loading | GameState::InGame | GameState::Paused
+        )
+    }
+
+    /// Run condition for systems that keep the game world up to date, even while paused
+    pub fn in_game(state: Res<CurrentState<GameState>>) -> bool {
+        matches!(state.0, GameState::InGame | GameState::Paused)
+    }
+
     /// Simple system to facilitate changing GameState via F1 key
     /// TODO: This is good enough for debugging, but should be reworked eventually
     fn input_state_change(
@@ -68,9 +92,13 @@ pub mod client {
     ) {
         if input.just_pressed(KeyCode::F1) {
             let new_state = match state.0 {
-                GameState::Menu => GameState::InGame,
+                GameState::Menu => GameState::Loading,
+                GameState::Loading => GameState::Menu,
                 GameState::Credits => GameState::Menu,
                 GameState::InGame => GameState::Credits,
+                GameState::Paused => GameState::Menu,
+                GameState::Options => GameState::Menu,
+                GameState::Connect => GameState::Menu,
             };
             info!(
                 "attempting to change GameState from {:?} to {:?}",
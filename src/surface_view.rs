@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{
+    player::{
+        client::{CameraBoundsBox, CameraZoom, LocalPlayer},
+        PLAYER_AND_BLOCK_SIZE,
+    },
+    states::client::GameState,
+    world::{to_world_point_x, to_world_point_y, CHUNK_HEIGHT, CHUNK_WIDTH},
+    CharacterCamera, WIN_H, WIN_W,
+};
+
+/// How quickly the camera moves to its target, higher is faster
+const SURFACE_VIEW_SPEED: f32 = 5.;
+/// How close (in pixels and scale) the camera must be to its target to stop moving
+const SURFACE_VIEW_EPSILON: f32 = 0.01;
+
+/// Zooms the camera out to show the whole surface chunk
+pub struct SurfaceViewPlugin;
+
+impl Plugin for SurfaceViewPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SurfaceView::default())
+            .add_system(v_toggles_surface_view.run_in_state(GameState::InGame))
+            .add_system(
+                animate_surface_view
+                    .run_in_state(GameState::InGame)
+                    .after("handle_camera_movement"),
+            )
+            .add_exit_system(GameState::InGame, reset_surface_view)
+            .add_exit_system(GameState::Paused, reset_surface_view);
+    }
+}
+
+/// State of the surface view; should be a resource
+#[derive(Default)]
+pub struct SurfaceView {
+    /// Whether we are showing the surface
+    pub enabled: bool,
+    /// Whether the camera is still being moved by us, stays true while enabled
+    animating: bool,
+}
+
+/// Camera center and scale that fit the rectangle from min to max in a viewport of the given size
+/// A scale above 1 zooms out
+pub fn frame_extent(min: Vec2, max: Vec2, viewport: Vec2) -> (Vec2, f32) {
+    let center = (min + max) / 2.;
+    let size = max - min;
+    let scale = (size.x / viewport.x).max(size.y / viewport.y);
+    (center, scale)
+}
+
+/// Camera center and scale that show the whole surface chunk
+fn surface_framing() -> (Vec2, f32) {
+    // block positions are their centers
+    let half_block = PLAYER_AND_BLOCK_SIZE / 2.;
+    let min = Vec2::new(
+        to_world_point_x(0) - half_block,
+        to_world_point_y(CHUNK_HEIGHT - 1, 0) - half_block,
+    );
+    let max = Vec2::new(
+        to_world_point_x(CHUNK_WIDTH - 1) + half_block,
+        to_world_point_y(0, 0) + half_block,
+    );
+    frame_extent(min, max, Vec2::new(WIN_W, WIN_H))
+}
+
+/// Make the V key toggle the surface view
+fn v_toggles_surface_view(input: Res<Input<KeyCode>>, mut view: ResMut<SurfaceView>) {
+    if input.just_pressed(KeyCode::V) {
+        view.enabled = !view.enabled;
+        view.animating = true;
+        info!("surface view enabled: {}", view.enabled);
+    }
+}
+
+/// Smoothly move the camera to the surface, or back to the player once disabled
+/// Scales the camera transform rather than the projection, so camera children keep covering the screen
+fn animate_surface_view(
+    time: Res<Time>,
+    zoom: Res<CameraZoom>,
+    mut view: ResMut<SurfaceView>,
+    bounds: Query<&CameraBoundsBox, With<LocalPlayer>>,
+    mut camera: Query<&mut Transform, With<CharacterCamera>>,
+) {
+    if !view.animating {
+        return;
+    }
+
+    let (target_center, target_scale) = if view.enabled {
+        surface_framing()
+    } else {
+        // back to wherever the player had zoomed to
+        match bounds.get_single() {
+            Ok(bounds) => (bounds.center_coord.truncate(), zoom.0),
+            Err(_) => (Vec2::ZERO, zoom.0),
+        }
+    };
+
+    let t = (SURFACE_VIEW_SPEED * time.delta_seconds()).min(1.);
+
+    for mut transform in camera.iter_mut() {
+        let center = transform.translation.truncate().lerp(target_center, t);
+        let scale = transform.scale.x + (target_scale - transform.scale.x) * t;
+
+        let done = center.distance(target_center) < SURFACE_VIEW_EPSILON
+            && (scale - target_scale).abs() < SURFACE_VIEW_EPSILON;
+
+        if done {
+            transform.translation = target_center.extend(transform.translation.z);
+            transform.scale = Vec3::new(target_scale, target_scale, 1.);
+            // hand the camera back once we're back at the player
+            if !view.enabled {
+                view.animating = false;
+            }
+        } else {
+            transform.translation = center.extend(transform.translation.z);
+            transform.scale = Vec3::new(scale, scale, 1.);
+        }
+    }
+}
+
+/// Leave the surface view (instantly) when leaving the game
+fn reset_surface_view(
+    mut view: ResMut<SurfaceView>,
+    mut camera: Query<&mut Transform, With<CharacterCamera>>,
+) {
+    *view = SurfaceView::default();
+    for mut transform in camera.iter_mut() {
+        transform.scale = Vec3::ONE;
+    }
+}
+
+/// unit tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing_fits_extent() {
+        let viewport = Vec2::new(WIN_W, WIN_H);
+
+        // wider than the viewport
+        let (center, scale) =
+            frame_extent(Vec2::new(-100., -50.), Vec2::new(3900., 450.), viewport);
+        assert_eq!(center, Vec2::new(1900., 200.));
+        assert_eq!(scale, 4000. / WIN_W);
+        assert!(viewport.y * scale >= 500.);
+
+        // taller than the viewport
+        let (center, scale) = frame_extent(Vec2::new(0., -2000.), Vec2::new(100., 0.), viewport);
+        assert_eq!(center, Vec2::new(50., -1000.));
+        assert_eq!(scale, 2000. / WIN_H);
+        assert!(viewport.x * scale >= 100.);
+
+        // the whole surface chunk is visible
+        let (_, scale) = surface_framing();
+        assert!(viewport.x * scale >= CHUNK_WIDTH as f32 * PLAYER_AND_BLOCK_SIZE);
+        assert!(viewport.y * scale >= CHUNK_HEIGHT as f32 * PLAYER_AND_BLOCK_SIZE);
+    }
+}
@@ -1,17 +1,18 @@
 use crate::{
     network::BINCODE_CONFIG,
     procedural_functions::{
-        self, dist_to_vein, generate_perlin_noise, generate_random_cave, generate_random_vein,
-        generate_random_vein_count,
+        self, dist_sq_to_vein, generate_fractal_noise, generate_random_cave,
+        generate_random_vein_count, generate_spaced_veins, CAVE_NOISE_OCTAVES,
     },
     states,
 };
 use bevy::prelude::*;
-use bincode::{BorrowDecode, Decode, Encode};
+use bincode::{Decode, Encode};
 use iyes_loopless::prelude::*;
+use std::collections::{HashMap, HashSet};
 use strum_macros::EnumIter;
 
-use crate::player::PlayerPosition;
+use crate::player::{PlayerPosition, PLAYER_MINE_DURATION};
 
 pub const CHUNK_HEIGHT: usize = 64;
 pub const CHUNK_WIDTH: usize = 128;
@@ -19,11 +20,129 @@ pub const CHUNK_WIDTH: usize = 128;
 // how many chunks should always be generated below the lowest player
 const GEN_CHUNKS_AHEAD: u64 = 3;
 
-const BASE_SEED: u64 = 82981925813;
+/// The server drops chunks that are further than this many chunks from every connected player
+pub const CHUNK_UNLOAD_DISTANCE: u64 = 2;
+
+// otherwise chunks would be dropped right after they are generated
+const _: () = assert!(GEN_CHUNKS_AHEAD - 1 <= CHUNK_UNLOAD_DISTANCE);
+
+/// How many chunks the server generates on startup, including the surface
+pub const DEFAULT_INITIAL_CHUNKS: u64 = 2;
+/// Most chunks that can be generated on startup
+pub const MAX_INITIAL_CHUNKS: u64 = 64;
+
+/// Seed used when none is picked, by tests and the client's debug chunks
+pub const DEFAULT_SEED: u64 = 82981925813;
+/// Mixed into a surface seed when picking the surface biomes
+const SURFACE_BIOME_SEED: u64 = 20471;
+/// Surface biomes that a seed can pick from, the first one is the usual world
+const SURFACE_CHOICES: [SurfaceBiomes; 4] = [
+    SurfaceBiomes {
+        top: BiomeType::Sand,
+        bottom: BiomeType::Sedimentary,
+    },
+    SurfaceBiomes {
+        top: BiomeType::Sand,
+        bottom: BiomeType::Basalt,
+    },
+    SurfaceBiomes {
+        top: BiomeType::Sand,
+        bottom: BiomeType::Felsic,
+    },
+    SurfaceBiomes {
+        top: BiomeType::Sedimentary,
+        bottom: BiomeType::Felsic,
+    },
+];
+/// Mixed into the world seed for the surface decoration rolls
+const DECORATION_SEED: u64 = 61357;
+/// One in this many surface columns gets a decoration
+const DECORATION_ODDS: usize = 12;
+
+/// Rows of air above the ground in a flat world
+pub const FLAT_SURFACE_HEIGHT: usize = 16;
+/// What a flat world is made of
+const FLAT_BLOCK: BlockType = BlockType::Limestone;
+
+/// Largest number of blocks that a single admin fill is allowed to change
+pub const MAX_FILL_AREA: usize = 32 * 32;
+
+/// Most water blocks that can move in one game tick
+pub const MAX_WATER_MOVES_PER_TICK: usize = 256;
 
 /// Increase for smaller caves
 /// Decrease for bigger caves
-const PERLIN_CAVE_THRESHOLD: f32 = 0.25;
+/// Summing octaves evens the noise out, so this is lower than for plain Perlin noise
+/// to keep about a fifth of each chunk hollow
+const PERLIN_CAVE_THRESHOLD: f32 = 0.17;
+
+/// Every this many chunks down, a biome's rarer ores get as common again as they are at the surface
+const RARE_ORE_DEPTH_SCALE: f32 = 20.;
+/// Most times more common the rarer ores get with depth, so the main ore stays the main one
+const RARE_ORE_MAX_BOOST: f32 = 3.;
+
+/// The seed every part of world generation is derived from; should be a resource on the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct WorldSeed(pub u64);
+
+impl Default for WorldSeed {
+    fn default() -> Self {
+        Self(DEFAULT_SEED)
+    }
+}
+
+/// Options for world generation; should be a resource on the server
+#[derive(Debug, Clone, Copy)]
+pub struct GenerationSettings {
+    /// Same as the WorldSeed resource, kept here so generation doesn't need it passed separately
+    pub seed: u64,
+    /// Carve caves out of underground chunks
+    pub caves: bool,
+    /// What the surface chunk is made of
+    pub surface: SurfaceBiomes,
+    /// Skip all of the above and make solid ground under a flat surface, for testing
+    pub flat: bool,
+}
+
+impl Default for GenerationSettings {
+    fn default() -> Self {
+        Self {
+            seed: DEFAULT_SEED,
+            caves: true,
+            surface: SurfaceBiomes::default(),
+            flat: false,
+        }
+    }
+}
+
+/// The two biomes of the surface chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SurfaceBiomes {
+    /// Fills the low ground between the hills
+    pub top: BiomeType,
+    /// Makes up the hills and everything under the top biome, the chunks below start out in it
+    pub bottom: BiomeType,
+}
+
+impl Default for SurfaceBiomes {
+    /// Sand over limestone
+    fn default() -> Self {
+        SURFACE_CHOICES[0]
+    }
+}
+
+impl SurfaceBiomes {
+    /// Pick the surface biomes for a seed, the same seed always picks the same ones
+    pub fn from_seed(seed: u64) -> Self {
+        let choice = procedural_functions::generate_random_values(
+            procedural_functions::generate_seed(seed, vec![SURFACE_BIOME_SEED]),
+            1,
+            0,
+            SURFACE_CHOICES.len(),
+        )[0];
+        SURFACE_CHOICES[choice as usize]
+    }
+}
 
 pub mod client {
     use super::*;
@@ -31,20 +150,34 @@ pub mod client {
 
     impl Plugin for WorldPlugin {
         fn build(&self, app: &mut App) {
-            // TODO: get baseline terrain from server, then insert it as a resource
-            // then make a system that spawns in the entities from the resource
-            app.add_enter_system(states::client::GameState::InGame, create_world)
+            // the terrain starts empty, the server's baseline fills it in while loading
+            app.add_enter_system(states::client::GameState::Loading, create_world)
                 .add_system_set(
                     ConditionSet::new()
                         .run_in_state(states::client::GameState::InGame)
                         .with_system(f2_prints_terrain_encoding)
                         .with_system(f3_prints_terrain_info)
+                        .with_system(f6_prints_player_chunk)
                         .into(),
                 )
-                .add_exit_system(states::client::GameState::InGame, destroy_world);
+                .add_exit_system(
+                    states::client::GameState::Loading,
+                    destroy_world.run_if(baseline_pending),
+                )
+                .add_exit_system(states::client::GameState::InGame, destroy_world)
+                .add_exit_system(states::client::GameState::Paused, destroy_world);
         }
     }
 
+    /// Whether the server's first complete terrain has arrived and been rendered; should be a resource
+    /// Loading only moves on to the game once it has
+    pub struct BaselineReceived(pub bool);
+
+    /// Run condition for cleaning up when loading is left without getting into the game
+    pub fn baseline_pending(received: Res<BaselineReceived>) -> bool {
+        !received.0
+    }
+
     fn create_world(mut commands: Commands) {
         info!("creating terrain on client");
 
@@ -53,11 +186,13 @@ pub mod client {
 
         // now add as resource
         commands.insert_resource(terrain);
+        commands.insert_resource(BlockEntities::default());
+        commands.insert_resource(BaselineReceived(false));
     }
 }
 
 pub mod server {
-    use crate::network::server::ConnectedClientInfo;
+    use crate::{args::ServerArgs, network::server::ConnectedClientInfo};
 
     use super::*;
 
@@ -65,68 +200,252 @@ pub mod server {
 
     impl Plugin for WorldPlugin {
         fn build(&self, app: &mut App) {
-            app.add_enter_system(
-                states::server::GameState::Running,
-                create_world.label("create_world"),
-            );
+            app.insert_resource(WorldReady(false))
+                .add_enter_system(
+                    states::server::GameState::Running,
+                    create_world.label("create_world"),
+                )
+                .add_enter_system(
+                    states::server::GameState::Running,
+                    mark_world_ready.after("create_world").after("load_server"),
+                );
 
-            app.add_exit_system(states::server::GameState::Running, destroy_world);
+            app.add_exit_system(states::server::GameState::Running, destroy_world)
+                .add_exit_system(states::server::GameState::Running, unmark_world_ready);
         }
     }
 
+    /// Whether the terrain is done being generated or loaded; should be a resource
+    /// Nothing should be sent to clients until it is
+    pub struct WorldReady(pub bool);
+
+    /// Runs after generation and loading, inserting through commands so that it lands after their terrain
+    fn mark_world_ready(mut commands: Commands) {
+        info!("world is ready");
+        commands.insert_resource(WorldReady(true));
+    }
+
+    fn unmark_world_ready(mut commands: Commands) {
+        commands.insert_resource(WorldReady(false));
+    }
+
     pub fn check_generate_new_chunks(
         query: Query<&PlayerPosition, With<ConnectedClientInfo>>,
         mut terrain: ResMut<Terrain>,
+        settings: Res<GenerationSettings>,
+        mut biomes: ResMut<BiomeCache>,
     ) {
-        // the highest numbered (lowest in the world) chunk in our terrain
-        let highest_numbered_chunk_in_terrain = if terrain.chunks.len() == 0 {
-            0
+        for position in query.iter() {
+            let player_chunk_number = (-position.y) as u64 / CHUNK_HEIGHT as u64;
+
+            // info!("found player at chunk {}", player_chunk_number);
+
+            // generate the chunk above (it may have been unloaded), the player's, and more below
+            let first = player_chunk_number.saturating_sub(1);
+            for target_chunk in first..player_chunk_number + GEN_CHUNKS_AHEAD {
+                if terrain.get_chunk(target_chunk).is_some() {
+                    continue;
+                }
+
+                // generate the chunk
+                let chunk = generate_chunk(target_chunk, &settings, &mut biomes);
+
+                // add the chunk to our terrain resource
+                terrain.insert_chunk(chunk);
+            }
+        }
+    }
+
+    /// The chunk with this number as it is first generated
+    fn generate_chunk(
+        chunk_number: u64,
+        settings: &GenerationSettings,
+        biomes: &mut BiomeCache,
+    ) -> Chunk {
+        if settings.flat {
+            Chunk::new_flat(chunk_number, FLAT_SURFACE_HEIGHT)
+        } else if chunk_number == 0 {
+            Chunk::new_surface(settings)
         } else {
-            (terrain.chunks.len() - 1) as u64
+            Chunk::new_with_biomes(chunk_number, settings, biomes)
+        }
+    }
+
+    /// Drop chunks that are more than CHUNK_UNLOAD_DISTANCE chunks away from every connected player,
+    /// so digging deep doesn't grow the terrain forever
+    /// Dropped chunks are generated again if a player comes back, so only chunks that are still
+    /// the same as when they were generated are dropped; changed ones stay loaded and get saved
+    /// Nothing is dropped while nobody is connected, so the world is kept for the next player
+    pub fn despawn_distant_chunks(
+        query: Query<&PlayerPosition, With<ConnectedClientInfo>>,
+        mut terrain: ResMut<Terrain>,
+        settings: Res<GenerationSettings>,
+        mut biomes: ResMut<BiomeCache>,
+        mut changed: Local<HashSet<u64>>,
+    ) {
+        let player_chunks: Vec<u64> = query
+            .iter()
+            .map(|position| (-position.y).max(0.) as u64 / CHUNK_HEIGHT as u64)
+            .collect();
+        if player_chunks.is_empty() {
+            return;
+        }
+
+        let is_near = |chunk: &Chunk| {
+            player_chunks
+                .iter()
+                .any(|p| p.abs_diff(chunk.chunk_number) <= CHUNK_UNLOAD_DISTANCE)
         };
 
-        // info!(
-        //     "our highest chunk is chunk {}",
-        //     highest_numbered_chunk_in_terrain
-        // );
+        // changed chunks are only compared with a generated one once, they stay changed
+        let distant: Vec<u64> = terrain
+            .chunks
+            .values()
+            .filter(|chunk| !is_near(chunk) && !changed.contains(&chunk.chunk_number))
+            .map(|chunk| chunk.chunk_number)
+            .collect();
+        for chunk_number in distant {
+            // unwrap OK since it came from the terrain, reading doesn't mark the terrain as changed
+            let chunk = terrain.get_chunk(chunk_number).unwrap();
+            if *chunk == generate_chunk(chunk_number, &settings, &mut biomes) {
+                debug!("unloading chunk {}", chunk_number);
+                terrain.chunks.remove(&chunk_number);
+            } else {
+                debug!("keeping changed chunk {} loaded", chunk_number);
+                changed.insert(chunk_number);
+            }
+        }
+    }
 
-        for position in query.iter() {
-            let player_chunk_number = (-position.y) as u64 / CHUNK_HEIGHT as u64;
+    /// Let water flow for one game tick, see flow_water
+    /// Clients see it move through the usual block deletion and placement deltas
+    pub fn update_water(mut terrain: ResMut<Terrain>) {
+        // look before taking the terrain mutably, so it is only marked as changed when water moves
+        let moves = water_moves(&terrain);
+        if moves.is_empty() {
+            return;
+        }
+        for ((from_x, from_y), (to_x, to_y)) in moves {
+            terrain.set_block(from_x, from_y, None);
+            terrain.set_block(to_x, to_y, Some(Block::new(BlockType::Water)));
+        }
+    }
 
-            // info!("found player at chunk {}", player_chunk_number);
+    /// Where water blocks will move this tick, as (from, to) global positions
+    /// Each water block falls one cell if it can, otherwise it slides one cell sideways off a ledge
+    /// Lowest water goes first, so a falling column falls together, and at most
+    /// MAX_WATER_MOVES_PER_TICK blocks move so a big flood can't stall the tick
+    pub fn water_moves(terrain: &Terrain) -> Vec<((usize, usize), (usize, usize))> {
+        let mut water: Vec<(usize, usize)> = terrain
+            .chunks
+            .values()
+            .flat_map(|chunk| {
+                let chunk_top = chunk.chunk_number as usize * CHUNK_HEIGHT;
+                chunk.blocks.iter().enumerate().flat_map(move |(y, row)| {
+                    row.iter()
+                        .enumerate()
+                        .filter(|(_, block)| block.map(|b| b.block_type) == Some(BlockType::Water))
+                        .map(move |(x, _)| (x, chunk_top + y))
+                })
+            })
+            .collect();
+        water.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        // cells that will be empty or full once the moves so far are done
+        let mut emptied = HashSet::new();
+        let mut filled = HashSet::new();
+        let is_free = |x: usize, y: usize, emptied: &HashSet<_>, filled: &HashSet<_>| {
+            x < CHUNK_WIDTH
+                && terrain.get_chunk((y / CHUNK_HEIGHT) as u64).is_some()
+                && !filled.contains(&(x, y))
+                && (terrain.get_block(x, y).is_none() || emptied.contains(&(x, y)))
+        };
 
-            // check if we need to generate more chunks below, assume we already generated the chunks above
-            for offset in 0..GEN_CHUNKS_AHEAD {
-                if player_chunk_number + offset > highest_numbered_chunk_in_terrain {
-                    let target_chunk = player_chunk_number + offset;
+        let mut moves = Vec::new();
+        for (x, y) in water {
+            if moves.len() >= MAX_WATER_MOVES_PER_TICK {
+                break;
+            }
 
-                    // generate the chunk
-                    let chunk = Chunk::new(target_chunk);
+            let target = if is_free(x, y + 1, &emptied, &filled) {
+                Some((x, y + 1))
+            } else {
+                [x.checked_sub(1), Some(x + 1)]
+                    .into_iter()
+                    .flatten()
+                    .find(|&side| {
+                        is_free(side, y, &emptied, &filled)
+                            && is_free(side, y + 1, &emptied, &filled)
+                    })
+                    .map(|side| (side, y))
+            };
 
-                    // add the chunk to our terrain resource
-                    terrain.chunks.push(chunk);
-                }
+            if let Some(to) = target {
+                emptied.insert((x, y));
+                filled.insert(to);
+                moves.push(((x, y), to));
             }
         }
+        moves
     }
 
-    fn create_world(mut commands: Commands) {
-        info!("creating terrain on server");
+    /// The generation settings picked by the server's arguments, for a world with this seed
+    pub fn generation_settings(args: &ServerArgs, seed: WorldSeed) -> GenerationSettings {
+        GenerationSettings {
+            seed: seed.0,
+            caves: !args.no_caves,
+            surface: args
+                .surface_seed
+                .map_or_else(SurfaceBiomes::default, SurfaceBiomes::from_seed),
+            flat: args.flat,
+        }
+    }
 
-        // create now, insert as resource later
-        let mut terrain = Terrain::empty();
+    pub fn create_world(mut commands: Commands, args: Res<ServerArgs>) {
+        info!(
+            "creating {} chunks of terrain on server",
+            args.initial_chunks
+        );
 
-        // Generate one chunk
-        create_surface_chunk(&mut terrain);
+        let seed = match args.seed {
+            Some(seed) => WorldSeed(seed),
+            None => {
+                let seed = WorldSeed(rand::random());
+                info!(
+                    "no seed given, generating with {}; pass --seed {} to get this world again",
+                    seed.0, seed.0
+                );
+                seed
+            }
+        };
 
-        // generate another chunk (index 1)
-        let chunk = Chunk::new(1);
+        let settings = generation_settings(&args, seed);
+        info!("surface biomes: {:?}", settings.surface);
 
-        // add the chunk to our terrain resource
-        terrain.chunks.push(chunk);
+        // a fresh cache, so nothing from a previous world is reused
+        let mut biomes = BiomeCache::new(&settings);
+
+        // create now, insert as resource later
+        let terrain = if settings.flat {
+            Terrain::new_flat(args.initial_chunks, FLAT_SURFACE_HEIGHT)
+        } else {
+            Terrain::new_with_progress(
+                args.initial_chunks,
+                &settings,
+                &mut biomes,
+                |done, total| info!("generated chunk {}/{}", done, total),
+            )
+        };
+        // same seed and options give the same hash, handy for comparing worlds
+        info!("world hash: {}", terrain.content_hash());
 
         // now add as resource
         commands.insert_resource(terrain);
+        commands.insert_resource(biomes);
+
+        // keep settings around for chunks generated later
+        commands.insert_resource(settings);
+        commands.insert_resource(seed);
     }
 
     #[derive(Debug)]
@@ -157,33 +476,140 @@ pub mod server {
         }
 
         // find if we have the chunk in our terrain
-        for chunk in &mut terrain.chunks {
-            if chunk.chunk_number == (chunk_number as u64) {
-                // we have found our chunk
-                let block_opt = &mut chunk.blocks[block_y_in_chunk][x];
-
-                match block_opt {
-                    Some(block) => {
-                        // clone block data so we can give it to the caller
-                        let clone = block.clone();
-
-                        // remove the block from our data array
-                        // original block is dropped here
-                        *block_opt = None;
-
-                        // give the clone back to the caller
-                        // TODO: maybe give a different data type?
-                        return Ok(clone);
-                    }
-                    None => {
-                        // warn!("no block exists at ({}, {})", x, y);
-                        return Err(DestroyBlockError::BlockDoesntExist);
-                    }
+        let chunk = terrain
+            .get_chunk_mut(chunk_number as u64)
+            .ok_or(DestroyBlockError::ChunkNotLoaded)?;
+
+        match chunk.get(x, block_y_in_chunk).copied() {
+            Some(block) => {
+                // remove the block from our data array
+                chunk.set(x, block_y_in_chunk, None);
+
+                // give the copy back to the caller
+                // TODO: maybe give a different data type?
+                Ok(block)
+            }
+            None => {
+                // warn!("no block exists at ({}, {})", x, y);
+                Err(DestroyBlockError::BlockDoesntExist)
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub enum PlaceBlockError {
+        /// Tried to search past array index in X direction
+        InvalidX,
+        /// Corresponding chunk location is not loaded (outside Y)
+        ChunkNotLoaded,
+        /// There is already a block at the location
+        BlockExists,
+    }
+
+    /// Place a block at a global position, only into empty space
+    pub fn place_block(
+        x: usize,
+        y: usize,
+        block_type: BlockType,
+        terrain: &mut Terrain,
+    ) -> Result<(), PlaceBlockError> {
+        let chunk_number = y / CHUNK_HEIGHT;
+        let block_y_in_chunk = y % CHUNK_HEIGHT;
+
+        if x >= CHUNK_WIDTH {
+            return Err(PlaceBlockError::InvalidX);
+        }
+
+        let chunk = terrain
+            .get_chunk_mut(chunk_number as u64)
+            .ok_or(PlaceBlockError::ChunkNotLoaded)?;
+
+        if chunk.get(x, block_y_in_chunk).is_some() {
+            return Err(PlaceBlockError::BlockExists);
+        }
+
+        chunk.set(x, block_y_in_chunk, Some(Block::new(block_type)));
+        Ok(())
+    }
+
+    /// Roll the drop table for a block mined at a global position
+    /// Uses the world seed and position, so the same block always gives the same drops
+    pub fn roll_drops(
+        seed: WorldSeed,
+        block_type: BlockType,
+        x: usize,
+        y: usize,
+    ) -> Vec<BlockType> {
+        block_type
+            .drops()
+            .into_iter()
+            .enumerate()
+            .filter(|(i, (_, chance))| {
+                let roll = procedural_functions::generate_seed(
+                    seed.0,
+                    vec![x as u64, y as u64, *i as u64],
+                ) % 100;
+                roll < *chance as u64
+            })
+            .map(|(_, (drop, _))| drop)
+            .collect()
+    }
+
+    #[derive(Debug)]
+    pub enum FillError {
+        /// Rectangle extends past the world in X direction
+        InvalidX,
+        /// Rectangle covers more than MAX_FILL_AREA blocks
+        TooLarge,
+    }
+
+    /// Set every block in a rectangle (global, inclusive corners) to the given block type,
+    /// or clear them if block_type is None
+    /// Blocks in chunks that aren't loaded are skipped
+    /// Returns the chunk numbers that were changed
+    pub fn fill_area(
+        (x1, y1): (usize, usize),
+        (x2, y2): (usize, usize),
+        block_type: Option<BlockType>,
+        terrain: &mut Terrain,
+    ) -> Result<Vec<u64>, FillError> {
+        // allow corners in any order
+        let (min_x, max_x) = (x1.min(x2), x1.max(x2));
+        let (min_y, max_y) = (y1.min(y2), y1.max(y2));
+
+        if max_x >= CHUNK_WIDTH {
+            return Err(FillError::InvalidX);
+        }
+
+        // corners far apart can overflow working out the area, which is too large anyway
+        let area = (max_y - min_y)
+            .checked_add(1)
+            .and_then(|height| height.checked_mul(max_x - min_x + 1));
+        if area.map_or(true, |area| area > MAX_FILL_AREA) {
+            return Err(FillError::TooLarge);
+        }
+
+        let mut changed_chunks = Vec::new();
+
+        for chunk in terrain.chunks.values_mut() {
+            let chunk_top = chunk.chunk_number as usize * CHUNK_HEIGHT;
+            let chunk_bottom = chunk_top + CHUNK_HEIGHT - 1;
+
+            // skip chunks that don't overlap the rectangle
+            if max_y < chunk_top || min_y > chunk_bottom {
+                continue;
+            }
+
+            for y in min_y.max(chunk_top)..=max_y.min(chunk_bottom) {
+                for x in min_x..=max_x {
+                    chunk.set(x, y - chunk_top, block_type.map(Block::new));
                 }
             }
+
+            changed_chunks.push(chunk.chunk_number);
         }
 
-        Err(DestroyBlockError::ChunkNotLoaded)
+        Ok(changed_chunks)
     }
 }
 
@@ -195,6 +621,8 @@ fn destroy_world(mut commands: Commands, query: Query<Entity, With<RenderedBlock
     }
 
     commands.remove_resource::<Terrain>();
+    commands.remove_resource::<BlockEntities>();
+    commands.remove_resource::<BiomeCache>();
 }
 
 /// Represents a change in world state can be either a complete "terrain" (vec of chunks)
@@ -203,6 +631,13 @@ fn destroy_world(mut commands: Commands, query: Query<Entity, With<RenderedBlock
 pub enum WorldDelta {
     NewChunks(Terrain),
     BlockDelete(BlockDelete),
+    /// A block appeared (or changed type) where the client has something else
+    BlockPlace {
+        chunk_number: u64,
+        x: usize,
+        y: usize,
+        block_type: BlockType,
+    },
 }
 
 /// Represents a single-block change (only deletion!) in a chunk
@@ -220,31 +655,146 @@ pub struct BlockDelete {
 /// On the server, this represents the entire game world
 /// On the client, this represents the part of the game world that the client knows about
 /// In a packet, this is a baseline transfer from server -> client
-#[derive(Encode, Decode, Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Terrain {
-    /// Vector of chunks, each one contains its own chunk_number
-    /// TODO: potentially convert into a symbol table for faster lookups?
-    pub chunks: Vec<Chunk>,
+    /// Loaded chunks by their chunk_number, which may have gaps (e.g. after the server unloads some)
+    pub chunks: HashMap<u64, Chunk>,
+}
+
+// encode as a vec sorted by chunk number, so the same terrain always gives the same bytes
+impl Encode for Terrain {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        bincode::Encode::encode(&self.sorted_chunks(), encoder)?;
+        Ok(())
+    }
+}
+
+impl Decode for Terrain {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let chunks: Vec<Chunk> = bincode::Decode::decode(decoder)?;
+        Ok(chunks.into_iter().collect())
+    }
+}
+
+bincode::impl_borrow_decode!(Terrain);
+
+impl FromIterator<Chunk> for Terrain {
+    fn from_iter<I: IntoIterator<Item = Chunk>>(chunks: I) -> Self {
+        Terrain {
+            chunks: chunks.into_iter().map(|c| (c.chunk_number, c)).collect(),
+        }
+    }
 }
 
 impl Terrain {
+    /// The chunk with this number, if it is loaded
+    pub fn get_chunk(&self, chunk_number: u64) -> Option<&Chunk> {
+        self.chunks.get(&chunk_number)
+    }
+
+    /// Mutable version of get_chunk
+    pub fn get_chunk_mut(&mut self, chunk_number: u64) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&chunk_number)
+    }
+
+    /// Add a chunk, replacing any loaded chunk with the same number
+    pub fn insert_chunk(&mut self, chunk: Chunk) {
+        self.chunks.insert(chunk.chunk_number, chunk);
+    }
+
+    /// The block at a global position, None if it is empty or its chunk isn't loaded
+    pub fn get_block(&self, x: usize, y: usize) -> Option<&Block> {
+        self.get_chunk((y / CHUNK_HEIGHT) as u64)?
+            .get(x, y % CHUNK_HEIGHT)
+    }
+
+    /// Set (or clear, with None) the block at a global position
+    /// Returns false without changing anything if the position isn't in a loaded chunk
+    pub fn set_block(&mut self, x: usize, y: usize, block: Option<Block>) -> bool {
+        self.get_chunk_mut((y / CHUNK_HEIGHT) as u64)
+            .is_some_and(|chunk| chunk.set(x, y % CHUNK_HEIGHT, block))
+    }
+
+    /// All loaded chunks in order of their number, for when the order matters
+    pub fn sorted_chunks(&self) -> Vec<&Chunk> {
+        let mut chunks: Vec<&Chunk> = self.chunks.values().collect();
+        chunks.sort_by_key(|c| c.chunk_number);
+        chunks
+    }
+
+    /// Hash of every block type in the loaded chunks, in chunk number order
+    /// Generation is seeded, so the same seed and settings should always give the same hash
+    /// 64 bit FNV-1a over one byte per block, so it doesn't change with the Rust version
+    pub fn content_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut hash = FNV_OFFSET_BASIS;
+        for chunk in self.sorted_chunks() {
+            for row in &chunk.blocks {
+                for block in row {
+                    // block types are only ever added at the end, so their numbers stay the same
+                    let byte = block.map_or(u8::MAX, |b| b.block_type as u8);
+                    hash = (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+                }
+            }
+        }
+        hash
+    }
+
     /// Create a terrain with specified number of chunks
     /// Chunks contain default blocks and are numbered from 0 to len-1
     pub fn new(num_chunks: u64) -> Terrain {
-        let chunks = (0..num_chunks).map(|d| Chunk::new(d)).collect();
+        (0..num_chunks).map(Chunk::new).collect()
+    }
+
+    /// Create a world like the server does on startup: the surface, then num_chunks - 1 chunks below it
+    /// Calls progress(done, total) after each chunk, since this takes a while for many chunks
+    pub fn new_with_progress(
+        num_chunks: u64,
+        settings: &GenerationSettings,
+        biomes: &mut BiomeCache,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Terrain {
+        let mut terrain = Terrain::empty();
 
-        Terrain { chunks }
+        for chunk_number in 0..num_chunks {
+            let chunk = if chunk_number == 0 {
+                Chunk::new_surface(settings)
+            } else {
+                Chunk::new_with_biomes(chunk_number, settings, biomes)
+            };
+            terrain.insert_chunk(chunk);
+
+            progress(chunk_number + 1, num_chunks);
+        }
+
+        terrain
+    }
+
+    /// Create a world of solid blocks with surface_height rows of air above them, numbered from 0
+    /// No caves, veins, biomes or decorations, for testing movement and networking
+    pub fn new_flat(num_chunks: u64, surface_height: usize) -> Terrain {
+        (0..num_chunks)
+            .map(|d| Chunk::new_flat(d, surface_height))
+            .collect()
     }
 
     /// Creates a terrain with no chunks
     pub fn empty() -> Terrain {
-        Terrain { chunks: Vec::new() }
+        Terrain {
+            chunks: HashMap::new(),
+        }
     }
 }
 
 /// Represents a chunk of blocks; stored in the Terrain resource
-/// TODO: maybe custom bitpack for Encode and Decode?
-#[derive(Encode, Decode, Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Chunk {
     /// 2D array [x, y]
     pub blocks: [[Option<Block>; CHUNK_WIDTH]; CHUNK_HEIGHT],
@@ -252,8 +802,71 @@ pub struct Chunk {
     pub chunk_number: u64,
 }
 
+// run-length encode the blocks row-major as (count, block type) pairs;
+// most of a chunk is long runs of the same block, so this is far smaller than every cell
+impl Encode for Chunk {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        let mut runs: Vec<(u16, Option<BlockType>)> = Vec::new();
+        for block in self.blocks.iter().flatten() {
+            let block_type = block.map(|b| b.block_type);
+            match runs.last_mut() {
+                Some((count, last)) if *last == block_type && *count < u16::MAX => *count += 1,
+                _ => runs.push((1, block_type)),
+            }
+        }
+        bincode::Encode::encode(&runs, encoder)?;
+        bincode::Encode::encode(&self.chunk_number, encoder)?;
+        Ok(())
+    }
+}
+
+impl Decode for Chunk {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let runs: Vec<(u16, Option<BlockType>)> = bincode::Decode::decode(decoder)?;
+        let chunk_number: u64 = bincode::Decode::decode(decoder)?;
+
+        let found: usize = runs.iter().map(|&(count, _)| count as usize).sum();
+        if found != CHUNK_WIDTH * CHUNK_HEIGHT {
+            return Err(bincode::error::DecodeError::ArrayLengthMismatch {
+                required: CHUNK_WIDTH * CHUNK_HEIGHT,
+                found,
+            });
+        }
+
+        let mut chunk = Chunk::empty(chunk_number);
+        let mut cells = chunk.blocks.iter_mut().flatten();
+        for (count, block_type) in runs {
+            for cell in cells.by_ref().take(count as usize) {
+                *cell = block_type.map(Block::new);
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+bincode::impl_borrow_decode!(Chunk);
+
 impl Chunk {
+    /// Generate the chunk at depth with the default generation settings
     pub fn new(depth: u64) -> Self {
+        Self::new_with_settings(depth, &GenerationSettings::default())
+    }
+
+    pub fn new_with_settings(depth: u64, settings: &GenerationSettings) -> Self {
+        Self::new_with_biomes(depth, settings, &mut BiomeCache::new(settings))
+    }
+
+    /// Generate the chunk at depth, looking up its biomes and those of the chunk above in biomes
+    pub fn new_with_biomes(
+        depth: u64,
+        settings: &GenerationSettings,
+        biomes: &mut BiomeCache,
+    ) -> Self {
         // start with empty chunk
         let mut c = Chunk {
             blocks: [[None; CHUNK_WIDTH]; CHUNK_HEIGHT],
@@ -261,83 +874,30 @@ impl Chunk {
         };
         let tree = true;
 
+        let layers = ChunkLayers::new(settings, depth, biomes);
+
         // generate chunks for current and previous chunk
         let mut veins = Vec::new();
         if depth > 0 {
-            for vein_number in 0..generate_random_vein_count(BASE_SEED, depth - 1) {
-                veins.push(Vein::new(depth, vein_number));
-            }
-        }
-        for vein_number in 0..generate_random_vein_count(BASE_SEED, depth) {
-            veins.push(Vein::new(depth, vein_number));
-        }
-
-        // get prev biome
-        let mut prev_biome_search: Option<BiomeType> = None;
-
-        if depth > 0 {
-            let mut curr_search_depth = depth - 1;
-
-            while prev_biome_search.is_none() {
-                prev_biome_search = if depth > 0 {
-                    procedural_functions::generate_chunk_biome_change(BASE_SEED, curr_search_depth)
-                } else {
-                    Some(BiomeType::Sand)
-                };
-                info! {
-                    "Trying to find biome for {} - currently {:?}",
-                    curr_search_depth,
-                    prev_biome_search
-                }
-                if curr_search_depth == 0 {
-                    break; // can't put >= 0 in the while condititon since it's unsigned and that'll always be true
-                }
-                curr_search_depth -= 1;
-            }
+            let above = ChunkLayers::new(settings, depth - 1, biomes);
+            veins.extend(Vein::generate_chunk_veins(settings.seed, depth - 1, &above));
         }
+        veins.extend(Vein::generate_chunk_veins(settings.seed, depth, &layers));
 
-        let prev_biome = prev_biome_search.unwrap_or(BiomeType::Sand);
-
-        // Determine biome of chunk and whether there will be a biome change
-        let biome_change = procedural_functions::generate_chunk_biome_change(BASE_SEED, depth)
-            .unwrap_or(prev_biome);
-
-        let average_biome_change_depth = procedural_functions::generate_random_values(
-            procedural_functions::generate_seed(BASE_SEED, vec![depth, 432]),
-            1,
-            3,
-            10,
-        )[0] as usize;
-
-        let biome_change_depths = procedural_functions::generate_random_values(
-            procedural_functions::generate_seed(BASE_SEED, vec![depth, 234]),
-            64, // interpolate between 64 values
-            average_biome_change_depth - 2,
-            average_biome_change_depth + 2, // 5 block range
-        );
-
-        info!(
-            "Chunk {} has biome change from {:?} to {:?} between {} and {}",
-            depth,
-            prev_biome,
-            biome_change,
-            average_biome_change_depth + 2,
-            average_biome_change_depth - 2,
+        debug!(
+            "Chunk {} has biome change from {:?} to {:?}",
+            depth, layers.top, layers.bottom,
         );
 
-        let perlin_vals = generate_perlin_noise(depth, BASE_SEED);
+        // no need for noise if we aren't making caves
+        let perlin_vals = settings
+            .caves
+            .then(|| generate_fractal_noise(depth, settings.seed, CAVE_NOISE_OCTAVES));
 
         // Loop through chunk, filling in where blocks should be
         for x in 0..CHUNK_WIDTH {
             for y in 0..CHUNK_HEIGHT {
-                let biome_change_ypos =
-                    procedural_functions::slice_pos_x(x, &biome_change_depths).round() as usize - 1;
-
-                let mut block_type = if y >= biome_change_ypos {
-                    biome_change.primary_block()
-                } else {
-                    prev_biome.primary_block()
-                };
+                let mut block_type = layers.biome_at(x, y).primary_block();
 
                 // Check if this is within the bounds of an ore vein
                 for vein in &veins {
@@ -351,9 +911,9 @@ impl Chunk {
                             0
                         };
 
-                        let dist = dist_to_vein(vein, x as f32, (y + y_offset) as f32);
+                        let dist = dist_sq_to_vein(vein, x as f32, (y + y_offset) as f32);
 
-                        if dist < (vein.thickness_sq / 2.).into() {
+                        if dist < vein.radius_sq() {
                             /* info!(
                                 "Block at chunk {} {},{} in vein from {},{} to {},{} ({})",
                                 depth,
@@ -365,39 +925,31 @@ impl Chunk {
                                 vein.end_y,
                                 dist
                             ); */
-                            block_type = if y >= biome_change_ypos {
-                                biome_change.ore_block()
-                            } else {
-                                prev_biome.ore_block()
-                            };
+                            block_type = vein.ore;
                         }
                     }
                 }
 
                 //Add Cave Functionality
-                if perlin_vals[y][x] > PERLIN_CAVE_THRESHOLD {
-                    block_type = BlockType::CaveVoid;
+                if let Some(perlin_vals) = &perlin_vals {
+                    if perlin_vals[y][x] > PERLIN_CAVE_THRESHOLD {
+                        block_type = BlockType::CaveVoid;
+                    }
                 }
 
                 if block_type != BlockType::CaveVoid {
-                    c.blocks[y][x] = Some(Block {
-                        block_type,
-                        entity: None,
-                    });
+                    c.blocks[y][x] = Some(Block { block_type });
                 } else {
-                    let primary_block_type = if y >= biome_change_ypos {
-                        biome_change.primary_block()
-                    } else {
-                        prev_biome.primary_block()
-                    };
+                    let biome = layers.biome_at(x, y);
+                    let primary_block_type = biome.primary_block();
                     //Checks if you can make trees, if there is room for a tree, and the block it would place a tree is the current biome primary block
-                    if tree
-                        && y > 4
-                        && y < CHUNK_HEIGHT - 1
-                        && x > 4
-                        && c.blocks[y + 1][x - 2] != None
-                        && c.blocks[y + 1][x - 2].unwrap().block_type == primary_block_type
-                    {
+                    if let Some(tree_kind) = biome.tree_kind().filter(|_| {
+                        tree && y > 4
+                            && y < CHUNK_HEIGHT - 1
+                            && x > 4
+                            && c.blocks[y + 1][x - 2] != None
+                            && c.blocks[y + 1][x - 2].unwrap().block_type == primary_block_type
+                    }) {
                         //sees how tall it can make the tree
                         let mut max = 0;
                         for height in (0..=y).rev() {
@@ -409,47 +961,17 @@ impl Chunk {
                         if y - max > 2 {
                             //Randomizes the height of the tree
                             let random_height = procedural_functions::generate_random_values(
-                                BASE_SEED + x as u64, //adds x to make it more random if it has the same max and current y position
+                                settings.seed.wrapping_add(x as u64), //adds x to make it more random if it has the same max and current y position
                                 2,
                                 max,
                                 y,
                             );
                             max = *random_height.get(0).unwrap() as usize;
                         }
-                        if y - max > 2 && structure_fit(c.blocks, x, max) {
-                            // 02220
-                            // 02120
-                            // 00100
-                            // 00100
-                            //Creates the trunk
-                            for height in (max + 1..=y).rev() {
-                                c.blocks[height][x - 2] = Some(Block {
-                                    block_type: BlockType::Trunk,
-                                    entity: None,
-                                });
-                            }
-                            //Creates the Leaves
-                            c.blocks[max + 1][x - 1] = Some(Block {
-                                block_type: BlockType::Leaves,
-                                entity: None,
-                            });
-                            c.blocks[max + 1][x - 2] = Some(Block {
-                                block_type: BlockType::Leaves,
-                                entity: None,
-                            });
-                            c.blocks[max + 1][x - 3] = Some(Block {
-                                block_type: BlockType::Leaves,
-                                entity: None,
-                            });
-                            c.blocks[max + 2][x - 1] = Some(Block {
-                                block_type: BlockType::Leaves,
-                                entity: None,
-                            });
-                            c.blocks[max + 2][x - 3] = Some(Block {
-                                block_type: BlockType::Leaves,
-                                entity: None,
-                            });
-                        // tree=false;
+                        if y - max >= tree_kind.min_height()
+                            && place_tree(&mut c.blocks, x - 2, y, max + 1, tree_kind)
+                        {
+                            // tree=false;
                         } else {
                             c.blocks[y][x] = None;
                         }
@@ -470,128 +992,354 @@ impl Chunk {
         }
     }
 
-    pub fn new_surface() -> Self {
-        // Create surface chunk with perlin slice functions
+    /// Get the block at (x, y) in this chunk
+    /// None if there is no block there or if the position is outside of the chunk
+    pub fn get(&self, x: usize, y: usize) -> Option<&Block> {
+        self.blocks.get(y)?.get(x)?.as_ref()
+    }
 
-        let mut c = Chunk {
-            blocks: [[None; CHUNK_WIDTH]; CHUNK_HEIGHT],
-            chunk_number: 0,
-        };
+    /// Set (or clear, with None) the block at (x, y) in this chunk
+    /// Returns false without changing anything if the position is outside of the chunk
+    pub fn set(&mut self, x: usize, y: usize, block: Option<Block>) -> bool {
+        match self.blocks.get_mut(y).and_then(|row| row.get_mut(x)) {
+            Some(cell) => {
+                *cell = block;
+                true
+            }
+            None => false,
+        }
+    }
 
-        let random_vals = procedural_functions::generate_random_values(
-            BASE_SEED, //Use hard-coded seed for now
-            16,        //16 random values, so 16 points to interpolate between
-            3, 16, //Peaks as high as 16 blocks
-        );
-        let random_sand_depths = procedural_functions::generate_random_values(
-            BASE_SEED, //Use hard-coded seed for now
-            32,        //32 random values, so 32 points to interpolate between
-            16, 31, //Peaks as high as 16 blocks
-        );
-        let random_trees = procedural_functions::generate_random_values(
-            BASE_SEED, //Use hard-coded seed for now
+    /// Draw this chunk as text, one line per row and one character per block (see
+    /// BlockType::ascii_char), with '.' for empty cells
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::with_capacity((CHUNK_WIDTH + 1) * CHUNK_HEIGHT);
+        for row in &self.blocks {
+            for block in row {
+                ascii.push(block.map_or('.', |b| b.block_type.ascii_char()));
+            }
+            ascii.push('\n');
+        }
+        ascii
+    }
+
+    /// A chunk of a flat world, solid everywhere at least surface_height rows below the top of the world
+    pub fn new_flat(chunk_number: u64, surface_height: usize) -> Self {
+        let mut c = Chunk::empty(chunk_number);
+        let chunk_top = chunk_number as usize * CHUNK_HEIGHT;
+        for (y, row) in c.blocks.iter_mut().enumerate() {
+            if chunk_top + y >= surface_height {
+                *row = [Some(Block::new(FLAT_BLOCK)); CHUNK_WIDTH];
+            }
+        }
+        c
+    }
+
+    pub fn new_surface(settings: &GenerationSettings) -> Self {
+        // Create surface chunk with perlin slice functions
+        let mut c = Chunk {
+            blocks: [[None; CHUNK_WIDTH]; CHUNK_HEIGHT],
+            chunk_number: 0,
+        };
+
+        let random_vals = procedural_functions::generate_random_values(
+            settings.seed,
+            16, //16 random values, so 16 points to interpolate between
+            3,
+            16, //Peaks as high as 16 blocks
+        );
+        let random_trees = procedural_functions::generate_random_values(
+            settings.seed,
             CHUNK_WIDTH,
             0,
             CHUNK_WIDTH / 8,
         );
+        let random_decorations = procedural_functions::generate_random_values(
+            procedural_functions::generate_seed(settings.seed, vec![DECORATION_SEED]),
+            CHUNK_WIDTH,
+            0,
+            DECORATION_ODDS,
+        );
 
-        let octave2 = procedural_functions::perlin_slice(BASE_SEED + 25, 32, CHUNK_WIDTH, 8);
+        let octave2 =
+            procedural_functions::perlin_slice(settings.seed.wrapping_add(25), 32, CHUNK_WIDTH, 8);
 
         // generate chunks for chunk
-        let mut veins = Vec::new();
-        for vein_number in 0..generate_random_vein_count(BASE_SEED, 0) {
-            veins.push(Vein::new(0, vein_number));
-        }
+        let layers = ChunkLayers::surface(settings);
+        let veins = Vein::generate_chunk_veins(settings.seed, 0, &layers);
 
         // Loop through chunk, filling in where blocks should be
         for x in 0..CHUNK_WIDTH {
             let hill_top = (procedural_functions::slice_pos_x(x, &random_vals).round() as i32
                 + octave2[x]) as usize
                 - 1;
-            let sand_depth =
-                procedural_functions::slice_pos_x(x, &random_sand_depths).round() as usize - 1;
 
-            if random_trees[x] == 1 {
+            // only palms are small enough to grow on the hills for now
+            let surface_biome = layers.biome_at(x, hill_top);
+            if random_trees[x] == 1 && surface_biome.tree_kind() == Some(TreeKind::Palm) {
                 let block_type = BlockType::PalmTreeBlock;
 
-                c.blocks[hill_top - 1][x] = Some(Block {
-                    block_type,
-                    entity: None,
-                });
+                c.blocks[hill_top - 1][x] = Some(Block { block_type });
+            } else if random_decorations[x] == 0 {
+                let block_type = surface_biome.surface_decoration();
+
+                c.blocks[hill_top - 1][x] = Some(Block { block_type });
             }
             for y in hill_top..CHUNK_HEIGHT {
-                let mut block_type = if y <= sand_depth {
-                    BiomeType::Sand.primary_block()
-                } else {
-                    BiomeType::Sedimentary.primary_block()
-                };
+                let mut block_type = layers.biome_at(x, y).primary_block();
 
                 // Check if this is within the bounds of an ore vein
                 for vein in &veins {
                     // Only look at veins originating in previous or current chunk
                     if vein.chunk_number == 0 {
-                        let dist = dist_to_vein(vein, x as f32, y as f32);
+                        let dist = dist_sq_to_vein(vein, x as f32, y as f32);
 
-                        if dist < (vein.thickness_sq / 2.).into() {
+                        if dist < vein.radius_sq() {
                             // info!(
                             //     "Block at chunk 0 {},{} in vein from {},{} to {},{} ({})",
                             //     x, y, vein.start_x, vein.start_y, vein.end_x, vein.end_y, dist
                             // );
-                            block_type = if y <= sand_depth {
-                                BiomeType::Sand.ore_block()
-                            } else {
-                                BiomeType::Sedimentary.ore_block()
-                            };
+                            block_type = vein.ore;
                         }
                     }
                 }
 
-                c.blocks[y][x] = Some(Block {
-                    block_type,
-                    entity: None,
-                });
+                c.blocks[y][x] = Some(Block { block_type });
             }
         }
 
         return c;
     }
 }
-fn structure_fit(blocks: [[Option<Block>; CHUNK_WIDTH]; CHUNK_HEIGHT], x: usize, y: usize) -> bool {
-    if x > 4 && x < CHUNK_WIDTH {
-        if blocks[y][x - 3] == None
-            && blocks[y][x - 1] == None
-            && blocks[y + 1][x - 1] == None
-            && blocks[y + 1][x - 3] == None
-            && blocks[y + 2][x - 1] == None
-            && blocks[y + 2][x - 3] == None
-        {
-            return true;
+
+/// The two biomes a chunk is made of, before caves and ores are cut into it
+pub struct ChunkLayers {
+    /// Biome at the top of the chunk, the sand on the surface
+    pub top: BiomeType,
+    /// Biome below the change
+    pub bottom: BiomeType,
+    /// First row of the bottom biome in each column
+    bottom_rows: [usize; CHUNK_WIDTH],
+}
+
+impl ChunkLayers {
+    /// Layers of the chunk at depth, looking up underground biomes in biomes
+    pub fn new(settings: &GenerationSettings, depth: u64, biomes: &mut BiomeCache) -> Self {
+        if depth == 0 {
+            Self::surface(settings)
+        } else {
+            Self::underground(settings.seed, depth, biomes.chunk_biomes(depth))
+        }
+    }
+
+    /// The surface chunk, settings.surface top down to a wavy sand depth and its bottom below
+    pub fn surface(settings: &GenerationSettings) -> Self {
+        let random_sand_depths = procedural_functions::generate_random_values(
+            settings.seed,
+            32, //32 random values, so 32 points to interpolate between
+            16,
+            31, //Peaks as high as 16 blocks
+        );
+        let mut bottom_rows = [0; CHUNK_WIDTH];
+        for (x, row) in bottom_rows.iter_mut().enumerate() {
+            let sand_depth =
+                procedural_functions::slice_pos_x(x, &random_sand_depths).round() as usize - 1;
+            *row = sand_depth + 1;
+        }
+        Self {
+            top: settings.surface.top,
+            bottom: settings.surface.bottom,
+            bottom_rows,
+        }
+    }
+
+    /// An underground chunk, changing from prev_biome to biome_change a few rows down
+    pub fn underground(
+        seed: u64,
+        depth: u64,
+        (prev_biome, biome_change): (BiomeType, BiomeType),
+    ) -> Self {
+        let average_biome_change_depth = procedural_functions::generate_random_values(
+            procedural_functions::generate_seed(seed, vec![depth, 432]),
+            1,
+            3,
+            10,
+        )[0] as usize;
+
+        let biome_change_depths = procedural_functions::generate_random_values(
+            procedural_functions::generate_seed(seed, vec![depth, 234]),
+            64, // interpolate between 64 values
+            average_biome_change_depth - 2,
+            average_biome_change_depth + 2, // 5 block range
+        );
+
+        let mut bottom_rows = [0; CHUNK_WIDTH];
+        for (x, row) in bottom_rows.iter_mut().enumerate() {
+            *row = procedural_functions::slice_pos_x(x, &biome_change_depths).round() as usize - 1;
+        }
+        Self {
+            top: prev_biome,
+            bottom: biome_change,
+            bottom_rows,
+        }
+    }
+
+    /// Biome at x, y in the chunk
+    pub fn biome_at(&self, x: usize, y: usize) -> BiomeType {
+        if y >= self.bottom_rows[x] {
+            self.bottom
+        } else {
+            self.top
         }
     }
-    return false;
 }
 
-#[derive(Encode, Decode, Debug, PartialEq, Clone)]
-pub enum OreType {
-    Primary,
+/// Biomes at the top of a chunk and below its biome change, which is the same biome if it has none
+/// The top biome is wherever the closest chunk above with a biome change changed to,
+/// or surface_bottom (the biome at the bottom of the surface chunk) if none did
+pub fn chunk_biomes(seed: u64, depth: u64, surface_bottom: BiomeType) -> (BiomeType, BiomeType) {
+    // the surface chunk isn't generated from a biome change, it always ends in its bottom biome
+    let prev_biome = (1..depth)
+        .rev()
+        .find_map(|d| procedural_functions::generate_chunk_biome_change(seed, d))
+        .unwrap_or(surface_bottom);
+
+    // Determine biome of chunk and whether there will be a biome change
+    let biome_change =
+        procedural_functions::generate_chunk_biome_change(seed, depth).unwrap_or(prev_biome);
+
+    (prev_biome, biome_change)
+}
+
+/// chunk_biomes for each chunk number, worked out from the chunk above instead of searching
+/// back through every chunk; should be a resource
+/// Replaced when the world is created, so it never outlives the world it was filled for
+pub struct BiomeCache {
+    /// Seed of the world this cache is for
+    seed: u64,
+    /// Bottom biome of the world's surface chunk
+    surface_bottom: BiomeType,
+    biomes: HashMap<u64, (BiomeType, BiomeType)>,
+}
+
+impl BiomeCache {
+    /// An empty cache for a world generated with the given settings
+    pub fn new(settings: &GenerationSettings) -> Self {
+        Self {
+            seed: settings.seed,
+            surface_bottom: settings.surface.bottom,
+            biomes: HashMap::new(),
+        }
+    }
+
+    /// Same as chunk_biomes(seed, depth, surface_bottom), but only computed once per chunk
+    pub fn chunk_biomes(&mut self, depth: u64) -> (BiomeType, BiomeType) {
+        if let Some(&biomes) = self.biomes.get(&depth) {
+            return biomes;
+        }
+
+        // go up to the closest chunk we already know, then fill in going down
+        let mut start = depth;
+        while start > 1 && !self.biomes.contains_key(&(start - 1)) {
+            start -= 1;
+        }
+
+        for d in start..=depth {
+            let biomes = match self.biomes.get(&d.wrapping_sub(1)) {
+                // a chunk starts in whatever biome the one above it ended in,
+                // except below the surface, which chunk_biomes handles
+                Some(&(_, above_bottom)) if d > 1 => {
+                    let change = procedural_functions::generate_chunk_biome_change(self.seed, d)
+                        .unwrap_or(above_bottom);
+                    (above_bottom, change)
+                }
+                _ => chunk_biomes(self.seed, d, self.surface_bottom),
+            };
+            self.biomes.insert(d, biomes);
+        }
+
+        self.biomes[&depth]
+    }
+}
+
+/// Whether a tree of `kind` with the top of its trunk at (x, top) has room, so it doesn't grow into
+/// walls: the columns beside the trunk must be empty from just above its canopy to the canopy's bottom
+fn structure_fit(
+    blocks: &[[Option<Block>; CHUNK_WIDTH]; CHUNK_HEIGHT],
+    x: usize,
+    top: usize,
+    kind: TreeKind,
+) -> bool {
+    let depth = kind.canopy().iter().map(|(_, dy)| *dy).max().unwrap_or(0);
+    let sides = kind
+        .canopy()
+        .iter()
+        .map(|(dx, _)| dx.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+    if top == 0 || x < sides || x + sides >= CHUNK_WIDTH || top + depth >= CHUNK_HEIGHT {
+        return false;
+    }
+    (top - 1..=top + depth).all(|row| {
+        (x - sides..=x + sides).all(|column| column == x || blocks[row][column].is_none())
+    })
+}
+
+/// Stamps a tree of `kind` with its trunk in column x, from row y up to row top
+/// Returns false without placing anything if it doesn't fit, see structure_fit
+fn place_tree(
+    blocks: &mut [[Option<Block>; CHUNK_WIDTH]; CHUNK_HEIGHT],
+    x: usize,
+    y: usize,
+    top: usize,
+    kind: TreeKind,
+) -> bool {
+    if !structure_fit(blocks, x, top, kind) {
+        return false;
+    }
+
+    for row in blocks.iter_mut().take(y + 1).skip(top) {
+        row[x] = Some(Block {
+            block_type: kind.trunk_block(),
+        });
+    }
+    // leaves replace the trunk where they overlap
+    for (dx, dy) in kind.canopy() {
+        blocks[top + dy][(x as isize + dx) as usize] = Some(Block {
+            block_type: kind.leaf_block(),
+        });
+    }
+    true
 }
 
 /// Represents an ore vein; stored in the Terrain resource
 #[derive(Encode, Decode, Debug, PartialEq, Clone)]
 pub struct Vein {
-    pub ore_type: OreType,
     pub chunk_number: u64,
     pub start_x: usize,
     pub start_y: usize,
     pub end_x: i16, // i16 because they can hypothetically be negative - which won't break anything
     pub end_y: i16,
     pub thickness_sq: f32, // squared thickness - so we don't need to do square roots
+    /// Picked from the ores of the biome the vein starts in, and used for all of it, even where
+    /// it runs into another biome or chunk
+    pub ore: BlockType,
 }
 
 impl Vein {
-    pub fn new(chunk_number: u64, vein_number: u64) -> Self {
-        // Hard-coded seed for now
-        generate_random_vein(BASE_SEED, chunk_number, vein_number)
+    /// All the veins that start in a chunk, spread out so they don't clump together
+    pub fn generate_chunk_veins(seed: u64, chunk_number: u64, layers: &ChunkLayers) -> Vec<Self> {
+        generate_spaced_veins(
+            seed,
+            chunk_number,
+            generate_random_vein_count(seed, chunk_number),
+            &|x, y| layers.biome_at(x, y),
+        )
+    }
+
+    /// Square of half the thickness, blocks closer than this to the vein's center line are ore
+    pub fn radius_sq(&self) -> f32 {
+        self.thickness_sq / 4.
     }
 }
 
@@ -603,12 +1351,12 @@ pub struct Cave {
 }
 
 impl Cave {
-    pub fn new(chunk_number: u64) -> Self {
-        generate_random_cave(BASE_SEED, chunk_number)
+    pub fn new(seed: u64, chunk_number: u64) -> Self {
+        generate_random_cave(seed, chunk_number)
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BiomeType {
     // if adding to this, also update Distribution in procedural_functions
     Sand,
@@ -630,75 +1378,127 @@ impl BiomeType {
             Self::Ultramafic => BlockType::Gabbro,
         }
     }
-    pub fn ore_block(&self) -> BlockType {
+    /// Ores that veins in this biome can be made of, with their relative weights
+    /// The first one is the biome's main ore
+    pub fn ore_blocks(&self) -> &'static [(BlockType, u32)] {
+        match self {
+            Self::Sand => &[(BlockType::Clay, 1)],
+            Self::Sedimentary => &[(BlockType::Coal, 4), (BlockType::Iron, 1)],
+            Self::Basalt => &[(BlockType::Iron, 1)],
+            Self::Felsic => &[(BlockType::Quartz, 4), (BlockType::Iron, 1)],
+            Self::Mafic => &[(BlockType::Labradorite, 1)],
+            Self::Ultramafic => &[(BlockType::Peridot, 1)],
+        }
+    }
+    /// Pick one of ore_blocks by weight, roll is in 0..1
+    /// Ores after the main one get more common the deeper the chunk, up to RARE_ORE_MAX_BOOST times
+    pub fn pick_ore(&self, roll: f32, chunk_number: u64) -> BlockType {
+        let ores = self.ore_blocks();
+        let boost = (1. + chunk_number as f32 / RARE_ORE_DEPTH_SCALE).min(RARE_ORE_MAX_BOOST);
+        let weight = |i: usize, weight: u32| {
+            if i == 0 {
+                weight as f32
+            } else {
+                weight as f32 * boost
+            }
+        };
+        let total: f32 = ores
+            .iter()
+            .enumerate()
+            .map(|(i, (_, w))| weight(i, *w))
+            .sum();
+        let mut target = roll.clamp(0., 1.) * total;
+        for (i, (ore, w)) in ores.iter().enumerate() {
+            let w = weight(i, *w);
+            if target < w {
+                return *ore;
+            }
+            target -= w;
+        }
+        // only reachable with a roll of 1
+        ores[ores.len() - 1].0
+    }
+    /// What kind of tree grows in this biome, None if nothing does
+    pub fn tree_kind(&self) -> Option<TreeKind> {
         match self {
-            Self::Sand => BlockType::Clay,
-            Self::Sedimentary => BlockType::Coal,
-            Self::Basalt => BlockType::Iron,
-            Self::Felsic => BlockType::Quartz,
-            Self::Mafic => BlockType::Labradorite,
-            Self::Ultramafic => BlockType::Peridot,
+            Self::Sand => Some(TreeKind::Palm),
+            Self::Sedimentary => Some(TreeKind::Broadleaf),
+            Self::Felsic | Self::Mafic => Some(TreeKind::Pine),
+            Self::Basalt | Self::Ultramafic => None,
+        }
+    }
+    /// The small prop that can lie on top of this biome's surface
+    pub const fn surface_decoration(&self) -> BlockType {
+        match self {
+            Self::Sand => BlockType::Shell,
+            _ => BlockType::Rock,
         }
     }
 }
 
-/// _Not_ a component; stored in a Chunk
-#[derive(Copy, Clone, Debug)]
-pub struct Block {
-    /// What kind of block is this
-    pub block_type: BlockType,
-    /// The sprite entity that renders this block
-    pub entity: Option<Entity>,
+/// Kinds of trees that biomes can grow, see BiomeType::tree_kind
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TreeKind {
+    /// A single palm block on the surface, a trunk with palm leaves elsewhere
+    Palm,
+    /// Trunk with a few leaves on top
+    Broadleaf,
+    /// Taller trunk with layers of needles, in darker blocks
+    Pine,
 }
 
-impl Block {
-    /// Easily create a block without an Entity
-    fn new(block_type: BlockType) -> Block {
-        Block {
-            block_type,
-            entity: None,
+impl TreeKind {
+    /// Shortest trunk, in blocks, that there must be room for
+    pub fn min_height(&self) -> usize {
+        match self {
+            Self::Palm | Self::Broadleaf => 3,
+            Self::Pine => 5,
         }
     }
-}
-
-// simple comparison, useful for testing
-impl PartialEq for Block {
-    fn eq(&self, other: &Self) -> bool {
-        // ignore entity differences
-        self.block_type == other.block_type
+    /// Where the leaves go, as (dx, dy) from the top of the trunk with dy going down
+    pub fn canopy(&self) -> &'static [(isize, usize)] {
+        match self {
+            // LLL
+            // LTL
+            //  T
+            Self::Palm | Self::Broadleaf => &[(-1, 0), (0, 0), (1, 0), (-1, 1), (1, 1)],
+            //  L
+            // LLL
+            //  L
+            // LTL
+            //  T
+            Self::Pine => &[(0, 0), (-1, 1), (0, 1), (1, 1), (0, 2), (-1, 3), (1, 3)],
+        }
     }
-}
-
-// only encode/decode the block type, not the entity
-impl Encode for Block {
-    fn encode<E: bincode::enc::Encoder>(
-        &self,
-        encoder: &mut E,
-    ) -> Result<(), bincode::error::EncodeError> {
-        bincode::Encode::encode(&self.block_type, encoder)?;
-        Ok(())
+    /// Block for the trunk of a tree
+    pub fn trunk_block(&self) -> BlockType {
+        match self {
+            Self::Palm | Self::Broadleaf => BlockType::Trunk,
+            Self::Pine => BlockType::PineTrunk,
+        }
+    }
+    /// Block for the top of a tree
+    pub fn leaf_block(&self) -> BlockType {
+        match self {
+            Self::Palm => BlockType::PalmTreeBlock,
+            Self::Broadleaf => BlockType::Leaves,
+            Self::Pine => BlockType::PineNeedles,
+        }
     }
 }
 
-impl Decode for Block {
-    fn decode<D: bincode::de::Decoder>(
-        decoder: &mut D,
-    ) -> Result<Self, bincode::error::DecodeError> {
-        Ok(Self {
-            block_type: bincode::Decode::decode(decoder)?,
-            entity: None,
-        })
-    }
+/// _Not_ a component; stored in a Chunk
+/// Pure data, the client keeps track of rendered sprites in BlockEntities
+#[derive(Copy, Clone, Debug, Encode, Decode, PartialEq, Eq)]
+pub struct Block {
+    /// What kind of block is this
+    pub block_type: BlockType,
 }
 
-impl<'de> bincode::BorrowDecode<'de> for Block {
-    fn borrow_decode<D: bincode::de::BorrowDecoder<'de>>(
-        decoder: &mut D,
-    ) -> Result<Self, bincode::error::DecodeError> {
-        Ok(Self {
-            block_type: bincode::BorrowDecode::borrow_decode(decoder)?,
-            entity: None,
-        })
+impl Block {
+    /// Easily create a block
+    pub fn new(block_type: BlockType) -> Block {
+        Block { block_type }
     }
 }
 
@@ -706,9 +1506,20 @@ impl<'de> bincode::BorrowDecode<'de> for Block {
 #[derive(Component)]
 pub struct RenderedBlock;
 
+/// The sprite entity for every rendered block; client-only resource
+#[derive(Default, Debug)]
+pub struct BlockEntities {
+    /// Keyed by (chunk number, x, y) with the position inside the chunk
+    pub entities: HashMap<(u64, usize, usize), Entity>,
+}
+
 /// A distinct type of block, with its own texture
-#[derive(Copy, Clone, Debug, Encode, Decode, PartialEq, Eq, EnumIter, Hash)]
+/// Ordered by declaration, used to sort maps keyed by block type before encoding
+#[derive(
+    Copy, Clone, Debug, Default, Encode, Decode, PartialEq, Eq, PartialOrd, Ord, EnumIter, Hash,
+)]
 pub enum BlockType {
+    #[default]
     Sand, // primary blocks
     Limestone,
     Basalt,
@@ -725,6 +1536,18 @@ pub enum BlockType {
     PalmTreeBlock,
     Leaves,
     Trunk,
+    PineNeedles,
+    PineTrunk,
+    Shell, // surface decorations
+    Rock,
+    Water,
+    Sapling, // only from mining
+    RefinedClay,
+    RefinedCoal,
+    RefinedIron,
+    RefinedQuartz,
+    RefinedLabradorite,
+    RefinedPeridot,
 }
 
 impl BlockType {
@@ -747,91 +1570,307 @@ impl BlockType {
             BlockType::PalmTreeBlock => "PalmTreeBlock.png",
             BlockType::Leaves => "Leaves.png",
             BlockType::Trunk => "Trunk.png",
+            BlockType::PineNeedles => "PineNeedles.png",
+            BlockType::PineTrunk => "PineTrunk.png",
+            BlockType::Shell => "Shell.png",
+            BlockType::Rock => "Rock.png",
+            BlockType::Water => "Water.png",
+            BlockType::Sapling => "Sapling.png",
+            BlockType::RefinedClay => "RefinedClay.png",
+            BlockType::RefinedCoal => "RefinedCoal.png",
+            BlockType::RefinedIron => "RefinedIron.png",
+            BlockType::RefinedQuartz => "RefinedQuartz.png",
+            BlockType::RefinedLabradorite => "RefinedLabradorite.png",
+            BlockType::RefinedPeridot => "RefinedPeridot.png",
         }
     }
 
     pub const fn is_real_block(&self) -> bool {
         match self {
-            BlockType::CaveVoid | BlockType::PalmTreeBlock => false,
+            BlockType::CaveVoid | BlockType::PalmTreeBlock | BlockType::Water => false,
             _ => true,
         }
     }
+
+    /// Character that represents this block in Chunk::to_ascii
+    pub const fn ascii_char(&self) -> char {
+        match self {
+            BlockType::Sand => ':',
+            BlockType::Limestone
+            | BlockType::Basalt
+            | BlockType::Granite
+            | BlockType::Diabase
+            | BlockType::Gabbro => '#',
+            BlockType::Clay
+            | BlockType::Coal
+            | BlockType::Iron
+            | BlockType::Quartz
+            | BlockType::Labradorite
+            | BlockType::Peridot => 'o',
+            BlockType::CaveVoid => ' ',
+            BlockType::Trunk | BlockType::PineTrunk => 'T',
+            BlockType::PalmTreeBlock | BlockType::Leaves | BlockType::PineNeedles => '*',
+            BlockType::Shell | BlockType::Rock | BlockType::Sapling => ',',
+            BlockType::Water => '~',
+            BlockType::RefinedClay
+            | BlockType::RefinedCoal
+            | BlockType::RefinedIron
+            | BlockType::RefinedQuartz
+            | BlockType::RefinedLabradorite
+            | BlockType::RefinedPeridot => '=',
+        }
+    }
+
+    /// Whether players collide with this block, decorations and water can be walked through
+    pub const fn is_solid(&self) -> bool {
+        !matches!(
+            self,
+            BlockType::Shell | BlockType::Rock | BlockType::Water | BlockType::Sapling
+        )
+    }
+
+    /// What mining this block can give, as (block type, percent chance to get one)
+    pub fn drops(&self) -> Vec<(BlockType, u32)> {
+        match self {
+            // sometimes drops a sapling
+            BlockType::Leaves => vec![(BlockType::Sapling, 20)],
+            BlockType::CaveVoid | BlockType::Water => Vec::new(),
+            // ores come out refined
+            _ => vec![(self.refined().unwrap_or(*self), 100)],
+        }
+    }
+
+    /// What this ore turns into when it is mined, None if it isn't an ore
+    pub const fn refined(&self) -> Option<BlockType> {
+        match self {
+            BlockType::Clay => Some(BlockType::RefinedClay),
+            BlockType::Coal => Some(BlockType::RefinedCoal),
+            BlockType::Iron => Some(BlockType::RefinedIron),
+            BlockType::Quartz => Some(BlockType::RefinedQuartz),
+            BlockType::Labradorite => Some(BlockType::RefinedLabradorite),
+            BlockType::Peridot => Some(BlockType::RefinedPeridot),
+            _ => None,
+        }
+    }
+
+    /// How long this block takes to mine, as a multiple of PLAYER_MINE_DURATION
+    pub const fn hardness(&self) -> f32 {
+        match self {
+            BlockType::PalmTreeBlock
+            | BlockType::Leaves
+            | BlockType::PineNeedles
+            | BlockType::Shell
+            | BlockType::Sapling => 0.25,
+            BlockType::Sand | BlockType::Clay | BlockType::Rock => 0.5,
+            BlockType::Trunk | BlockType::PineTrunk => 0.75,
+            BlockType::Limestone | BlockType::Coal | BlockType::CaveVoid | BlockType::Water => 1.,
+            // bricks of the refined ore
+            BlockType::RefinedClay
+            | BlockType::RefinedCoal
+            | BlockType::RefinedIron
+            | BlockType::RefinedQuartz
+            | BlockType::RefinedLabradorite
+            | BlockType::RefinedPeridot => 1.,
+            BlockType::Basalt | BlockType::Iron => 1.25,
+            BlockType::Granite | BlockType::Diabase | BlockType::Quartz => 1.5,
+            BlockType::Labradorite => 1.75,
+            // ultramafic
+            BlockType::Gabbro | BlockType::Peridot => 2.,
+        }
+    }
+
+    /// Seconds of mining it takes to break this block
+    pub fn mine_duration(&self) -> f32 {
+        PLAYER_MINE_DURATION * self.hardness()
+    }
+
+    /// Whether this block is one of the biome ores
+    pub const fn is_ore(&self) -> bool {
+        matches!(
+            self,
+            BlockType::Clay
+                | BlockType::Coal
+                | BlockType::Iron
+                | BlockType::Quartz
+                | BlockType::Labradorite
+                | BlockType::Peridot
+        )
+    }
+
+    /// Whether a player can be spawned standing on top of this block
+    pub const fn is_safe_spawn_surface(&self) -> bool {
+        !matches!(
+            self,
+            BlockType::CaveVoid
+                | BlockType::PalmTreeBlock
+                | BlockType::Leaves
+                | BlockType::Trunk
+                | BlockType::PineNeedles
+                | BlockType::PineTrunk
+                | BlockType::Shell
+                | BlockType::Rock
+                | BlockType::Water
+                | BlockType::Sapling
+        )
+    }
+}
+
+/// Find where a new player should spawn: on top of the first surface column
+/// (scanning from the left) whose top block is safe to stand on
+pub fn find_spawn_point(terrain: &Terrain) -> Option<PlayerPosition> {
+    let surface = terrain.get_chunk(0)?;
+
+    for x in 0..CHUNK_WIDTH {
+        // topmost block in this column
+        let top = (0..CHUNK_HEIGHT).find_map(|y| surface.blocks[y][x].map(|block| (y, block)));
+
+        match top {
+            // need a free cell above the block to stand in
+            Some((y, block)) if y > 0 && block.block_type.is_safe_spawn_surface() => {
+                return Some(PlayerPosition {
+                    x: x as f32,
+                    y: -((y - 1) as f32),
+                });
+            }
+            _ => continue,
+        }
+    }
+
+    None
+}
+
+/// Find the ore block closest to a position (in block units, like PlayerPosition)
+/// Returns the global (x, y) block coordinates of the ore, only searches the loaded chunks
+pub fn nearest_ore(terrain: &Terrain, position: &PlayerPosition) -> Option<(usize, usize)> {
+    nearest_block(terrain, position, |block_type| block_type.is_ore())
+}
+
+/// Find the closest block whose type matches the filter, see nearest_ore
+pub fn nearest_block(
+    terrain: &Terrain,
+    position: &PlayerPosition,
+    filter: impl Fn(BlockType) -> bool,
+) -> Option<(usize, usize)> {
+    let mut nearest = None;
+    let mut nearest_dist_sq = f32::INFINITY;
+
+    // in order, so ties always go the same way
+    for chunk in terrain.sorted_chunks() {
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                match chunk.blocks[y][x] {
+                    Some(block) if filter(block.block_type) => {
+                        let global_y = chunk.chunk_number as usize * CHUNK_HEIGHT + y;
+
+                        // positions grow downwards as negative numbers
+                        let dx = x as f32 - position.x;
+                        let dy = -(global_y as f32) - position.y;
+                        let dist_sq = dx * dx + dy * dy;
+
+                        if dist_sq < nearest_dist_sq {
+                            nearest_dist_sq = dist_sq;
+                            nearest = Some((x, global_y));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    nearest
 }
 
-/// Create all blocks in chunk as actual entities (and store references to entity in chunk.blocks)
+/// Create all blocks in chunk as actual entities (and store references to them in entities)
 pub fn spawn_chunk(
     chunk_number: u64,
     commands: &mut Commands,
     assets: &Res<AssetServer>,
     terrain: &mut Terrain,
+    entities: &mut BlockEntities,
 ) {
-    let mut chunk = Chunk::new(chunk_number);
+    let chunk = Chunk::new(chunk_number);
     //Calls function to loop through and create the entities and render them
-    render_chunk(commands, assets, &mut chunk);
+    render_chunk(commands, assets, &chunk, entities);
     // add the chunk to our terrain resource
-    terrain.chunks.push(chunk);
+    terrain.insert_chunk(chunk);
 }
 
-pub fn render_chunk(commands: &mut Commands, assets: &Res<AssetServer>, chunk: &mut Chunk) {
-    info!("rendering chunk #{}", chunk.chunk_number);
+pub fn render_chunk(
+    commands: &mut Commands,
+    assets: &Res<AssetServer>,
+    chunk: &Chunk,
+    entities: &mut BlockEntities,
+) {
+    debug!("rendering chunk #{}", chunk.chunk_number);
     //spawns each entity and links it to the block
     for x in 0..CHUNK_WIDTH {
         for y in 0..CHUNK_HEIGHT {
-            let block_opt = &mut chunk.blocks[y][x];
-
             // if there is a block at this location
-            if let Some(block) = block_opt {
-                // spawn in the sprite for the block
-                let entity = commands
-                    .spawn()
-                    .insert_bundle(SpriteBundle {
-                        texture: assets.load(block.block_type.image_file_path()),
-                        transform: Transform {
-                            translation: Vec3::from_array([
-                                to_world_point_x(x),
-                                to_world_point_y(y, chunk.chunk_number),
-                                1.,
-                            ]),
-                            ..default()
-                        },
-                        ..default()
-                    })
-                    .insert(RenderedBlock)
-                    .id();
-
-                // link the entity to the block
-                block.entity = Option::Some(entity);
+            if let Some(block) = chunk.get(x, y) {
+                render_block(commands, assets, chunk.chunk_number, x, y, block, entities);
             }
             // else there is no block and we don't have to spawn any sprite
         }
     }
 }
 
-pub fn derender_chunk(commands: &mut Commands, chunk: &mut Chunk) {
+/// Spawn the sprite for a single block
+pub fn render_block(
+    commands: &mut Commands,
+    assets: &Res<AssetServer>,
+    chunk_number: u64,
+    x: usize,
+    y: usize,
+    block: &Block,
+    entities: &mut BlockEntities,
+) {
+    let entity = commands
+        .spawn()
+        .insert_bundle(SpriteBundle {
+            // handles are shared per path, so hot-reloaded textures show up here too
+            texture: assets.load(block.block_type.image_file_path()),
+            transform: Transform {
+                translation: Vec3::from_array([
+                    to_world_point_x(x),
+                    to_world_point_y(y, chunk_number),
+                    1.,
+                ]),
+                ..default()
+            },
+            ..default()
+        })
+        .insert(RenderedBlock)
+        .id();
+
+    // link the entity to the block
+    // despawn any sprite that was already there so it isn't leaked
+    if let Some(old) = entities.entities.insert((chunk_number, x, y), entity) {
+        commands.entity(old).despawn();
+    }
+}
+
+pub fn derender_chunk(commands: &mut Commands, chunk: &Chunk, entities: &mut BlockEntities) {
     //Despawns each entity and un asigns them
-    info!("derendering chunk #{}", chunk.chunk_number);
+    debug!("derendering chunk #{}", chunk.chunk_number);
     for x in 0..CHUNK_WIDTH {
         for y in 0..CHUNK_HEIGHT {
-            let block_opt = &mut chunk.blocks[y][x];
-            if let Some(block) = block_opt {
-                match block.entity {
-                    Some(entity) => {
-                        commands.entity(entity).despawn();
-                    }
-                    None => {}
-                };
-                block.entity = None;
-            }
+            derender_block(commands, chunk.chunk_number, x, y, entities);
         }
     }
 }
 
-/// Create all blocks in surface chunk as actual entities (and store references to entity in chunk.blocks)
-pub fn create_surface_chunk(terrain: &mut Terrain) {
-    // chunk will get rendered by client
-    let chunk = Chunk::new_surface();
-
-    terrain.chunks.push(chunk);
+/// Despawn the sprite for a single block, if it has one
+pub fn derender_block(
+    commands: &mut Commands,
+    chunk_number: u64,
+    x: usize,
+    y: usize,
+    entities: &mut BlockEntities,
+) {
+    if let Some(entity) = entities.entities.remove(&(chunk_number, x, y)) {
+        commands.entity(entity).despawn();
+    }
 }
 
 pub fn block_exists(x: usize, y: usize, terrain: &mut Terrain) -> bool {
@@ -845,31 +1884,9 @@ pub fn block_exists(x: usize, y: usize, terrain: &mut Terrain) -> bool {
     }
 
     // find if we have the chunk in our terrain
-    for chunk in &mut terrain.chunks {
-        if chunk.chunk_number == (chunk_number as u64) {
-            // we have found our chunk
-            let block_opt = &mut chunk.blocks[block_y_in_chunk][x];
-
-            match block_opt {
-                Some(block) => {
-                    match block.entity {
-                        Some(_entity) => {
-                            return true;
-                        }
-                        None => {
-                            warn!("block at ({}, {}) exists but had no entity attached!", x, y);
-                            return true;
-                        }
-                    };
-                }
-                None => {
-                    return false;
-                }
-            }
-        }
-    }
-
-    return false;
+    terrain
+        .get_chunk(chunk_number as u64)
+        .is_some_and(|chunk| chunk.get(x, block_y_in_chunk).is_some())
 }
 
 pub fn to_world_point_x(x: usize) -> f32 {
@@ -904,13 +1921,36 @@ fn f3_prints_terrain_info(input: Res<Input<KeyCode>>, terrain: Res<Terrain>) {
 
     let mut id_str = String::new();
 
-    for chunk in &terrain.chunks {
+    for chunk in terrain.sorted_chunks() {
         id_str.push_str(&format!("{}, ", chunk.chunk_number));
     }
 
     info!("terrain has {} chunks: {}", terrain.chunks.len(), id_str);
 }
 
+/// Make the F6 key print the chunk the local player is in as ASCII art
+fn f6_prints_player_chunk(
+    input: Res<Input<KeyCode>>,
+    terrain: Res<Terrain>,
+    player: Query<&PlayerPosition, With<crate::player::client::LocalPlayer>>,
+) {
+    if !input.just_pressed(KeyCode::F6) {
+        return;
+    }
+
+    let position = match player.get_single() {
+        Ok(position) => position,
+        Err(_) => return,
+    };
+
+    // above the surface counts as the surface chunk
+    let chunk_number = (-position.y).max(0.) as u64 / CHUNK_HEIGHT as u64;
+    match terrain.get_chunk(chunk_number) {
+        Some(chunk) => info!("chunk {}:\n{}", chunk_number, chunk.to_ascii()),
+        None => warn!("chunk {} is not loaded", chunk_number),
+    }
+}
+
 /// Make the F2 key dump the encoded terrain
 fn f2_prints_terrain_encoding(input: Res<Input<KeyCode>>, terrain: Res<Terrain>) {
     // return early if F2 was not just pressed
@@ -947,6 +1987,17 @@ fn f2_prints_terrain_encoding(input: Res<Input<KeyCode>>, terrain: Res<Terrain>)
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn softer_blocks_mine_faster() {
+        assert!(BlockType::Sand.hardness() < BlockType::Limestone.hardness());
+        assert_eq!(BlockType::Clay.hardness(), BlockType::Sand.hardness());
+        assert!(BlockType::Limestone.hardness() < BlockType::Granite.hardness());
+        assert!(BlockType::Granite.hardness() < BlockType::Gabbro.hardness());
+        assert!(BlockType::Coal.hardness() < BlockType::Peridot.hardness());
+        assert!(BlockType::Sand.mine_duration() < PLAYER_MINE_DURATION);
+    }
 
     #[test]
     fn encode_decode_block() {
@@ -978,7 +2029,7 @@ mod tests {
         let original = {
             let mut terrain = Terrain::new(2);
             // change some block
-            terrain.chunks[1].blocks[1][1] = Some(Block::new(BlockType::Limestone));
+            terrain.get_chunk_mut(1).unwrap().blocks[1][1] = Some(Block::new(BlockType::Limestone));
             terrain
         };
         let encoded = bincode::encode_to_vec(&original, BINCODE_CONFIG).unwrap();
@@ -1003,4 +2054,979 @@ mod tests {
         assert!(terrain_size > block_size);
         assert!(chunk_size > block_size);
     }
+
+    #[test]
+    fn chunk_round_trips_through_run_length_encoding() {
+        let chunk = Chunk::new(3);
+        let encoded = bincode::encode_to_vec(&chunk, BINCODE_CONFIG).unwrap();
+        let (decoded, len): (Chunk, usize) =
+            bincode::decode_from_slice(&encoded, BINCODE_CONFIG).unwrap();
+        assert_eq!(decoded, chunk);
+        assert_eq!(len, encoded.len());
+
+        let mut uniform = Chunk::empty(7);
+        for cell in uniform.blocks.iter_mut().flatten() {
+            *cell = Some(Block::new(BlockType::Limestone));
+        }
+        let encoded = bincode::encode_to_vec(&uniform, BINCODE_CONFIG).unwrap();
+        assert!(
+            encoded.len() < 100,
+            "uniform chunk took {} bytes",
+            encoded.len()
+        );
+        let (decoded, _): (Chunk, usize) =
+            bincode::decode_from_slice(&encoded, BINCODE_CONFIG).unwrap();
+        assert_eq!(decoded, uniform);
+    }
+
+    #[test]
+    fn short_chunk_runs_fail_to_decode() {
+        let runs: Vec<(u16, Option<BlockType>)> = vec![(10, None)];
+        let encoded = bincode::encode_to_vec((runs, 0u64), BINCODE_CONFIG).unwrap();
+        assert!(bincode::decode_from_slice::<Chunk, _>(&encoded, BINCODE_CONFIG).is_err());
+    }
+
+    #[test]
+    fn spawn_skips_unsafe_columns() {
+        let mut surface = Chunk::empty(0);
+        // column 0 is empty, column 1 is topped by leaves, column 2 is topped by sand
+        surface.blocks[5][1] = Some(Block::new(BlockType::Leaves));
+        surface.blocks[6][1] = Some(Block::new(BlockType::Sand));
+        surface.blocks[7][2] = Some(Block::new(BlockType::Sand));
+        let terrain: Terrain = [surface].into_iter().collect();
+
+        let spawn = find_spawn_point(&terrain).unwrap();
+        assert_eq!(spawn.x, 2.);
+        assert_eq!(spawn.y, -6.);
+    }
+
+    #[test]
+    fn fill_area_clears_rectangle() {
+        // two completely solid chunks
+        let mut solid: Terrain = [Chunk::empty(0), Chunk::empty(1)].into_iter().collect();
+        for chunk in solid.chunks.values_mut() {
+            chunk.blocks = [[Some(Block::new(BlockType::Limestone)); CHUNK_WIDTH]; CHUNK_HEIGHT];
+        }
+
+        // clear a rectangle that crosses into chunk 1, corners given backwards
+        let changed = server::fill_area(
+            (5, CHUNK_HEIGHT + 1),
+            (2, CHUNK_HEIGHT - 2),
+            None,
+            &mut solid,
+        )
+        .unwrap();
+        assert_eq!(changed.len(), 2);
+
+        for global_y in 0..CHUNK_HEIGHT * 2 {
+            for x in 0..CHUNK_WIDTH {
+                let chunk = &solid.chunks[&((global_y / CHUNK_HEIGHT) as u64)];
+                let inside = (2..=5).contains(&x)
+                    && (CHUNK_HEIGHT - 2..=CHUNK_HEIGHT + 1).contains(&global_y);
+                assert_eq!(
+                    chunk.blocks[global_y % CHUNK_HEIGHT][x].is_none(),
+                    inside,
+                    "block at ({}, {})",
+                    x,
+                    global_y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fill_area_rejects_oversized() {
+        let mut terrain = Terrain::new(1);
+        let res = server::fill_area(
+            (0, 0),
+            (CHUNK_WIDTH - 1, CHUNK_HEIGHT - 1),
+            None,
+            &mut terrain,
+        );
+        assert!(matches!(res, Err(server::FillError::TooLarge)));
+        // too tall to even work out the area
+        let res = server::fill_area((0, 0), (0, usize::MAX), None, &mut terrain);
+        assert!(matches!(res, Err(server::FillError::TooLarge)));
+        let res = server::fill_area((0, 0), (CHUNK_WIDTH - 1, usize::MAX), None, &mut terrain);
+        assert!(matches!(res, Err(server::FillError::TooLarge)));
+        let res = server::fill_area((0, 0), (CHUNK_WIDTH, 0), None, &mut terrain);
+        assert!(matches!(res, Err(server::FillError::InvalidX)));
+    }
+
+    #[test]
+    fn nearest_ore_picks_closest() {
+        let mut terrain: Terrain = [Chunk::empty(0), Chunk::empty(1)].into_iter().collect();
+        // no ore at all
+        let position = PlayerPosition { x: 5., y: -5. };
+        assert_eq!(nearest_ore(&terrain, &position), None);
+
+        // non-ore blocks are ignored, even when closer
+        let surface = terrain.get_chunk_mut(0).unwrap();
+        surface.blocks[5][6] = Some(Block::new(BlockType::Limestone));
+        surface.blocks[2][15] = Some(Block::new(BlockType::Coal));
+        surface.blocks[9][3] = Some(Block::new(BlockType::Iron));
+        // closest one is in the next chunk down
+        terrain.get_chunk_mut(1).unwrap().blocks[0][5] = Some(Block::new(BlockType::Peridot));
+        assert_eq!(
+            nearest_ore(
+                &terrain,
+                &PlayerPosition {
+                    x: 5.,
+                    y: -(CHUNK_HEIGHT as f32 - 2.),
+                }
+            ),
+            Some((5, CHUNK_HEIGHT))
+        );
+
+        // from near the top, the iron is closer than the coal
+        assert_eq!(nearest_ore(&terrain, &position), Some((3, 9)));
+
+        // searching for one type ignores the others
+        let coal = |b| b == BlockType::Coal;
+        assert_eq!(nearest_block(&terrain, &position, coal), Some((15, 2)));
+        let clay = |b| b == BlockType::Clay;
+        assert_eq!(nearest_block(&terrain, &position, clay), None);
+    }
+
+    #[test]
+    fn biomes_mix_their_ores() {
+        // weights pick in order
+        assert_eq!(BiomeType::Sedimentary.pick_ore(0., 0), BlockType::Coal);
+        assert_eq!(BiomeType::Sedimentary.pick_ore(0.79, 0), BlockType::Coal);
+        assert_eq!(BiomeType::Sedimentary.pick_ore(0.81, 0), BlockType::Iron);
+        assert_eq!(BiomeType::Sedimentary.pick_ore(1., 0), BlockType::Iron);
+        assert_eq!(BiomeType::Basalt.pick_ore(0.99, 0), BlockType::Iron);
+
+        // across many veins, mostly the main ore with some of the other
+        let sedimentary = (BiomeType::Sedimentary, BiomeType::Sedimentary);
+        let mut coal = 0;
+        let mut iron = 0;
+        for chunk_number in 0..20 {
+            let layers = ChunkLayers::underground(DEFAULT_SEED, chunk_number, sedimentary);
+            for vein in Vein::generate_chunk_veins(DEFAULT_SEED, chunk_number, &layers) {
+                match vein.ore {
+                    BlockType::Coal => coal += 1,
+                    BlockType::Iron => iron += 1,
+                    other => panic!("{:?} isn't a sedimentary ore", other),
+                }
+            }
+        }
+        assert!(iron > 0);
+        assert!(coal > iron);
+    }
+
+    #[test]
+    fn rarer_ores_are_more_common_deeper() {
+        // deep down iron gets a bigger share, but coal is still the main ore
+        assert_eq!(BiomeType::Sedimentary.pick_ore(0.79, 100), BlockType::Iron);
+        assert_eq!(BiomeType::Sedimentary.pick_ore(0.5, 100), BlockType::Coal);
+        // biomes with one ore always give it
+        assert_eq!(BiomeType::Basalt.pick_ore(0., 100), BlockType::Iron);
+
+        let sedimentary = (BiomeType::Sedimentary, BiomeType::Sedimentary);
+        let iron_share = |chunks: std::ops::Range<u64>| {
+            let mut iron = 0;
+            let mut total = 0;
+            for chunk_number in chunks {
+                let layers = ChunkLayers::underground(DEFAULT_SEED, chunk_number, sedimentary);
+                for vein in Vein::generate_chunk_veins(DEFAULT_SEED, chunk_number, &layers) {
+                    if vein.ore == BlockType::Iron {
+                        iron += 1;
+                    }
+                    total += 1;
+                }
+            }
+            iron as f32 / total as f32
+        };
+        let shallow = iron_share(0..20);
+        let deep = iron_share(100..200);
+        assert!(deep > shallow * 1.3, "{} deep vs {} shallow", deep, shallow);
+        assert!(deep < 0.5);
+    }
+
+    #[test]
+    fn veins_keep_their_ore_across_chunks_and_biomes() {
+        let settings = GenerationSettings {
+            caves: false,
+            ..Default::default()
+        };
+        let mut biomes = BiomeCache::new(&settings);
+        let chunks: Vec<Chunk> = (0..20)
+            .map(|depth| Chunk::new_with_biomes(depth, &settings, &mut biomes))
+            .collect();
+        let layers: Vec<ChunkLayers> = (0..20)
+            .map(|depth| ChunkLayers::new(&settings, depth, &mut biomes))
+            .collect();
+        // the surface has hills and decorations over its veins, so start below it
+        let veins: Vec<Vein> = (1..19)
+            .flat_map(|depth| {
+                Vein::generate_chunk_veins(settings.seed, depth, &layers[depth as usize])
+            })
+            .collect();
+        let in_vein = |vein: &Vein, x: usize, y: usize| {
+            let top = vein.chunk_number as usize * CHUNK_HEIGHT;
+            y >= top && dist_sq_to_vein(vein, x as f32, (y - top) as f32) < vein.radius_sq()
+        };
+
+        let mut other_biome_blocks = 0;
+        for (i, vein) in veins.iter().enumerate() {
+            let start_biome =
+                layers[vein.chunk_number as usize].biome_at(vein.start_x, vein.start_y);
+            // veins never reach further than the chunk below the one they start in
+            for chunk in &chunks[vein.chunk_number as usize..=vein.chunk_number as usize + 1] {
+                for x in 0..CHUNK_WIDTH {
+                    for y in 0..CHUNK_HEIGHT {
+                        let world_y = chunk.chunk_number as usize * CHUNK_HEIGHT + y;
+                        // where veins overlap the later one wins, so only check blocks in just this one
+                        if !in_vein(vein, x, world_y)
+                            || veins
+                                .iter()
+                                .enumerate()
+                                .any(|(j, other)| j != i && in_vein(other, x, world_y))
+                        {
+                            continue;
+                        }
+
+                        assert_eq!(
+                            chunk.get(x, y).map(|block| block.block_type),
+                            Some(vein.ore),
+                            "vein {:?} at {}, {}",
+                            vein,
+                            x,
+                            world_y
+                        );
+                        if layers[chunk.chunk_number as usize].biome_at(x, y) != start_biome {
+                            other_biome_blocks += 1;
+                        }
+                    }
+                }
+            }
+        }
+        // some veins did run out of the biome they started in
+        assert!(other_biome_blocks > 0);
+    }
+
+    #[test]
+    fn vein_thickness_is_a_diameter() {
+        // thickness 2, so anything within 1 block of the center line
+        let vein = Vein {
+            chunk_number: 0,
+            start_x: 5,
+            start_y: 5,
+            end_x: 15,
+            end_y: 5,
+            thickness_sq: 4.,
+            ore: BlockType::Coal,
+        };
+        let inside = |x: f32, y: f32| dist_sq_to_vein(&vein, x, y) < vein.radius_sq();
+
+        assert!(inside(10., 5.));
+        assert!(inside(10., 5.9));
+        assert!(inside(10., 4.1));
+        assert!(inside(4.1, 5.));
+        assert!(!inside(10., 6.1));
+        assert!(!inside(10., 3.9));
+        assert!(!inside(3.9, 5.));
+        assert!(!inside(16.1, 5.));
+    }
+
+    #[test]
+    fn chunk_access_out_of_range() {
+        let mut chunk = Chunk::empty(0);
+        let block = Block::new(BlockType::Iron);
+
+        // in range
+        assert!(chunk.set(CHUNK_WIDTH - 1, CHUNK_HEIGHT - 1, Some(block)));
+        assert_eq!(chunk.get(CHUNK_WIDTH - 1, CHUNK_HEIGHT - 1), Some(&block));
+        assert_eq!(chunk.get(0, 0), None);
+
+        // out of range doesn't panic
+        assert_eq!(chunk.get(CHUNK_WIDTH, 0), None);
+        assert_eq!(chunk.get(0, CHUNK_HEIGHT), None);
+        assert!(!chunk.set(CHUNK_WIDTH, 0, Some(block)));
+        assert!(!chunk.set(0, usize::MAX, None));
+    }
+
+    #[test]
+    fn no_caves_fills_chunks() {
+        let settings = GenerationSettings {
+            caves: false,
+            ..default()
+        };
+        for depth in 1..4 {
+            // caves are the only source of empty cells below the surface
+            let chunk = Chunk::new_with_settings(depth, &settings);
+            for y in 0..CHUNK_HEIGHT {
+                for x in 0..CHUNK_WIDTH {
+                    assert!(chunk.get(x, y).is_some(), "hole at ({}, {})", x, y);
+                }
+            }
+        }
+
+        // with caves there are holes
+        let chunk = Chunk::new(1);
+        assert!(chunk.blocks.iter().flatten().any(|b| b.is_none()));
+    }
+
+    #[test]
+    fn drop_tables() {
+        // most blocks drop themselves
+        assert_eq!(
+            server::roll_drops(WorldSeed::default(), BlockType::Granite, 3, 70),
+            vec![BlockType::Granite]
+        );
+
+        // ores drop their refined item instead
+        use strum::IntoEnumIterator;
+        for ore in BlockType::iter().filter(|b| b.is_ore()) {
+            let drops = server::roll_drops(WorldSeed::default(), ore, 3, 70);
+            assert_eq!(drops.len(), 1);
+            assert_ne!(drops[0], ore);
+            assert_eq!(Some(drops[0]), ore.refined());
+        }
+
+        // leaves only ever drop saplings, and not always
+        let drops: Vec<Vec<BlockType>> = (0..CHUNK_WIDTH)
+            .map(|x| server::roll_drops(WorldSeed::default(), BlockType::Leaves, x, 10))
+            .collect();
+        assert!(drops.iter().flatten().all(|b| *b == BlockType::Sapling));
+        assert!(drops.iter().any(|d| d.is_empty()));
+        assert!(drops.iter().any(|d| !d.is_empty()));
+
+        // the same block always rolls the same
+        for (x, drop) in drops.iter().enumerate() {
+            assert_eq!(
+                &server::roll_drops(WorldSeed::default(), BlockType::Leaves, x, 10),
+                drop
+            );
+        }
+    }
+
+    #[test]
+    fn server_creates_initial_chunks() {
+        use clap::Parser;
+
+        let seed = DEFAULT_SEED.to_string();
+        let args = match crate::args::GameArgs::parse_from([
+            "game",
+            "server",
+            "--initial-chunks",
+            "4",
+            "--seed",
+            &seed,
+        ]) {
+            crate::args::GameArgs::Server(args) => args,
+            _ => unreachable!(),
+        };
+        let mut world = World::new();
+        world.insert_resource(args);
+
+        let mut stage = SystemStage::single_threaded().with_system(server::create_world);
+        stage.run(&mut world);
+
+        assert_eq!(*world.resource::<WorldSeed>(), WorldSeed(DEFAULT_SEED));
+        let terrain = world.resource::<Terrain>();
+        assert_eq!(terrain.chunks.len(), 4);
+        // chunks are under their own number, starting with the surface
+        for (number, chunk) in &terrain.chunks {
+            assert_eq!(chunk.chunk_number, *number);
+        }
+        assert_eq!(
+            terrain.chunks[&0],
+            Chunk::new_surface(&GenerationSettings::default())
+        );
+    }
+
+    #[test]
+    fn generation_reports_progress() {
+        let mut calls = Vec::new();
+        let terrain = Terrain::new_with_progress(
+            5,
+            &GenerationSettings::default(),
+            &mut BiomeCache::new(&GenerationSettings::default()),
+            |done, total| calls.push((done, total)),
+        );
+
+        assert_eq!(terrain.chunks.len(), 5);
+        assert_eq!(calls, vec![(1, 5), (2, 5), (3, 5), (4, 5), (5, 5)]);
+    }
+
+    #[test]
+    fn cached_biomes_match_computed() {
+        let mut cache = BiomeCache::new(&GenerationSettings::default());
+        // out of order, so some lookups start from a known chunk and some from the top
+        for depth in [40, 0, 1, 2, 7, 39, 41, 100, 3] {
+            assert_eq!(
+                cache.chunk_biomes(depth),
+                chunk_biomes(DEFAULT_SEED, depth, SurfaceBiomes::default().bottom),
+                "depth {}",
+                depth
+            );
+        }
+        assert_eq!(
+            Chunk::new_with_biomes(7, &GenerationSettings::default(), &mut cache),
+            Chunk::new(7)
+        );
+
+        // regenerating the world throws away whatever was cached before
+        let mut world = World::new();
+        let mut stale = BiomeCache::new(&GenerationSettings::default());
+        stale
+            .biomes
+            .insert(5, (BiomeType::Basalt, BiomeType::Felsic));
+        world.insert_resource(stale);
+        let args = ["game", "server", "--initial-chunks", "3"];
+        match crate::args::GameArgs::try_parse_from(args).unwrap() {
+            crate::args::GameArgs::Server(args) => world.insert_resource(args),
+            crate::args::GameArgs::Client(_) => panic!("parsed server args as client args"),
+        }
+        SystemStage::single_threaded()
+            .with_system(server::create_world)
+            .run(&mut world);
+        // no --seed, so compare against whatever random seed was picked
+        let seed = world.resource::<WorldSeed>().0;
+        let mut cache = world.resource_mut::<BiomeCache>();
+        assert!(!cache.biomes.contains_key(&5));
+        assert_eq!(
+            cache.chunk_biomes(5),
+            chunk_biomes(seed, 5, SurfaceBiomes::default().bottom)
+        );
+    }
+
+    /// Every tree block in `blocks` as (x, y, type), in reading order
+    fn tree_layout(
+        blocks: &[[Option<Block>; CHUNK_WIDTH]; CHUNK_HEIGHT],
+    ) -> Vec<(usize, usize, BlockType)> {
+        let mut layout = Vec::new();
+        for (y, row) in blocks.iter().enumerate() {
+            for (x, block) in row.iter().enumerate() {
+                if let Some(block) = block {
+                    layout.push((x, y, block.block_type));
+                }
+            }
+        }
+        layout
+    }
+
+    #[test]
+    fn tree_kinds_have_their_own_shapes() {
+        use BlockType::*;
+
+        let mut blocks = [[None; CHUNK_WIDTH]; CHUNK_HEIGHT];
+        assert!(place_tree(&mut blocks, 10, 20, 17, TreeKind::Broadleaf));
+        assert_eq!(
+            tree_layout(&blocks),
+            vec![
+                (9, 17, Leaves),
+                (10, 17, Leaves),
+                (11, 17, Leaves),
+                (9, 18, Leaves),
+                (10, 18, Trunk),
+                (11, 18, Leaves),
+                (10, 19, Trunk),
+                (10, 20, Trunk),
+            ]
+        );
+
+        let mut blocks = [[None; CHUNK_WIDTH]; CHUNK_HEIGHT];
+        assert!(place_tree(&mut blocks, 10, 20, 15, TreeKind::Pine));
+        assert_eq!(
+            tree_layout(&blocks),
+            vec![
+                (10, 15, PineNeedles),
+                (9, 16, PineNeedles),
+                (10, 16, PineNeedles),
+                (11, 16, PineNeedles),
+                (10, 17, PineNeedles),
+                (9, 18, PineNeedles),
+                (10, 18, PineTrunk),
+                (11, 18, PineNeedles),
+                (10, 19, PineTrunk),
+                (10, 20, PineTrunk),
+            ]
+        );
+
+        // palms away from the surface are broadleaf shaped
+        assert_eq!(TreeKind::Palm.canopy(), TreeKind::Broadleaf.canopy());
+        assert!(TreeKind::Pine.min_height() > TreeKind::Broadleaf.min_height());
+    }
+
+    #[test]
+    fn trees_need_room_beside_their_trunk() {
+        let mut blocks = [[None; CHUNK_WIDTH]; CHUNK_HEIGHT];
+        // a wall just above where the leaves would go
+        blocks[16][11] = Some(Block::new(BlockType::Limestone));
+        let wall = tree_layout(&blocks);
+
+        assert!(!place_tree(&mut blocks, 10, 20, 17, TreeKind::Broadleaf));
+        assert!(!place_tree(&mut blocks, 10, 20, 15, TreeKind::Pine));
+        assert_eq!(tree_layout(&blocks), wall);
+
+        // or a chunk edge
+        assert!(!place_tree(&mut blocks, 0, 20, 17, TreeKind::Broadleaf));
+        assert!(!place_tree(&mut blocks, 10, 20, 0, TreeKind::Broadleaf));
+        assert_eq!(tree_layout(&blocks), wall);
+
+        // further up the wall is out of the way
+        assert!(place_tree(&mut blocks, 10, 20, 18, TreeKind::Broadleaf));
+    }
+
+    #[test]
+    fn trees_follow_biomes() {
+        let tree_blocks = |chunk: &Chunk| -> Vec<BlockType> {
+            let mut found: Vec<BlockType> = chunk
+                .blocks
+                .iter()
+                .flatten()
+                .flatten()
+                .map(|b| b.block_type)
+                .filter(|t| {
+                    matches!(
+                        t,
+                        BlockType::PalmTreeBlock
+                            | BlockType::Trunk
+                            | BlockType::Leaves
+                            | BlockType::PineTrunk
+                            | BlockType::PineNeedles
+                    )
+                })
+                .collect();
+            found.sort();
+            found.dedup();
+            found
+        };
+
+        // the surface is sand, so palms
+        assert_eq!(BiomeType::Sand.tree_kind(), Some(TreeKind::Palm));
+        assert_eq!(
+            tree_blocks(&Chunk::new_surface(&GenerationSettings::default())),
+            vec![BlockType::PalmTreeBlock]
+        );
+
+        // nothing grows in basalt
+        assert_eq!(BiomeType::Basalt.tree_kind(), None);
+        let basalt = (1..100)
+            .find(|&d| {
+                chunk_biomes(DEFAULT_SEED, d, SurfaceBiomes::default().bottom)
+                    == (BiomeType::Basalt, BiomeType::Basalt)
+            })
+            .expect("no all basalt chunk");
+        assert_eq!(tree_blocks(&Chunk::new(basalt)), vec![]);
+
+        // and it's still deterministic
+        assert_eq!(Chunk::new(basalt), Chunk::new(basalt));
+    }
+
+    #[test]
+    fn chunk_ascii_art() {
+        let mut chunk = Chunk::empty(3);
+        chunk.set(0, 0, Some(Block::new(BlockType::Granite)));
+        chunk.set(1, 0, Some(Block::new(BlockType::Iron)));
+        chunk.set(2, 1, Some(Block::new(BlockType::PineTrunk)));
+        chunk.set(3, 1, Some(Block::new(BlockType::CaveVoid)));
+        chunk.set(
+            CHUNK_WIDTH - 1,
+            CHUNK_HEIGHT - 1,
+            Some(Block::new(BlockType::Sand)),
+        );
+
+        let ascii = chunk.to_ascii();
+        let lines: Vec<&str> = ascii.lines().collect();
+        assert_eq!(lines.len(), CHUNK_HEIGHT);
+        assert!(lines.iter().all(|line| line.len() == CHUNK_WIDTH));
+
+        let empty = ".".repeat(CHUNK_WIDTH);
+        assert_eq!(lines[0], format!("#o{}", &empty[2..]));
+        assert_eq!(lines[1], format!("..T {}", &empty[4..]));
+        assert_eq!(lines[2], empty);
+        assert_eq!(lines[CHUNK_HEIGHT - 1], format!("{}:", &empty[1..]));
+    }
+
+    #[test]
+    fn chunk_below_surface_continues_its_biome() {
+        let surface = Chunk::new_surface(&GenerationSettings::default());
+        let below = Chunk::new_with_settings(
+            1,
+            &GenerationSettings {
+                caves: false,
+                ..default()
+            },
+        );
+        let biome_blocks = |biome: BiomeType| {
+            let mut blocks = vec![biome.primary_block()];
+            blocks.extend(biome.ore_blocks().iter().map(|(ore, _)| *ore));
+            blocks
+        };
+
+        // the surface always ends under the sand
+        for block in surface.blocks[CHUNK_HEIGHT - 1].iter() {
+            let block = block.expect("hole in the bottom of the surface");
+            assert!(biome_blocks(SurfaceBiomes::default().bottom).contains(&block.block_type));
+        }
+
+        // and the next chunk picks up from there
+        let (prev_biome, biome_change) =
+            chunk_biomes(DEFAULT_SEED, 1, SurfaceBiomes::default().bottom);
+        assert_eq!(prev_biome, SurfaceBiomes::default().bottom);
+        for block in below.blocks[0].iter().flatten() {
+            assert!(
+                biome_blocks(prev_biome).contains(&block.block_type)
+                    || biome_blocks(biome_change).contains(&block.block_type)
+            );
+        }
+        assert!(below.blocks[0]
+            .iter()
+            .flatten()
+            .any(|b| b.block_type == prev_biome.primary_block()));
+    }
+
+    #[test]
+    fn flat_world_is_solid_below_the_surface() {
+        let terrain = Terrain::new_flat(3, 20);
+        assert_eq!(terrain.chunks.len(), 3);
+
+        for chunk in terrain.chunks.values() {
+            for y in 0..CHUNK_HEIGHT {
+                let global_y = chunk.chunk_number as usize * CHUNK_HEIGHT + y;
+                for x in 0..CHUNK_WIDTH {
+                    let block = chunk.get(x, y);
+                    if global_y < 20 {
+                        assert_eq!(
+                            block, None,
+                            "block above the surface at ({}, {})",
+                            x, global_y
+                        );
+                    } else {
+                        assert!(block.is_some(), "hole at ({}, {})", x, global_y);
+                    }
+                }
+            }
+        }
+
+        // somewhere to stand
+        let spawn = find_spawn_point(&terrain).unwrap();
+        assert_eq!(spawn.y, -19.);
+    }
+
+    #[test]
+    fn surface_biomes_follow_the_seed() {
+        let picks: Vec<SurfaceBiomes> = (0..64).map(SurfaceBiomes::from_seed).collect();
+
+        // stable for a seed
+        assert_eq!(
+            picks[..],
+            (0..64).map(SurfaceBiomes::from_seed).collect::<Vec<_>>()[..]
+        );
+        // but seeds don't all pick the same, and the usual surface is still one of them
+        assert!(picks.iter().any(|p| *p != picks[0]));
+        assert!(picks.contains(&SurfaceBiomes::default()));
+
+        // the surface is made of what was picked, and the chunk below starts where it ends
+        let surface = *picks
+            .iter()
+            .find(|p| **p != SurfaceBiomes::default())
+            .unwrap();
+        let settings = GenerationSettings {
+            surface,
+            ..default()
+        };
+        let chunk = Chunk::new_surface(&settings);
+        assert!(chunk.blocks[CHUNK_HEIGHT - 1]
+            .iter()
+            .flatten()
+            .any(|b| b.block_type == surface.bottom.primary_block()));
+        assert_eq!(
+            chunk_biomes(DEFAULT_SEED, 1, surface.bottom).0,
+            surface.bottom
+        );
+        assert_eq!(
+            BiomeCache::new(&settings).chunk_biomes(1),
+            chunk_biomes(DEFAULT_SEED, 1, surface.bottom)
+        );
+    }
+
+    #[test]
+    fn world_follows_the_seed() {
+        let settings = |seed| GenerationSettings { seed, ..default() };
+        assert_eq!(
+            Chunk::new_surface(&settings(5)),
+            Chunk::new_surface(&settings(5))
+        );
+        assert_ne!(
+            Chunk::new_surface(&settings(5)),
+            Chunk::new_surface(&settings(6))
+        );
+        assert_ne!(
+            Chunk::new_with_settings(3, &settings(5)),
+            Chunk::new_with_settings(3, &settings(6))
+        );
+    }
+
+    #[test]
+    fn decorations_sit_on_the_surface() {
+        let rolls = procedural_functions::generate_random_values(
+            procedural_functions::generate_seed(DEFAULT_SEED, vec![DECORATION_SEED]),
+            CHUNK_WIDTH,
+            0,
+            DECORATION_ODDS,
+        );
+        let chunk = Chunk::new_surface(&GenerationSettings::default());
+
+        let mut found = 0;
+        for (x, roll) in rolls.into_iter().enumerate() {
+            let top = (0..CHUNK_HEIGHT)
+                .find_map(|y| chunk.blocks[y][x].map(|b| (y, b.block_type)))
+                .expect("empty surface column");
+
+            match top {
+                (y, BlockType::Shell | BlockType::Rock) => {
+                    assert_eq!(roll, 0, "decoration at unrolled column {}", x);
+                    let below = chunk.blocks[y + 1][x].expect("floating decoration");
+                    assert!(below.block_type.is_solid());
+                    assert!(!top.1.is_solid());
+                    found += 1;
+                }
+                // trees win over decorations
+                (_, BlockType::PalmTreeBlock) => {}
+                _ => assert_ne!(roll, 0, "missing decoration at column {}", x),
+            }
+        }
+        assert!(found > 0);
+
+        assert_eq!(
+            Chunk::new_surface(&GenerationSettings::default()),
+            Chunk::new_surface(&GenerationSettings::default())
+        );
+    }
+
+    #[test]
+    fn render_mapping_follows_blocks() {
+        use bevy::ecs::system::SystemState;
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(bevy::asset::AssetPlugin)
+            .insert_resource(BlockEntities::default());
+        let world = &mut app.world;
+
+        let mut chunk = Chunk::empty(1);
+        chunk.set(1, 2, Some(Block::new(BlockType::Sand)));
+        chunk.set(3, 4, Some(Block::new(BlockType::Coal)));
+
+        let mut state: SystemState<(Commands, Res<AssetServer>, ResMut<BlockEntities>)> =
+            SystemState::new(world);
+        let rendered_count = |world: &mut World| {
+            world
+                .query_filtered::<Entity, With<RenderedBlock>>()
+                .iter(world)
+                .count()
+        };
+
+        // one sprite per block
+        let (mut commands, assets, mut entities) = state.get_mut(world);
+        render_chunk(&mut commands, &assets, &chunk, &mut entities);
+        state.apply(world);
+        let entities = world.resource::<BlockEntities>();
+        assert_eq!(entities.entities.len(), 2);
+        let mined = entities.entities[&(1, 1, 2)];
+        assert_eq!(rendered_count(world), 2);
+
+        // mining removes exactly that block's sprite
+        chunk.set(1, 2, None);
+        let (mut commands, _, mut entities) = state.get_mut(world);
+        derender_block(&mut commands, 1, 1, 2, &mut entities);
+        state.apply(world);
+        assert!(world.get_entity(mined).is_none());
+        assert_eq!(rendered_count(world), 1);
+
+        // rendering again doesn't leak sprites
+        let (mut commands, assets, mut entities) = state.get_mut(world);
+        render_chunk(&mut commands, &assets, &chunk, &mut entities);
+        state.apply(world);
+        assert_eq!(rendered_count(world), 1);
+
+        // and derendering clears everything
+        let (mut commands, _, mut entities) = state.get_mut(world);
+        derender_chunk(&mut commands, &chunk, &mut entities);
+        state.apply(world);
+        assert!(world.resource::<BlockEntities>().entities.is_empty());
+        assert_eq!(rendered_count(world), 0);
+    }
+
+    #[test]
+    fn distant_chunks_are_unloaded() {
+        let mut world = World::new();
+        world.insert_resource(Terrain::new_flat(11, FLAT_SURFACE_HEIGHT));
+        world.insert_resource(GenerationSettings {
+            flat: true,
+            ..default()
+        });
+        world.insert_resource(BiomeCache::new(&GenerationSettings::default()));
+        let player = world
+            .spawn()
+            .insert(PlayerPosition {
+                x: 5.,
+                y: -((10 * CHUNK_HEIGHT + 3) as f32),
+            })
+            .insert(crate::network::server::ConnectedClientInfo::default())
+            .id();
+        let chunk_numbers = |world: &World| {
+            world
+                .resource::<Terrain>()
+                .sorted_chunks()
+                .iter()
+                .map(|c| c.chunk_number)
+                .collect::<Vec<_>>()
+        };
+
+        SystemStage::single_threaded()
+            .with_system(server::despawn_distant_chunks)
+            .run(&mut world);
+        assert_eq!(chunk_numbers(&world), vec![8, 9, 10]);
+        assert!(world.resource::<Terrain>().get_chunk(7).is_none());
+        assert_eq!(
+            world
+                .resource::<Terrain>()
+                .get_chunk(9)
+                .unwrap()
+                .chunk_number,
+            9
+        );
+
+        // coming back up generates the surface again, in order
+        world.get_mut::<PlayerPosition>(player).unwrap().y = 0.;
+        SystemStage::single_threaded()
+            .with_system(server::check_generate_new_chunks)
+            .with_system(server::despawn_distant_chunks.after(server::check_generate_new_chunks))
+            .run(&mut world);
+        assert_eq!(chunk_numbers(&world), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn changed_chunks_are_kept_loaded() {
+        let mut world = World::new();
+        let mut terrain = Terrain::new_flat(4, FLAT_SURFACE_HEIGHT);
+        // dig a hole in the surface
+        terrain.set_block(5, FLAT_SURFACE_HEIGHT, None);
+        world.insert_resource(terrain);
+        world.insert_resource(GenerationSettings {
+            flat: true,
+            ..default()
+        });
+        world.insert_resource(BiomeCache::new(&GenerationSettings::default()));
+        let player = world
+            .spawn()
+            .insert(PlayerPosition {
+                x: 5.,
+                y: -((10 * CHUNK_HEIGHT + 3) as f32),
+            })
+            .insert(crate::network::server::ConnectedClientInfo::default())
+            .id();
+        let mut stage = SystemStage::single_threaded()
+            .with_system(server::check_generate_new_chunks)
+            .with_system(server::despawn_distant_chunks.after(server::check_generate_new_chunks));
+
+        // far below, only the surface is kept from the old chunks
+        stage.run(&mut world);
+        let terrain = world.resource::<Terrain>();
+        assert!(terrain.get_chunk(0).is_some());
+        assert!((1..=7).all(|n| terrain.get_chunk(n).is_none()));
+
+        // and the hole is still there when coming back up
+        world.get_mut::<PlayerPosition>(player).unwrap().y = 0.;
+        stage.run(&mut world);
+        let terrain = world.resource::<Terrain>();
+        assert_eq!(terrain.get_block(5, FLAT_SURFACE_HEIGHT), None);
+        assert!(terrain.get_block(6, FLAT_SURFACE_HEIGHT).is_some());
+    }
+
+    #[test]
+    fn water_falls_down_an_empty_column() {
+        let mut world = World::new();
+        // empty down to a floor on row 20
+        let mut terrain: Terrain = [Chunk::empty(0)].into_iter().collect();
+        for x in 0..CHUNK_WIDTH {
+            terrain.set_block(x, 20, Some(Block::new(BlockType::Limestone)));
+        }
+        terrain.set_block(5, 2, Some(Block::new(BlockType::Water)));
+        world.insert_resource(terrain);
+        let mut stage = SystemStage::single_threaded().with_system(server::update_water);
+        let water_at = |world: &World, x, y| {
+            world.resource::<Terrain>().get_block(x, y) == Some(&Block::new(BlockType::Water))
+        };
+
+        // one cell per tick
+        for _ in 0..3 {
+            stage.run(&mut world);
+        }
+        assert!(!water_at(&world, 5, 2));
+        assert!(water_at(&world, 5, 5));
+
+        // settles on the floor and stays there
+        for _ in 0..30 {
+            stage.run(&mut world);
+        }
+        assert!(water_at(&world, 5, 19));
+        assert!(server::water_moves(world.resource::<Terrain>()).is_empty());
+    }
+
+    #[test]
+    fn water_spreads_off_ledges() {
+        // a stack of two on a floor, the top one slides off the bottom one and falls beside it
+        let mut terrain: Terrain = [Chunk::empty(0)].into_iter().collect();
+        for x in 0..CHUNK_WIDTH {
+            terrain.set_block(x, 10, Some(Block::new(BlockType::Limestone)));
+        }
+        terrain.set_block(5, 9, Some(Block::new(BlockType::Water)));
+        terrain.set_block(5, 8, Some(Block::new(BlockType::Water)));
+        assert_eq!(server::water_moves(&terrain), vec![((5, 8), (4, 8))]);
+
+        // water doesn't fall out of loaded chunks
+        let mut terrain: Terrain = [Chunk::empty(0)].into_iter().collect();
+        terrain.set_block(0, CHUNK_HEIGHT - 1, Some(Block::new(BlockType::Water)));
+        assert!(server::water_moves(&terrain).is_empty());
+
+        // a big flood only moves so much at once
+        let mut terrain: Terrain = [Chunk::empty(0), Chunk::empty(1)].into_iter().collect();
+        for y in 0..CHUNK_HEIGHT {
+            for x in 0..CHUNK_WIDTH {
+                terrain.set_block(x, y, Some(Block::new(BlockType::Water)));
+            }
+        }
+        assert_eq!(
+            server::water_moves(&terrain).len(),
+            MAX_WATER_MOVES_PER_TICK
+        );
+    }
+
+    /// Hashes of generated worlds with the default seed and settings
+    /// If a change to generation is meant to change the world, replace these with the new hashes
+    const GOLDEN_UNDERGROUND_HASH: u64 = 69712121238629477;
+    const GOLDEN_WORLD_HASH: u64 = 10014662194605655314;
+
+    #[test]
+    fn generation_matches_golden_hashes() {
+        // chunks, veins and caves
+        let underground = Terrain::new(3).content_hash();
+        assert_eq!(
+            underground, GOLDEN_UNDERGROUND_HASH,
+            "underground generation changed, new hash is {}",
+            underground
+        );
+
+        // with the surface on top, for biomes, trees and decorations
+        let settings = GenerationSettings::default();
+        let world =
+            Terrain::new_with_progress(3, &settings, &mut BiomeCache::new(&settings), |_, _| {})
+                .content_hash();
+        assert_eq!(
+            world, GOLDEN_WORLD_HASH,
+            "world generation changed, new hash is {}",
+            world
+        );
+
+        // the hash only depends on what's in the chunks
+        assert_eq!(Terrain::new(3).content_hash(), underground);
+        assert_ne!(Terrain::new(2).content_hash(), underground);
+    }
 }